@@ -1,15 +1,15 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, path::PathBuf, rc::Rc};
 
 use miette::Result;
-use pkg_rs::{bridge::*, config::Config, input::PkgDeclaration};
+use pkg_rs::{bridge::*, config::Config, db::Db, input::PkgDeclaration};
 
 fn main() -> Result<()> {
     let config = Config::load(PathBuf::from(".tmp/config/config.kdl"))?;
     let db_path = tempfile::NamedTempFile::new().unwrap().path().to_path_buf();
     let bridge_api = BridgeApi::new(
         config.bridges_set.clone(),
-        &vec!["bridge1".to_string()],
-        &db_path,
+        &["bridge1".to_string()],
+        Rc::new(Db::new(&db_path)?),
     )?;
 
     let res = bridge_api.update(
@@ -18,6 +18,8 @@ fn main() -> Result<()> {
             name: "pkg1".to_string(),
             input: "pkg1".to_string(),
             attributes: HashMap::new(),
+            tags: Vec::new(),
+            bridge: None,
         },
     )?;
 
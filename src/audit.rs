@@ -0,0 +1,295 @@
+// the audit log, a line of json for every bridge run and every install,
+// remove and link, each one has the hash of the one before it so an edited
+// or removed line breaks the chain (`pkg audit show` checks it)
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use miette::Diagnostic;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::event::{Event, EventSink};
+
+// next to the db by default, `pkg clean --logs` doesn't touch it
+pub const FILE_NAME: &str = "audit.log";
+
+// the `prev` of the first record
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+// enough for the last record, the commands are short
+const TAIL_SIZE: u64 = 64 * 1024;
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum AuditError {
+    #[error(transparent)]
+    #[diagnostic(code(audit::io_error))]
+    IoError(#[from] std::io::Error),
+
+    #[error("The audit log {path:?} was changed at the record {seq}")]
+    #[diagnostic(
+        code(audit::tampered),
+        help("A record was edited, removed or added by hand, the ones after it can't be trusted")
+    )]
+    Tampered { path: PathBuf, seq: u64 },
+}
+
+type Result<T, E = AuditError> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditRecord {
+    pub seq: u64,
+    // unix seconds
+    pub time: u64,
+    // `me (uid 0, sudo)`
+    pub user: String,
+    // the pkg command line
+    pub command: String,
+    // `install`, `remove`, `link`, `adopt` or `bridge <operation>`
+    pub action: String,
+    // the pkg, or `<bridge>/<pkg>` for a bridge run
+    pub target: String,
+    // the exit code of a bridge run
+    pub code: Option<i32>,
+    pub prev: String,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditRecord {
+    fn content(&self) -> serde_json::Value {
+        serde_json::json!({
+            "seq": self.seq,
+            "time": self.time,
+            "user": self.user,
+            "command": self.command,
+            "action": self.action,
+            "target": self.target,
+            "code": self.code,
+            "prev": self.prev,
+        })
+    }
+
+    // of the content rebuilt from the fields, so it's the same when it's read back
+    fn compute_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.prev.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(self.content().to_string().as_bytes());
+
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    fn to_line(&self) -> String {
+        let mut line = self.content();
+        line["hash"] = self.hash.clone().into();
+        line.to_string()
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let value = serde_json::from_str::<serde_json::Value>(line).ok()?;
+        let string = |key: &str| value.get(key)?.as_str().map(String::from);
+
+        Some(Self {
+            seq: value.get("seq")?.as_u64()?,
+            time: value.get("time")?.as_u64()?,
+            user: string("user")?,
+            command: string("command")?,
+            action: string("action")?,
+            target: string("target")?,
+            code: match value.get("code")? {
+                serde_json::Value::Null => None,
+                code => Some(i32::try_from(code.as_i64()?).ok()?),
+            },
+            prev: string("prev")?,
+            hash: string("hash")?,
+        })
+    }
+}
+
+// who runs pkg, the one behind sudo too
+pub fn current_user() -> String {
+    #[cfg(unix)]
+    // SAFETY: no arguments, it can't fail
+    let uid = unsafe { libc::geteuid() }.to_string();
+    // no uids on windows, `USERNAME` is the user
+    #[cfg(windows)]
+    let uid = "?";
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "?".to_string());
+
+    match std::env::var("SUDO_USER") {
+        Ok(sudo_user) if !sudo_user.is_empty() => format!("{sudo_user} (uid {uid}, sudo)"),
+        _ => format!("{user} (uid {uid})"),
+    }
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // appended under an exclusive lock, the async bridge runs and an other
+    // pkg process (`pkg audit show` while a build runs) see whole records
+    pub fn record(&self, action: &str, target: &str, code: Option<i32>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut options = OpenOptions::new();
+        options.create(true).read(true).append(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&self.path)?;
+        let _lock = FileLock::exclusive(&file)?;
+
+        let (seq, prev) = match self.last_record(&mut file)? {
+            Some(last) => (last.seq + 1, last.hash),
+            None => (1, GENESIS.to_string()),
+        };
+
+        let mut record = AuditRecord {
+            seq,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            user: current_user(),
+            command: std::env::args().collect::<Vec<String>>().join(" "),
+            action: action.to_string(),
+            target: target.to_string(),
+            code,
+            prev,
+            hash: String::new(),
+        };
+        record.hash = record.compute_hash();
+
+        writeln!(file, "{}", record.to_line())?;
+        file.sync_data()?;
+
+        Ok(())
+    }
+
+    // a last line that isn't a record is a broken chain, the next record
+    // would hide it
+    fn last_record(&self, file: &mut File) -> Result<Option<AuditRecord>> {
+        let len = file.metadata()?.len();
+        file.seek(SeekFrom::Start(len.saturating_sub(TAIL_SIZE)))?;
+
+        // it can start in the middle of a char
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)?;
+        let tail = String::from_utf8_lossy(&tail);
+
+        let Some(line) = tail.lines().rev().find(|l| !l.trim().is_empty()) else {
+            return Ok(None);
+        };
+
+        AuditRecord::parse(line)
+            .map(Some)
+            .ok_or_else(|| AuditError::Tampered {
+                path: self.path.clone(),
+                seq: 0,
+            })
+    }
+
+    // every record, checked from the first one
+    pub fn read(&self) -> Result<Vec<AuditRecord>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = std::fs::read_to_string(&self.path)?;
+        let mut records = Vec::new();
+        let mut prev = GENESIS.to_string();
+
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            let seq = records.len() as u64 + 1;
+            let tampered = || AuditError::Tampered {
+                path: self.path.clone(),
+                seq,
+            };
+
+            let record = AuditRecord::parse(line).ok_or_else(tampered)?;
+            if record.seq != seq || record.prev != prev || record.hash != record.compute_hash() {
+                return Err(tampered());
+            }
+
+            prev = record.hash.clone();
+            records.push(record);
+        }
+
+        Ok(records)
+    }
+}
+
+// on a handle of its own, the file is still written through the other one
+// (the lock is the one of the open file, both handles share it)
+struct FileLock(File);
+
+impl FileLock {
+    fn exclusive(file: &File) -> std::io::Result<Self> {
+        let file = file.try_clone()?;
+        file.lock()?;
+        Ok(Self(file))
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+// records what a build did and passes the events on, a record that can't be
+// written is an `AuditFailed` event
+#[derive(Debug)]
+pub struct AuditSink<S> {
+    log: AuditLog,
+    inner: S,
+}
+
+impl<S: EventSink> AuditSink<S> {
+    pub fn new(log: AuditLog, inner: S) -> Self {
+        Self { log, inner }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: EventSink> EventSink for AuditSink<S> {
+    fn emit(&mut self, event: Event) {
+        let recorded = match &event {
+            Event::PackageInstalled { name } => self.log.record("install", name, None),
+            Event::PackageRemoved { name } => self.log.record("remove", name, None),
+            Event::LinkDone { .. } => self.log.record("link", "", None),
+            _ => Ok(()),
+        };
+
+        self.inner.emit(event);
+
+        if let Err(err) = recorded {
+            self.inner.emit(Event::AuditFailed {
+                error: err.to_string(),
+            });
+        }
+    }
+}
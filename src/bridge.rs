@@ -1,27 +1,225 @@
-use crate::{DEFAULT_LOG_DIR, DEFAULT_WORKING_DIR, db::Db, input::PkgDeclaration};
-use miette::{Diagnostic, IntoDiagnostic, Result};
+use crate::{
+    DEFAULT_LOG_DIR, DEFAULT_WORKING_DIR,
+    audit::{AuditError, AuditLog},
+    db::{Artifact, Db, DbError, PkgMetadata},
+    fs::dir_size,
+    input::PkgDeclaration,
+    manifest::{BridgeManifest, LATEST_PROTOCOL, ManifestError},
+    secrets::{self, SecretError, Secrets},
+};
+use miette::Diagnostic;
 use std::{
-    collections::HashMap,
-    env,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
     fs::OpenOptions,
     io::Write,
-    path::PathBuf,
-    process::{self, Output},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 use thiserror::Error;
 
 use crate::{Pkg, PkgType, PkgVersion, input};
 
+pub const BRIDGE_ENTRY_POINT_NAME: &str = "run";
+
+// a wasi component, run by the `wasm_bridges` feature
+pub const WASM_ENTRY_POINT_NAME: &str = "run.wasm";
+
+#[cfg(feature = "async")]
+mod async_api;
+mod backend;
+pub mod default_impls;
+#[cfg(feature = "wasm_bridges")]
+pub(crate) mod wasm;
+
+#[cfg(feature = "async")]
+pub use async_api::AsyncBridgeApi;
+#[cfg(feature = "wasm_bridges")]
+pub use backend::WasmBackend;
+pub use backend::{
+    BridgeBackend, DaemonBackend, OperationContext, OperationOutcome, ProcessBackend,
+    SandboxEscape, sandbox_path,
+};
+
+// the `exec` of a pkg is written to its working dir to be run
+const EXEC_FILE_NAME: &str = "pkg_exec";
+
+// written in the working dir of every operation, its path is `$pkg_opts`
+const OPTS_FILE_NAME: &str = "pkg_opts.json";
+
+// the opts file has the resolved secrets, it's removed when the run is over
+// (an early return too) whatever the retention of the working dir
+struct OptsFile(PathBuf);
+
+impl Drop for OptsFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Bridge {
     name: String,
-    entry_point: PathBuf,
+    backend: Arc<dyn BridgeBackend>,
+    protocol: u32,
+    limits: Limits,
+    retry: RetryOverride,
+    // the bridge handles `reinstall` it self
+    native_reinstall: bool,
+    // the `version` of its manifest
+    version: Option<String>,
+}
+
+// the rate limits of the manifest, the clones of a bridge share the last
+// start, so the interval holds between the operations of every thread
+#[derive(Debug, Clone, Default)]
+struct Limits {
+    // only the async api runs the operations in parallel
+    #[cfg_attr(not(feature = "async"), allow(dead_code))]
+    max_parallel: Option<usize>,
+    min_interval: Option<Duration>,
+    last_start: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Limits {
+    fn from_manifest(manifest: &BridgeManifest) -> Self {
+        Self {
+            max_parallel: manifest.max_parallel,
+            min_interval: manifest.min_interval,
+            last_start: Arc::default(),
+        }
+    }
+
+    // blocks until `min-interval` passed since the last start, the lock is
+    // held while waiting so the next one waits for this one
+    fn wait_turn(&self) {
+        let Some(interval) = self.min_interval else {
+            return;
+        };
+
+        let mut last_start = self
+            .last_start
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if let Some(elapsed) = last_start.map(|start| start.elapsed())
+            && elapsed < interval
+        {
+            std::thread::sleep(interval - elapsed);
+        }
+
+        *last_start = Some(Instant::now());
+    }
 }
 
 #[derive(Debug)]
 pub struct BridgeApi {
     bridges: Vec<Bridge>,
-    db: Db,
+    db: Rc<Db>,
+    options: BridgeOptions,
+    // the exit code of the last run of each (bridge, pkg), what the failure
+    // report of a build tells
+    exit_codes: RefCell<HashMap<(String, String), i32>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BridgeOptions {
+    pub log_dir: PathBuf,
+    pub working_dir: PathBuf,
+    pub workdir_retention: WorkdirRetention,
+    // in bytes, the oldest working dirs are removed when they take more
+    pub workdir_max_size: Option<u64>,
+    pub retry: RetryPolicy,
+    pub env: EnvPolicy,
+    // the attributes with these names (`*token*`) are redacted from the logs
+    // and the errors, the `redact` node of the `bridges` config
+    pub redact: Vec<String>,
+    // every run of a bridge is recorded in it, with its exit code
+    pub audit: Option<AuditLog>,
+}
+
+// which env vars of pkg the bridges get, the `env` block of the `bridges`
+// section of the config. a bridge is a third party script, often run as root
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvPolicy {
+    // only these (`LC_*` is a prefix, `*_TOKEN` a suffix), all of them if it's empty
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    // added to the env of every bridge (`HTTP_PROXY`...)
+    pub set: Vec<(String, String)>,
+}
+
+// pkg sets them for the bridges (the non-interactive mode), they are never filtered
+const ALWAYS_PASSED_ENV: &[&str] = &["PKG_NON_INTERACTIVE", "GIT_TERMINAL_PROMPT"];
+
+// which operation working dirs survive the operation
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WorkdirRetention {
+    // only the last failed one of each pkg, to debug it
+    #[default]
+    KeepOnFailure,
+    KeepAlways,
+    Never,
+}
+
+// how a failed operation is retried, the `bridges` section of the config
+// gives it, the manifest of a bridge and the `retries` attribute of a pkg
+// override it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    // the retries after the first run, none by default
+    pub retries: u32,
+    // before the first retry, doubled before each next one
+    pub backoff: Duration,
+    pub on: RetryOn,
+}
+
+// which failures are retried
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RetryOn {
+    // only when the bridge says it's worth it, a `__RETRY` line on its
+    // stderr (a network error...)
+    #[default]
+    Sentinel,
+    // every failure, but the default impls ones
+    Failure,
+}
+
+// what a bridge manifest sets of the retry policy
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RetryOverride {
+    pub retries: Option<u32>,
+    pub backoff: Option<Duration>,
+    pub on: Option<RetryOn>,
+}
+
+// what an operation returns, the working dir is where the bridge run, so
+// the caller can clean it after it moves the pkg out of it
+#[derive(Debug)]
+pub struct OperationOutput {
+    pub pkg: Option<Pkg>,
+    pub work_dir: PathBuf,
+}
+
+impl OperationOutput {
+    // the pkg of an install, an update or a reinstall with the working dir
+    // it's in, an operation that left none is an error
+    pub fn into_pkg(self) -> Result<(Pkg, PathBuf)> {
+        match self.pkg {
+            Some(pkg) => Ok((pkg, self.work_dir)),
+            None => Err(BridgeApiError::NoPkgReturned(self.work_dir)),
+        }
+    }
+}
+
+// the output of `info`: the latest version of the pkg upstream and what the
+// bridge tells about it
+#[derive(Debug, Clone)]
+pub struct UpstreamInfo {
+    pub version: PkgVersion,
+    pub metadata: PkgMetadata,
 }
 
 #[derive(Debug)]
@@ -29,6 +227,8 @@ pub struct BridgeOutput {
     version: PkgVersion,
     pkg_path: PathBuf,
     pkg_type: PkgType,
+    artifacts: Vec<Artifact>,
+    metadata: PkgMetadata,
 }
 
 #[derive(Debug, PartialEq)]
@@ -36,6 +236,9 @@ pub enum Operation {
     Install,
     Update,
     Remove,
+    Reinstall,
+    // what the upstream has of the pkg, nothing is installed
+    Info,
 }
 
 #[derive(Debug)]
@@ -72,6 +275,31 @@ pub enum BridgeApiError {
     #[diagnostic(code(bridge::bridge_error))]
     BridgeError(String),
 
+    #[error("Bridge network error: {0}")]
+    #[diagnostic(
+        code(bridge::network_error),
+        help("It's retried if retries are allowed, see `pkg docs bridges`")
+    )]
+    BridgeNetworkError(String),
+
+    #[error("Bridge couldn't find the pkg: {0}")]
+    #[diagnostic(code(bridge::pkg_not_found), help("Check the input of the pkg"))]
+    PkgNotFoundUpstream(String),
+
+    #[error("Checksum mismatch: {0}")]
+    #[diagnostic(
+        code(bridge::checksum_mismatch),
+        help("The download is corrupted or was changed, nothing was installed")
+    )]
+    ChecksumMismatch(String),
+
+    #[error("Bridge doesn't support this operation: {0}")]
+    #[diagnostic(
+        code(bridge::unsupported_operation),
+        help("A bridge can use the default impl of an operation, see `pkg docs protocol`")
+    )]
+    UnsupportedOperation(String),
+
     #[error("Bridge entry point is not executable: {0}")]
     #[diagnostic(
         code(bridge::bridge_entry_point_not_executable),
@@ -92,6 +320,10 @@ pub enum BridgeApiError {
     #[diagnostic(code(bridge::bridge_failed))]
     BridgeFailedAtRuntime(String),
 
+    #[error("Bridge returned no pkg, its working dir is {0:?}")]
+    #[diagnostic(code(bridge::no_pkg_returned))]
+    NoPkgReturned(PathBuf),
+
     #[error("Bridge returned a wrong version format: {0}")]
     #[diagnostic(
         code(bridge::bridge_wrong_version_format),
@@ -136,9 +368,65 @@ pub enum BridgeApiError {
     #[error("The pkg path should be a file if type is single executable: {0}")]
     #[diagnostic(code(bridge::PkgPathWithTrySingleExecutableShouldBeFile))]
     PkgPathWithTrySingleExecutableShouldBeFile(PathBuf),
+
+    #[error("Bridge returned an artifact out of the pkg: {0}")]
+    #[diagnostic(
+        code(bridge::artifact_out_of_pkg),
+        help("The artifacts should be files in the pkg directory, they're stored with it")
+    )]
+    ArtifactOutOfPkg(PathBuf),
+
+    #[error("Bridge returned a path out of its sandbox: {0}")]
+    #[diagnostic(
+        code(bridge::path_out_of_sandbox),
+        help("A wasm bridge can only return paths in its working dir (`/` in the component)")
+    )]
+    PathOutOfSandbox(PathBuf),
+
+    #[error("Wrong value of the `{attribute}` attribute of {pkg}")]
+    #[diagnostic(code(bridge::wrong_attribute))]
+    WrongAttribute {
+        pkg: String,
+        attribute: &'static str,
+    },
+
+    #[error("Missing commands needed by the bridges: {missing}")]
+    #[diagnostic(code(bridge::missing_host_commands), help("{hint}"))]
+    MissingHostCommands { missing: String, hint: String },
+
+    #[error("The bridge {0} is a wasm component (`run.wasm`)")]
+    #[diagnostic(
+        code(bridge::wasm_bridges_not_enabled),
+        help("pkg should be built with the `wasm_bridges` feature to run it")
+    )]
+    WasmBridgesNotEnabled(String),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    DbError(#[from] DbError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    ManifestError(#[from] ManifestError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    SecretError(#[from] SecretError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    AuditError(#[from] AuditError),
 }
 
-fn write_logs(pkg_name: &str, log_file: &PathBuf, bridge_output: &Output) -> Result<()> {
+type Result<T, E = BridgeApiError> = std::result::Result<T, E>;
+
+// the secrets of the pkg are redacted from the output
+fn write_logs(
+    pkg_name: &str,
+    log_file: &Path,
+    bridge_output: &OperationOutcome,
+    secrets: &Secrets,
+) -> Result<()> {
     let mut log_file_handle = OpenOptions::new()
         .create(true)
         .append(true)
@@ -146,53 +434,332 @@ fn write_logs(pkg_name: &str, log_file: &PathBuf, bridge_output: &Output) -> Res
         .map_err(|err| BridgeApiError::BridgeFailedToOpenLogFile(err.to_string()))?;
 
     // Write stdout to log
-    log_file_handle
-        .write_all(format!("\n|PKG={}|:::::::\n", &pkg_name).as_bytes())
-        .into_diagnostic()?;
-    log_file_handle
-        .write_all("|STDOUT|::::::::\n".as_bytes())
-        .into_diagnostic()?;
-    log_file_handle
-        .write_all(&bridge_output.stdout)
-        .into_diagnostic()?;
-    log_file_handle.write_all(b"\n").into_diagnostic()?;
-    log_file_handle
-        .write_all("\n|STDERR|::::::::\n".as_bytes())
-        .into_diagnostic()?;
-    log_file_handle
-        .write_all(&bridge_output.stderr)
-        .into_diagnostic()?;
-    log_file_handle.write_all(b"\n").into_diagnostic()?;
+    log_file_handle.write_all(format!("\n|PKG={}|:::::::\n", &pkg_name).as_bytes())?;
+    log_file_handle.write_all("|STDOUT|::::::::\n".as_bytes())?;
+    log_file_handle.write_all(&secrets.redact_bytes(&bridge_output.stdout))?;
+    log_file_handle.write_all(b"\n")?;
+    log_file_handle.write_all("\n|STDERR|::::::::\n".as_bytes())?;
+    log_file_handle.write_all(&secrets.redact_bytes(&bridge_output.stderr))?;
+    log_file_handle.write_all(b"\n")?;
 
     Ok(())
 }
 
-mod default_impls {
-    use std::path::PathBuf;
+fn write_retry_log(
+    log_file: &Path,
+    attempt: u32,
+    retries: u32,
+    delay: Duration,
+) -> std::io::Result<()> {
+    let mut log_file_handle = OpenOptions::new().append(true).open(log_file)?;
+
+    log_file_handle.write_all(
+        format!(
+            "|RETRY|::::::::: {attempt}/{retries} in {}ms\n",
+            delay.as_millis()
+        )
+        .as_bytes(),
+    )
+}
 
-    use miette::{IntoDiagnostic, Result};
-    pub fn remove() -> Result<bool> {
-        let pkg_path = std::env::var("pkg_path").unwrap();
-        let mut removed = false;
-        if PathBuf::from(&pkg_path).exists() {
-            if PathBuf::from(&pkg_path).is_dir() {
-                std::fs::remove_dir_all(&pkg_path).into_diagnostic()?;
-            } else {
-                std::fs::remove_file(&pkg_path).into_diagnostic()?;
+// what a pkg was installed (or updated) from, `operation` is `install` or
+// `update`, if it's the same the next time the bridge would do the same
+// again, the attributes are sorted in the json so the order in the inputs
+// doesn't matter
+pub fn cache_key(
+    bridge_name: &str,
+    pkg: &PkgDeclaration,
+    operation: &str,
+    version: &PkgVersion,
+) -> String {
+    let attributes = pkg
+        .attributes
+        .iter()
+        .map(|(key, value)| (key.clone(), attribute_json(value)))
+        .collect::<serde_json::Map<_, _>>();
+
+    let content = format!(
+        "{bridge_name}\0{}\0{}\0{operation}\0{version}",
+        pkg.input,
+        serde_json::Value::Object(attributes)
+    );
+
+    // fnv-1a, it's stored in the db so it should be the same from a build of
+    // pkg to another (the std hasher isn't)
+    let hash = content.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    format!("{hash:016x}")
+}
+
+fn attribute_json(value: &input::AttributeValue) -> serde_json::Value {
+    match value {
+        input::AttributeValue::String(value) => value.clone().into(),
+        input::AttributeValue::Integer(value) => (*value).into(),
+        input::AttributeValue::Float(value) => (*value).into(),
+        input::AttributeValue::Boolean(value) => (*value).into(),
+        input::AttributeValue::List(values) => values.iter().map(attribute_json).collect(),
+        input::AttributeValue::Map(map) => map
+            .iter()
+            .map(|(key, value)| (key.clone(), attribute_json(value)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+    }
+}
+
+// a missing file is an error, not a file that's not executable
+fn is_executable(path: &Path) -> Result<bool> {
+    path.metadata()?;
+    Ok(crate::fs::platform::is_executable(path))
+}
+
+// `curl (bridge1, bridge2), tar (bridge1)` and how to install them
+pub fn missing_host_commands(mut missing: Vec<(String, String)>) -> BridgeApiError {
+    missing.sort();
+
+    let mut commands: Vec<(String, Vec<String>)> = Vec::new();
+    for (command, bridge) in missing {
+        match commands.last_mut() {
+            Some((last, bridges)) if *last == command => bridges.push(bridge),
+            _ => commands.push((command, vec![bridge])),
+        }
+    }
+
+    let names = commands
+        .iter()
+        .map(|(command, _)| command.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let hint = match crate::host::install_command() {
+        Some(install) => format!("Try: `{install} {names}` (the package names may differ)"),
+        None => format!("Install {names} with the package manager of the host"),
+    };
+
+    BridgeApiError::MissingHostCommands {
+        missing: commands
+            .iter()
+            .map(|(command, bridges)| format!("{command} ({})", bridges.join(", ")))
+            .collect::<Vec<_>>()
+            .join(", "),
+        hint,
+    }
+}
+
+// the bridges in the bridge set that have a `run` (or `run.wasm`) entry point
+// the bridge of the set an instance of the config runs, a bridge that's not
+// an instance runs it self
+pub fn resolve_alias<'a>(aliases: &'a BTreeMap<String, String>, bridge_name: &'a str) -> &'a str {
+    aliases
+        .get(bridge_name)
+        .map(String::as_str)
+        .unwrap_or(bridge_name)
+}
+
+pub fn available_bridges(bridge_set_path: &Path) -> Result<Vec<String>> {
+    let mut bridges = Vec::new();
+
+    if !bridge_set_path.is_dir() {
+        return Ok(bridges);
+    }
+
+    for entry in bridge_set_path.read_dir()? {
+        let path = entry?.path();
+
+        if (path.join(BRIDGE_ENTRY_POINT_NAME).is_file()
+            || path.join(WASM_ENTRY_POINT_NAME).is_file())
+            && let Some(name) = path.file_name().and_then(|n| n.to_str())
+        {
+            bridges.push(name.to_string());
+        }
+    }
+
+    bridges.sort();
+    Ok(bridges)
+}
+
+impl Default for BridgeOptions {
+    fn default() -> Self {
+        Self {
+            log_dir: crate::host::platform_dir(DEFAULT_LOG_DIR),
+            working_dir: crate::host::platform_dir(DEFAULT_WORKING_DIR),
+            workdir_retention: WorkdirRetention::default(),
+            workdir_max_size: None,
+            retry: RetryPolicy::default(),
+            env: EnvPolicy::default(),
+            redact: secrets::default_sensitive_attributes(),
+            audit: None,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: Duration::from_secs(1),
+            on: RetryOn::default(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn with(self, other: &RetryOverride) -> Self {
+        Self {
+            retries: other.retries.unwrap_or(self.retries),
+            backoff: other.backoff.unwrap_or(self.backoff),
+            on: other.on.unwrap_or(self.on),
+        }
+    }
+
+    // a `__ERR` that won't pass by trying again (not-found, checksum-mismatch...)
+    // is never retried, a `__ERR network` always can be
+    pub fn is_retryable(&self, outcome: &OperationOutcome) -> bool {
+        match self.on {
+            RetryOn::Sentinel => outcome.wants_retry(),
+            RetryOn::Failure => {
+                !outcome.success()
+                    && !outcome.wants_default_impl()
+                    && outcome
+                        .structured_error()
+                        .is_none_or(|(code, _)| code == backend::ERROR_NETWORK)
             }
-            removed = true;
         }
-        Ok(removed)
+    }
+
+    // the wait before the retry `attempt` (from 1)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
     }
 }
 
-// NOTE: unix only
-fn is_executable(path: &PathBuf) -> Result<bool> {
-    use std::os::unix::fs::PermissionsExt;
+impl EnvPolicy {
+    pub fn allows(&self, name: &str) -> bool {
+        let matches = |pattern: &String| secrets::name_matches(pattern, name);
 
-    let metadata = path.metadata().into_diagnostic()?;
-    let permissions = metadata.permissions();
-    Ok(permissions.mode() & 0o111 != 0) // Check if any execute bit is set
+        ALWAYS_PASSED_ENV.contains(&name)
+            || ((self.allow.is_empty() || self.allow.iter().any(matches))
+                && !self.deny.iter().any(matches))
+    }
+
+    // the env the bridges start with, none is the whole env of pkg
+    pub fn bridge_env(&self) -> Option<Vec<(String, String)>> {
+        if *self == Self::default() {
+            return None;
+        }
+
+        let mut env = std::env::vars_os()
+            .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+            .filter(|(key, _)| self.allows(key))
+            .collect::<Vec<_>>();
+        env.extend(self.set.iter().cloned());
+
+        Some(env)
+    }
+}
+
+impl std::fmt::Display for RetryOn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sentinel => write!(f, "sentinel"),
+            Self::Failure => write!(f, "failure"),
+        }
+    }
+}
+
+impl std::str::FromStr for RetryOn {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "sentinel" => Ok(Self::Sentinel),
+            "failure" => Ok(Self::Failure),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for WorkdirRetention {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::KeepOnFailure => write!(f, "keep-on-failure"),
+            Self::KeepAlways => write!(f, "keep-always"),
+            Self::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl std::str::FromStr for WorkdirRetention {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "keep-on-failure" => Ok(Self::KeepOnFailure),
+            "keep-always" => Ok(Self::KeepAlways),
+            "never" => Ok(Self::Never),
+            _ => Err(()),
+        }
+    }
+}
+
+fn sub_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+
+    if !dir.is_dir() {
+        return Ok(dirs);
+    }
+
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+
+    Ok(dirs)
+}
+
+// the `<working_dir>/<bridge>/<pkg>/<timestamp>` dirs under the dir, oldest first
+fn timestamped_dirs(dir: &Path) -> Result<Vec<(u128, PathBuf)>> {
+    let mut dirs = Vec::new();
+
+    if !dir.is_dir() {
+        return Ok(dirs);
+    }
+
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+
+        if let Some(timestamp) = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.parse::<u128>().ok())
+            && path.is_dir()
+        {
+            dirs.push((timestamp, path));
+        }
+    }
+
+    dirs.sort();
+    Ok(dirs)
+}
+
+impl BridgeApiError {
+    // the typed error of a `__ERR <code> <message>` line, or the whole stderr
+    pub fn from_failure(outcome: &OperationOutcome) -> Self {
+        let Some((code, message)) = outcome.structured_error() else {
+            return Self::BridgeError(String::from_utf8_lossy(&outcome.stderr).trim().to_string());
+        };
+
+        match code.as_str() {
+            backend::ERROR_NETWORK => Self::BridgeNetworkError(message),
+            backend::ERROR_NOT_FOUND => Self::PkgNotFoundUpstream(message),
+            backend::ERROR_CHECKSUM_MISMATCH => Self::ChecksumMismatch(message),
+            backend::ERROR_UNSUPPORTED_OP => Self::UnsupportedOperation(message),
+            _ => Self::BridgeError(format!("{code}: {message}")),
+        }
+    }
 }
 
 impl Operation {
@@ -201,21 +768,58 @@ impl Operation {
             Operation::Install => "install".to_string(),
             Operation::Update => "update".to_string(),
             Operation::Remove => "remove".to_string(),
+            Operation::Reinstall => "reinstall".to_string(),
+            Operation::Info => "info".to_string(),
         }
     }
 }
 
 impl BridgeApi {
-    pub fn new(
+    pub fn new(bridge_set_path: PathBuf, needed_bridges: &[String], db: Rc<Db>) -> Result<Self> {
+        Self::new_with_aliases(bridge_set_path, needed_bridges, &BTreeMap::new(), db)
+    }
+
+    // `aliases` are the instances of the config, `github-work` runs the
+    // `github` bridge of the set
+    pub fn new_with_aliases(
         bridge_set_path: PathBuf,
         needed_bridges: &[String],
-        db_path: &PathBuf,
+        aliases: &BTreeMap<String, String>,
+        db: Rc<Db>,
     ) -> Result<Self> {
-        let bridges = Self::load_bridges(&bridge_set_path, needed_bridges)?;
+        let bridges = Self::load_bridges(&bridge_set_path, needed_bridges, aliases)?;
 
-        let db = Db::new(db_path)?;
+        Ok(Self {
+            bridges,
+            db,
+            options: BridgeOptions::default(),
+            exit_codes: RefCell::default(),
+        })
+    }
 
-        Ok(Self { bridges, db })
+    pub fn with_options(mut self, options: BridgeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // runs the pkgs of the bridge with the backend instead of the bridge set,
+    // a native bridge or a mock in the tests, it gets the latest protocol
+    pub fn with_backend(
+        mut self,
+        bridge_name: &str,
+        backend: impl BridgeBackend + 'static,
+    ) -> Self {
+        self.bridges.retain(|b| b.name != bridge_name);
+        self.bridges.push(Bridge {
+            name: bridge_name.to_string(),
+            backend: Arc::new(backend),
+            protocol: LATEST_PROTOCOL,
+            limits: Limits::default(),
+            retry: RetryOverride::default(),
+            native_reinstall: false,
+            version: None,
+        });
+        self
     }
 
     pub fn run_operation(
@@ -223,20 +827,125 @@ impl BridgeApi {
         bridge_name: &str,
         pkg: &PkgDeclaration,
         operation: Operation,
-    ) -> Result<Option<Pkg>> {
-        let bridge_entry_point = &self
-            .bridges
-            .iter()
-            .find(|b| b.name == bridge_name)
-            .ok_or(BridgeApiError::BridgeNotFound(bridge_name.to_string()))?
-            .entry_point;
+    ) -> Result<OperationOutput> {
+        let work_dir = self.setup_working_directory(bridge_name, &pkg.name)?;
 
-        Self::setup_working_directory(bridge_name, &pkg.name)?;
+        let result = self
+            .resolve_bridge(bridge_name, pkg, &work_dir)
+            .and_then(|bridge| self.run_operation_in(&bridge, pkg, operation, &work_dir));
 
-        let input = pkg.input.to_string();
-        let attributes = &pkg.attributes;
+        if result.is_err()
+            && self.options.workdir_retention == WorkdirRetention::Never
+            && work_dir.exists()
+        {
+            std::fs::remove_dir_all(&work_dir)?;
+        }
 
-        let log_file = PathBuf::from(format!("{}/{}.log", &DEFAULT_LOG_DIR, &bridge_name));
+        self.prune_working_dirs(bridge_name, &pkg.name, &work_dir)?;
+
+        result
+    }
+
+    // the bridge the pkg is declared in, unless the pkg brings its own
+    fn resolve_bridge(
+        &self,
+        bridge_name: &str,
+        pkg: &PkgDeclaration,
+        work_dir: &Path,
+    ) -> Result<Bridge> {
+        let entry_point = match &pkg.bridge {
+            None => {
+                return Ok(self
+                    .bridges
+                    .iter()
+                    .find(|b| b.name == bridge_name)
+                    .ok_or(BridgeApiError::BridgeNotFound(bridge_name.to_string()))?
+                    .clone());
+            }
+            Some(input::BridgeOverride::Path(path)) if path.is_dir() => {
+                let manifest = BridgeManifest::load(path)?;
+                let entry_point = path.join(BRIDGE_ENTRY_POINT_NAME);
+
+                if !entry_point.is_file() {
+                    return Err(BridgeApiError::BridgeNotFound(
+                        entry_point.display().to_string(),
+                    ));
+                }
+
+                return self.override_bridge(bridge_name, entry_point, manifest.protocol);
+            }
+            Some(input::BridgeOverride::Path(path)) => {
+                if !path.is_file() {
+                    return Err(BridgeApiError::BridgeNotFound(path.display().to_string()));
+                }
+
+                path.clone()
+            }
+            Some(input::BridgeOverride::Exec(script)) => {
+                let entry_point = work_dir.join(EXEC_FILE_NAME);
+
+                let script = if script.starts_with("#!") {
+                    script.clone()
+                } else {
+                    format!("#!/bin/sh\n{script}")
+                };
+                std::fs::write(&entry_point, script)?;
+                crate::fs::platform::set_executable(&entry_point)?;
+
+                entry_point
+            }
+        };
+
+        self.override_bridge(bridge_name, entry_point, 1)
+    }
+
+    fn override_bridge(
+        &self,
+        bridge_name: &str,
+        entry_point: PathBuf,
+        protocol: u32,
+    ) -> Result<Bridge> {
+        if !is_executable(&entry_point)? {
+            return Err(BridgeApiError::BridgeEntryPointNotExecutable(entry_point));
+        }
+
+        Ok(Bridge {
+            name: bridge_name.to_string(),
+            backend: Arc::new(ProcessBackend::new(entry_point)),
+            protocol,
+            limits: Limits::default(),
+            retry: RetryOverride::default(),
+            native_reinstall: false,
+            version: None,
+        })
+    }
+
+    // the config, then the manifest of the bridge, then the pkg
+    fn retry_policy(&self, bridge: &Bridge, pkg: &PkgDeclaration) -> Result<RetryPolicy> {
+        let mut retry = self.options.retry.with(&bridge.retry);
+
+        if let Some(value) = pkg.attributes.get("retries") {
+            retry.retries = match value {
+                input::AttributeValue::Integer(n) => u32::try_from(*n).ok(),
+                _ => None,
+            }
+            .ok_or_else(|| BridgeApiError::WrongAttribute {
+                pkg: pkg.name.clone(),
+                attribute: "retries",
+            })?;
+        }
+
+        Ok(retry)
+    }
+
+    fn run_operation_in(
+        &self,
+        bridge: &Bridge,
+        pkg: &PkgDeclaration,
+        operation: Operation,
+        work_dir: &Path,
+    ) -> Result<OperationOutput> {
+        let log_file = self.log_file(&bridge.name);
 
         let log_file_parent = log_file.parent().unwrap();
         let _ = std::fs::create_dir_all(log_file_parent)
@@ -248,7 +957,7 @@ impl BridgeApi {
 
         let mut pkg_path = None;
 
-        if (operation == Operation::Update) || (operation == Operation::Remove) {
+        if operation != Operation::Install {
             pkg_path = self
                 .db
                 .get_pkgs_by_name(std::slice::from_ref(&pkg.name))?
@@ -259,114 +968,339 @@ impl BridgeApi {
             // the correct result
         }
 
-        Self::pass_opts_to_env(attributes, pkg_path, &log_file.to_string_lossy())?;
+        // only now, the secrets are in the working dir for the operation only
+        let (resolved, secrets) = self.resolve_secrets(pkg)?;
 
-        let mut bridge = process::Command::new(bridge_entry_point);
-        bridge.arg(operation.display());
-        bridge.arg(input.clone());
+        let run = |operation: &Operation, pkg_path: Option<&PathBuf>| {
+            self.run_bridge(bridge, &resolved, &secrets, operation, pkg_path, work_dir)
+        };
 
-        let bridge_output = bridge.output();
+        let installed = |output: OperationOutcome| -> Result<Option<Pkg>> {
+            let parsed_output =
+                Self::parse_bridge_output(output, bridge.protocol, work_dir, &secrets)?;
+            Ok(Some(Pkg {
+                name: pkg.name.clone(),
+                version: parsed_output.version,
+                path: parsed_output.pkg_path,
+                pkg_type: parsed_output.pkg_type,
+                artifacts: parsed_output.artifacts,
+                metadata: parsed_output.metadata,
+            }))
+        };
 
-        // Write the log
-        if let Ok(output) = &bridge_output {
-            write_logs(&pkg.name, &log_file, output)?;
-        }
-
-        match bridge_output {
-            Ok(output) => {
-                // Bridge command succeeded
-                let res = match operation {
-                    Operation::Install => {
-                        let parsed_output = Self::parse_bridge_output(output)?;
-                        let pkg = Pkg {
-                            name: pkg.name.clone(),
-                            version: parsed_output.version,
-                            path: parsed_output.pkg_path,
-                            pkg_type: parsed_output.pkg_type,
-                        };
-                        Ok(Some(pkg))
-                    }
-                    Operation::Update => {
-                        let success = output.status.success();
-                        let stderr = String::from_utf8(output.stderr.clone()).into_diagnostic()?;
-                        let stderr = stderr.trim();
-
-                        let output = if !success
-                            && output.status.code().unwrap() == 1
-                            && stderr == "__IMPL_DEFAULT"
-                        {
-                            let output = process::Command::new(bridge_entry_point)
-                                .arg(Operation::Install.display())
-                                .arg(input.clone())
-                                .output();
-
-                            if let Ok(bridge_output) = &output {
-                                write_logs(&pkg.name, &log_file, bridge_output)?;
-
-                                if bridge_output.status.success() {
-                                    let _ = default_impls::remove()?;
-                                }
-                            }
-
-                            output.into_diagnostic()?
-                        } else {
-                            output
-                        };
-
-                        let parsed_output = Self::parse_bridge_output(output)?;
-                        let pkg = Pkg {
-                            name: pkg.name.clone(),
-                            version: parsed_output.version,
-                            path: parsed_output.pkg_path,
-                            pkg_type: parsed_output.pkg_type,
-                        };
-                        Ok(Some(pkg))
-                    }
-                    Operation::Remove => {
-                        let success = output.status.success();
-                        let stderr = String::from_utf8(output.stderr).into_diagnostic()?;
-                        let stderr = stderr.trim();
-
-                        if !success // if it failed
-                            && output.status.code().unwrap() == 1 // and return 1
-                            && stderr == "__IMPL_DEFAULT"
-                        // and print the the
-                        // stderr __IMPL_DEFAULT
-                        // a log right
-                        {
-                            default_impls::remove()?;
-                        } else {
-                            return Err(BridgeApiError::BridgeError(stderr.to_string()).into());
-                        }
-                        Ok(None)
+        // the remove of the bridge, or the default one, the old pkg is gone after it
+        let remove = |output: OperationOutcome| -> Result<()> {
+            if output.wants_default_impl() {
+                if let Some(pkg_path) = &pkg_path {
+                    default_impls::remove(pkg_path)?;
+                }
+                Ok(())
+            } else if output.success() {
+                Ok(())
+            } else {
+                Err(BridgeApiError::from_failure(&output))
+            }
+        };
+
+        let pkg = match operation {
+            Operation::Install => installed(run(&operation, None)?)?,
+            Operation::Update => {
+                let output = run(&operation, pkg_path.as_ref())?;
+
+                let output = if output.wants_default_impl() {
+                    default_impls::update(pkg_path.as_deref(), || {
+                        run(&Operation::Install, pkg_path.as_ref())
+                    })?
+                } else {
+                    output
+                };
+
+                installed(output)?
+            }
+            Operation::Remove => {
+                remove(run(&operation, pkg_path.as_ref())?)?;
+
+                // nothing to move out of the working dir
+                self.clean_working_dir(work_dir)?;
+
+                None
+            }
+            // the old pkg is removed first, then the new one is installed in
+            // the same working dir, unless the bridge does it it self
+            Operation::Reinstall => {
+                let native = if bridge.native_reinstall {
+                    Some(run(&operation, pkg_path.as_ref())?)
+                        .filter(|output| !output.wants_default_impl())
+                } else {
+                    None
+                };
+
+                let output = match native {
+                    Some(output) => output,
+                    None => {
+                        remove(run(&Operation::Remove, pkg_path.as_ref())?)?;
+                        run(&Operation::Install, None)?
                     }
                 };
 
-                Self::clear_env(&attributes.keys().map(|s| s.to_string()).collect())?;
+                installed(output)?
+            }
+            // its output is read by `info`
+            Operation::Info => {
+                run(&operation, None)?;
+                self.clean_working_dir(work_dir)?;
 
-                res
+                None
             }
-            Err(err) => {
-                Self::clear_env(&attributes.keys().map(|s| s.to_string()).collect())?;
+        };
 
-                Err(BridgeApiError::BridgeFailedAtRuntime(err.to_string()).into())
+        Ok(OperationOutput {
+            pkg,
+            work_dir: work_dir.to_path_buf(),
+        })
+    }
+
+    // a bridge that couldn't be started has no exit code
+    fn audit(
+        &self,
+        bridge: &Bridge,
+        pkg: &PkgDeclaration,
+        operation: &Operation,
+        outcome: Option<&OperationOutcome>,
+    ) -> Result<()> {
+        if let Some(audit) = &self.options.audit {
+            audit.record(
+                &format!("bridge {}", operation.display()),
+                &format!("{}/{}", bridge.name, pkg.name),
+                outcome.map(|outcome| outcome.code),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn log_file(&self, bridge_name: &str) -> PathBuf {
+        self.options.log_dir.join(format!("{bridge_name}.log"))
+    }
+
+    // none if the bridge wasn't run for the pkg, or couldn't be started
+    pub fn exit_code(&self, bridge_name: &str, pkg_name: &str) -> Option<i32> {
+        self.exit_codes
+            .borrow()
+            .get(&(bridge_name.to_string(), pkg_name.to_string()))
+            .copied()
+    }
+
+    // the pkg with its secrets, and what's redacted from the logs and the
+    // errors: the secrets and the attributes with a sensitive name
+    fn resolve_secrets(&self, pkg: &PkgDeclaration) -> Result<(PkgDeclaration, Secrets)> {
+        let (resolved, mut secrets) = secrets::resolve(pkg)?;
+        secrets.add_sensitive(&resolved, &self.options.redact);
+
+        Ok((resolved, secrets))
+    }
+
+    // one run of the bridge (with its retries), logged
+    fn run_bridge(
+        &self,
+        bridge: &Bridge,
+        pkg: &PkgDeclaration,
+        secrets: &Secrets,
+        operation: &Operation,
+        pkg_path: Option<&PathBuf>,
+        work_dir: &Path,
+    ) -> Result<OperationOutcome> {
+        let log_file = &self.log_file(&bridge.name);
+        let retry = self.retry_policy(bridge, pkg)?;
+        let opts_file = OptsFile(Self::write_opts_file(
+            pkg, operation, pkg_path, log_file, work_dir,
+        )?);
+
+        let mut envs = Self::bridge_env(pkg_path, log_file, work_dir, &opts_file.0);
+        if bridge.protocol < 2 {
+            for (key, value) in &pkg.attributes {
+                Self::push_attribute_env(&mut envs, key, value);
             }
         }
+
+        let base_env = self.options.env.bridge_env();
+        let ctx = OperationContext {
+            envs: &envs,
+            base_env: base_env.as_deref(),
+            work_dir,
+        };
+
+        let mut attempt = 0;
+        let bridge_output = loop {
+            bridge.limits.wait_turn();
+            let outcome = bridge.backend.execute(operation, pkg, &ctx);
+            self.audit(bridge, pkg, operation, outcome.as_ref().ok())?;
+            let outcome = match outcome {
+                Ok(outcome) => outcome,
+                Err(err) => break Err(err),
+            };
+
+            if attempt >= retry.retries || !retry.is_retryable(&outcome) {
+                break Ok(outcome);
+            }
+
+            attempt += 1;
+            let delay = retry.delay(attempt);
+            // the last attempt is logged below
+            let _ = write_logs(&pkg.name, log_file, &outcome, secrets);
+            let _ = write_retry_log(log_file, attempt, retry.retries, delay);
+            std::thread::sleep(delay);
+        };
+
+        // the working dir can be kept to debug it, not with the secrets in it
+        drop(opts_file);
+
+        // Write the log
+        if let Ok(output) = &bridge_output {
+            self.exit_codes
+                .borrow_mut()
+                .insert((bridge.name.clone(), pkg.name.clone()), output.code);
+            write_logs(&pkg.name, log_file, output, secrets)?;
+        }
+
+        // the stderr ends up in the errors, a bridge can echo its token
+        bridge_output
+            .map(|output| OperationOutcome {
+                stderr: secrets.redact_bytes(&output.stderr),
+                ..output
+            })
+            .map_err(|err| {
+                match err
+                    .get_ref()
+                    .and_then(|inner| inner.downcast_ref::<SandboxEscape>())
+                {
+                    Some(SandboxEscape(path)) => BridgeApiError::PathOutOfSandbox(path.clone()),
+                    None => BridgeApiError::BridgeFailedAtRuntime(err.to_string()),
+                }
+            })
     }
 
-    pub fn install(&self, bridge_name: &str, pkg: &PkgDeclaration) -> Result<Pkg> {
-        self.run_operation(bridge_name, pkg, Operation::Install)
-            .map(|p| p.unwrap())
+    // returns the installed pkg and the working dir it's in
+    pub fn install(&self, bridge_name: &str, pkg: &PkgDeclaration) -> Result<(Pkg, PathBuf)> {
+        self.run_operation(bridge_name, pkg, Operation::Install)?
+            .into_pkg()
     }
 
-    pub fn update(&self, bridge_name: &str, pkg: &PkgDeclaration) -> Result<Pkg> {
-        self.run_operation(bridge_name, pkg, Operation::Update)
-            .map(|p| p.unwrap())
+    pub fn update(&self, bridge_name: &str, pkg: &PkgDeclaration) -> Result<(Pkg, PathBuf)> {
+        self.run_operation(bridge_name, pkg, Operation::Update)?
+            .into_pkg()
+    }
+
+    // the old pkg is removed (by the bridge or the default impl) before the new
+    // one is installed, or the bridge reinstalls it it self (`reinstall #true`
+    // in its manifest)
+    pub fn reinstall(&self, bridge_name: &str, pkg: &PkgDeclaration) -> Result<(Pkg, PathBuf)> {
+        self.run_operation(bridge_name, pkg, Operation::Reinstall)?
+            .into_pkg()
     }
 
     pub fn remove(&self, bridge_name: &str, pkg: &PkgDeclaration) -> Result<bool> {
         let res = self.run_operation(bridge_name, pkg, Operation::Remove)?;
-        Ok(res.is_none())
+        Ok(res.pkg.is_none())
+    }
+
+    // the latest version of the pkg and its metadata, the bridge installs
+    // nothing (`UnsupportedOperation` if it has no `info`)
+    pub fn info(&self, bridge_name: &str, pkg: &PkgDeclaration) -> Result<UpstreamInfo> {
+        let work_dir = self.setup_working_directory(bridge_name, &pkg.name)?;
+
+        let result = self
+            .resolve_bridge(bridge_name, pkg, &work_dir)
+            .and_then(|bridge| {
+                let (resolved, secrets) = self.resolve_secrets(pkg)?;
+                let output = self.run_bridge(
+                    &bridge,
+                    &resolved,
+                    &secrets,
+                    &Operation::Info,
+                    None,
+                    &work_dir,
+                )?;
+                Self::parse_info_output(output, &secrets)
+            });
+
+        // there's nothing to keep in it
+        if work_dir.exists() {
+            std::fs::remove_dir_all(&work_dir)?;
+        }
+        self.prune_working_dirs(bridge_name, &pkg.name, &work_dir)?;
+
+        result
+    }
+
+    // called once the pkg is stored, unless the config asks to keep it
+    pub fn clean_working_dir(&self, work_dir: &Path) -> Result<()> {
+        if self.options.workdir_retention != WorkdirRetention::KeepAlways && work_dir.exists() {
+            std::fs::remove_dir_all(work_dir)?;
+        }
+
+        Ok(())
+    }
+
+    // the dirs of the older operations on the pkg are useless unless we keep
+    // them all, then the whole working dir is capped to the max size
+    fn prune_working_dirs(&self, bridge_name: &str, pkg_name: &str, current: &Path) -> Result<()> {
+        if self.options.workdir_retention != WorkdirRetention::KeepAlways {
+            let pkg_dir = self.options.working_dir.join(bridge_name).join(pkg_name);
+
+            for (_, dir) in timestamped_dirs(&pkg_dir)? {
+                if dir != current {
+                    std::fs::remove_dir_all(&dir)?;
+                }
+            }
+        }
+
+        let Some(max_size) = self.options.workdir_max_size else {
+            return Ok(());
+        };
+
+        let mut dirs = Vec::new();
+        for bridge_dir in sub_dirs(&self.options.working_dir)? {
+            for pkg_dir in sub_dirs(&bridge_dir)? {
+                dirs.extend(timestamped_dirs(&pkg_dir)?);
+            }
+        }
+        dirs.sort();
+
+        let mut dirs = dirs
+            .into_iter()
+            .map(|(_, dir)| {
+                let size = dir_size(&dir);
+                (dir, size)
+            })
+            .collect::<Vec<(PathBuf, u64)>>();
+        let mut total = dirs.iter().map(|(_, size)| size).sum::<u64>();
+
+        // oldest first, and never the one the caller still needs
+        for (dir, size) in dirs.drain(..) {
+            if total <= max_size {
+                break;
+            }
+
+            if dir != current {
+                std::fs::remove_dir_all(&dir)?;
+                total -= size;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn has_bridge(&self, bridge_name: &str) -> bool {
+        self.bridges.iter().any(|b| b.name == bridge_name)
+    }
+
+    // the `version` of the manifest of the bridge, none without one
+    pub fn bridge_version(&self, bridge_name: &str) -> Option<&str> {
+        self.bridges
+            .iter()
+            .find(|b| b.name == bridge_name)
+            .and_then(|b| b.version.as_deref())
     }
 
     pub fn default_impls_remove(&self, pkg_name: &str) -> Result<bool> {
@@ -374,30 +1308,28 @@ impl BridgeApi {
             .db
             .get_pkgs_by_name(std::slice::from_ref(&pkg_name.to_string()))?
             .first()
-            .expect("Failed to get pkg from db, can't remove it")
+            .ok_or_else(|| DbError::PkgNotFound(pkg_name.to_string()))?
             .path
             .clone();
-        unsafe {
-            std::env::set_var("pkg_path", pkg_path);
-        }
-        use default_impls::remove;
 
-        remove()
+        Ok(default_impls::remove(&pkg_path)?)
     }
 
-    fn parse_bridge_output(bridge_output: Output) -> Result<BridgeOutput> {
+    // relative paths in the output are relative to the bridge working dir
+    fn parse_bridge_output(
+        bridge_output: OperationOutcome,
+        protocol: u32,
+        work_dir: &Path,
+        secrets: &Secrets,
+    ) -> Result<BridgeOutput> {
         const BRIDGE_OUTPUT_SEPARATOR: char = ',';
-        const VERSION_SEPARATOR: char = '.';
 
-        if !bridge_output.status.success() {
-            return Err(BridgeApiError::BridgeError(
-                String::from_utf8(bridge_output.stderr)
-                    .unwrap_or("failed to parse bridge output".to_string()),
-            ))?;
+        if !bridge_output.success() {
+            return Err(BridgeApiError::from_failure(&bridge_output));
         }
 
         // to string
-        let bridge_output = String::from_utf8(bridge_output.stdout).into_diagnostic()?;
+        let bridge_output = String::from_utf8_lossy(&bridge_output.stdout).into_owned();
 
         // get the first line of the bridge output
         let first_line =
@@ -419,7 +1351,9 @@ impl BridgeApi {
         let pkg_type;
 
         if split.len() > 3 || split.len() < 2 {
-            return Err(BridgeApiError::BridgeWrongOutput(bridge_output))?;
+            return Err(BridgeApiError::BridgeWrongOutput(
+                secrets.redact(&bridge_output),
+            ))?;
         } else {
             pkg_path = PathBuf::from(split.first().unwrap().to_string());
             let version_str = split.get(1).unwrap().to_string();
@@ -428,20 +1362,11 @@ impl BridgeApi {
                 None => PkgType::SingleExecutable,
             };
 
-            let version_split = version_str.split(VERSION_SEPARATOR).collect::<Vec<&str>>();
-
-            if version_split.len() != 3 {
-                return Err(BridgeApiError::BridgeWrongVersionFormat(version_str))?;
-            } else {
-                version = PkgVersion {
-                    first_cell: version_split[0].to_string(),
-                    second_cell: version_split[1].to_string(),
-                    third_cell: version_split[2].to_string(),
-                };
-            }
+            version = PkgVersion::parse(&version_str)
+                .ok_or(BridgeApiError::BridgeWrongVersionFormat(version_str))?;
         }
 
-        let pwd = std::env::current_dir().into_diagnostic()?;
+        let pwd = work_dir;
 
         let pkg_path = if pkg_path.is_relative() {
             pwd.join(pkg_path)
@@ -505,24 +1430,78 @@ impl BridgeApi {
             return Err(BridgeApiError::PkgEntryPointIsNotExecutable(path.clone()))?;
         }
 
+        // the next lines can give artifacts (`systemd-unit,./app/app.service`)
+        // and, from the protocol 2, the metadata (`license,MIT`), the other
+        // lines are ignored
+        let mut metadata = PkgMetadata::default();
+        let mut artifacts = Vec::new();
+        for artifact in bridge_output
+            .lines()
+            .skip(1)
+            .map(str::trim)
+            .filter(|l| protocol < 2 || !metadata.parse_line(l))
+            .filter_map(Artifact::parse)
+        {
+            let path = pwd.join(artifact.path());
+
+            if path == pkg_path || !path.starts_with(&pkg_path) || !path.is_file() {
+                return Err(BridgeApiError::ArtifactOutOfPkg(path));
+            }
+
+            artifacts.push(artifact.with_path(path));
+        }
+
         Ok(BridgeOutput {
             version,
             pkg_path,
             pkg_type,
+            artifacts,
+            metadata,
         })
     }
 
-    fn load_bridges(bridge_set_path: &PathBuf, needed_bridges: &[String]) -> Result<Vec<Bridge>> {
-        const BRIDGE_ENTRY_POINT_NAME: &str = "run";
+    // `version` on the first line, then the metadata lines of the install
+    fn parse_info_output(output: OperationOutcome, secrets: &Secrets) -> Result<UpstreamInfo> {
+        // there's no default impl of it
+        if output.wants_default_impl() {
+            return Err(BridgeApiError::UnsupportedOperation("info".to_string()));
+        }
+        if !output.success() {
+            return Err(BridgeApiError::from_failure(&output));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        let mut lines = stdout.lines().map(str::trim);
+        let first_line = lines
+            .next()
+            .filter(|line| !line.is_empty())
+            .ok_or_else(|| BridgeApiError::BridgeWrongOutput(secrets.redact(&stdout)))?;
+        let version = PkgVersion::parse(first_line)
+            .ok_or_else(|| BridgeApiError::BridgeWrongVersionFormat(first_line.to_string()))?;
+
+        let mut metadata = PkgMetadata::default();
+        for line in lines {
+            metadata.parse_line(line);
+        }
 
+        Ok(UpstreamInfo { version, metadata })
+    }
+
+    fn load_bridges(
+        bridge_set_path: &Path,
+        needed_bridges: &[String],
+        aliases: &BTreeMap<String, String>,
+    ) -> Result<Vec<Bridge>> {
         if !bridge_set_path.exists() {
-            return Err(BridgeApiError::BridgeSetNotFound(bridge_set_path.clone()).into());
+            return Err(BridgeApiError::BridgeSetNotFound(
+                bridge_set_path.to_path_buf(),
+            ));
         };
 
         if !bridge_set_path.is_dir() {
-            return Err(
-                BridgeApiError::BridgeSetPathAreNotADirectory(bridge_set_path.clone()).into(),
-            );
+            return Err(BridgeApiError::BridgeSetPathAreNotADirectory(
+                bridge_set_path.to_path_buf(),
+            ));
         }
 
         let content = bridge_set_path
@@ -530,6 +1509,8 @@ impl BridgeApi {
             .map_err(BridgeApiError::IoError)?;
 
         let mut bridges = Vec::<Bridge>::new();
+        // `(command, bridge)`, all of them are reported at once
+        let mut missing_commands = Vec::<(String, String)>::new();
 
         for file in content {
             let file = file.map_err(BridgeApiError::IoError)?;
@@ -543,7 +1524,13 @@ impl BridgeApi {
                     .unwrap()
                     .to_string();
 
-                if !needed_bridges.contains(&bridge_name) {
+                // the bridge it self and its instances, each is a bridge of its own
+                let names = needed_bridges
+                    .iter()
+                    .filter(|name| resolve_alias(aliases, name) == bridge_name)
+                    .cloned()
+                    .collect::<Vec<String>>();
+                if names.is_empty() {
                     continue;
                 }
 
@@ -555,10 +1542,52 @@ impl BridgeApi {
                         ))?;
                     }
 
-                    bridges.push(Bridge {
-                        name: bridge_name,
-                        entry_point: entry_point_path,
-                    });
+                    let manifest = BridgeManifest::load(&bridge_dir)?;
+
+                    for command in &manifest.needs {
+                        if crate::host::find_command(command).is_none() {
+                            missing_commands.push((command.clone(), bridge_name.clone()));
+                        }
+                    }
+
+                    let backend: Arc<dyn BridgeBackend> = if manifest.daemon {
+                        Arc::new(DaemonBackend::new(entry_point_path))
+                    } else {
+                        Arc::new(ProcessBackend::new(entry_point_path))
+                    };
+
+                    for name in names {
+                        bridges.push(Bridge {
+                            name,
+                            backend: backend.clone(),
+                            protocol: manifest.protocol,
+                            limits: Limits::from_manifest(&manifest),
+                            retry: manifest.retry,
+                            native_reinstall: manifest.reinstall,
+                            version: manifest.version.clone(),
+                        });
+                    }
+                } else if bridge_dir.join(WASM_ENTRY_POINT_NAME).is_file() {
+                    #[cfg(not(feature = "wasm_bridges"))]
+                    Err(BridgeApiError::WasmBridgesNotEnabled(bridge_name.clone()))?;
+
+                    #[cfg(feature = "wasm_bridges")]
+                    {
+                        let manifest = BridgeManifest::load(&bridge_dir)?;
+                        let backend: Arc<dyn BridgeBackend> =
+                            Arc::new(WasmBackend::new(bridge_dir.join(WASM_ENTRY_POINT_NAME)));
+                        for name in names {
+                            bridges.push(Bridge {
+                                protocol: manifest.protocol,
+                                limits: Limits::from_manifest(&manifest),
+                                retry: manifest.retry,
+                                native_reinstall: manifest.reinstall,
+                                version: manifest.version.clone(),
+                                name,
+                                backend: backend.clone(),
+                            });
+                        }
+                    }
                 }
             }
         }
@@ -566,77 +1595,143 @@ impl BridgeApi {
         let missing_bridges = needed_bridges
             .iter()
             .filter(|b| !bridges.iter().any(|bridge| &bridge.name == *b))
-            .cloned()
+            .map(|b| resolve_alias(aliases, b).to_string())
             .collect::<Vec<String>>();
 
         if !missing_bridges.is_empty() {
             return Err(BridgeApiError::BridgeNotFound(
                 missing_bridges.first().unwrap().to_string(),
-            )
-            .into());
+            ));
+        }
+
+        if !missing_commands.is_empty() {
+            return Err(missing_host_commands(missing_commands));
         }
 
         Ok(bridges)
     }
 
-    fn pass_opts_to_env(
-        attributes: &HashMap<String, input::AttributeValue>,
-        pkg_path: Option<PathBuf>,
-        log_file: &str,
-    ) -> Result<(), BridgeApiError> {
-        unsafe {
-            if let Some(path) = pkg_path {
-                if env::var("pkg_path").is_ok() {
-                    env::remove_var("pkg_path");
-                }
-                env::set_var("pkg_path", path);
-            }
-
-            if env::var("pkg_log_file").is_ok() {
-                env::remove_var("pkg_log_file");
-            }
-            env::set_var("pkg_log_file", log_file);
+    // the env of the bridge process only, the pkg process env is never touched,
+    // the attributes are added to it only for the bridges of the protocol 1
+    fn bridge_env(
+        pkg_path: Option<&PathBuf>,
+        log_file: &Path,
+        work_dir: &Path,
+        opts_file: &Path,
+    ) -> Vec<(String, String)> {
+        let mut envs = Vec::new();
+
+        if let Some(path) = pkg_path {
+            envs.push(("pkg_path".to_string(), path.to_string_lossy().into_owned()));
         }
 
-        for (key, value) in attributes {
-            let value = match value {
-                input::AttributeValue::String(value) => value.to_string(),
-                input::AttributeValue::Integer(value) => value.to_string(),
-                input::AttributeValue::Float(value) => value.to_string(),
-                input::AttributeValue::Boolean(value) => value.to_string(),
-            };
-
-            if env::var(key).is_ok() {
-                unsafe {
-                    env::remove_var(key);
-                }
-            }
-
-            unsafe {
-                env::set_var(key, value);
-            }
-        }
+        envs.push((
+            "pkg_log_file".to_string(),
+            log_file.to_string_lossy().into_owned(),
+        ));
+        envs.push((
+            "pkg_work_dir".to_string(),
+            work_dir.to_string_lossy().into_owned(),
+        ));
+
+        envs.push((
+            "pkg_opts".to_string(),
+            opts_file.to_string_lossy().into_owned(),
+        ));
+        envs.push((
+            default_impls::VERSION_ENV.to_string(),
+            default_impls::VERSION.to_string(),
+        ));
+
+        envs.push(("pkg_os".to_string(), crate::host::os().to_string()));
+        envs.push(("pkg_arch".to_string(), std::env::consts::ARCH.to_string()));
+        envs.push(("pkg_libc".to_string(), crate::host::libc().to_string()));
+
+        envs
+    }
 
-        Ok(())
+    // everything the bridge gets, as json, in `$pkg_opts`
+    fn write_opts_file(
+        pkg: &PkgDeclaration,
+        operation: &Operation,
+        pkg_path: Option<&PathBuf>,
+        log_file: &Path,
+        work_dir: &Path,
+    ) -> Result<PathBuf> {
+        let attributes = pkg
+            .attributes
+            .iter()
+            .map(|(key, value)| (key.clone(), attribute_json(value)))
+            .collect::<serde_json::Map<_, _>>();
+
+        let opts = serde_json::json!({
+            "operation": operation.display(),
+            "name": pkg.name,
+            "input": pkg.input,
+            "pkg_path": pkg_path,
+            "log_file": log_file,
+            "work_dir": work_dir,
+            "attributes": attributes,
+        });
+
+        let opts_file = work_dir.join(OPTS_FILE_NAME);
+        // only pkg (and the bridges it runs) can read the secrets in it
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&opts_file)?
+            .write_all(opts.to_string().as_bytes())?;
+
+        Ok(opts_file)
     }
 
-    fn clear_env(attributes_keys: &Vec<String>) -> Result<()> {
-        for key in attributes_keys {
-            if env::var(key).is_ok() {
-                unsafe {
-                    env::remove_var(key);
+    // a list `assets "a" "b"` is `assets="a b"`, `assets_0="a"`, `assets_1="b"`
+    // and `assets_len=2`, a map `build { cmd "make"; }` is `build_cmd="make"`
+    fn push_attribute_env(
+        envs: &mut Vec<(String, String)>,
+        key: &str,
+        value: &input::AttributeValue,
+    ) {
+        let value = match value {
+            input::AttributeValue::String(value) => value.to_string(),
+            input::AttributeValue::Integer(value) => value.to_string(),
+            input::AttributeValue::Float(value) => value.to_string(),
+            input::AttributeValue::Boolean(value) => value.to_string(),
+            input::AttributeValue::List(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    Self::push_attribute_env(envs, &format!("{key}_{i}"), value);
                 }
+                envs.push((format!("{key}_len"), values.len().to_string()));
+
+                values
+                    .iter()
+                    .filter_map(|value| match value {
+                        input::AttributeValue::List(_) | input::AttributeValue::Map(_) => None,
+                        input::AttributeValue::String(value) => Some(value.to_string()),
+                        input::AttributeValue::Integer(value) => Some(value.to_string()),
+                        input::AttributeValue::Float(value) => Some(value.to_string()),
+                        input::AttributeValue::Boolean(value) => Some(value.to_string()),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
             }
-        }
-        Ok(())
+            input::AttributeValue::Map(map) => {
+                for (sub_key, value) in map {
+                    Self::push_attribute_env(envs, &format!("{key}_{sub_key}"), value);
+                }
+                return;
+            }
+        };
+
+        envs.push((key.to_string(), value));
     }
 
-    fn setup_working_directory(bridge_name: &str, pkg_name: &str) -> Result<PathBuf> {
+    fn setup_working_directory(&self, bridge_name: &str, pkg_name: &str) -> Result<PathBuf> {
         use std::time::{SystemTime, UNIX_EPOCH};
 
-        let tmp_dir_base = PathBuf::from(DEFAULT_WORKING_DIR)
-            .join(bridge_name)
-            .join(pkg_name);
+        let tmp_dir_base = self.options.working_dir.join(bridge_name).join(pkg_name);
 
         let tmp_dir = loop {
             let timestamp = SystemTime::now()
@@ -651,11 +1746,13 @@ impl BridgeApi {
             }
         };
 
-        // Create the directory
-        std::fs::create_dir_all(&tmp_dir).into_diagnostic()?;
-
-        // Change to the directory
-        std::env::set_current_dir(&tmp_dir).into_diagnostic()?;
+        // Create the directory, the others can't look in it
+        std::fs::create_dir_all(&tmp_dir)?;
+        #[cfg(unix)]
+        std::fs::set_permissions(
+            &tmp_dir,
+            std::os::unix::fs::PermissionsExt::from_mode(0o700),
+        )?;
 
         Ok(tmp_dir)
     }
@@ -0,0 +1,173 @@
+// the `BridgeApi` for the async frontends (a gui...), every operation runs on
+// the blocking pool of tokio, at most `max_jobs` at the same time (and at most
+// the `max-parallel` of its bridge), the others wait for a free slot
+use std::{cell::RefCell, collections::HashMap, path::PathBuf, rc::Rc, sync::Arc};
+
+use tokio::{
+    sync::Semaphore,
+    task::{self, JoinSet},
+};
+
+use super::{Bridge, BridgeApi, BridgeApiError, BridgeOptions, Result, UpstreamInfo};
+use crate::{
+    Pkg,
+    db::{Db, DbError},
+    input::PkgDeclaration,
+};
+
+#[derive(Debug, Clone)]
+pub struct AsyncBridgeApi {
+    bridges: Arc<Vec<Bridge>>,
+    options: BridgeOptions,
+    db_path: PathBuf,
+    jobs: Arc<Semaphore>,
+    // the bridges with a `max-parallel`
+    bridge_jobs: Arc<HashMap<String, Arc<Semaphore>>>,
+}
+
+thread_local! {
+    // the connection of a worker of the blocking pool, opened by its first
+    // operation and kept as long as the worker
+    static WORKER_DBS: RefCell<HashMap<PathBuf, Rc<Db>>> = RefCell::new(HashMap::new());
+}
+
+// the db is checked and migrated once by the `BridgeApi` the async one is
+// made of, the workers only reopen it
+fn worker_db(path: &PathBuf) -> std::result::Result<Rc<Db>, DbError> {
+    WORKER_DBS.with(|dbs| {
+        if let Some(db) = dbs.borrow().get(path) {
+            return Ok(db.clone());
+        }
+
+        let db = Rc::new(Db::reopen(path)?);
+        dbs.borrow_mut().insert(path.clone(), db.clone());
+        Ok(db)
+    })
+}
+
+fn task_error(err: impl std::error::Error + Send + Sync + 'static) -> BridgeApiError {
+    BridgeApiError::IoError(std::io::Error::other(err))
+}
+
+impl AsyncBridgeApi {
+    // the bridges and the options of the blocking api, every worker has its
+    // own connection to the same db (it's in WAL mode, the readers don't wait
+    // for the writer)
+    pub fn new(api: BridgeApi, max_jobs: usize) -> Self {
+        let bridge_jobs = api
+            .bridges
+            .iter()
+            .filter_map(|bridge| {
+                let max = bridge.limits.max_parallel?;
+                Some((bridge.name.clone(), Arc::new(Semaphore::new(max))))
+            })
+            .collect();
+
+        Self {
+            bridge_jobs: Arc::new(bridge_jobs),
+            db_path: api.db.path.clone(),
+            bridges: Arc::new(api.bridges),
+            options: api.options,
+            jobs: Arc::new(Semaphore::new(max_jobs.max(1))),
+        }
+    }
+
+    async fn run<T, F>(&self, bridge_name: Option<&str>, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&BridgeApi) -> Result<T> + Send + 'static,
+    {
+        // the slot of the bridge first, so a busy bridge doesn't hold the
+        // slots the others could use
+        let _bridge_job = match bridge_name.and_then(|name| self.bridge_jobs.get(name)) {
+            Some(jobs) => Some(jobs.acquire().await.map_err(task_error)?),
+            None => None,
+        };
+        let _job = self.jobs.acquire().await.map_err(task_error)?;
+
+        let bridges = self.bridges.clone();
+        let options = self.options.clone();
+        let db_path = self.db_path.clone();
+
+        task::spawn_blocking(move || {
+            let api = BridgeApi {
+                bridges: bridges.as_ref().clone(),
+                db: worker_db(&db_path)?,
+                options,
+                exit_codes: Default::default(),
+            };
+
+            f(&api)
+        })
+        .await
+        .map_err(task_error)?
+    }
+
+    pub async fn install(&self, bridge_name: &str, pkg: PkgDeclaration) -> Result<(Pkg, PathBuf)> {
+        let name = bridge_name.to_string();
+        self.run(Some(bridge_name), move |api| api.install(&name, &pkg))
+            .await
+    }
+
+    pub async fn update(&self, bridge_name: &str, pkg: PkgDeclaration) -> Result<(Pkg, PathBuf)> {
+        let name = bridge_name.to_string();
+        self.run(Some(bridge_name), move |api| api.update(&name, &pkg))
+            .await
+    }
+
+    pub async fn reinstall(
+        &self,
+        bridge_name: &str,
+        pkg: PkgDeclaration,
+    ) -> Result<(Pkg, PathBuf)> {
+        let name = bridge_name.to_string();
+        self.run(Some(bridge_name), move |api| api.reinstall(&name, &pkg))
+            .await
+    }
+
+    pub async fn info(&self, bridge_name: &str, pkg: PkgDeclaration) -> Result<UpstreamInfo> {
+        let name = bridge_name.to_string();
+        self.run(Some(bridge_name), move |api| api.info(&name, &pkg))
+            .await
+    }
+
+    pub async fn remove(&self, bridge_name: &str, pkg: PkgDeclaration) -> Result<bool> {
+        let name = bridge_name.to_string();
+        self.run(Some(bridge_name), move |api| api.remove(&name, &pkg))
+            .await
+    }
+
+    // the db is used from the blocking pool too, it takes a job slot like
+    // the operations so a frontend can't flood it
+    pub async fn with_db<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Db) -> std::result::Result<T, DbError> + Send + 'static,
+    {
+        self.run(None, move |api| Ok(f(&api.db)?)).await
+    }
+
+    // installs the pkgs at the same time (up to `max_jobs`), the results are
+    // in the order of the pkgs
+    pub async fn install_all(
+        &self,
+        bridge_name: &str,
+        pkgs: Vec<PkgDeclaration>,
+    ) -> Vec<Result<(Pkg, PathBuf)>> {
+        let mut set = JoinSet::new();
+
+        for (i, pkg) in pkgs.into_iter().enumerate() {
+            let api = self.clone();
+            let bridge_name = bridge_name.to_string();
+            set.spawn(async move { (i, api.install(&bridge_name, pkg).await) });
+        }
+
+        let mut results = Vec::new();
+        while let Some(joined) = set.join_next().await {
+            results.push(joined.unwrap_or_else(|err| (usize::MAX, Err(task_error(err)))));
+        }
+        results.sort_by_key(|(i, _)| *i);
+
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+}
@@ -0,0 +1,399 @@
+// how an operation is run, the planner and the `BridgeApi` don't care if the
+// bridge is an executable, a wasm component or some rust code in a test
+use std::{
+    io::{BufRead, BufReader, Write},
+    path::{Component, Path, PathBuf},
+    process,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+use super::Operation;
+use crate::input::PkgDeclaration;
+
+// `__ERR <code> <message>`, the last line of the stderr of a failed bridge
+pub const ERROR_PREFIX: &str = "__ERR";
+pub const ERROR_NETWORK: &str = "network";
+pub const ERROR_NOT_FOUND: &str = "not-found";
+pub const ERROR_CHECKSUM_MISMATCH: &str = "checksum-mismatch";
+pub const ERROR_UNSUPPORTED_OP: &str = "unsupported-op";
+
+// what a backend gets for one operation
+#[derive(Debug)]
+pub struct OperationContext<'a> {
+    // `pkg_work_dir`, `pkg_opts`... and the attributes for the protocol 1
+    pub envs: &'a [(String, String)],
+    // what the process starts with instead of the env of pkg (the `env` of
+    // the config), the `envs` are added to it
+    pub base_env: Option<&'a [(String, String)]>,
+    pub work_dir: &'a Path,
+}
+
+fn apply_base_env(command: &mut process::Command, base_env: Option<&[(String, String)]>) {
+    if let Some(base_env) = base_env {
+        command
+            .env_clear()
+            .envs(base_env.iter().map(|(k, v)| (k, v)));
+    }
+}
+
+// what the bridge printed and its exit code, it's parsed by the `BridgeApi`
+#[derive(Debug, Clone, Default)]
+pub struct OperationOutcome {
+    pub code: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+// a sandboxed backend (the wasm one) returned a path out of its work dir, the
+// `BridgeApi` makes it a `BridgeApiError::PathOutOfSandbox`
+#[derive(Error, Debug)]
+#[error("{0:?} is out of the sandbox of the bridge")]
+pub struct SandboxEscape(pub PathBuf);
+
+// a path of the sandbox (`/x` is `<work_dir>/x`) on the host, `..` and the
+// links the bridge made in its work dir can't take it out of it
+pub fn sandbox_path(work_dir: &Path, path: &str) -> Result<PathBuf, SandboxEscape> {
+    let escape = || SandboxEscape(PathBuf::from(path));
+
+    let relative = Path::new(path).strip_prefix("/").unwrap_or(Path::new(path));
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(escape());
+    }
+
+    let work_dir = work_dir
+        .canonicalize()
+        .unwrap_or_else(|_| work_dir.to_path_buf());
+    let path = work_dir.join(relative);
+
+    match path.canonicalize() {
+        Ok(resolved) if resolved.starts_with(&work_dir) => Ok(resolved),
+        Ok(_) => Err(escape()),
+        // it's not there, the `BridgeApi` says so when it checks the output
+        Err(_) => Ok(path),
+    }
+}
+
+pub trait BridgeBackend: std::fmt::Debug + Send + Sync {
+    fn execute(
+        &self,
+        operation: &Operation,
+        declaration: &PkgDeclaration,
+        ctx: &OperationContext,
+    ) -> std::io::Result<OperationOutcome>;
+}
+
+impl OperationOutcome {
+    pub fn success(&self) -> bool {
+        self.code == 0
+    }
+
+    // the bridge failed for something that can pass (a network error...)
+    pub fn wants_retry(&self) -> bool {
+        !self.success()
+            && (String::from_utf8_lossy(&self.stderr)
+                .lines()
+                .any(|line| line.trim() == "__RETRY")
+                || self
+                    .structured_error()
+                    .is_some_and(|(code, _)| code == ERROR_NETWORK))
+    }
+
+    // the `(code, message)` of the last line of the stderr of a failed
+    // operation, if it's `__ERR <code> <message>`
+    pub fn structured_error(&self) -> Option<(String, String)> {
+        if self.success() {
+            return None;
+        }
+
+        let stderr = String::from_utf8_lossy(&self.stderr);
+        let line = stderr.lines().rev().find(|line| !line.trim().is_empty())?;
+        let rest = line
+            .trim()
+            .strip_prefix(ERROR_PREFIX)?
+            .strip_prefix(' ')?
+            .trim();
+        let (code, message) = rest.split_once(' ').unwrap_or((rest, ""));
+
+        (!code.is_empty()).then(|| (code.to_string(), message.trim().to_string()))
+    }
+
+    // the bridge asks pkg to do the operation it self (exit 1 and `__IMPL_DEFAULT`)
+    pub fn wants_default_impl(&self) -> bool {
+        super::default_impls::is_requested(self)
+    }
+}
+
+// the `run` executable, `run <operation> <input>` in the working dir
+#[derive(Debug)]
+pub struct ProcessBackend {
+    entry_point: PathBuf,
+}
+
+impl ProcessBackend {
+    pub fn new(entry_point: PathBuf) -> Self {
+        Self { entry_point }
+    }
+}
+
+impl BridgeBackend for ProcessBackend {
+    fn execute(
+        &self,
+        operation: &Operation,
+        declaration: &PkgDeclaration,
+        ctx: &OperationContext,
+    ) -> std::io::Result<OperationOutcome> {
+        let mut command = process::Command::new(&self.entry_point);
+        apply_base_env(&mut command, ctx.base_env);
+
+        let output = command
+            .arg(operation.display())
+            .arg(&declaration.input)
+            .current_dir(ctx.work_dir)
+            .envs(ctx.envs.iter().map(|(k, v)| (k, v)))
+            // its output is captured, a prompt would hang the build unseen
+            .stdin(process::Stdio::null())
+            .output()?;
+
+        Ok(OperationOutcome {
+            // killed by a signal
+            code: output.status.code().unwrap_or(-1),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+}
+
+// the `run daemon` of a bridge with `daemon #true` in its manifest, for the
+// bridges that take long to start (a language runtime, an api login...): it's
+// started at the first operation and kept for the next ones. every operation
+// is a json-rpc request on a line of its stdin, it answers on a line of its
+// stdout, one operation at a time
+#[derive(Debug)]
+pub struct DaemonBackend {
+    entry_point: PathBuf,
+    daemon: Mutex<Option<Daemon>>,
+}
+
+#[derive(Debug)]
+struct Daemon {
+    child: process::Child,
+    // taken on drop, the daemon stops at the end of its stdin
+    stdin: Option<process::ChildStdin>,
+    stdout: BufReader<process::ChildStdout>,
+    next_id: u64,
+}
+
+// how long a daemon has to stop after its stdin is closed, it's killed after
+const DAEMON_STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+impl DaemonBackend {
+    pub fn new(entry_point: PathBuf) -> Self {
+        Self {
+            entry_point,
+            daemon: Mutex::new(None),
+        }
+    }
+}
+
+impl BridgeBackend for DaemonBackend {
+    fn execute(
+        &self,
+        operation: &Operation,
+        declaration: &PkgDeclaration,
+        ctx: &OperationContext,
+    ) -> std::io::Result<OperationOutcome> {
+        let mut daemon = self
+            .daemon
+            .lock()
+            .map_err(|_| std::io::Error::other("the daemon of the bridge panicked"))?;
+
+        if daemon.is_none() {
+            *daemon = Some(Daemon::spawn(&self.entry_point, ctx.base_env)?);
+        }
+
+        let params = serde_json::json!({
+            "name": declaration.name,
+            "input": declaration.input,
+            "work_dir": ctx.work_dir,
+            "env": ctx
+                .envs
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone().into()))
+                .collect::<serde_json::Map<_, _>>(),
+        });
+
+        let result = daemon
+            .as_mut()
+            .expect("the daemon is started above")
+            .call(&operation.display(), params);
+
+        // a daemon that died (or spoke nonsense) is started again for the next operation
+        if result.is_err() {
+            *daemon = None;
+        }
+
+        result
+    }
+}
+
+impl Daemon {
+    fn spawn(entry_point: &Path, base_env: Option<&[(String, String)]>) -> std::io::Result<Self> {
+        let mut command = process::Command::new(entry_point);
+        apply_base_env(&mut command, base_env);
+
+        let mut child = command
+            .arg("daemon")
+            .current_dir(entry_point.parent().unwrap_or(Path::new("/")))
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::piped())
+            // the stderr of an operation is in its response
+            .stderr(process::Stdio::null())
+            .spawn()?;
+
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().map(BufReader::new);
+
+        match (stdin, stdout) {
+            (Some(stdin), Some(stdout)) => Ok(Self {
+                child,
+                stdin: Some(stdin),
+                stdout,
+                next_id: 1,
+            }),
+            _ => Err(std::io::Error::other("the daemon has no stdin or stdout")),
+        }
+    }
+
+    fn call(
+        &mut self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> std::io::Result<OperationOutcome> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or_else(|| std::io::Error::other("the daemon is stopped"))?;
+        writeln!(stdin, "{request}")?;
+        stdin.flush()?;
+
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "the daemon of the bridge exited",
+            ));
+        }
+
+        let invalid = |message: &str| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+        };
+
+        let response: serde_json::Value =
+            serde_json::from_str(&line).map_err(|err| invalid(&err.to_string()))?;
+
+        if response.get("id").and_then(|v| v.as_u64()) != Some(id) {
+            return Err(invalid("the daemon answered another request"));
+        }
+
+        let text = |value: &serde_json::Value, key: &str| {
+            value
+                .get(key)
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .as_bytes()
+                .to_vec()
+        };
+
+        // a json-rpc error is a failed operation, its message is the stderr
+        if let Some(error) = response.get("error") {
+            return Ok(OperationOutcome {
+                code: 1,
+                stdout: Vec::new(),
+                stderr: text(error, "message"),
+            });
+        }
+
+        let result = response
+            .get("result")
+            .ok_or_else(|| invalid("the daemon answered without a result"))?;
+
+        Ok(OperationOutcome {
+            code: result
+                .get("code")
+                .and_then(|v| v.as_i64())
+                .and_then(|v| i32::try_from(v).ok())
+                .unwrap_or(0),
+            stdout: text(result, "stdout"),
+            stderr: text(result, "stderr"),
+        })
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        drop(self.stdin.take());
+
+        let started = Instant::now();
+        while started.elapsed() < DAEMON_STOP_TIMEOUT {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// the `run.wasm` component
+#[cfg(feature = "wasm_bridges")]
+#[derive(Debug)]
+pub struct WasmBackend {
+    component: PathBuf,
+}
+
+#[cfg(feature = "wasm_bridges")]
+impl WasmBackend {
+    pub fn new(component: PathBuf) -> Self {
+        Self { component }
+    }
+}
+
+#[cfg(feature = "wasm_bridges")]
+impl BridgeBackend for WasmBackend {
+    fn execute(
+        &self,
+        operation: &Operation,
+        declaration: &PkgDeclaration,
+        ctx: &OperationContext,
+    ) -> std::io::Result<OperationOutcome> {
+        super::wasm::run(
+            &self.component,
+            &[operation.display(), declaration.input.clone()],
+            ctx.envs,
+            ctx.work_dir,
+        )
+        // the io errors (a `SandboxEscape`...) are kept as they are
+        .map_err(|err| match err.downcast::<std::io::Error>() {
+            Ok(err) => err,
+            Err(err) => std::io::Error::other(err),
+        })
+    }
+}
@@ -0,0 +1,60 @@
+// what pkg does for a bridge that doesn't implement an operation: the bridge
+// exits with 1 and prints `__IMPL_DEFAULT` in its stderr (a rust backend
+// returns `default_impls::requested()`), then pkg:
+// - remove: removes the installed pkg path
+// - update: runs the install of the bridge, the old pkg is removed once the
+//   new one is installed (it's kept if the install fails)
+// - reinstall: runs the remove of the bridge (or the default one), then its install
+//
+// the bridges get the version of these rules in `$pkg_default_impls`, it
+// changes when an operation gets a default impl or a default impl changes
+use std::path::Path;
+
+use super::{OperationOutcome, Result};
+
+pub const SENTINEL: &str = "__IMPL_DEFAULT";
+pub const VERSION: u32 = 1;
+pub const VERSION_ENV: &str = "pkg_default_impls";
+
+// the outcome a rust backend returns to get the default impl
+pub fn requested() -> OperationOutcome {
+    OperationOutcome {
+        code: 1,
+        stdout: Vec::new(),
+        stderr: SENTINEL.as_bytes().to_vec(),
+    }
+}
+
+pub fn is_requested(outcome: &OperationOutcome) -> bool {
+    outcome.code == 1 && String::from_utf8_lossy(&outcome.stderr).trim() == SENTINEL
+}
+
+// false if there was nothing to remove
+pub fn remove(pkg_path: &Path) -> std::io::Result<bool> {
+    let mut removed = false;
+    if pkg_path.exists() {
+        if pkg_path.is_dir() {
+            std::fs::remove_dir_all(pkg_path)?;
+        } else {
+            std::fs::remove_file(pkg_path)?;
+        }
+        removed = true;
+    }
+    Ok(removed)
+}
+
+// `install` runs the install of the bridge, its outcome is the one of the update
+pub fn update(
+    pkg_path: Option<&Path>,
+    install: impl FnOnce() -> Result<OperationOutcome>,
+) -> Result<OperationOutcome> {
+    let outcome = install()?;
+
+    if outcome.success()
+        && let Some(pkg_path) = pkg_path
+    {
+        remove(pkg_path)?;
+    }
+
+    Ok(outcome)
+}
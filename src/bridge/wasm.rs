@@ -0,0 +1,159 @@
+// a bridge can be a `run.wasm` wasi component instead of a `run` executable,
+// it's run by wasmtime with only its working dir preopened, so it can't touch
+// anything else, and the same component runs on every os
+use std::{path::Path, sync::mpsc, time::Duration};
+
+use wasmtime::{
+    Config, Engine, Store,
+    component::{Component, Linker, ResourceTable},
+};
+use wasmtime_wasi::{
+    FsPerms, WasiCtx, WasiCtxBuilder, WasiCtxView, WasiView,
+    p2::{bindings::sync::Command, pipe::MemoryOutputPipe},
+};
+
+// the bridges can write a lot of logs, but not unlimited
+const MAX_OUTPUT_SIZE: usize = 16 * 1024 * 1024;
+
+// a component that loops forever would hang the build, it's stopped after
+// this (a process bridge can be killed, a component runs in pkg)
+const MAX_RUN_TIME: Duration = Duration::from_secs(60 * 60);
+
+struct State {
+    ctx: WasiCtx,
+    table: ResourceTable,
+}
+
+impl WasiView for State {
+    fn ctx(&mut self) -> WasiCtxView<'_> {
+        WasiCtxView {
+            ctx: &mut self.ctx,
+            table: &mut self.table,
+        }
+    }
+}
+
+use super::{OperationOutcome, backend::sandbox_path};
+
+// runs the component like the `run` executable would be run, and returns
+// what it printed with a 0 or 1 exit code, so the caller doesn't care
+pub fn run(
+    component_path: &Path,
+    args: &[String],
+    envs: &[(String, String)],
+    work_dir: &Path,
+) -> wasmtime::Result<OperationOutcome> {
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+    let engine = Engine::new(&config)?;
+    let component = Component::from_file(&engine, component_path)?;
+
+    let mut linker = Linker::<State>::new(&engine);
+    wasmtime_wasi::p2::add_to_linker_sync(&mut linker)?;
+
+    let stdout = MemoryOutputPipe::new(MAX_OUTPUT_SIZE);
+    let stderr = MemoryOutputPipe::new(MAX_OUTPUT_SIZE);
+
+    let mut program_args = vec![super::WASM_ENTRY_POINT_NAME.to_string()];
+    program_args.extend_from_slice(args);
+
+    // the working dir is the root of the component, the paths in it are
+    // rewritten, the others (the log file...) can't be reached anyway
+    let envs = envs
+        .iter()
+        .map(
+            |(key, value)| match Path::new(value).strip_prefix(work_dir) {
+                Ok(rest) => (key.clone(), Path::new("/").join(rest).display().to_string()),
+                Err(_) => (key.clone(), value.clone()),
+            },
+        )
+        .collect::<Vec<_>>();
+
+    let ctx = WasiCtxBuilder::new()
+        .args(&program_args)
+        .envs(&envs)
+        .env("PWD", "/")
+        .stdout(stdout.clone())
+        .stderr(stderr.clone())
+        .preopened_dir(work_dir, "/", FsPerms::ReadWrite)?
+        .build();
+
+    let mut store = Store::new(
+        &engine,
+        State {
+            ctx,
+            table: ResourceTable::new(),
+        },
+    );
+
+    // the epoch is moved once, when the run takes too long, and the
+    // component traps at its next check. the watcher stops with the run
+    store.set_epoch_deadline(1);
+    let (done, run_done) = mpsc::channel::<()>();
+    let watched = engine.clone();
+    std::thread::spawn(move || {
+        if let Err(mpsc::RecvTimeoutError::Timeout) = run_done.recv_timeout(MAX_RUN_TIME) {
+            watched.increment_epoch();
+        }
+    });
+
+    let command = Command::instantiate(&mut store, &component, &linker)?;
+    let result = command.wasi_cli_run().call_run(&mut store);
+    drop(done);
+    let result = result?;
+
+    let code = if result.is_ok() { 0 } else { 1 };
+
+    let stdout = String::from_utf8_lossy(&stdout.contents()).into_owned();
+    let stdout = if result.is_ok() {
+        to_host_output(&stdout, work_dir)?
+    } else {
+        stdout
+    };
+
+    Ok(OperationOutcome {
+        code,
+        stdout: stdout.into_bytes(),
+        stderr: stderr.contents().to_vec(),
+    })
+}
+
+// the pkg path and entry point of the output line are in the sandbox (`/x`
+// is `<work_dir>/x`), and a component can't `chmod +x`, so pkg does it. a path
+// out of the work dir is a `SandboxEscape`, nothing is touched
+pub(crate) fn to_host_output(stdout: &str, work_dir: &Path) -> std::io::Result<String> {
+    let mut lines = stdout.lines();
+    let Some(first_line) = lines.next() else {
+        return Ok(stdout.to_string());
+    };
+
+    let mut fields = first_line
+        .trim()
+        .split(',')
+        .map(|f| f.to_string())
+        .collect::<Vec<_>>();
+
+    for i in [0, 2] {
+        let Some(field) = fields.get_mut(i) else {
+            continue;
+        };
+
+        let path = sandbox_path(work_dir, field).map_err(std::io::Error::other)?;
+
+        #[cfg(unix)]
+        if path.is_file() {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mut permissions = path.metadata()?.permissions();
+            permissions.set_mode(permissions.mode() | 0o755);
+            std::fs::set_permissions(&path, permissions)?;
+        }
+
+        *field = path.display().to_string();
+    }
+
+    Ok(std::iter::once(fields.join(","))
+        .chain(lines.map(|l| l.to_string()))
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
@@ -0,0 +1,553 @@
+// the jobs of a build (`pkg build`, `update`, `rebuild` and `autoremove`):
+// every pkg goes through its bridge, the store and the db, then everything is
+// linked. what happens is told to an `EventSink`, the cli renders it with its
+// progress bars and a frontend (or a test) can plug its own
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Pkg, Result,
+    bridge::{self, BridgeApi, BridgeApiError},
+    db::{self, Db, DbSnapshot},
+    event::{Event, EventSink, Step},
+    fs::{self, Fs},
+    hooks::{self, HookError, Hooks},
+    input::PkgDeclaration,
+    license::LicensePolicy,
+    systemd::{Systemctl, SystemdError, UnitOptions},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Job {
+    Install,
+    Update,
+    Remove,
+    Reinstall,
+}
+
+// there's one at a time, for the pkg being built
+#[allow(clippy::large_enum_variant)]
+enum Action {
+    // the pkg and the working dir it's in
+    Add(Result<(Pkg, PathBuf), BridgeApiError>),
+    Remove(Result<bool, BridgeApiError>),
+}
+
+// the jobs of one bridge, run in their order
+pub struct BridgeJobs<'a> {
+    pub bridge: &'a str,
+    pub jobs: Vec<(Job, &'a [PkgDeclaration])>,
+    // the pkgs left as they are, with why
+    pub skipped: Vec<(String, String)>,
+    // what the bridge is going to do, told before the jobs
+    pub install: usize,
+    pub remove: usize,
+    pub update: usize,
+}
+
+// what the jobs need, the same for every bridge of the build
+pub struct Build<'a> {
+    pub db: &'a Db,
+    pub fs: &'a Fs,
+    pub bridge_api: &'a BridgeApi,
+    pub systemctl: &'a Systemctl,
+    // the `hooks` section of the config
+    pub hooks: &'a Hooks,
+    pub licenses: &'a LicensePolicy,
+    pub allow_license_violations: bool,
+    // the bridges are run again even for what didn't change
+    pub force: bool,
+    pub log_dir: &'a Path,
+}
+
+// what the jobs did, for the steps after them and the summary
+#[derive(Debug, Default)]
+pub struct BuildOutcome {
+    pub installed: usize,
+    pub removed: usize,
+    // a `daemon-reload` and the `enable --now` of the units after the link
+    pub units_changed: bool,
+    pub units_to_enable: Vec<PathBuf>,
+    // the pkgs installed or updated by this build that have `post-link`
+    // hooks, with their bridge for the log file
+    pub post_link_hooks: Vec<(String, String, Vec<String>)>,
+}
+
+impl Job {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Job::Install => "install",
+            Job::Update => "update",
+            Job::Remove => "remove",
+            Job::Reinstall => "reinstall",
+        }
+    }
+}
+
+impl Build<'_> {
+    pub fn run_bridge(
+        &self,
+        jobs: BridgeJobs,
+        outcome: &mut BuildOutcome,
+        sink: &mut dyn EventSink,
+    ) -> Result<()> {
+        sink.emit(Event::BridgeStarted {
+            bridge: jobs.bridge.to_string(),
+            install: jobs.install,
+            remove: jobs.remove,
+            update: jobs.update,
+        });
+
+        for (name, reason) in jobs.skipped {
+            sink.emit(Event::PackageSkipped { name, reason });
+        }
+
+        // taken for every bridge, because the previous one may has changed the db
+        let snapshot = self.db.snapshot()?;
+
+        for (job, pkgs) in jobs.jobs {
+            if pkgs.is_empty() {
+                continue;
+            }
+
+            sink.emit(Event::JobStarted {
+                job: job.name().to_string(),
+                pkgs: pkgs.len(),
+            });
+
+            // what the past runs of these pkgs took, used for the ETA
+            let past_durations = self.db.get_average_durations(job.name())?;
+            let estimates = pkgs
+                .iter()
+                .map(|p| past_durations.get(&p.name).copied())
+                .collect::<Vec<Option<Duration>>>();
+
+            for (i, pkg) in pkgs.iter().enumerate() {
+                if let Some(remaining) = estimate_remaining(&estimates[i..]) {
+                    sink.emit(Event::Eta { remaining });
+                }
+
+                sink.emit(Event::PackageStarted {
+                    name: pkg.name.clone(),
+                    index: i + 1,
+                    total: pkgs.len(),
+                });
+
+                self.run_pkg(jobs.bridge, job, pkg, &snapshot, outcome, sink)?;
+            }
+
+            sink.emit(Event::JobDone);
+        }
+
+        Ok(())
+    }
+
+    fn run_pkg(
+        &self,
+        bridge_name: &str,
+        job: Job,
+        pkg: &PkgDeclaration,
+        snapshot: &DbSnapshot,
+        outcome: &mut BuildOutcome,
+        sink: &mut dyn EventSink,
+    ) -> Result<()> {
+        let failed = |step: Step, err: &dyn std::fmt::Display| Event::PackageFailed {
+            name: pkg.name.clone(),
+            step,
+            error: err.to_string(),
+            exit_code: (step == Step::BridgeOperation)
+                .then(|| self.bridge_api.exit_code(bridge_name, &pkg.name))
+                .flatten(),
+        };
+
+        let pkg_hooks = match Hooks::from_attributes(&pkg.name, &pkg.attributes) {
+            Ok(pkg_hooks) => pkg_hooks,
+            Err(err) => {
+                sink.emit(failed(Step::Hook, &err));
+                return Ok(());
+            }
+        };
+
+        let unit_options = match UnitOptions::from_attributes(&pkg.name, &pkg.attributes) {
+            Ok(unit_options) => unit_options,
+            Err(err) => {
+                sink.emit(failed(Step::Units, &err));
+                return Ok(());
+            }
+        };
+
+        if job == Job::Remove {
+            match self.stop_pkg_units(&pkg.name) {
+                Ok(disabled) => outcome.units_changed |= disabled,
+                Err(err) => {
+                    sink.emit(failed(Step::Units, &err));
+                    return Ok(());
+                }
+            }
+
+            if let Err(err) = self.run_pre_remove_hooks(&pkg.name, bridge_name) {
+                sink.emit(failed(Step::Hook, &err));
+                return Ok(());
+            }
+        }
+
+        // a reinstall is the same as an install
+        let cache_operation = match job {
+            Job::Update => "update",
+            _ => "install",
+        };
+        let cache_key =
+            |version: &db::Version| bridge::cache_key(bridge_name, pkg, cache_operation, version);
+
+        // an update only knows the upstream version didn't change if the pkg
+        // is pinned to one
+        let cacheable = match job {
+            Job::Reinstall => true,
+            Job::Update => pkg.attributes.contains_key("version"),
+            Job::Install | Job::Remove => false,
+        };
+        // a pkg installed by another version of its bridge is run again
+        let bridge_version = self
+            .bridge_api
+            .bridge_version(bridge_name)
+            .unwrap_or_default();
+        if cacheable
+            && !self.force
+            && let Some(record) = snapshot.get(&pkg.name)
+            && record.bridge_version == bridge_version
+            && self.db.get_cache_key(&pkg.name, cache_operation)?
+                == Some(cache_key(&record.pkg.version))
+        {
+            sink.emit(Event::PackageUnchanged {
+                name: pkg.name.clone(),
+            });
+            return Ok(());
+        }
+
+        let started_at = Instant::now();
+
+        let action = match job {
+            Job::Install => Action::Add(self.bridge_api.install(bridge_name, pkg)),
+            Job::Update => Action::Add(self.bridge_api.update(bridge_name, pkg)),
+            // the pkgs of a bridge that only has inline bridges
+            Job::Remove if !self.bridge_api.has_bridge(bridge_name) => {
+                Action::Remove(self.bridge_api.default_impls_remove(&pkg.name))
+            }
+            Job::Remove => Action::Remove(self.bridge_api.remove(bridge_name, pkg)),
+            Job::Reinstall => Action::Add(self.bridge_api.reinstall(bridge_name, pkg)),
+        };
+
+        if let Action::Add(Ok(_)) | Action::Remove(Ok(_)) = action {
+            // a failed stat shouldn't fail the build
+            let _ = self
+                .db
+                .record_duration(&pkg.name, job.name(), started_at.elapsed());
+        }
+
+        match action {
+            Action::Add(Err(err)) | Action::Remove(Err(err)) => {
+                sink.emit(failed(Step::BridgeOperation, &err));
+            }
+            Action::Add(Ok((mut installed, work_dir))) => {
+                // it stays in its working dir, like a pkg that can't be stored
+                if let Err(err) = self
+                    .licenses
+                    .check(&installed.name, installed.metadata.license.as_deref())
+                {
+                    if !self.allow_license_violations {
+                        sink.emit(failed(Step::License, &err));
+                        return Ok(());
+                    }
+
+                    sink.emit(Event::LicenseViolation {
+                        name: installed.name.clone(),
+                        license: installed.metadata.license.clone().unwrap_or_default(),
+                    });
+                }
+
+                sink.emit(Event::PackageStoring {
+                    name: installed.name.clone(),
+                });
+
+                if let Err(err) = self
+                    .fs
+                    .store_or_overwrite(&mut [&mut installed], Some(bridge_name))
+                {
+                    sink.emit(failed(Step::Store, &err));
+                    return Ok(());
+                }
+
+                let bridge_name = bridge_name.to_string();
+                let db_written = match job {
+                    Job::Update | Job::Reinstall => self.db.update_pkg(&installed, &bridge_name),
+                    Job::Install | Job::Remove => {
+                        self.db.install_bridge_pkgs(&[&installed], &bridge_name)
+                    }
+                };
+                if let Err(err) = db_written
+                    .and_then(|_| self.db.set_pkg_tags(&installed.name, &pkg.tags))
+                    .and_then(|_| {
+                        self.db
+                            .set_pkg_deps(&installed.name, &pkg.deps(), pkg.is_auto())
+                    })
+                    .and_then(|_| {
+                        self.db
+                            .set_pkg_bridge_version(&installed.name, bridge_version)
+                    })
+                    .and_then(|_| {
+                        self.db
+                            .set_pkg_pre_remove(&installed.name, &pkg_hooks.pre_remove)
+                    })
+                    .and_then(|_| {
+                        self.db.set_cache_key(
+                            &installed.name,
+                            cache_operation,
+                            &cache_key(&installed.version),
+                        )
+                    })
+                {
+                    sink.emit(failed(Step::DbWrite, &err));
+                    return Ok(());
+                }
+
+                let units = fs::units(&installed.artifacts).cloned();
+                if unit_options.enable {
+                    outcome.units_to_enable.extend(units);
+                }
+                outcome.units_changed |= unit_options.reload && !installed.artifacts.is_empty();
+
+                if !pkg_hooks.post_link.is_empty() {
+                    outcome.post_link_hooks.push((
+                        installed.name.clone(),
+                        bridge_name,
+                        pkg_hooks.post_link,
+                    ));
+                }
+
+                // the pkg is moved out of it now, a left over dir is not worth failing for
+                let _ = self.bridge_api.clean_working_dir(&work_dir);
+
+                outcome.installed += 1;
+                sink.emit(Event::PackageInstalled {
+                    name: installed.name,
+                });
+            }
+            Action::Remove(Ok(true)) => {
+                sink.emit(Event::PackageStoring {
+                    name: pkg.name.clone(),
+                });
+
+                let unstored = self.fs.remove_pkgs(&[&pkg.name]).and_then(|mut removed| {
+                    removed.pop().map_or(Ok(false), |(_, removed)| removed)
+                });
+                if let Err(err) = unstored {
+                    sink.emit(failed(Step::Unstore, &err));
+                    return Ok(());
+                }
+
+                if let Err(err) = self.db.remove_pkgs(std::slice::from_ref(&pkg.name)) {
+                    sink.emit(failed(Step::DbRemove, &err));
+                    return Ok(());
+                }
+
+                outcome.removed += 1;
+                sink.emit(Event::PackageRemoved {
+                    name: pkg.name.clone(),
+                });
+            }
+            Action::Remove(Ok(false)) => {
+                sink.emit(failed(
+                    Step::BridgeOperation,
+                    &"the remove operation returned false",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // the pkgs of a bridge that isn't in the inputs anymore, by its own remove
+    // when its dir is still in the bridges set (`own_api`) or by the default
+    // one, true if the remove of the bridge failed for some
+    pub fn remove_out_of_service(
+        &self,
+        bridge_name: &str,
+        pkgs: &[Pkg],
+        own_api: Option<&BridgeApi>,
+        outcome: &mut BuildOutcome,
+        sink: &mut dyn EventSink,
+    ) -> bool {
+        let mut bridge_remove_failed = false;
+
+        sink.emit(Event::BridgeStarted {
+            bridge: bridge_name.to_string(),
+            install: 0,
+            remove: pkgs.len(),
+            update: 0,
+        });
+        sink.emit(Event::JobStarted {
+            job: Job::Remove.name().to_string(),
+            pkgs: pkgs.len(),
+        });
+
+        for (i, pkg) in pkgs.iter().enumerate() {
+            sink.emit(Event::PackageStarted {
+                name: pkg.name.clone(),
+                index: i + 1,
+                total: pkgs.len(),
+            });
+
+            let failed = |step: Step, error: String, exit_code: Option<i32>| Event::PackageFailed {
+                name: pkg.name.clone(),
+                step,
+                error,
+                exit_code,
+            };
+
+            match self.stop_pkg_units(&pkg.name) {
+                Ok(disabled) => outcome.units_changed |= disabled,
+                Err(err) => {
+                    sink.emit(failed(Step::Units, err.to_string(), None));
+                    continue;
+                }
+            }
+
+            if let Err(err) = self.run_pre_remove_hooks(&pkg.name, bridge_name) {
+                sink.emit(failed(Step::Hook, err.to_string(), None));
+                continue;
+            }
+
+            let (removed, exit_code) = match own_api {
+                Some(api) => {
+                    let removed = api
+                        .remove(bridge_name, &pkg.to_pkg_declaration_with_empty_attributes())
+                        .inspect_err(|_| bridge_remove_failed = true);
+                    (removed, api.exit_code(bridge_name, &pkg.name))
+                }
+                None => (self.bridge_api.default_impls_remove(&pkg.name), None),
+            };
+
+            if let Err(err) = removed {
+                sink.emit(failed(Step::BridgeOperation, err.to_string(), exit_code));
+                continue;
+            }
+
+            let _ = self.fs.remove_pkgs(std::slice::from_ref(&&pkg.name));
+
+            if let Err(err) = self.db.remove_pkgs(std::slice::from_ref(&pkg.name)) {
+                sink.emit(failed(Step::DbRemove, err.to_string(), None));
+                continue;
+            }
+
+            outcome.removed += 1;
+            sink.emit(Event::PackageRemoved {
+                name: pkg.name.clone(),
+            });
+        }
+
+        sink.emit(Event::JobDone);
+
+        bridge_remove_failed
+    }
+
+    // the link, the units and the `post-link` hooks once the jobs are done
+    pub fn finish(&self, outcome: &BuildOutcome, sink: &mut dyn EventSink) -> Result<()> {
+        self.fs.link_with_events(sink)?;
+
+        if outcome.units_changed
+            && let Err(err) = self
+                .systemctl
+                .daemon_reload()
+                .and_then(|_| self.systemctl.enable_now(&outcome.units_to_enable))
+        {
+            sink.emit(Event::UnitsFailed {
+                error: err.to_string(),
+            });
+        }
+
+        // the config ones once, if the build changed something
+        if outcome.installed + outcome.removed > 0
+            && let Err(err) = hooks::run(
+                hooks::POST_LINK,
+                &self.hooks.post_link,
+                None,
+                "HOOKS",
+                &self.log_dir.join(hooks::LOG_FILE_NAME),
+            )
+        {
+            sink.emit(Event::HookFailed {
+                pkg: None,
+                error: err.to_string(),
+            });
+        }
+        for (pkg_name, bridge_name, commands) in &outcome.post_link_hooks {
+            if let Err(err) = hooks::run(
+                hooks::POST_LINK,
+                commands,
+                Some(pkg_name),
+                &format!("PKG={pkg_name}"),
+                &self.log_dir.join(format!("{bridge_name}.log")),
+            ) {
+                sink.emit(Event::HookFailed {
+                    pkg: Some(pkg_name.clone()),
+                    error: err.to_string(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // before the pkg is removed, true if some of its units were enabled
+    fn stop_pkg_units(&self, pkg_name: &str) -> Result<bool, SystemdError> {
+        let units = self
+            .db
+            .get_pkgs_by_name(&[pkg_name.to_string()])
+            .unwrap_or_default()
+            .iter()
+            .flat_map(|pkg| fs::units(&pkg.artifacts).cloned().collect::<Vec<PathBuf>>())
+            .collect::<Vec<PathBuf>>();
+
+        self.systemctl.disable_now(&units)
+    }
+
+    // the config ones then the pkg ones (kept in the db at install)
+    fn run_pre_remove_hooks(&self, pkg_name: &str, bridge_name: &str) -> Result<(), HookError> {
+        let log_file = self.log_dir.join(format!("{bridge_name}.log"));
+        let label = format!("PKG={pkg_name}");
+
+        hooks::run(
+            hooks::PRE_REMOVE,
+            &self.hooks.pre_remove,
+            Some(pkg_name),
+            &label,
+            &log_file,
+        )?;
+
+        // a db error here is not a hook failing, the pkg is removed without them
+        let pkg_commands = self.db.get_pkg_pre_remove(pkg_name).unwrap_or_default();
+        hooks::run(
+            hooks::PRE_REMOVE,
+            &pkg_commands,
+            Some(pkg_name),
+            &label,
+            &log_file,
+        )
+    }
+}
+
+// sums the known estimates, pkgs that never ran are assumed to take the
+// average of the known ones, nothing known at all means no ETA
+pub fn estimate_remaining(estimates: &[Option<Duration>]) -> Option<Duration> {
+    let known = estimates.iter().flatten().collect::<Vec<&Duration>>();
+
+    if known.is_empty() {
+        return None;
+    }
+
+    let average = known.iter().copied().sum::<Duration>() / known.len() as u32;
+
+    Some(estimates.iter().map(|e| e.unwrap_or(average)).sum())
+}
@@ -1,4 +1,5 @@
-use clap::{ColorChoice, Parser, Subcommand};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 #[cfg(feature = "cli_complation")]
 #[derive(Clone, Debug, clap::ValueEnum)]
@@ -12,13 +13,97 @@ pub enum Shell {
     PowerShell, // NOTE: this is not needed really because this is unix only
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum DocsTopic {
+    Inputs,
+    Bridges,
+    Protocol,
+    Store,
+    Db,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ColorMode {
+    /// Colors on a terminal, unless `NO_COLOR` is set
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum InfoSort {
+    Name,
+    Version,
+    Size,
+    InstalledAt,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ImportFormat {
+    /// A `Brewfile` (`brew bundle dump`)
+    Brewfile,
+    /// `apt list --installed`, `apt-mark showmanual` or `dpkg --get-selections`
+    Apt,
+    /// `cargo install --list`
+    Cargo,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// A shell script
+    Bootstrap,
+    /// The script in a `#cloud-config`, run on the first boot
+    CloudInit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum PkgTypeFilter {
+    Executable,
+    Directory,
+}
+
 #[derive(Parser)]
 #[command(name = "pkg")]
 #[command(version, about, long_about = None)] // Read from `Cargo.toml`
-#[command(color = ColorChoice::Always)] // Always show colors
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Print every SQL statement with its parameters and timing to stderr
+    #[arg(long, global = true)]
+    pub trace_db: bool,
+
+    /// When to color the output
+    #[arg(long, global = true, value_enum, default_value_t = ColorMode::Auto)]
+    pub color: ColorMode,
+
+    /// Only print the failures, no progress
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Never prompt (sudo runs with `-n`, the questions get their default answer), implied without a terminal on stdin
+    #[arg(long, global = true)]
+    pub non_interactive: bool,
+
+    /// Let a build remove all the packages of a bridge, or more than a quarter of the packages, without asking
+    #[arg(long, global = true)]
+    pub allow_mass_remove: bool,
+
+    /// Only warn when a package has a license the `licenses` section of the config doesn't allow
+    #[arg(long, global = true)]
+    pub allow_license_violations: bool,
+
+    /// Don't ask before removing or downgrading packages
+    #[arg(short, long, global = true)]
+    pub yes: bool,
+
+    /// Use this profile of the config (its `profiles` block, and the `when profile` of the inputs)
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Provision the system mounted at this path (a chroot or a container rootfs), the target dir, load paths, db and logs are taken in it
+    #[arg(long, global = true, value_name = "PATH")]
+    pub root: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
@@ -29,32 +114,302 @@ pub enum Commands {
         /// even update the installed packages via the update command
         #[arg(short, long)]
         update: bool,
+
+        /// Only the packages with one of these tags
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+
+        /// Not the packages with one of these tags, the installed ones that have it are removed
+        #[arg(long = "exclude-tag")]
+        exclude_tags: Vec<String>,
+
+        /// Only these packages, the others are left as they are
+        #[arg(long, value_delimiter = ',')]
+        only: Vec<String>,
+
+        /// Not these packages, they are left as they are
+        #[arg(long, value_delimiter = ',')]
+        exclude: Vec<String>,
+
+        /// Only the packages of this bridge
+        #[arg(long)]
+        bridge: Option<String>,
+
+        /// Only the packages that failed in the last build (its failure report)
+        #[arg(long, conflicts_with_all = ["tags", "exclude_tags", "only", "exclude", "bridge"])]
+        retry_failed: bool,
     },
 
-    /// Force sync all packages (reinstall everything)
-    Rebuild,
+    /// Force sync all packages (reinstall everything that changed since its install)
+    Rebuild {
+        /// Only reinstall these packages, even if they didn't change ( default: all )
+        packages: Vec<String>,
+
+        /// Only reinstall the packages of this bridge
+        #[arg(long)]
+        bridge: Option<String>,
+
+        /// Reinstall even the packages that didn't change
+        #[arg(long)]
+        force: bool,
+    },
 
     /// Update packages
     #[command(alias = "u")]
     Update {
         /// Specific packages to update ( default: all )
         packages: Option<Vec<String>>,
+
+        /// Update even the packages pinned to the version they have
+        #[arg(long)]
+        force: bool,
+
+        /// Only the packages that aren't pinned to a version (no `version` attribute)
+        #[arg(long)]
+        unpinned: bool,
     },
 
+    /// Remove the packages installed only as dependencies (`auto=#true`) that nothing needs anymore
+    Autoremove,
+
     /// List installed packages
     Info {
         /// A packge to show information about ( default: all )
         package: Option<Vec<String>>,
+
+        /// Only the packages of this bridge
+        #[arg(long)]
+        bridge: Option<String>,
+
+        /// Only the packages of this type
+        #[arg(long = "type", value_enum)]
+        pkg_type: Option<PkgTypeFilter>,
+
+        /// Only the packages whose name matches the glob, e.g. `*fd*`
+        #[arg(long, value_name = "GLOB")]
+        glob: Option<String>,
+
+        /// The order of the list, the biggest first for `size` and the newest first for `installed-at`
+        #[arg(long, value_enum, default_value_t = InfoSort::Name)]
+        sort: InfoSort,
+
+        /// Show the bridge, the size, the install date and the metadata too
+        #[arg(short, long)]
+        long: bool,
+
+        /// List the files of the packages, in the store and their links ( like `dpkg -L` )
+        #[arg(long, conflicts_with = "long")]
+        files: bool,
+
+        /// With `--files`, the files of the directory packages as a tree
+        #[arg(long, requires = "files")]
+        tree: bool,
+
+        /// Ask the bridges for the latest version and the metadata of the declared packages, nothing is installed
+        #[arg(long, conflicts_with_all = ["files", "long"], requires = "package")]
+        remote: bool,
+
+        /// Print the packages as json
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List the installed packages with a newer version upstream, as their bridges tell ( `info` )
+    Outdated {
+        /// Only these packages ( default: all )
+        packages: Vec<String>,
+
+        /// Only the packages of this bridge
+        #[arg(long)]
+        bridge: Option<String>,
+
+        /// Print the packages as json
+        #[arg(long)]
+        json: bool,
     },
 
     /// Link packages in PATH
     Link,
 
-    /// Clean cache and temporary files
-    Clean,
+    /// Take over a package installed by hand, it's moved to the target dir and linked
+    Adopt {
+        /// The name of the package
+        name: String,
+
+        /// The executable or the directory of the package
+        path: PathBuf,
+
+        /// The executable in the directory, relative to it
+        #[arg(long)]
+        entry_point: Option<PathBuf>,
+
+        /// Copy it instead of moving it
+        #[arg(long)]
+        copy: bool,
+    },
+
+    /// Check the config, the inputs and the bridges without doing anything
+    Check,
+
+    /// Run an installed package, even if the load path isn't in the PATH yet
+    Run {
+        /// The package to run
+        package: String,
+
+        /// The arguments passed to the package
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Compare the installed packages with another machine's exported db
+    Compare {
+        /// A copy of the other machine's db file
+        state: PathBuf,
+    },
+
+    /// Show what each package and bridge takes on disk
+    Du {
+        /// Print the report as json
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Clean cache and temporary files (logs, working dirs and cache if no category is given)
+    Clean {
+        /// The bridges logs
+        #[arg(long)]
+        logs: bool,
+
+        /// The bridges working dirs
+        #[arg(long)]
+        workdir: bool,
+
+        /// The cache
+        #[arg(long)]
+        cache: bool,
+
+        /// What's in the target dir but not in the db
+        #[arg(long)]
+        store_orphans: bool,
+
+        /// Only show how much disk would be freed
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Show if the machine is in sync: the pending installs and removals, the broken links, the disk usage and the last build
+    Status {
+        /// Show what pkg detected about the host (container, systemd...)
+        #[arg(long)]
+        env: bool,
+    },
+
+    /// Show how the installed packages differ from the inputs: the missing, the undeclared and the drifted ones
+    Diff {
+        /// Print the diff as json
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Make inputs from the package list of another package manager
+    Import {
+        /// The format of the list
+        #[arg(long)]
+        format: ImportFormat,
+
+        /// The list, `-` for the stdin
+        file: PathBuf,
+
+        /// Send a kind of packages (`brew`, `cask`, `mas`, `apt`, `cargo`...) to another bridge, e.g. `cask=brew-cask`
+        #[arg(long, value_name = "KIND=BRIDGE")]
+        map: Vec<String>,
+
+        /// Write the inputs to this file instead of printing them
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+
+    /// Make a script that installs pkg with this config and these inputs on a new machine, then builds
+    Export {
+        /// What the script is
+        #[arg(long, default_value = "bootstrap")]
+        format: ExportFormat,
+
+        /// Write it to this file instead of printing it
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+
+    /// Print the state of the packages in the Prometheus text format
+    Metrics {
+        /// Write them to this .prom file instead (for the textfile collector of the node exporter)
+        #[arg(long)]
+        textfile: Option<PathBuf>,
+    },
+
+    /// Build every time the inputs or the config change
+    Watch {
+        /// Wait this long without a change before building, in milliseconds
+        #[arg(long, value_name = "MS", default_value_t = 500)]
+        debounce: u64,
+    },
+
+    /// Run `pkg update` on a schedule, with a systemd timer (or cron without systemd)
+    Schedule {
+        #[command(subcommand)]
+        command: ScheduleCommands,
+    },
+
+    /// Let the desktop users run pkg through polkit (pkexec)
+    Polkit {
+        #[command(subcommand)]
+        command: PolkitCommands,
+    },
+
+    /// Install or update the bridges of the set from a bridge registry
+    Bridge {
+        #[command(subcommand)]
+        command: BridgeCommands,
+    },
+
+    /// Manage the inputs (the files where the packages are declared)
+    Inputs {
+        #[command(subcommand)]
+        command: InputsCommands,
+    },
+
+    /// Check or show the config
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+
+    /// Back up or restore the db
+    Db {
+        #[command(subcommand)]
+        command: DbCommands,
+    },
+
+    /// Review the audit log (every bridge run, install, remove and link)
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommands,
+    },
 
     /// Some notes can help insha'Allah
-    Docs,
+    Docs {
+        /// The topic to read ( default: the list of the topics )
+        #[arg(value_enum)]
+        topic: Option<DocsTopic>,
+
+        /// Print it instead of opening it in `$PAGER`
+        #[arg(long)]
+        no_pager: bool,
+    },
+
+    /// Print the installed packages, for the completion scripts
+    #[command(name = "complete-pkgs", hide = true)]
+    CompletePkgs,
 
     #[cfg(feature = "cli_complation")]
     /// Generate shell completion scripts for your clap::Command
@@ -65,6 +420,175 @@ pub enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+pub enum InputsCommands {
+    /// Clone a shared inputs template into the inputs dir
+    Init {
+        /// The git repository of the template
+        #[arg(long)]
+        from: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BridgeCommands {
+    /// Fetch a bridge from the registry into the bridges set
+    Install {
+        /// The name of the bridge in the registry
+        name: String,
+
+        /// The registry ( default: `bridges.registry` of the config )
+        #[arg(long)]
+        registry: Option<String>,
+
+        /// Replace a bridge of the set that wasn't installed from a registry
+        #[arg(long)]
+        force: bool,
+    },
+    /// Fetch again the bridges installed from a registry
+    Update {
+        /// Only these bridges ( default: all of them )
+        names: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Report all the problems of the config at once
+    Check,
+    /// Print the config pkg uses, with the defaults and the profile applied
+    Show,
+    /// Print a value of the config pkg uses
+    Get {
+        /// The nodes joined with dots, e.g. `output.target-dir`
+        key: String,
+    },
+    /// Change a value in the config file, the rest of the file is kept as it is
+    Set {
+        /// The nodes joined with dots, e.g. `output.target-dir` or `profiles.user.db.path`
+        key: String,
+        /// The value (more than one for the lists, e.g. `hooks.post-link`)
+        #[arg(required = true)]
+        values: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum DbCommands {
+    /// Copy the db ( default: a new automatic backup )
+    Backup {
+        /// Where to write the copy
+        path: Option<PathBuf>,
+    },
+
+    /// Replace the db with a backup, the current db is backed up first
+    Restore {
+        /// The backup, `latest` for the last automatic one
+        path: PathBuf,
+    },
+
+    /// Rebuild the db file to give the space of the removed packages back
+    Vacuum,
+}
+
+#[derive(Subcommand)]
+pub enum ScheduleCommands {
+    /// Write and enable the timer ( default: daily )
+    Install {
+        /// Every day
+        #[arg(long, conflicts_with = "weekly")]
+        daily: bool,
+
+        /// Every week
+        #[arg(long)]
+        weekly: bool,
+    },
+    /// Show the schedule and its next run
+    Status,
+    /// Disable and remove the timer
+    Remove,
+}
+
+#[derive(Subcommand)]
+pub enum PolkitCommands {
+    /// Write the polkit policy of this pkg, pkexec asks for it by name
+    Install,
+    /// Print the policy
+    Show,
+    /// Remove the policy
+    Remove,
+}
+
+#[derive(Subcommand)]
+pub enum AuditCommands {
+    /// Check the hash chain of the audit log and print its records
+    Show {
+        /// Only the last records
+        #[arg(long, value_name = "N")]
+        last: Option<usize>,
+
+        /// Print the records as json
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl Commands {
+    // running a pkg is what the user does, not what the system does, and
+    // checking should work in a CI without sudo
+    pub fn needs_root(&self) -> bool {
+        !matches!(
+            self,
+            Commands::Run { .. }
+                | Commands::Check
+                | Commands::Import { .. }
+                | Commands::Export { .. }
+                | Commands::CompletePkgs
+                | Commands::Docs { .. }
+                | Commands::Config { .. }
+                | Commands::Polkit {
+                    command: PolkitCommands::Show
+                }
+        )
+    }
+
+    // the commands that only read the installed pkgs, an unprivileged user
+    // runs them on the db opened read only instead of through sudo
+    pub fn reads_only(&self) -> bool {
+        matches!(
+            self,
+            Commands::Info { .. } | Commands::Status { .. } | Commands::Outdated { .. }
+        )
+    }
+
+    // commands that touch the fs or the db, only one of them can run at a time
+    pub fn is_mutating(&self) -> bool {
+        matches!(
+            self,
+            Commands::Build { .. }
+                | Commands::Rebuild { .. }
+                | Commands::Update { .. }
+                | Commands::Autoremove
+                | Commands::Bridge { .. }
+                | Commands::Link
+                | Commands::Adopt { .. }
+                | Commands::Db { .. }
+                | Commands::Clean { .. }
+        )
+    }
+
+    // the commands that run the bridges, the notifiers are told how they went
+    pub fn build_name(&self) -> Option<&'static str> {
+        match self {
+            Commands::Build { .. } => Some("build"),
+            Commands::Rebuild { .. } => Some("rebuild"),
+            Commands::Update { .. } => Some("update"),
+            Commands::Autoremove => Some("autoremove"),
+            _ => None,
+        }
+    }
+}
+
 // Helper function to parse CLI arguments
 pub fn parse_args() -> Cli {
     Cli::parse()
@@ -0,0 +1,135 @@
+// the completion scripts of clap, with the installed pkgs completed for the
+// commands that take a pkg name, the scripts ask `pkg complete-pkgs` for
+// them (it reads the db) each time
+use std::io::Write;
+
+use clap::CommandFactory;
+use clap_complete::Shell as ClapShell;
+use clap_complete_nushell::Nushell;
+
+use crate::cmd::{Cli, Shell};
+
+// the hidden command that prints the installed pkgs, one per line
+pub const PKG_NAMES_COMMAND: &str = "complete-pkgs";
+
+// the commands (and their aliases) whose positional args are installed pkgs
+const PKG_NAME_COMMANDS: [&str; 4] = ["info", "update", "u", "run"];
+
+const BASH_PKG_NAMES: &str = r#"
+_pkg_with_pkg_names() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    case "${COMP_WORDS[1]}" in
+        {commands})
+            if [[ ${COMP_CWORD} -ge 2 && ${cur} != -* ]]; then
+                COMPREPLY=( $(compgen -W "$(pkg {names} 2>/dev/null)" -- "${cur}") )
+                return 0
+            fi
+            ;;
+    esac
+    _pkg "$@"
+}
+
+complete -F _pkg_with_pkg_names -o bashdefault -o default pkg
+"#;
+
+// replaces the end of the clap script, that registers `_pkg`
+const ZSH_DISPATCH: &str = r#"if [ "$funcstack[1]" = "_pkg" ]; then
+    _pkg "$@"
+else
+    compdef _pkg pkg
+fi"#;
+
+const ZSH_PKG_NAMES: &str = r#"_pkg_with_pkg_names() {
+    if (( CURRENT > 2 )) && [[ ${words[2]} == ({commands}) && ${words[CURRENT]} != -* ]]; then
+        compadd -- ${(f)"$(pkg {names} 2>/dev/null)"}
+    else
+        _pkg "$@"
+    fi
+}
+
+if [ "$funcstack[1]" = "_pkg" ]; then
+    _pkg_with_pkg_names "$@"
+else
+    compdef _pkg_with_pkg_names pkg
+fi"#;
+
+const FISH_PKG_NAMES: &str = r#"
+complete -c pkg -n "__fish_seen_subcommand_from {commands}" -f -a "(pkg {names} 2>/dev/null)"
+"#;
+
+const NUSHELL_PKG_NAMES: &str = r#"  def "nu-complete pkg installed" [] {
+    ^pkg {names} | complete | get stdout | lines
+  }
+"#;
+
+fn fill(script: &str, separator: &str) -> String {
+    script
+        .replace("{commands}", &PKG_NAME_COMMANDS.join(separator))
+        .replace("{names}", PKG_NAMES_COMMAND)
+}
+
+pub fn generate(shell: &Shell, out: &mut dyn Write) -> std::io::Result<()> {
+    let mut cmd = Cli::command();
+    let mut script = Vec::new();
+
+    match shell {
+        Shell::Bash => clap_complete::generate(ClapShell::Bash, &mut cmd, "pkg", &mut script),
+        Shell::Fish => clap_complete::generate(ClapShell::Fish, &mut cmd, "pkg", &mut script),
+        Shell::Zsh => clap_complete::generate(ClapShell::Zsh, &mut cmd, "pkg", &mut script),
+        Shell::Elvish => clap_complete::generate(ClapShell::Elvish, &mut cmd, "pkg", &mut script),
+        Shell::Nushell => clap_complete::generate(Nushell, &mut cmd, "pkg", &mut script),
+        Shell::PowerShell => {
+            clap_complete::generate(ClapShell::PowerShell, &mut cmd, "pkg", &mut script)
+        }
+    }
+
+    let script = String::from_utf8_lossy(&script);
+    let script = match shell {
+        Shell::Bash => format!("{script}{}", fill(BASH_PKG_NAMES, "|")),
+        Shell::Zsh => script.replace(ZSH_DISPATCH, &fill(ZSH_PKG_NAMES, "|")),
+        Shell::Fish => format!("{script}{}", fill(FISH_PKG_NAMES, " ")),
+        Shell::Nushell => nushell_pkg_names(&script),
+        // only the static completions
+        Shell::Elvish | Shell::PowerShell => script.into_owned(),
+    };
+
+    out.write_all(script.as_bytes())
+}
+
+// the pkg args of the `export extern "pkg <command>"` blocks get the completer
+fn nushell_pkg_names(script: &str) -> String {
+    let mut result = String::new();
+    let mut in_pkg_command = false;
+
+    for line in script.lines() {
+        let trimmed = line.trim_start();
+
+        if trimmed.starts_with("export extern") {
+            in_pkg_command = PKG_NAME_COMMANDS
+                .iter()
+                .any(|c| trimmed.starts_with(&format!("export extern \"pkg {c}\"")));
+        }
+
+        let is_pkg_arg = ["...packages:", "...package:", "package:"]
+            .iter()
+            .any(|arg| trimmed.starts_with(arg));
+
+        if in_pkg_command && is_pkg_arg {
+            result.push_str(&line.replacen(
+                ": string",
+                ": string@\"nu-complete pkg installed\"",
+                1,
+            ));
+        } else {
+            result.push_str(line);
+        }
+        result.push('\n');
+
+        if trimmed.starts_with("module completions") {
+            result.push('\n');
+            result.push_str(&fill(NUSHELL_PKG_NAMES, ""));
+        }
+    }
+
+    result
+}
@@ -1,12 +1,33 @@
-use kdl::{KdlDocument, KdlError};
-use miette::{Diagnostic, IntoDiagnostic, Result, SourceSpan};
+use kdl::{KdlDocument, KdlDocumentFormat, KdlEntry, KdlError, KdlNode, KdlNodeFormat, KdlValue};
+use miette::{Diagnostic, SourceSpan};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     env,
     path::{Path, PathBuf},
 };
 use thiserror::Error;
 
+use crate::{
+    audit,
+    bridge::{EnvPolicy, RetryPolicy, WorkdirRetention},
+    fs::{LinkStrategy, StorePermissions},
+    hooks::{self, Hooks},
+    input::{self, AttributeValue},
+    license::LicensePolicy,
+    manifest,
+    notify::Notifiers,
+    privilege::Escalation,
+    schedule::ScheduleOptions,
+    secrets,
+};
+
+// `inputs { git "https://.." branch="main"; }`, synced before the inputs are read
+#[derive(Debug, Clone)]
+pub struct GitInputs {
+    pub url: String,
+    pub branch: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct Config {
     pub path: PathBuf,
@@ -15,6 +36,57 @@ pub struct Config {
     pub target_dir: PathBuf,
     pub db_path: PathBuf,
     pub load_path: PathBuf,
+    pub load_paths: BTreeMap<String, PathBuf>,
+    // where the systemd units of the pkgs are linked, none means they aren't
+    pub unit_dir: Option<PathBuf>,
+    pub link_strategy: LinkStrategy,
+    pub store_permissions: StorePermissions,
+    // `hooks { post-link "systemctl daemon-reload"; }`, run once per build
+    pub hooks: Hooks,
+    pub workdir_retention: WorkdirRetention,
+    pub workdir_max_size: Option<u64>,
+    pub retry: RetryPolicy,
+    // `bridges { env { allow "PATH" "HOME"; set HTTP_PROXY=".."; } }`
+    pub bridge_env: EnvPolicy,
+    // `bridges { redact "*token*" "*password*"; }`, the attributes kept out
+    // of the logs
+    pub redact: Vec<String>,
+    // `bridges { defaults { github { asset-pattern "*linux*"; } } }`, the
+    // attributes every pkg of the bridge gets when it doesn't set them
+    pub bridge_defaults: BTreeMap<String, HashMap<String, AttributeValue>>,
+    // `bridges { instances { github-work "github" { token ".."; } } }`, the
+    // bridges of the inputs run by a bridge of the set with another name. their
+    // block is their defaults, over the ones of the bridge they run
+    pub bridge_aliases: BTreeMap<String, String>,
+    // `bridges { registry "https://../index.kdl" }`, where `pkg bridge install`
+    // fetches the bridges from
+    pub bridge_registry: Option<String>,
+    pub trace_db: bool,
+    // matched by the `when profile=".."` nodes of the inputs
+    pub profile: Option<String>,
+    // from the `vars` section, usable as `${NAME}` in the paths and the inputs
+    pub vars: HashMap<String, String>,
+    // empty `source_dir` with it means the checkout lives in the cache dir
+    pub inputs_git: Option<GitInputs>,
+    // `root "/mnt/newsys"` or `--root`, the system pkg provisions from the host
+    pub root: Option<PathBuf>,
+    // `log-dir` and `work-dir` (or `$PKG_LOG_DIR` and `$PKG_WORK_DIR`), none
+    // means the default of the host
+    pub log_dir: Option<PathBuf>,
+    pub work_dir: Option<PathBuf>,
+    // the git inputs checkout..., `None` for the one of the host
+    pub cache_dir: Option<PathBuf>,
+    // `audit-log`, next to the db by default
+    pub audit_log: PathBuf,
+    // `licenses { allow "MIT" "Apache-2.0"; deny "AGPL-3.0-only"; }`
+    pub licenses: LicensePolicy,
+    // `schedule { jitter "1h"; only-unpinned #true; }`, for `pkg schedule install`
+    pub schedule: ScheduleOptions,
+    // `notify { on "failure"; webhook "https://..."; }`, after every build and update
+    pub notify: Notifiers,
+    // `escalate "doas"`, how a user becomes root. none means the first of
+    // sudo, doas and run0 in the PATH
+    pub escalate: Option<Escalation>,
 }
 
 #[derive(Error, Debug, Diagnostic)]
@@ -47,17 +119,357 @@ pub enum ConfigError {
         bad_span: SourceSpan,
     },
 
+    #[error("Undefined variable `{name}`")]
+    #[diagnostic(
+        code(config::undefined_variable),
+        help("Declare it in the `vars` section of the config")
+    )]
+    UndefinedVariable {
+        name: String,
+        #[source_code]
+        src: String,
+        #[label("used here")]
+        bad_span: SourceSpan,
+    },
+
     #[error("missing config file")]
     #[diagnostic(code(config::missing_config_file))]
     MissingConfigFile,
+
+    #[error("Invalid value for `{name}`")]
+    #[diagnostic(code(config::wrong_value))]
+    InvalidValue {
+        name: &'static str,
+        expected: &'static str,
+        #[source_code]
+        src: String,
+        #[label("expected {expected}")]
+        bad_span: SourceSpan,
+    },
+
+    #[error("Unknown config node `{name}`")]
+    #[diagnostic(code(config::unknown_node), help("Expected one of: {known}"))]
+    UnknownNode {
+        name: String,
+        known: String,
+        #[source_code]
+        src: String,
+        #[label("unknown")]
+        bad_span: SourceSpan,
+    },
+
+    #[error("{count} problems in the config")]
+    #[diagnostic(code(config::invalid))]
+    Invalid {
+        count: usize,
+        #[related]
+        problems: Vec<ConfigError>,
+    },
+
+    #[error("No `{0}` in the config")]
+    #[diagnostic(
+        code(config::unknown_key),
+        help("The keys are the nodes of the config joined with dots, e.g. `output.target-dir`")
+    )]
+    UnknownKey(String),
+
+    #[error("Unknown profile `{name}`")]
+    #[diagnostic(
+        code(config::unknown_profile),
+        help("The profiles of the config are: {known}")
+    )]
+    UnknownProfile { name: String, known: String },
+}
+
+type Result<T, E = ConfigError> = std::result::Result<T, E>;
+
+// the nodes pkg knows, the others are reported (a typo is never ignored)
+const TOP_NODES: &[&str] = &[
+    "inputs",
+    "output",
+    "db",
+    "bridges",
+    "hooks",
+    "vars",
+    "profile",
+    "profiles",
+    "root",
+    "log-dir",
+    "work-dir",
+    "cache-dir",
+    "audit-log",
+    "licenses",
+    "schedule",
+    "notify",
+    "escalate",
+];
+const INPUTS_NODES: &[&str] = &["path", "bridges-set", "git"];
+const OUTPUT_NODES: &[&str] = &[
+    "target-dir",
+    "load-path",
+    "load-paths",
+    "unit-dir",
+    "link-strategy",
+    "normalize-permissions",
+    "setuid-allowed",
+];
+const DB_NODES: &[&str] = &["path", "trace"];
+const BRIDGES_NODES: &[&str] = &[
+    "workdir-retention",
+    "workdir-max-size",
+    "retries",
+    "retry-backoff",
+    "retry-on",
+    "env",
+    "redact",
+    "defaults",
+    "instances",
+    "registry",
+];
+const ENV_NODES: &[&str] = &["allow", "deny", "set"];
+const HOOKS_NODES: &[&str] = &[hooks::POST_LINK, hooks::PRE_REMOVE];
+const LICENSES_NODES: &[&str] = &["allow", "deny"];
+const SCHEDULE_NODES: &[&str] = &["jitter", "only-unpinned"];
+const NOTIFY_NODES: &[&str] = &["on", "exec", "desktop", "webhook"];
+
+// the defaults of the optional paths, the inputs and the bridges default to
+// the config dir
+#[cfg(all(unix, not(target_os = "macos")))]
+const DEFAULT_TARGET_DIR: &str = "/opt/pkg";
+#[cfg(all(unix, not(target_os = "macos")))]
+const DEFAULT_LOAD_PATH: &str = "/usr/local/pkg";
+#[cfg(all(unix, not(target_os = "macos")))]
+const DEFAULT_DB_PATH: &str = "/var/db/pkg/packages.db";
+// the homebrew way on macos, everything under one prefix: `/opt/pkg` on apple
+// silicon and `/usr/local/pkg` on intel (`/usr/local` is the one that's
+// writable there)
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+const DEFAULT_TARGET_DIR: &str = "/opt/pkg/store";
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+const DEFAULT_LOAD_PATH: &str = "/opt/pkg/bin";
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+const DEFAULT_DB_PATH: &str = "/opt/pkg/var/db/packages.db";
+#[cfg(all(target_os = "macos", not(target_arch = "aarch64")))]
+const DEFAULT_TARGET_DIR: &str = "/usr/local/pkg/store";
+#[cfg(all(target_os = "macos", not(target_arch = "aarch64")))]
+const DEFAULT_LOAD_PATH: &str = "/usr/local/pkg/bin";
+#[cfg(all(target_os = "macos", not(target_arch = "aarch64")))]
+const DEFAULT_DB_PATH: &str = "/usr/local/pkg/var/db/packages.db";
+// in `%LOCALAPPDATA%` on windows
+#[cfg(windows)]
+const DEFAULT_TARGET_DIR: &str = "pkg/store";
+#[cfg(windows)]
+const DEFAULT_LOAD_PATH: &str = "pkg/bin";
+#[cfg(windows)]
+const DEFAULT_DB_PATH: &str = "pkg/db/packages.db";
+const DEFAULT_BRIDGES_SET: &str = ".bridges";
+
+fn expand_home(path: &str) -> PathBuf {
+    if let Some(stripped) = path.strip_prefix("~/") {
+        if let Some(home_dir) = env::var_os("HOME") {
+            // Linux/MacOS
+            Path::new(&home_dir).join(stripped)
+        } else if let Some(home_dir) = env::var_os("USERPROFILE") {
+            // Windows
+            Path::new(&home_dir).join(stripped)
+        } else {
+            PathBuf::from(path)
+        }
+    } else {
+        PathBuf::from(path)
+    }
+}
+
+// reads the nodes of the config, a wrong one is kept as a problem (and its
+// default is used) so all of them are reported at once
+struct Reader<'a> {
+    src: &'a str,
+    vars: HashMap<String, String>,
+    problems: Vec<ConfigError>,
+}
+
+impl Reader<'_> {
+    fn invalid(&mut self, node: &KdlNode, name: &'static str, expected: &'static str) {
+        self.problems.push(ConfigError::InvalidValue {
+            name,
+            expected,
+            src: self.src.to_string(),
+            bad_span: node.span(),
+        });
+    }
+
+    // `inputs { .. }`..., none if it's not there
+    fn section<'d>(
+        &mut self,
+        parent: &'d KdlDocument,
+        name: &'static str,
+        known: &[&str],
+    ) -> Option<&'d KdlDocument> {
+        let node = parent.get(name)?;
+        let Some(section) = node.children() else {
+            self.invalid(node, name, "a block of nodes");
+            return None;
+        };
+
+        self.unknown_nodes(section, known);
+        Some(section)
+    }
+
+    fn unknown_nodes(&mut self, section: &KdlDocument, known: &[&str]) {
+        for node in section.nodes() {
+            if !known.contains(&node.name().value()) {
+                self.problems.push(ConfigError::UnknownNode {
+                    name: node.name().value().to_string(),
+                    known: known.join(", "),
+                    src: self.src.to_string(),
+                    bad_span: node.name().span(),
+                });
+            }
+        }
+    }
+
+    fn string(&mut self, parent: Option<&KdlDocument>, name: &'static str) -> Option<String> {
+        let node = parent?.get(name)?;
+        match node.entries().first().and_then(|e| e.value().as_string()) {
+            Some(value) => Some(value.to_string()),
+            None => {
+                self.invalid(node, name, "a string");
+                None
+            }
+        }
+    }
+
+    fn bool(&mut self, parent: Option<&KdlDocument>, name: &'static str) -> Option<bool> {
+        let node = parent?.get(name)?;
+        match node.entries().first().and_then(|e| e.value().as_bool()) {
+            Some(value) => Some(value),
+            None => {
+                self.invalid(node, name, "#true or #false");
+                None
+            }
+        }
+    }
+
+    fn parsed<T>(
+        &mut self,
+        parent: Option<&KdlDocument>,
+        name: &'static str,
+        expected: &'static str,
+        parse: impl FnOnce(&str) -> Option<T>,
+    ) -> Option<T> {
+        let node = parent?.get(name)?;
+        let parsed = node
+            .entries()
+            .first()
+            .and_then(|e| e.value().as_string())
+            .and_then(parse);
+        if parsed.is_none() {
+            self.invalid(node, name, expected);
+        }
+        parsed
+    }
+
+    fn path(&mut self, parent: Option<&KdlDocument>, name: &'static str) -> Option<PathBuf> {
+        let node = parent?.get(name)?;
+        let Some(value) = node.entries().first().and_then(|e| e.value().as_string()) else {
+            self.problems.push(ConfigError::InvalidPath {
+                src: self.src.to_string(),
+                bad_span: node.span(),
+            });
+            return None;
+        };
+
+        self.expand_path(node, value)
+    }
+
+    // `~/` and the `${NAME}` vars
+    fn expand_path(&mut self, node: &KdlNode, value: &str) -> Option<PathBuf> {
+        match input::expand(value, &self.vars) {
+            Ok(value) => Some(expand_home(&value)),
+            Err(name) => {
+                self.problems.push(ConfigError::UndefinedVariable {
+                    name,
+                    src: self.src.to_string(),
+                    bad_span: node.span(),
+                });
+                None
+            }
+        }
+    }
+
+    // read like an attribute of the inputs: `assets "a" "b"` is a list, a
+    // block is a map and a bare node is `#true`
+    fn attribute(&mut self, node: &KdlNode) -> Option<AttributeValue> {
+        if let Some(children) = node.children() {
+            let mut map = BTreeMap::new();
+            for child in children.nodes() {
+                map.insert(child.name().value().to_string(), self.attribute(child)?);
+            }
+            return Some(AttributeValue::Map(map));
+        }
+
+        let mut values = Vec::new();
+        for entry in node.entries() {
+            if entry.name().is_some() {
+                self.invalid(node, "defaults", "values or a block of attributes");
+                return None;
+            }
+
+            values.push(match entry.value() {
+                KdlValue::String(value) => match input::expand(value, &self.vars) {
+                    Ok(value) => AttributeValue::String(value),
+                    Err(name) => {
+                        self.problems.push(ConfigError::UndefinedVariable {
+                            name,
+                            src: self.src.to_string(),
+                            bad_span: node.span(),
+                        });
+                        return None;
+                    }
+                },
+                KdlValue::Integer(value) => AttributeValue::Integer(*value as i64),
+                KdlValue::Float(value) => AttributeValue::Float(*value),
+                KdlValue::Bool(value) => AttributeValue::Boolean(*value),
+                KdlValue::Null => {
+                    self.invalid(node, "defaults", "a string, a number or a bool");
+                    return None;
+                }
+            });
+        }
+
+        Some(match values.len() {
+            0 => AttributeValue::Boolean(true),
+            1 => values.remove(0),
+            _ => AttributeValue::List(values),
+        })
+    }
+
+    fn strings(&mut self, node: &KdlNode, name: &'static str) -> Vec<String> {
+        let strings = node
+            .entries()
+            .iter()
+            .map(|e| e.value().as_string().map(String::from))
+            .collect::<Option<Vec<String>>>();
+
+        strings.unwrap_or_else(|| {
+            self.invalid(node, name, "strings");
+            Vec::new()
+        })
+    }
 }
 
 impl Config {
     pub fn load(path: PathBuf) -> Result<Self> {
+        Self::load_profile(path, None)
+    }
+
+    // `profile` (from `--profile`) replaces the `profile` of the config, and
+    // the `profiles` block of that name overrides the rest of the config
+    pub fn load_profile(path: PathBuf, profile: Option<String>) -> Result<Self> {
         let config_file =
             std::fs::read_to_string(&path).map_err(|_| ConfigError::MissingConfigFile)?;
 
-        let kdl = config_file.parse::<KdlDocument>().into_diagnostic()?;
+        let kdl = config_file.parse::<KdlDocument>()?;
 
         let config_node = kdl
             .get("config")
@@ -67,88 +479,826 @@ impl Config {
             .children()
             .ok_or(ConfigError::MissingValue("config node is empty"))?;
 
-        // Store the children documents in a HashMap
-        let mut config = HashMap::new();
-
-        // Helper function to get and clone children documents
-        fn get_and_store_children(
-            config: &mut HashMap<String, KdlDocument>,
-            parent: &KdlDocument,
-            key: &'static str,
-        ) -> Result<(), ConfigError> {
-            let children = parent
-                .get(key)
-                .ok_or(ConfigError::MissingValue(key))?
-                .children()
-                .ok_or(ConfigError::MissingValue(key))?
-                .clone(); // Clone to get owned KdlDocument
-
-            config.insert(key.to_string(), children);
-            Ok(())
-        }
-
-        get_and_store_children(&mut config, content, "inputs")?;
-        get_and_store_children(&mut config, content, "output")?;
-        get_and_store_children(&mut config, content, "db")?;
-
-        fn expand_home(path: &str) -> PathBuf {
-            if let Some(stripped) = path.strip_prefix("~/") {
-                if let Some(home_dir) = env::var_os("HOME") {
-                    // Linux/MacOS
-                    Path::new(&home_dir).join(stripped)
-                } else if let Some(home_dir) = env::var_os("USERPROFILE") {
-                    // Windows
-                    Path::new(&home_dir).join(stripped)
-                } else {
-                    PathBuf::from(path)
+        let mut reader = Reader {
+            src: &config_file,
+            vars: input::builtin_vars(),
+            problems: Vec::new(),
+        };
+
+        let profile = profile.or_else(|| reader.string(Some(content), "profile"));
+        let mut content = content.clone();
+        if let Some(profile) = &profile {
+            apply_profile(&mut content, profile)?;
+        }
+        let content = &content;
+
+        reader.unknown_nodes(content, TOP_NODES);
+        let inputs = reader.section(content, "inputs", INPUTS_NODES);
+        let output = reader.section(content, "output", OUTPUT_NODES);
+        let db = reader.section(content, "db", DB_NODES);
+        let bridges = reader.section(content, "bridges", BRIDGES_NODES);
+        let hooks_section = reader.section(content, "hooks", HOOKS_NODES);
+        let licenses_section = reader.section(content, "licenses", LICENSES_NODES);
+        let schedule = reader.section(content, "schedule", SCHEDULE_NODES);
+        let notify_section = reader.section(content, "notify", NOTIFY_NODES);
+
+        let mut vars = HashMap::new();
+        for var in content
+            .get("vars")
+            .and_then(|n| n.children())
+            .map(|c| c.nodes())
+            .unwrap_or_default()
+        {
+            match var.entries().first().and_then(|e| e.value().as_string()) {
+                Some(value) => {
+                    vars.insert(var.name().value().to_string(), value.to_string());
                 }
-            } else {
-                PathBuf::from(path)
+                None => reader.invalid(var, "vars", "a string"),
             }
         }
+        reader.vars.extend(vars.clone());
 
-        // Helper function to get string values from nodes
-        fn get_node_value_as_string(
-            parent: &KdlDocument,
-            node_name: &'static str,
-            src: &str,
-        ) -> Result<PathBuf, ConfigError> {
-            let node = parent
-                .get(node_name)
-                .ok_or(ConfigError::MissingValue(node_name))?;
+        // `load-paths { user "~/.local/bin" }`, chosen by the `load-path` attribute of a pkg
+        let mut load_paths = BTreeMap::new();
+        for node in output
+            .and_then(|o| o.get("load-paths"))
+            .and_then(|n| n.children())
+            .map(|c| c.nodes())
+            .unwrap_or_default()
+        {
+            let Some(value) = node.entries().first().and_then(|e| e.value().as_string()) else {
+                reader.problems.push(ConfigError::InvalidPath {
+                    src: config_file.clone(),
+                    bad_span: node.span(),
+                });
+                continue;
+            };
 
-            let value = node
+            if let Some(path) = reader.expand_path(node, value) {
+                load_paths.insert(node.name().value().to_string(), path);
+            }
+        }
+
+        let mut config_hooks = Hooks::default();
+        for node in hooks_section.map(|h| h.nodes()).unwrap_or_default() {
+            let (commands, name) = match node.name().value() {
+                hooks::POST_LINK => (&mut config_hooks.post_link, hooks::POST_LINK),
+                hooks::PRE_REMOVE => (&mut config_hooks.pre_remove, hooks::PRE_REMOVE),
+                // reported with the unknown nodes
+                _ => continue,
+            };
+
+            commands.extend(reader.strings(node, name));
+        }
+
+        let setuid_allowed = match output.and_then(|o| o.get("setuid-allowed")) {
+            Some(node) => reader.strings(node, "setuid-allowed"),
+            None => Vec::new(),
+        };
+
+        let inputs_git = match inputs.and_then(|i| i.get("git")) {
+            Some(node) => {
+                let url = node.entries().first().and_then(|e| e.value().as_string());
+                let branch = node.get("branch").map(|b| b.as_string());
+
+                match (url, branch) {
+                    (Some(url), None) => Some(GitInputs {
+                        url: url.to_string(),
+                        branch: None,
+                    }),
+                    (Some(url), Some(Some(branch))) => Some(GitInputs {
+                        url: url.to_string(),
+                        branch: Some(branch.to_string()),
+                    }),
+                    _ => {
+                        reader.invalid(node, "git", "a url and an optional branch=\"..\"");
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let mut bridge_env = EnvPolicy::default();
+        if let Some(env) = bridges.and_then(|b| reader.section(b, "env", ENV_NODES)) {
+            if let Some(node) = env.get("allow") {
+                bridge_env.allow = reader.strings(node, "allow");
+            }
+            if let Some(node) = env.get("deny") {
+                bridge_env.deny = reader.strings(node, "deny");
+            }
+            if let Some(node) = env.get("set") {
+                for entry in node.entries() {
+                    match (entry.name(), entry.value().as_string()) {
+                        (Some(name), Some(value)) => bridge_env
+                            .set
+                            .push((name.value().to_string(), value.to_string())),
+                        _ => reader.invalid(node, "set", "NAME=\"value\" pairs"),
+                    }
+                }
+            }
+        }
+
+        let mut bridge_defaults = BTreeMap::new();
+        for bridge in bridges
+            .and_then(|b| b.get("defaults"))
+            .and_then(|n| n.children())
+            .map(|c| c.nodes())
+            .unwrap_or_default()
+        {
+            let Some(children) = bridge.children() else {
+                reader.invalid(bridge, "defaults", "a block of attributes per bridge");
+                continue;
+            };
+
+            let mut attributes = HashMap::new();
+            for node in children.nodes() {
+                if let Some(value) = reader.attribute(node) {
+                    attributes.insert(node.name().value().to_string(), value);
+                }
+            }
+            bridge_defaults.insert(bridge.name().value().to_string(), attributes);
+        }
+
+        let mut bridge_aliases = BTreeMap::new();
+        for instance in bridges
+            .and_then(|b| b.get("instances"))
+            .and_then(|n| n.children())
+            .map(|c| c.nodes())
+            .unwrap_or_default()
+        {
+            let Some(bridge) = instance
                 .entries()
                 .first()
-                .ok_or(ConfigError::MissingValue(node_name))?
-                .value()
-                .as_string()
-                .ok_or_else(|| ConfigError::InvalidPath {
-                    src: src.to_string(),
-                    bad_span: node.span(),
-                })?
-                .to_owned();
+                .and_then(|e| e.value().as_string())
+            else {
+                reader.invalid(instance, "instances", "the bridge it runs, a string");
+                continue;
+            };
+            let name = instance.name().value().to_string();
 
-            Ok(expand_home(value.as_str()))
+            let mut attributes = bridge_defaults.get(bridge).cloned().unwrap_or_default();
+            for node in instance.children().map(|c| c.nodes()).unwrap_or_default() {
+                if let Some(value) = reader.attribute(node) {
+                    attributes.insert(node.name().value().to_string(), value);
+                }
+            }
+            // what `defaults` gives the instance it self wins
+            attributes.extend(bridge_defaults.remove(&name).unwrap_or_default());
+            if !attributes.is_empty() {
+                bridge_defaults.insert(name.clone(), attributes);
+            }
+            bridge_aliases.insert(name, bridge.to_string());
         }
 
-        let src = kdl.to_string();
+        let redact = match bridges.and_then(|b| b.get("redact")) {
+            Some(node) => reader.strings(node, "redact"),
+            None => secrets::default_sensitive_attributes(),
+        };
+
+        // `exec` and `webhook` can be there more than once
+        let mut notify = Notifiers {
+            on: reader
+                .parsed(notify_section, "on", "`always` or `failure`", |v| {
+                    v.parse().ok()
+                })
+                .unwrap_or_default(),
+            desktop: reader.bool(notify_section, "desktop").unwrap_or(false),
+            ..Default::default()
+        };
+        for node in notify_section.map(|n| n.nodes()).unwrap_or_default() {
+            match node.name().value() {
+                "exec" => notify.exec.extend(reader.strings(node, "exec")),
+                "webhook" => notify.webhooks.extend(reader.strings(node, "webhook")),
+                _ => {}
+            }
+        }
+
+        let mut licenses = LicensePolicy::default();
+        if let Some(node) = licenses_section.and_then(|l| l.get("allow")) {
+            licenses.allow = reader.strings(node, "allow");
+        }
+        if let Some(node) = licenses_section.and_then(|l| l.get("deny")) {
+            licenses.deny = reader.strings(node, "deny");
+        }
 
-        Ok(Self {
+        let config_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        // with a git repo and no path, the checkout lives in the cache dir
+        let source_dir = match reader.path(inputs, "path") {
+            Some(path) => path,
+            None if inputs_git.is_some() => PathBuf::new(),
+            None => config_dir.clone(),
+        };
+
+        let retries = match bridges.and_then(|b| b.get("retries")) {
+            Some(node) => {
+                let retries = node
+                    .entries()
+                    .first()
+                    .and_then(|e| e.value().as_integer())
+                    .and_then(|v| u32::try_from(v).ok());
+                if retries.is_none() {
+                    reader.invalid(node, "retries", "a number of retries");
+                }
+                retries.unwrap_or_default()
+            }
+            None => 0,
+        };
+
+        let db_path = reader
+            .path(db, "path")
+            .unwrap_or_else(|| crate::host::platform_dir(DEFAULT_DB_PATH));
+
+        let config = Self {
+            source_dir,
+            bridges_set: reader
+                .path(inputs, "bridges-set")
+                .unwrap_or_else(|| config_dir.join(DEFAULT_BRIDGES_SET)),
+            target_dir: reader
+                .path(output, "target-dir")
+                .unwrap_or_else(|| crate::host::platform_dir(DEFAULT_TARGET_DIR)),
+            load_path: reader
+                .path(output, "load-path")
+                .unwrap_or_else(|| crate::host::platform_dir(DEFAULT_LOAD_PATH)),
+            load_paths,
+            unit_dir: reader.path(output, "unit-dir"),
+            hooks: config_hooks,
+            audit_log: reader
+                .path(Some(content), "audit-log")
+                .unwrap_or_else(|| db_path.with_file_name(audit::FILE_NAME)),
+            db_path,
+            link_strategy: reader
+                .parsed(
+                    output,
+                    "link-strategy",
+                    "symlink, hardlink, copy or wrapper-script",
+                    |v| v.parse().ok(),
+                )
+                .unwrap_or_default(),
+            store_permissions: StorePermissions {
+                normalize: reader.bool(output, "normalize-permissions").unwrap_or(true),
+                setuid_allowed,
+            },
+            workdir_retention: reader
+                .parsed(
+                    bridges,
+                    "workdir-retention",
+                    "keep-on-failure, keep-always or never",
+                    |v| v.parse().ok(),
+                )
+                .unwrap_or_default(),
+            workdir_max_size: reader.parsed(
+                bridges,
+                "workdir-max-size",
+                "a size like 512M or 2G",
+                parse_size,
+            ),
+            retry: RetryPolicy {
+                retries,
+                backoff: reader
+                    .parsed(
+                        bridges,
+                        "retry-backoff",
+                        "a duration like 500ms, 2s or 1m",
+                        manifest::parse_duration,
+                    )
+                    .unwrap_or(RetryPolicy::default().backoff),
+                on: reader
+                    .parsed(bridges, "retry-on", "sentinel or failure", |v| {
+                        v.parse().ok()
+                    })
+                    .unwrap_or_default(),
+            },
+            bridge_env,
+            redact,
+            bridge_defaults,
+            bridge_aliases,
+            bridge_registry: reader.string(bridges, "registry"),
+            licenses,
+            notify,
+            schedule: ScheduleOptions {
+                jitter: reader
+                    .parsed(
+                        schedule,
+                        "jitter",
+                        "a duration like 30m or 2h",
+                        manifest::parse_duration,
+                    )
+                    .unwrap_or(ScheduleOptions::default().jitter),
+                only_unpinned: reader.bool(schedule, "only-unpinned").unwrap_or(false),
+            },
+            trace_db: reader.bool(db, "trace").unwrap_or(false),
+            profile,
+            vars,
+            inputs_git,
+            root: reader.path(Some(content), "root"),
+            log_dir: env::var_os("PKG_LOG_DIR")
+                .map(PathBuf::from)
+                .or_else(|| reader.path(Some(content), "log-dir")),
+            work_dir: env::var_os("PKG_WORK_DIR")
+                .map(PathBuf::from)
+                .or_else(|| reader.path(Some(content), "work-dir")),
+            cache_dir: env::var_os("PKG_CACHE_DIR")
+                .map(PathBuf::from)
+                .or_else(|| reader.path(Some(content), "cache-dir")),
+            escalate: reader.parsed(
+                Some(content),
+                "escalate",
+                "sudo, doas, run0 or pkexec",
+                |v| v.parse().ok(),
+            ),
             path,
-            source_dir: get_node_value_as_string(config.get("inputs").unwrap(), "path", &src)?,
-            bridges_set: get_node_value_as_string(
-                config.get("inputs").unwrap(),
-                "bridges-set",
-                &src,
-            )?,
-            target_dir: get_node_value_as_string(
-                config.get("output").unwrap(),
-                "target-dir",
-                &src,
-            )?,
-            load_path: get_node_value_as_string(config.get("output").unwrap(), "load-path", &src)?,
-            db_path: get_node_value_as_string(config.get("db").unwrap(), "path", &src)?,
-        })
+        };
+
+        // in the order of the file
+        let mut problems = reader.problems;
+        problems.sort_by_key(|p| p.labels().and_then(|mut l| l.next()).map(|l| l.offset()));
+        match problems.len() {
+            0 => Ok(config),
+            1 => Err(problems.remove(0)),
+            count => Err(ConfigError::Invalid { count, problems }),
+        }
+    }
+
+    // the config as pkg uses it (the defaults, the vars, the profile and the
+    // root applied), it can be loaded back
+    pub fn to_kdl(&self) -> KdlDocument {
+        let path = |p: &Path| KdlValue::from(p.to_string_lossy().into_owned());
+
+        let mut inputs = vec![node("bridges-set", path(&self.bridges_set))];
+        if !self.source_dir.as_os_str().is_empty() {
+            inputs.insert(0, node("path", path(&self.source_dir)));
+        }
+        if let Some(git) = &self.inputs_git {
+            let mut git_node = node("git", git.url.clone());
+            if let Some(branch) = &git.branch {
+                git_node.push(KdlEntry::new_prop("branch", branch.clone()));
+            }
+            inputs.push(git_node);
+        }
+
+        let mut output = vec![
+            node("target-dir", path(&self.target_dir)),
+            node("load-path", path(&self.load_path)),
+        ];
+        if !self.load_paths.is_empty() {
+            output.push(block(
+                "load-paths",
+                self.load_paths
+                    .iter()
+                    .map(|(name, dir)| node(name, path(dir)))
+                    .collect(),
+            ));
+        }
+        if let Some(unit_dir) = &self.unit_dir {
+            output.push(node("unit-dir", path(unit_dir)));
+        }
+        output.push(node("link-strategy", self.link_strategy.to_string()));
+        output.push(node(
+            "normalize-permissions",
+            self.store_permissions.normalize,
+        ));
+        if !self.store_permissions.setuid_allowed.is_empty() {
+            output.push(list(
+                "setuid-allowed",
+                &self.store_permissions.setuid_allowed,
+            ));
+        }
+
+        let mut bridges = vec![
+            node("workdir-retention", self.workdir_retention.to_string()),
+            node("retries", i128::from(self.retry.retries)),
+            node("retry-backoff", format_duration(self.retry.backoff)),
+            node("retry-on", self.retry.on.to_string()),
+        ];
+        if let Some(size) = self.workdir_max_size {
+            bridges.insert(1, node("workdir-max-size", size.to_string()));
+        }
+        if self.bridge_env != EnvPolicy::default() {
+            let mut env = Vec::new();
+            if !self.bridge_env.allow.is_empty() {
+                env.push(list("allow", &self.bridge_env.allow));
+            }
+            if !self.bridge_env.deny.is_empty() {
+                env.push(list("deny", &self.bridge_env.deny));
+            }
+            if !self.bridge_env.set.is_empty() {
+                let mut set = KdlNode::new("set");
+                for (name, value) in &self.bridge_env.set {
+                    set.push(KdlEntry::new_prop(name.as_str(), value.clone()));
+                }
+                env.push(set);
+            }
+            bridges.push(block("env", env));
+        }
+        bridges.push(list("redact", &self.redact));
+        if let Some(registry) = &self.bridge_registry {
+            bridges.push(node("registry", registry.clone()));
+        }
+        if !self.bridge_aliases.is_empty() {
+            bridges.push(block(
+                "instances",
+                self.bridge_aliases
+                    .iter()
+                    .map(|(name, bridge)| node(name, bridge.clone()))
+                    .collect(),
+            ));
+        }
+        if !self.bridge_defaults.is_empty() {
+            bridges.push(block(
+                "defaults",
+                self.bridge_defaults
+                    .iter()
+                    .map(|(bridge, attributes)| {
+                        let attributes = attributes.iter().collect::<BTreeMap<_, _>>();
+                        block(
+                            bridge,
+                            attributes
+                                .into_iter()
+                                .map(|(name, value)| attribute_node(name, value))
+                                .collect(),
+                        )
+                    })
+                    .collect(),
+            ));
+        }
+
+        let mut content = Vec::new();
+        if let Some(profile) = &self.profile {
+            content.push(node("profile", profile.clone()));
+        }
+        if !self.vars.is_empty() {
+            let vars = self.vars.iter().collect::<BTreeMap<_, _>>();
+            content.push(block(
+                "vars",
+                vars.into_iter()
+                    .map(|(name, value)| node(name, value.clone()))
+                    .collect(),
+            ));
+        }
+        if let Some(log_dir) = &self.log_dir {
+            content.push(node("log-dir", path(log_dir)));
+        }
+        if let Some(work_dir) = &self.work_dir {
+            content.push(node("work-dir", path(work_dir)));
+        }
+        if let Some(cache_dir) = &self.cache_dir {
+            content.push(node("cache-dir", path(cache_dir)));
+        }
+        if let Some(escalate) = self.escalate {
+            content.push(node("escalate", escalate.to_string()));
+        }
+        content.push(node("audit-log", path(&self.audit_log)));
+        content.push(block("inputs", inputs));
+        content.push(block("output", output));
+        content.push(block(
+            "db",
+            vec![
+                node("path", path(&self.db_path)),
+                node("trace", self.trace_db),
+            ],
+        ));
+        content.push(block("bridges", bridges));
+
+        let mut hooks = Vec::new();
+        if !self.hooks.post_link.is_empty() {
+            hooks.push(list(hooks::POST_LINK, &self.hooks.post_link));
+        }
+        if !self.hooks.pre_remove.is_empty() {
+            hooks.push(list(hooks::PRE_REMOVE, &self.hooks.pre_remove));
+        }
+        if !hooks.is_empty() {
+            content.push(block("hooks", hooks));
+        }
+
+        if !self.licenses.is_empty() {
+            let mut licenses = Vec::new();
+            if !self.licenses.allow.is_empty() {
+                licenses.push(list("allow", &self.licenses.allow));
+            }
+            if !self.licenses.deny.is_empty() {
+                licenses.push(list("deny", &self.licenses.deny));
+            }
+            content.push(block("licenses", licenses));
+        }
+
+        if self.schedule != ScheduleOptions::default() {
+            content.push(block(
+                "schedule",
+                vec![
+                    node("jitter", format_duration(self.schedule.jitter)),
+                    node("only-unpinned", self.schedule.only_unpinned),
+                ],
+            ));
+        }
+
+        if self.notify != Notifiers::default() {
+            let mut notify = vec![node("on", self.notify.on.to_string())];
+            if !self.notify.exec.is_empty() {
+                notify.push(list("exec", &self.notify.exec));
+            }
+            if self.notify.desktop {
+                notify.push(node("desktop", true));
+            }
+            if !self.notify.webhooks.is_empty() {
+                notify.push(list("webhook", &self.notify.webhooks));
+            }
+            content.push(block("notify", notify));
+        }
+
+        let mut doc = KdlDocument::new();
+        doc.nodes_mut().push(block("config", content));
+        doc.autoformat();
+        doc
+    }
+
+    // `output.target-dir`: the values of the node, or the whole block
+    pub fn get(&self, key: &str) -> Option<String> {
+        let doc = self.to_kdl();
+        let mut node = doc.get("config")?;
+        for name in key.split('.') {
+            node = node.children()?.get(name)?;
+        }
+
+        match node.children() {
+            Some(children) => {
+                let mut children = children.clone();
+                children.autoformat();
+                Some(children.to_string())
+            }
+            None => Some(
+                node.entries()
+                    .iter()
+                    .map(|e| match e.value() {
+                        KdlValue::String(value) => value.clone(),
+                        value => value.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+        }
+    }
+
+    // moves the paths of the installed system under `root`, the inputs and
+    // the bridges stay the ones of the host
+    pub fn set_root(&mut self, root: PathBuf) {
+        self.target_dir = under_root(&root, &self.target_dir);
+        self.load_path = under_root(&root, &self.load_path);
+        for path in self.load_paths.values_mut() {
+            *path = under_root(&root, path);
+        }
+        self.unit_dir = self.unit_dir.as_ref().map(|dir| under_root(&root, dir));
+        self.db_path = under_root(&root, &self.db_path);
+        self.audit_log = under_root(&root, &self.audit_log);
+        // the working dirs are scratch space of the host
+        self.log_dir = self.log_dir.as_ref().map(|dir| under_root(&root, dir));
+        self.root = Some(root);
     }
+
+    // a path of the installed system, seen from the host
+    pub fn rooted(&self, path: &Path) -> PathBuf {
+        match &self.root {
+            Some(root) => under_root(root, path),
+            None => path.to_path_buf(),
+        }
+    }
+}
+
+// the sections of `profiles { name { .. } }` override the config node by
+// node (`output { load-path ".." }` only changes the load path), a section
+// that isn't in the config is added
+fn apply_profile(content: &mut KdlDocument, name: &str) -> Result<()> {
+    let Some(profiles) = content.get("profiles").and_then(|n| n.children()) else {
+        return Ok(());
+    };
+
+    let profile = profiles
+        .get(name)
+        .map(|n| n.children().cloned().unwrap_or_default())
+        .ok_or_else(|| ConfigError::UnknownProfile {
+            name: name.to_string(),
+            known: profiles
+                .nodes()
+                .iter()
+                .map(|n| n.name().value())
+                .collect::<Vec<_>>()
+                .join(", "),
+        })?;
+
+    for section in profile.nodes() {
+        match (content.get_mut(section.name().value()), section.children()) {
+            (Some(base), Some(overrides)) => {
+                let base = base.ensure_children();
+                for node in overrides.nodes() {
+                    match base.get_mut(node.name().value()) {
+                        Some(old) => *old = node.clone(),
+                        None => base.nodes_mut().push(node.clone()),
+                    }
+                }
+            }
+            (Some(base), None) => *base = section.clone(),
+            (None, _) => content.nodes_mut().push(section.clone()),
+        }
+    }
+
+    Ok(())
+}
+
+// sets `output.target-dir` (or `profiles.user.db.path`...) in the config
+// file, the sections are made if they aren't there and the rest of the file
+// (comments, formatting) is kept. the new file has to load, or it's not written
+pub fn set(path: &Path, key: &str, values: &[String]) -> Result<()> {
+    let mut doc = std::fs::read_to_string(path)
+        .map_err(|_| ConfigError::MissingConfigFile)?
+        .parse::<KdlDocument>()?;
+
+    let names = key.split('.').collect::<Vec<_>>();
+    let Some((name, sections)) = names.split_last() else {
+        return Err(ConfigError::UnknownKey(key.to_string()));
+    };
+
+    let config_node = doc
+        .get_mut("config")
+        .ok_or(ConfigError::MissingValue("config"))?;
+    let mut parent = config_node.ensure_children();
+    // a new node gets the indentation of its siblings, or one more step than
+    // its section (the step of the `config` children)
+    let step = indentation(parent).unwrap_or_else(|| "  ".to_string());
+    let mut section_indent = String::new();
+
+    for section in sections {
+        let indent = indentation(parent).unwrap_or_else(|| format!("{section_indent}{step}"));
+        if parent.get(section).is_none() {
+            let mut node = new_node(section, &indent);
+            let children = node.ensure_children();
+            children.set_format(KdlDocumentFormat {
+                leading: "\n".to_string(),
+                trailing: indent.clone(),
+            });
+            if let Some(format) = node.format_mut() {
+                format.before_children = " ".to_string();
+            }
+            parent.nodes_mut().push(node);
+        }
+        parent = parent
+            .get_mut(section)
+            .expect("the section was just added")
+            .ensure_children();
+        section_indent = indent;
+    }
+
+    if parent.get(name).is_none() {
+        let indent = indentation(parent).unwrap_or_else(|| format!("{section_indent}{step}"));
+        parent.nodes_mut().push(new_node(name, &indent));
+    }
+    let node = parent.get_mut(name).expect("the node was just added");
+    node.entries_mut().clear();
+    for value in values {
+        node.push(KdlEntry::new(typed_value(name, value)?));
+    }
+
+    let tmp = path.with_file_name(format!(
+        ".{}.pkg-set",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    std::fs::write(&tmp, doc.to_string())?;
+
+    match Config::load(tmp.clone()) {
+        Ok(_) => Ok(std::fs::rename(&tmp, path)?),
+        Err(err) => {
+            let _ = std::fs::remove_file(&tmp);
+            Err(err)
+        }
+    }
+}
+
+// the indentation of the nodes of a section, from its first node (the text
+// before it is split between the section and the node)
+fn indentation(section: &KdlDocument) -> Option<String> {
+    let leading = format!(
+        "{}{}",
+        section.format()?.leading,
+        section.nodes().first()?.format()?.leading
+    );
+    let indent = leading.rsplit('\n').next().unwrap_or_default();
+
+    indent
+        .chars()
+        .all(|c| c == ' ' || c == '\t')
+        .then(|| indent.to_string())
+}
+
+fn new_node(name: &str, indent: &str) -> KdlNode {
+    let mut node = KdlNode::new(name);
+    node.set_format(KdlNodeFormat {
+        leading: indent.to_string(),
+        terminator: "\n".to_string(),
+        ..Default::default()
+    });
+    node
+}
+
+// the few config values that aren't strings
+fn typed_value(name: &str, value: &str) -> Result<KdlValue> {
+    match name {
+        "normalize-permissions" | "trace" => match value.trim_start_matches('#') {
+            "true" => Ok(KdlValue::Bool(true)),
+            "false" => Ok(KdlValue::Bool(false)),
+            _ => Err(ConfigError::WrongValue("a boolean")),
+        },
+        "retries" => value
+            .parse::<i128>()
+            .map(KdlValue::Integer)
+            .map_err(|_| ConfigError::WrongValue("retries")),
+        _ => Ok(KdlValue::String(value.to_string())),
+    }
+}
+
+fn node(name: &str, value: impl Into<KdlValue>) -> KdlNode {
+    let mut node = KdlNode::new(name);
+    node.push(KdlEntry::new(value));
+    node
+}
+
+fn list(name: &str, values: &[String]) -> KdlNode {
+    let mut node = KdlNode::new(name);
+    for value in values {
+        node.push(KdlEntry::new(value.clone()));
+    }
+    node
+}
+
+fn block(name: &str, nodes: Vec<KdlNode>) -> KdlNode {
+    let mut node = KdlNode::new(name);
+    node.ensure_children().nodes_mut().extend(nodes);
+    node
+}
+
+// the way `Reader::attribute` reads it back
+fn attribute_node(name: &str, value: &AttributeValue) -> KdlNode {
+    let scalar = |value: &AttributeValue| match value {
+        AttributeValue::String(value) => Some(KdlValue::from(value.clone())),
+        AttributeValue::Integer(value) => Some(KdlValue::from(i128::from(*value))),
+        AttributeValue::Float(value) => Some(KdlValue::from(*value)),
+        AttributeValue::Boolean(value) => Some(KdlValue::from(*value)),
+        AttributeValue::List(_) | AttributeValue::Map(_) => None,
+    };
+
+    let mut node = KdlNode::new(name);
+    match value {
+        AttributeValue::List(values) => {
+            for value in values.iter().filter_map(scalar) {
+                node.push(KdlEntry::new(value));
+            }
+        }
+        AttributeValue::Map(map) => {
+            node.ensure_children()
+                .nodes_mut()
+                .extend(map.iter().map(|(name, value)| attribute_node(name, value)));
+        }
+        value => {
+            if let Some(value) = scalar(value) {
+                node.push(KdlEntry::new(value));
+            }
+        }
+    }
+    node
+}
+
+// the way `parse_duration` reads it
+fn format_duration(duration: std::time::Duration) -> String {
+    let millis = duration.as_millis();
+    if millis.is_multiple_of(3_600_000) && millis > 0 {
+        format!("{}h", millis / 3_600_000)
+    } else if millis.is_multiple_of(60_000) && millis > 0 {
+        format!("{}m", millis / 60_000)
+    } else if millis.is_multiple_of(1000) {
+        format!("{}s", millis / 1000)
+    } else {
+        format!("{millis}ms")
+    }
+}
+
+pub fn under_root(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+// `512`, `100K`, `20M`, `2G`...
+fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let (number, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => size.split_at(i),
+        None => (size, ""),
+    };
+
+    let multiplier: u64 = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1024,
+        "M" | "MB" => 1024 * 1024,
+        "G" | "GB" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
 }
@@ -1,20 +1,125 @@
-use std::{collections::HashMap, fmt::Debug, path::PathBuf};
-
-use miette::{Diagnostic, IntoDiagnostic, Result};
-use rusqlite::{Connection, Error as RusqliteError};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use miette::Diagnostic;
+use rusqlite::{
+    Connection, Error as RusqliteError, MAIN_DB, OpenFlags, OptionalExtension,
+    backup::Progress,
+    trace::{TraceEvent, TraceEventCodes},
+};
 use thiserror::Error;
 
-use crate::input::PkgDeclaration;
+use crate::input::{AttributeValue, PkgDeclaration};
+
+mod store;
+
+pub use store::{JsonStore, MemoryStore, StateStore};
+
+type Result<T, E = DbError> = std::result::Result<T, E>;
 
 pub type EntryPoint = PathBuf;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum PkgType {
     SingleExecutable,
     Directory(EntryPoint),
 }
 
-#[derive(Debug)]
+// what a bridge gives next to the pkg (more lines of its output), the paths
+// are in the pkg
+#[derive(Debug, Clone, PartialEq)]
+pub enum Artifact {
+    // linked in the `unit-dir` of the config
+    SystemdUnit(PathBuf),
+}
+
+pub const SYSTEMD_UNIT_ARTIFACT: &str = "systemd-unit";
+
+impl Artifact {
+    // `<class>,<path>`, as in the bridge output and the db
+    pub fn parse(line: &str) -> Option<Self> {
+        match line.split_once(',')? {
+            (SYSTEMD_UNIT_ARTIFACT, path) if !path.is_empty() => {
+                Some(Self::SystemdUnit(PathBuf::from(path)))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::SystemdUnit(path) => path,
+        }
+    }
+
+    pub fn with_path(&self, path: PathBuf) -> Self {
+        match self {
+            Self::SystemdUnit(_) => Self::SystemdUnit(path),
+        }
+    }
+}
+
+impl std::fmt::Display for Artifact {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SystemdUnit(path) => write!(f, "{SYSTEMD_UNIT_ARTIFACT},{}", path.display()),
+        }
+    }
+}
+
+// what a bridge tells about the pkg (the `description,<text>`... lines of its
+// output), shown in `pkg info --long`, the empty ones are `None`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PkgMetadata {
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    // a spdx expression, `MIT OR Apache-2.0`
+    pub license: Option<String>,
+    // where it was downloaded or built from
+    pub source_url: Option<String>,
+}
+
+impl PkgMetadata {
+    // a `<key>,<value>` line of the bridge output, false if it isn't one
+    pub fn parse_line(&mut self, line: &str) -> bool {
+        let Some((key, value)) = line.split_once(',') else {
+            return false;
+        };
+
+        let field = match key {
+            "description" => &mut self.description,
+            "homepage" => &mut self.homepage,
+            "license" => &mut self.license,
+            "source-url" => &mut self.source_url,
+            _ => return false,
+        };
+
+        let value = value.trim();
+        *field = (!value.is_empty()).then(|| value.to_string());
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    // the description, the homepage, the license and the source url
+    pub fn fields(&self) -> [Option<&str>; 4] {
+        [
+            self.description.as_deref(),
+            self.homepage.as_deref(),
+            self.license.as_deref(),
+            self.source_url.as_deref(),
+        ]
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Version {
     pub first_cell: String,
     pub second_cell: String,
@@ -23,12 +128,95 @@ pub struct Version {
 
 pub type Verstion = Version;
 
-#[derive(Debug)]
+// the columns of the pkg in the `packages` table, without the bridge
+fn pkg_row(pkg: &Pkg) -> Result<[String; 10]> {
+    let pkg_type = match &pkg.pkg_type {
+        PkgType::SingleExecutable => "SingleExecutable".to_string(),
+        PkgType::Directory(_) => "Directory".to_string(),
+    };
+
+    let pkg_path = pkg.path.to_str().ok_or(DbError::InvalidPath)?.to_string();
+
+    let entry_point = match &pkg.pkg_type {
+        PkgType::SingleExecutable => pkg_path.to_string(), // Convert &str to String
+        PkgType::Directory(ep) => ep.to_string_lossy().into_owned(), // Handle path conversion
+    };
+
+    let artifacts = pkg
+        .artifacts
+        .iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    Ok([
+        pkg.name.clone(),
+        pkg.version.to_string(),
+        pkg_path,
+        pkg_type,
+        entry_point,
+        artifacts,
+        // empty for none, like the other text columns
+        pkg.metadata.description.clone().unwrap_or_default(),
+        pkg.metadata.homepage.clone().unwrap_or_default(),
+        pkg.metadata.license.clone().unwrap_or_default(),
+        pkg.metadata.source_url.clone().unwrap_or_default(),
+    ])
+}
+
+impl Version {
+    // `x.y.z`
+    pub fn parse(version: &str) -> Option<Self> {
+        match version.split('.').collect::<Vec<&str>>()[..] {
+            [first, second, third] => Some(Self {
+                first_cell: first.to_string(),
+                second_cell: second.to_string(),
+                third_cell: third.to_string(),
+            }),
+            _ => None,
+        }
+    }
+
+    // cell by cell, as numbers when both are numbers
+    pub fn compare(&self, other: &Self) -> std::cmp::Ordering {
+        let cells = |v: &Self| {
+            [
+                v.first_cell.clone(),
+                v.second_cell.clone(),
+                v.third_cell.clone(),
+            ]
+        };
+
+        cells(self)
+            .iter()
+            .zip(cells(other).iter())
+            .map(|(a, b)| match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                _ => a.cmp(b),
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}",
+            self.first_cell, self.second_cell, self.third_cell
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Pkg {
     pub name: String,
     pub version: Version,
     pub path: PathBuf,
     pub pkg_type: PkgType,
+    pub artifacts: Vec<Artifact>,
+    pub metadata: PkgMetadata,
 }
 
 #[derive(Debug)]
@@ -37,6 +225,109 @@ pub struct Db {
     pub path: PathBuf,
 }
 
+#[derive(Debug, Clone)]
+pub struct PkgRecord {
+    pub pkg: Pkg,
+    pub bridge: String,
+    // unix time of the last install (or update), 0 if it's older than this column
+    pub installed_at: i64,
+    // the tags it was installed with
+    pub tags: Vec<String>,
+    // the pkgs it needs (its `deps` attribute)
+    pub deps: Vec<String>,
+    // installed only as a dependency, it's an orphan once nothing needs it
+    pub auto: bool,
+    // the version of the bridge that installed it, empty if it has none
+    pub bridge_version: String,
+}
+
+// the installed pkgs `Db::query` returns, what's `None` (or empty) doesn't
+// filter. the versions and the dates are inclusive
+#[derive(Debug, Clone, Default)]
+pub struct PkgFilter {
+    pub bridge: Option<String>,
+    pub names: Vec<String>,
+    // `*fd*`, `py?`, case sensitive (sqlite's GLOB)
+    pub name_glob: Option<String>,
+    pub min_version: Option<Version>,
+    pub max_version: Option<Version>,
+    pub pkg_type: Option<PkgKind>,
+    // unix time
+    pub installed_after: Option<i64>,
+    pub installed_before: Option<i64>,
+}
+
+// a `PkgType` without its entry point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PkgKind {
+    SingleExecutable,
+    Directory,
+}
+
+// all the installed pkgs loaded by one query, so planning doesn't need to
+// query the db for every pkg
+#[derive(Debug, Default)]
+pub struct DbSnapshot {
+    pub records: HashMap<String, PkgRecord>,
+}
+
+// how the local state differs from another machine's one
+#[derive(Debug, Default)]
+pub struct StateDiff {
+    pub only_local: Vec<Pkg>,
+    pub only_other: Vec<Pkg>,
+    // name, local version, other version
+    pub version_differs: Vec<(String, String, String)>,
+}
+
+// how the installed pkgs differ from the inputs, `pkg diff`
+#[derive(Debug, Default)]
+pub struct InputsDiff {
+    // declared and not installed: bridge, name
+    pub missing: Vec<(String, String)>,
+    // installed and declared nowhere (the adopted ones too): bridge, name, version
+    pub undeclared: Vec<(String, String, String)>,
+    // installed by another bridge than the declared one: name, installed bridge, declared bridge
+    pub bridge_differs: Vec<(String, String, String)>,
+    // pinned to another version (its `version` attribute): name, installed version, pinned version
+    pub version_drift: Vec<(String, String, String)>,
+}
+
+impl InputsDiff {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty()
+            && self.undeclared.is_empty()
+            && self.bridge_differs.is_empty()
+            && self.version_drift.is_empty()
+    }
+}
+
+// a finished build (or rebuild, update), the last ones are kept
+#[derive(Debug)]
+pub struct BuildRecord {
+    // unix time
+    pub finished_at: i64,
+    pub installed: usize,
+    pub removed: usize,
+    // the pkgs, links, hooks... that failed, the rest was done
+    pub failures: usize,
+    // 0 for the builds recorded before it was
+    pub duration: Duration,
+}
+
+// where a bridge of the bridges set came from, `pkg bridge install` records it
+#[derive(Debug, Clone, PartialEq)]
+pub struct BridgeSource {
+    pub name: String,
+    pub registry: String,
+    // empty when the registry doesn't say
+    pub version: String,
+    // the sha256 of the tarball or the commit of the registry
+    pub revision: String,
+    // unix time
+    pub installed_at: i64,
+}
+
 #[derive(Error, Debug, Diagnostic)]
 pub enum DbError {
     #[error(transparent)]
@@ -50,6 +341,150 @@ pub enum DbError {
     #[error("Invalid UTF-8 in package path")]
     #[diagnostic(code(db::invalid_utf8))]
     InvalidPath,
+
+    #[error("Package not installed: {0}")]
+    #[diagnostic(
+        code(db::pkg_not_found),
+        help("See `pkg info` for the installed packages")
+    )]
+    PkgNotFound(String),
+
+    #[error("The db {path:?} is corrupted: {problems}")]
+    #[diagnostic(
+        code(db::corrupted),
+        help(
+            "`pkg db restore latest` brings back the last automatic backup (`pkg db restore <path>` for another one)"
+        )
+    )]
+    Corrupted { path: PathBuf, problems: String },
+
+    #[error("No db backup at {0}")]
+    #[diagnostic(
+        code(db::backup_not_found),
+        help("The automatic backups are in the `<db>.backups` dir next to the db")
+    )]
+    BackupNotFound(PathBuf),
+
+    #[error("Invalid state file {path:?}: {error}")]
+    #[diagnostic(
+        code(db::bad_state_file),
+        help("It's written by pkg, restore it from a backup or remove it to start over")
+    )]
+    BadStateFile { path: PathBuf, error: String },
+}
+
+// the automatic backups taken before the commands that change the db
+pub const KEPT_BACKUPS: usize = 5;
+
+// `<db>.backups`, next to the db
+pub fn backups_dir(db_path: &Path) -> PathBuf {
+    db_path.with_extension("backups")
+}
+
+// a copy of the db as it is now in `<db>.backups/<millis>.db`, the oldest
+// are removed to keep only `keep` of them. it's taken before the db is opened
+// (and migrated), and nothing to back up the first time
+pub fn backup_rotating(db_path: &Path, keep: usize) -> Result<Option<PathBuf>> {
+    if !db_path.is_file() {
+        return Ok(None);
+    }
+
+    let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    // a corrupted copy would rotate a good backup out
+    check_integrity(&conn, db_path)?;
+
+    let dir = backups_dir(db_path);
+    std::fs::create_dir_all(&dir)?;
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let dest = dir.join(format!("{millis}.db"));
+
+    conn.backup(MAIN_DB, &dest, None)?;
+
+    let mut backups = list_backups(db_path)?;
+    while backups.len() > keep {
+        std::fs::remove_file(backups.remove(0))?;
+    }
+
+    Ok(Some(dest))
+}
+
+// `quick_check` reads every page but doesn't match the indexes with their
+// tables, it's fast enough to run every time the db is opened
+fn check_integrity(conn: &Connection, path: &Path) -> Result<()> {
+    let corrupted = |problems: String| DbError::Corrupted {
+        path: path.to_path_buf(),
+        problems,
+    };
+
+    let problems = conn
+        .prepare("PRAGMA quick_check;")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()
+        })
+        .map_err(|err| match err.sqlite_error_code() {
+            // a file that isn't a db at all, or a header that's broken
+            Some(rusqlite::ErrorCode::NotADatabase | rusqlite::ErrorCode::DatabaseCorrupt) => {
+                corrupted(err.to_string())
+            }
+            _ => err.into(),
+        })?;
+
+    match problems.as_slice() {
+        [ok] if ok == "ok" => Ok(()),
+        _ => Err(corrupted(problems.join(", "))),
+    }
+}
+
+// the automatic backups, the oldest first
+pub fn list_backups(db_path: &Path) -> Result<Vec<PathBuf>> {
+    let dir = backups_dir(db_path);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let millis = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse::<u128>().ok());
+
+        if let Some(millis) = millis
+            && path.extension().is_some_and(|e| e == "db")
+        {
+            backups.push((millis, path));
+        }
+    }
+    backups.sort();
+
+    Ok(backups.into_iter().map(|(_, path)| path).collect())
+}
+
+// every connection opened after `enable_tracing` sends its statements to
+// `tracing` (the `db` target), the cli prints them on stderr
+static TRACE_SQL: AtomicBool = AtomicBool::new(false);
+
+pub fn enable_tracing() {
+    TRACE_SQL.store(true, Ordering::Relaxed);
+}
+
+fn trace_sql(event: TraceEvent<'_>) {
+    if let TraceEvent::Profile(stmt, duration) = event {
+        let sql = stmt
+            .expanded_sql()
+            .unwrap_or_else(|| stmt.sql().into_owned());
+        // one line per statement, the sql consts are indented multi lines strings
+        let sql = sql.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+        tracing::trace!(target: "db", "{:>9.3}ms {}", duration.as_secs_f64() * 1000.0, sql);
+    }
 }
 
 mod sql {
@@ -61,23 +496,127 @@ mod sql {
         pkg_type TEXT NOT NULL,
         entry_point TEXT NOT NULL,
         bridge TEXT NOT NULL,
+        installed_at INTEGER NOT NULL DEFAULT 0,
+        tags TEXT NOT NULL DEFAULT '',
+        pre_remove TEXT NOT NULL DEFAULT '',
+        artifacts TEXT NOT NULL DEFAULT '',
+        description TEXT NOT NULL DEFAULT '',
+        homepage TEXT NOT NULL DEFAULT '',
+        license TEXT NOT NULL DEFAULT '',
+        source_url TEXT NOT NULL DEFAULT '',
+        deps TEXT NOT NULL DEFAULT '',
+        auto INTEGER NOT NULL DEFAULT 0,
+        bridge_version TEXT NOT NULL DEFAULT '',
         PRIMARY KEY (name)
     );
     "#; // NOTE: installing a package twice with or without a deficient version are not allowd in this implementing. and this is just my decision
+    // the dbs created before a column was added
+    pub const HAS_PKGS_COLUMN: &str = r#"
+    SELECT COUNT(*) FROM pragma_table_info('packages') WHERE name = ?;
+    "#;
+    pub const ADD_INSTALLED_AT_COLUMN: &str = r#"
+    ALTER TABLE packages ADD COLUMN installed_at INTEGER NOT NULL DEFAULT 0;
+    "#;
+    pub const ADD_TAGS_COLUMN: &str = r#"
+    ALTER TABLE packages ADD COLUMN tags TEXT NOT NULL DEFAULT '';
+    "#;
+    pub const ADD_PRE_REMOVE_COLUMN: &str = r#"
+    ALTER TABLE packages ADD COLUMN pre_remove TEXT NOT NULL DEFAULT '';
+    "#;
+    pub const CREATE_PKGS_VIEW: &str = r#"
+    CREATE TEMP VIEW packages AS SELECT *, {} FROM main.packages;
+    "#;
+    // stored one per line, `<class>,<path>`
+    pub const ADD_ARTIFACTS_COLUMN: &str = r#"
+    ALTER TABLE packages ADD COLUMN artifacts TEXT NOT NULL DEFAULT '';
+    "#;
+    // the metadata the bridges give, empty for none
+    pub const ADD_DESCRIPTION_COLUMN: &str = r#"
+    ALTER TABLE packages ADD COLUMN description TEXT NOT NULL DEFAULT '';
+    "#;
+    pub const ADD_HOMEPAGE_COLUMN: &str = r#"
+    ALTER TABLE packages ADD COLUMN homepage TEXT NOT NULL DEFAULT '';
+    "#;
+    pub const ADD_LICENSE_COLUMN: &str = r#"
+    ALTER TABLE packages ADD COLUMN license TEXT NOT NULL DEFAULT '';
+    "#;
+    pub const ADD_SOURCE_URL_COLUMN: &str = r#"
+    ALTER TABLE packages ADD COLUMN source_url TEXT NOT NULL DEFAULT '';
+    "#;
+    // the deps are stored comma separated, like the tags
+    pub const ADD_DEPS_COLUMN: &str = r#"
+    ALTER TABLE packages ADD COLUMN deps TEXT NOT NULL DEFAULT '';
+    "#;
+    pub const ADD_AUTO_COLUMN: &str = r#"
+    ALTER TABLE packages ADD COLUMN auto INTEGER NOT NULL DEFAULT 0;
+    "#;
+    pub const ADD_BRIDGE_VERSION_COLUMN: &str = r#"
+    ALTER TABLE packages ADD COLUMN bridge_version TEXT NOT NULL DEFAULT '';
+    "#;
+    // the commands are stored one per line
+    pub const SET_PKG_PRE_REMOVE: &str = r#"
+    UPDATE packages SET pre_remove = ?1 WHERE name = ?2;
+    "#;
+    pub const GET_PKG_PRE_REMOVE: &str = r#"
+    SELECT pre_remove FROM packages WHERE name = ?1;
+    "#;
+    // the tags are stored comma separated
+    pub const SET_PKG_TAGS: &str = r#"
+    UPDATE packages SET tags = ?1 WHERE name = ?2;
+    "#;
+    pub const SET_PKG_DEPS: &str = r#"
+    UPDATE packages SET deps = ?1, auto = ?2 WHERE name = ?3;
+    "#;
+    pub const SET_PKG_BRIDGE_VERSION: &str = r#"
+    UPDATE packages SET bridge_version = ?1 WHERE name = ?2;
+    "#;
+    // the rest of a record `StateStore::insert` is given
+    pub const SET_PKG_RECORD: &str = r#"
+    UPDATE packages SET installed_at = ?1, tags = ?2, deps = ?3, auto = ?4, bridge_version = ?5
+    WHERE name = ?6;
+    "#;
     pub const GET_PKGS: &str = r#"
-    SELECT name, version, path, pkg_type, entry_point FROM packages;
+    SELECT name, version, path, pkg_type, entry_point, artifacts, description, homepage, license, source_url FROM packages;
+    "#;
+
+    // the conditions of a `PkgFilter` are joined with AND
+    pub const QUERY_PKGS: &str = r#"
+    SELECT name, version, path, pkg_type, entry_point, artifacts, description, homepage, license, source_url, bridge, installed_at, tags, deps, auto, bridge_version FROM packages
+    WHERE {} ORDER BY name;
+    "#;
+
+    pub const GET_INSTALLED_NAMES: &str = r#"
+    SELECT name FROM packages WHERE name IN ({});
     "#;
 
-    pub const GET_PKGS_BY_NAME: &str = r#"
-    SELECT name, version, path, pkg_type FROM packages WHERE name = ?;
+    pub const GET_PKGS_WITH_BRIDGE: &str = r#"
+    SELECT name, version, path, pkg_type, entry_point, artifacts, description, homepage, license, source_url, bridge, installed_at, tags, deps, auto, bridge_version FROM packages;
     "#;
 
     pub const GET_PKGS_BY_NAMES: &str = r#"
-    SELECT name, version, path, pkg_type, entry_point FROM packages WHERE name IN ({});
+    SELECT name, version, path, pkg_type, entry_point, artifacts, description, homepage, license, source_url FROM packages WHERE name IN ({});
     "#;
     pub const INSERT_PKGS: &str = r#"
-    INSERT INTO packages (name, version, path, pkg_type, entry_point, bridge)
-    VALUES (?, ?, ?, ?, ?, ?);
+    INSERT INTO packages (name, version, path, pkg_type, entry_point, artifacts, description, homepage, license, source_url, bridge, installed_at)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, unixepoch());
+    "#;
+    // the row is replaced in place, it's never missing (the tags, the hooks
+    // and the cache keys of the pkg stay)
+    pub const UPDATE_PKG: &str = r#"
+    INSERT INTO packages (name, version, path, pkg_type, entry_point, artifacts, description, homepage, license, source_url, bridge, installed_at)
+    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, unixepoch())
+    ON CONFLICT (name) DO UPDATE SET
+        version = excluded.version,
+        path = excluded.path,
+        pkg_type = excluded.pkg_type,
+        entry_point = excluded.entry_point,
+        artifacts = excluded.artifacts,
+        description = excluded.description,
+        homepage = excluded.homepage,
+        license = excluded.license,
+        source_url = excluded.source_url,
+        bridge = excluded.bridge,
+        installed_at = excluded.installed_at;
     "#;
     pub const DELETE_PKGS: &str = r#"
     DELETE FROM packages WHERE name = ?;
@@ -86,11 +625,397 @@ mod sql {
     SELECT bridge FROM packages WHERE name = ?;
     "#;
     pub const GET_PKGS_BY_BRIDGE: &str = r#"
-    SELECT name, version, path, pkg_type, entry_point FROM packages WHERE bridge = ?;
+    SELECT name, version, path, pkg_type, entry_point, artifacts, description, homepage, license, source_url FROM packages WHERE bridge = ?;
     "#;
     pub const GET_BRIDGES: &str = r#"
     SELECT bridge FROM packages GROUP BY bridge;
     "#;
+
+    pub const CREATE_DURATIONS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS durations (
+        name TEXT NOT NULL,
+        operation TEXT NOT NULL,
+        duration_ms INTEGER NOT NULL
+    );
+    "#;
+    pub const INSERT_DURATION: &str = r#"
+    INSERT INTO durations (name, operation, duration_ms) VALUES (?1, ?2, ?3);
+    "#;
+    // keep only the last few runs so the estimate follows the package as it changes
+    pub const PRUNE_DURATIONS: &str = r#"
+    DELETE FROM durations WHERE name = ?1 AND operation = ?2 AND rowid NOT IN (
+        SELECT rowid FROM durations WHERE name = ?1 AND operation = ?2
+        ORDER BY rowid DESC LIMIT 5
+    );
+    "#;
+    pub const GET_AVERAGE_DURATIONS: &str = r#"
+    SELECT name, AVG(duration_ms) FROM durations WHERE operation = ? GROUP BY name;
+    "#;
+
+    pub const CREATE_CACHE_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS operation_cache (
+        name TEXT NOT NULL,
+        operation TEXT NOT NULL,
+        key TEXT NOT NULL,
+        PRIMARY KEY (name, operation)
+    );
+    "#;
+    pub const SET_CACHE_KEY: &str = r#"
+    INSERT OR REPLACE INTO operation_cache (name, operation, key) VALUES (?1, ?2, ?3);
+    "#;
+    pub const GET_CACHE_KEY: &str = r#"
+    SELECT key FROM operation_cache WHERE name = ?1 AND operation = ?2;
+    "#;
+    pub const DELETE_CACHE_KEYS: &str = r#"
+    DELETE FROM operation_cache WHERE name = ?;
+    "#;
+
+    pub const CREATE_BUILDS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS builds (
+        finished_at INTEGER NOT NULL,
+        installed INTEGER NOT NULL,
+        removed INTEGER NOT NULL,
+        failures INTEGER NOT NULL DEFAULT 0,
+        duration_ms INTEGER NOT NULL DEFAULT 0
+    );
+    "#;
+    pub const HAS_BUILDS_COLUMN: &str = r#"
+    SELECT COUNT(*) FROM pragma_table_info('builds') WHERE name = ?;
+    "#;
+    pub const ADD_BUILD_FAILURES_COLUMN: &str = r#"
+    ALTER TABLE builds ADD COLUMN failures INTEGER NOT NULL DEFAULT 0;
+    "#;
+    pub const ADD_BUILD_DURATION_COLUMN: &str = r#"
+    ALTER TABLE builds ADD COLUMN duration_ms INTEGER NOT NULL DEFAULT 0;
+    "#;
+    pub const INSERT_BUILD: &str = r#"
+    INSERT INTO builds (finished_at, installed, removed, failures, duration_ms)
+    VALUES (unixepoch(), ?1, ?2, ?3, ?4);
+    "#;
+    pub const PRUNE_BUILDS: &str = r#"
+    DELETE FROM builds WHERE rowid NOT IN (
+        SELECT rowid FROM builds ORDER BY rowid DESC LIMIT 20
+    );
+    "#;
+    pub const GET_LAST_BUILD: &str = r#"
+    SELECT finished_at, installed, removed, failures, duration_ms FROM builds ORDER BY rowid DESC LIMIT 1;
+    "#;
+
+    pub const CREATE_BRIDGE_SOURCES_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS bridge_sources (
+        name TEXT PRIMARY KEY,
+        registry TEXT NOT NULL,
+        version TEXT NOT NULL DEFAULT '',
+        revision TEXT NOT NULL DEFAULT '',
+        installed_at INTEGER NOT NULL
+    );
+    "#;
+    pub const SET_BRIDGE_SOURCE: &str = r#"
+    INSERT OR REPLACE INTO bridge_sources (name, registry, version, revision, installed_at)
+    VALUES (?1, ?2, ?3, ?4, unixepoch());
+    "#;
+    pub const GET_BRIDGE_SOURCES: &str = r#"
+    SELECT name, registry, version, revision, installed_at FROM bridge_sources ORDER BY name;
+    "#;
+}
+
+// a path in a `file:` uri, `?` and `#` would end it
+fn uri_escape(path: &str) -> String {
+    path.replace('%', "%25")
+        .replace('?', "%3f")
+        .replace('#', "%23")
+}
+
+// the tables and the columns a newer pkg added, what a db of an older one
+// is missing. it can run on every open
+fn migrate(conn: &Connection) -> Result<()> {
+    conn.execute(sql::CREATE_PKGS_TABLE, [])?;
+
+    for (column, add_column) in [
+        ("installed_at", sql::ADD_INSTALLED_AT_COLUMN),
+        ("tags", sql::ADD_TAGS_COLUMN),
+        ("pre_remove", sql::ADD_PRE_REMOVE_COLUMN),
+        ("artifacts", sql::ADD_ARTIFACTS_COLUMN),
+        ("description", sql::ADD_DESCRIPTION_COLUMN),
+        ("homepage", sql::ADD_HOMEPAGE_COLUMN),
+        ("license", sql::ADD_LICENSE_COLUMN),
+        ("source_url", sql::ADD_SOURCE_URL_COLUMN),
+        ("deps", sql::ADD_DEPS_COLUMN),
+        ("auto", sql::ADD_AUTO_COLUMN),
+        ("bridge_version", sql::ADD_BRIDGE_VERSION_COLUMN),
+    ] {
+        let has_column: bool = conn.query_row(sql::HAS_PKGS_COLUMN, [column], |row| row.get(0))?;
+        if !has_column {
+            conn.execute(add_column, [])?;
+        }
+    }
+    conn.execute(sql::CREATE_DURATIONS_TABLE, [])?;
+    conn.execute(sql::CREATE_BUILDS_TABLE, [])?;
+    for (column, add_column) in [
+        ("failures", sql::ADD_BUILD_FAILURES_COLUMN),
+        ("duration_ms", sql::ADD_BUILD_DURATION_COLUMN),
+    ] {
+        let has_column: bool =
+            conn.query_row(sql::HAS_BUILDS_COLUMN, [column], |row| row.get(0))?;
+        if !has_column {
+            conn.execute(add_column, [])?;
+        }
+    }
+    conn.execute(sql::CREATE_CACHE_TABLE, [])?;
+    conn.execute(sql::CREATE_BRIDGE_SOURCES_TABLE, [])?;
+
+    Ok(())
+}
+
+// the columns of `pkg_from_row`, then bridge, installed_at, tags, deps, auto
+// and bridge_version
+fn record_from_row(row: &rusqlite::Row) -> rusqlite::Result<PkgRecord> {
+    let list = |index: usize| -> rusqlite::Result<Vec<String>> {
+        Ok(row
+            .get::<_, String>(index)?
+            .split(',')
+            .filter(|item| !item.is_empty())
+            .map(|item| item.to_string())
+            .collect())
+    };
+
+    Ok(PkgRecord {
+        pkg: pkg_from_row(row)?,
+        bridge: row.get(10)?,
+        installed_at: row.get(11)?,
+        tags: list(12)?,
+        deps: list(13)?,
+        auto: row.get(14)?,
+        bridge_version: row.get(15)?,
+    })
+}
+
+// the columns should be in this order: name, version, path, pkg_type, entry_point, artifacts,
+// description, homepage, license, source_url
+fn pkg_from_row(row: &rusqlite::Row) -> rusqlite::Result<Pkg> {
+    let name: String = row.get(0)?;
+    let version: String = row.get(1)?;
+    let path: String = row.get(2)?;
+    let pkg_type: String = row.get(3)?;
+    let entry_point: String = row.get(4)?;
+    let artifacts: String = row.get(5)?;
+
+    // Parse version string into components
+    let version_parts: Vec<&str> = version.split('.').collect();
+    if version_parts.len() != 3 {
+        return Err(RusqliteError::InvalidQuery);
+    }
+
+    // Parse package type
+    let pkg_type = match pkg_type.as_str() {
+        "SingleExecutable" => PkgType::SingleExecutable,
+        "Directory" => PkgType::Directory(PathBuf::from(&entry_point)),
+        _ => return Err(RusqliteError::InvalidQuery),
+    };
+
+    Ok(Pkg {
+        name,
+        version: Version {
+            first_cell: version_parts[0].to_string(),
+            second_cell: version_parts[1].to_string(),
+            third_cell: version_parts[2].to_string(),
+        },
+        path: PathBuf::from(path),
+        pkg_type,
+        // the unknown ones are from a newer pkg
+        artifacts: artifacts.lines().filter_map(Artifact::parse).collect(),
+        metadata: PkgMetadata {
+            description: optional_text(row.get(6)?),
+            homepage: optional_text(row.get(7)?),
+            license: optional_text(row.get(8)?),
+            source_url: optional_text(row.get(9)?),
+        },
+    })
+}
+
+fn optional_text(text: String) -> Option<String> {
+    (!text.is_empty()).then_some(text)
+}
+
+impl PkgFilter {
+    // the filter outside of sql, for the stores that aren't a db
+    pub fn matches(&self, record: &PkgRecord) -> bool {
+        let kind = match record.pkg.pkg_type {
+            PkgType::SingleExecutable => PkgKind::SingleExecutable,
+            PkgType::Directory(_) => PkgKind::Directory,
+        };
+
+        self.bridge.as_ref().is_none_or(|b| *b == record.bridge)
+            && (self.names.is_empty() || self.names.contains(&record.pkg.name))
+            && self.name_glob.as_ref().is_none_or(|glob| {
+                glob::Pattern::new(glob).is_ok_and(|p| p.matches(&record.pkg.name))
+            })
+            && self.pkg_type.is_none_or(|t| t == kind)
+            && self
+                .installed_after
+                .is_none_or(|t| record.installed_at >= t)
+            && self
+                .installed_before
+                .is_none_or(|t| record.installed_at <= t)
+            && self.matches_version(&record.pkg.version)
+    }
+
+    pub fn matches_version(&self, version: &Version) -> bool {
+        self.min_version
+            .as_ref()
+            .is_none_or(|min| version.compare(min).is_ge())
+            && self
+                .max_version
+                .as_ref()
+                .is_none_or(|max| version.compare(max).is_le())
+    }
+}
+
+impl DbSnapshot {
+    pub fn is_installed(&self, pkg_name: &str) -> bool {
+        self.records.contains_key(pkg_name)
+    }
+
+    pub fn get(&self, pkg_name: &str) -> Option<&PkgRecord> {
+        self.records.get(pkg_name)
+    }
+
+    // sorted by name, so plans are the same from a run to another
+    pub fn pkgs_by_bridge(&self, bridge_name: &str) -> Vec<&PkgRecord> {
+        let mut records = self
+            .records
+            .values()
+            .filter(|r| r.bridge == bridge_name)
+            .collect::<Vec<&PkgRecord>>();
+
+        records.sort_by(|a, b| a.pkg.name.cmp(&b.pkg.name));
+        records
+    }
+
+    // the declared pkgs with their bridge, sorted by name like the rest of the diff
+    pub fn diff_inputs<'a>(
+        &self,
+        declared: impl IntoIterator<Item = (&'a str, &'a PkgDeclaration)>,
+    ) -> InputsDiff {
+        let mut diff = InputsDiff::default();
+        let mut declared_names = HashSet::new();
+
+        for (bridge, pkg) in declared {
+            declared_names.insert(pkg.name.as_str());
+
+            let Some(record) = self.get(&pkg.name) else {
+                diff.missing.push((bridge.to_string(), pkg.name.clone()));
+                continue;
+            };
+
+            if record.bridge != bridge {
+                diff.bridge_differs.push((
+                    pkg.name.clone(),
+                    record.bridge.clone(),
+                    bridge.to_string(),
+                ));
+            }
+
+            if let Some(AttributeValue::String(pinned)) = pkg.attributes.get("version") {
+                let drifted = match Version::parse(pinned) {
+                    Some(version) => version.compare(&record.pkg.version).is_ne(),
+                    None => pinned != &record.pkg.version.to_string(),
+                };
+                if drifted {
+                    diff.version_drift.push((
+                        pkg.name.clone(),
+                        record.pkg.version.to_string(),
+                        pinned.clone(),
+                    ));
+                }
+            }
+        }
+
+        for record in self.records.values() {
+            if !declared_names.contains(record.pkg.name.as_str()) {
+                diff.undeclared.push((
+                    record.bridge.clone(),
+                    record.pkg.name.clone(),
+                    record.pkg.version.to_string(),
+                ));
+            }
+        }
+
+        diff.missing.sort_by(|a, b| a.1.cmp(&b.1));
+        diff.undeclared.sort_by(|a, b| a.1.cmp(&b.1));
+        diff.bridge_differs.sort();
+        diff.version_drift.sort();
+        diff
+    }
+
+    // the `auto` pkgs no other pkg needs anymore, by the `deps` they were
+    // installed with. the deps of an orphan are orphans too
+    pub fn orphans(&self) -> Vec<&PkgRecord> {
+        let mut needed = HashSet::new();
+        let mut queue = self
+            .records
+            .values()
+            .filter(|r| !r.auto)
+            .collect::<Vec<&PkgRecord>>();
+        while let Some(record) = queue.pop() {
+            for dep in &record.deps {
+                if needed.insert(dep.as_str())
+                    && let Some(dep) = self.get(dep)
+                    && dep.auto
+                {
+                    queue.push(dep);
+                }
+            }
+        }
+
+        let mut orphans = self
+            .records
+            .values()
+            .filter(|r| r.auto && !needed.contains(r.pkg.name.as_str()))
+            .collect::<Vec<&PkgRecord>>();
+        orphans.sort_by(|a, b| a.pkg.name.cmp(&b.pkg.name));
+        orphans
+    }
+
+    // the pkgs of the bridge installed by another major version of it than
+    // `version`, the ones installed without a version aren't compared
+    pub fn installed_by_other_major(&self, bridge_name: &str, version: &str) -> Vec<&PkgRecord> {
+        let mut records = self
+            .pkgs_by_bridge(bridge_name)
+            .into_iter()
+            .filter(|r| crate::manifest::is_major_change(&r.bridge_version, version))
+            .collect::<Vec<&PkgRecord>>();
+        records.sort_by(|a, b| a.pkg.name.cmp(&b.pkg.name));
+        records
+    }
+
+    // the deps that aren't installed: pkg, dep
+    pub fn broken_deps(&self) -> Vec<(String, String)> {
+        let mut broken = self
+            .records
+            .values()
+            .flat_map(|r| {
+                r.deps
+                    .iter()
+                    .filter(|dep| !self.is_installed(dep))
+                    .map(|dep| (r.pkg.name.clone(), dep.clone()))
+            })
+            .collect::<Vec<(String, String)>>();
+        broken.sort();
+        broken
+    }
+
+    pub fn bridges(&self) -> Vec<String> {
+        let mut bridges = self
+            .records
+            .values()
+            .map(|r| r.bridge.clone())
+            .collect::<Vec<String>>();
+
+        bridges.sort();
+        bridges.dedup();
+        bridges
+    }
 }
 
 impl Pkg {
@@ -101,6 +1026,8 @@ impl Pkg {
             name: self.name.clone(),
             input: self.path.to_str().unwrap().to_string(),
             attributes: HashMap::new(),
+            tags: Vec::new(),
+            bridge: None,
         }
     }
 }
@@ -108,11 +1035,24 @@ impl Pkg {
 impl Db {
     pub fn new(path: &PathBuf) -> Result<Self> {
         let parent = path.parent().ok_or(DbError::InvalidPath)?;
-        std::fs::create_dir_all(parent).into_diagnostic()?;
+        std::fs::create_dir_all(parent)?;
 
-        let conn = Connection::open(path).into_diagnostic()?;
+        let conn = Connection::open(path)?;
 
-        conn.execute(sql::CREATE_PKGS_TABLE, []).into_diagnostic()?;
+        if TRACE_SQL.load(Ordering::Relaxed) {
+            conn.trace_v2(TraceEventCodes::SQLITE_TRACE_PROFILE, Some(trace_sql));
+        }
+
+        // wait for a concurrent writer instead of failing right away
+        conn.busy_timeout(Duration::from_secs(5))?;
+        check_integrity(&conn, path)?;
+        // the readers don't wait for the writer, and with the wal a commit
+        // only syncs at the checkpoints, it can't corrupt the db
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+
+        migrate(&conn)?;
 
         Ok(Self {
             conn,
@@ -120,32 +1060,257 @@ impl Db {
         })
     }
 
+    // one more connection to a db `new` already checked and migrated (the
+    // async api opens one per worker), only the pragmas of the connection
+    pub fn reopen(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)?;
+
+        if TRACE_SQL.load(Ordering::Relaxed) {
+            conn.trace_v2(TraceEventCodes::SQLITE_TRACE_PROFILE, Some(trace_sql));
+        }
+
+        conn.busy_timeout(Duration::from_secs(5))?;
+        // the wal mode is kept in the file, these two are not
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+
+        Ok(Self {
+            conn,
+            path: path.to_path_buf(),
+        })
+    }
+
+    // for dbs that are not ours (e.g. exported from another machine), so it
+    // never creates or migrates anything
+    pub fn open_read_only(path: &Path) -> Result<Self> {
+        let open = |path: &str, flags: OpenFlags| -> Result<Connection> {
+            let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY | flags)?;
+
+            if TRACE_SQL.load(Ordering::Relaxed) {
+                conn.trace_v2(TraceEventCodes::SQLITE_TRACE_PROFILE, Some(trace_sql));
+            }
+
+            conn.busy_timeout(Duration::from_secs(5))?;
+            Ok(conn)
+        };
+        let path_str = path.to_str().ok_or(DbError::InvalidPath)?;
+
+        // the db of root, read by a user: the wal index (`-shm`) can't be
+        // created in its dir, so it's read as immutable. it's the last
+        // checkpoint, what a running build writes isn't seen
+        let mut conn = open(path_str, OpenFlags::empty())?;
+        match check_integrity(&conn, path) {
+            Err(DbError::SqliteError(err))
+                if err.sqlite_error_code() == Some(rusqlite::ErrorCode::CannotOpen) =>
+            {
+                conn = open(
+                    &format!("file:{}?immutable=1", uri_escape(path_str)),
+                    OpenFlags::SQLITE_OPEN_URI,
+                )?;
+                check_integrity(&conn, path)?;
+            }
+            checked => checked?,
+        }
+
+        // the columns added later are read from a temp view with their
+        // defaults (the temp schema comes first), the db itself isn't touched
+        let mut missing = Vec::new();
+        for (column, default) in [
+            ("installed_at", "0"),
+            ("tags", "''"),
+            ("pre_remove", "''"),
+            ("artifacts", "''"),
+            ("description", "''"),
+            ("homepage", "''"),
+            ("license", "''"),
+            ("source_url", "''"),
+            ("deps", "''"),
+            ("auto", "0"),
+            ("bridge_version", "''"),
+        ] {
+            let has_column: bool =
+                conn.query_row(sql::HAS_PKGS_COLUMN, [column], |row| row.get(0))?;
+            if !has_column {
+                missing.push(format!("{default} AS {column}"));
+            }
+        }
+        if !missing.is_empty() {
+            conn.execute(
+                &sql::CREATE_PKGS_VIEW.replace("{}", &missing.join(", ")),
+                [],
+            )?;
+        }
+
+        Ok(Self {
+            conn,
+            path: path.to_path_buf(),
+        })
+    }
+
+    // a consistent copy even while another pkg reads the db
+    pub fn backup(&self, dest: &Path) -> Result<()> {
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        self.conn.backup(MAIN_DB, dest, None)?;
+        Ok(())
+    }
+
+    // the db as it is, not checked, created or migrated: what `restore`
+    // needs when the db is corrupted
+    pub fn open_unchecked(path: &PathBuf) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+
+        Ok(Self {
+            conn,
+            path: path.clone(),
+        })
+    }
+
+    // the space of the removed pkgs back to the fs, the wal too
+    pub fn vacuum(&self) -> Result<()> {
+        self.conn.execute_batch("VACUUM; PRAGMA optimize;")?;
+        self.conn
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE);", [], |_| Ok(()))?;
+
+        Ok(())
+    }
+
+    // replaces everything in the db with the backup
+    pub fn restore(&mut self, src: &Path) -> Result<()> {
+        // sqlite would create an empty one and restore it
+        if !src.is_file() {
+            return Err(DbError::BackupNotFound(src.to_path_buf()));
+        }
+
+        match self.conn.restore(MAIN_DB, src, None::<fn(Progress)>) {
+            // a corrupted db can't be restored over, it's emptied first
+            Err(err) if err.sqlite_error_code() == Some(rusqlite::ErrorCode::NotADatabase) => {
+                std::fs::File::create(&self.path)?;
+                self.conn = Connection::open(&self.path)?;
+                self.conn.busy_timeout(Duration::from_secs(5))?;
+                self.conn.restore(MAIN_DB, src, None::<fn(Progress)>)?;
+            }
+            result => result?,
+        }
+
+        Ok(())
+    }
+
+    pub fn compare(&self, other: &Db) -> Result<StateDiff> {
+        let local_pkgs = self.get_pkgs()?;
+        let mut other_pkgs = other.get_pkgs()?;
+
+        let mut diff = StateDiff::default();
+
+        for pkg in local_pkgs {
+            match other_pkgs.iter().position(|p| p.name == pkg.name) {
+                Some(index) => {
+                    let other_pkg = other_pkgs.remove(index);
+                    let (local_version, other_version) =
+                        (pkg.version.to_string(), other_pkg.version.to_string());
+
+                    if local_version != other_version {
+                        diff.version_differs
+                            .push((pkg.name, local_version, other_version));
+                    }
+                }
+                None => diff.only_local.push(pkg),
+            }
+        }
+
+        diff.only_other = other_pkgs;
+
+        Ok(diff)
+    }
+
+    pub fn snapshot(&self) -> Result<DbSnapshot> {
+        let mut stmt = self.conn.prepare_cached(sql::GET_PKGS_WITH_BRIDGE)?;
+
+        let rows = stmt.query_map([], record_from_row)?;
+
+        let mut records = HashMap::new();
+        for record in rows {
+            let record = record?;
+            records.insert(record.pkg.name.clone(), record);
+        }
+
+        Ok(DbSnapshot { records })
+    }
+
+    // the installed pkgs that match the filter, by name. the versions aren't
+    // comparable in sql, they're filtered after
+    pub fn query(&self, filter: &PkgFilter) -> Result<Vec<PkgRecord>> {
+        use rusqlite::types::Value;
+
+        let mut conditions = vec!["1".to_string()];
+        let mut params = Vec::new();
+
+        if let Some(bridge) = &filter.bridge {
+            conditions.push("bridge = ?".to_string());
+            params.push(Value::Text(bridge.clone()));
+        }
+        if !filter.names.is_empty() {
+            let placeholders = filter.names.iter().map(|_| "?").collect::<Vec<_>>();
+            conditions.push(format!("name IN ({})", placeholders.join(",")));
+            params.extend(filter.names.iter().cloned().map(Value::Text));
+        }
+        if let Some(glob) = &filter.name_glob {
+            conditions.push("name GLOB ?".to_string());
+            params.push(Value::Text(glob.clone()));
+        }
+        if let Some(kind) = filter.pkg_type {
+            conditions.push("pkg_type = ?".to_string());
+            params.push(Value::Text(
+                match kind {
+                    PkgKind::SingleExecutable => "SingleExecutable",
+                    PkgKind::Directory => "Directory",
+                }
+                .to_string(),
+            ));
+        }
+        if let Some(after) = filter.installed_after {
+            conditions.push("installed_at >= ?".to_string());
+            params.push(Value::Integer(after));
+        }
+        if let Some(before) = filter.installed_before {
+            conditions.push("installed_at <= ?".to_string());
+            params.push(Value::Integer(before));
+        }
+
+        let sql = sql::QUERY_PKGS.replace("{}", &conditions.join(" AND "));
+        let mut stmt = self.conn.prepare(&sql)?;
+        let records = stmt
+            .query_map(rusqlite::params_from_iter(params), record_from_row)?
+            .collect::<rusqlite::Result<Vec<PkgRecord>>>()?;
+
+        Ok(records
+            .into_iter()
+            .filter(|r| filter.matches_version(&r.pkg.version))
+            .collect())
+    }
+
     pub fn get_bridges(&self) -> Result<Vec<String>> {
-        let mut stmt = self.conn.prepare(sql::GET_BRIDGES).into_diagnostic()?;
-        let rows = stmt
-            .query_map([], |row| {
-                let bridge: String = row.get(0)?;
-                Ok(bridge)
-            })
-            .into_diagnostic()?;
+        let mut stmt = self.conn.prepare_cached(sql::GET_BRIDGES)?;
+        let rows = stmt.query_map([], |row| {
+            let bridge: String = row.get(0)?;
+            Ok(bridge)
+        })?;
 
         let mut bridges = Vec::new();
 
         for bridge in rows {
-            bridges.push(bridge.into_diagnostic()?);
+            bridges.push(bridge?);
         }
         Ok(bridges)
     }
 
     pub fn get_pkg_bridge_by_name(&self, pkg_name: &str) -> Result<String> {
-        let mut stmt = self
-            .conn
-            .prepare(sql::GET_PKG_BRIDGE_BY_NAME)
-            .into_diagnostic()?;
+        let mut stmt = self.conn.prepare_cached(sql::GET_PKG_BRIDGE_BY_NAME)?;
 
-        let bridge = stmt
-            .query_row([&pkg_name], |row| row.get(0))
-            .into_diagnostic()?;
+        let bridge = stmt.query_row([&pkg_name], |row| row.get(0))?;
 
         Ok(bridge)
     }
@@ -153,104 +1318,135 @@ impl Db {
     // wiil to be clean i don't understand everything here because my code make a lifetime
     // error so ai fix it with this code that has this weird 'a syntax
     pub fn which_pkgs_are_installed<'a>(&'a self, pkgs: &'a [String]) -> Result<Vec<&'a String>> {
-        let mut installed_pkgs = Vec::new();
-        let mut stmt = self.conn.prepare(sql::GET_PKGS_BY_NAME).into_diagnostic()?;
+        let installed_names = self.get_installed_names(pkgs)?;
 
-        for pkg in pkgs {
-            // We only care if the query returns any rows, not the actual data
-            let exists = stmt.exists([&pkg]).into_diagnostic()?;
-            if exists {
-                installed_pkgs.push(pkg);
-            }
+        Ok(pkgs
+            .iter()
+            .filter(|pkg| installed_names.contains(*pkg))
+            .collect())
+    }
+
+    // one `IN (...)` query instead of one query per pkg
+    fn get_installed_names(&self, pkgs: &[String]) -> Result<HashSet<String>> {
+        if pkgs.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let placeholders = pkgs.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+        let sql = sql::GET_INSTALLED_NAMES.replace("{}", &placeholders);
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(pkgs.iter()), |row| {
+            row.get::<_, String>(0)
+        })?;
+
+        let mut names = HashSet::new();
+        for name in rows {
+            names.insert(name?);
         }
 
-        Ok(installed_pkgs)
+        Ok(names)
+    }
+
+    pub fn set_pkg_tags(&self, pkg_name: &str, tags: &[String]) -> Result<()> {
+        self.conn
+            .execute(sql::SET_PKG_TAGS, [&tags.join(","), pkg_name])?;
+
+        Ok(())
+    }
+
+    // what `pkg autoremove` and `pkg status` find the orphans by
+    pub fn set_pkg_deps(&self, pkg_name: &str, deps: &[String], auto: bool) -> Result<()> {
+        self.conn.execute(
+            sql::SET_PKG_DEPS,
+            rusqlite::params![deps.join(","), auto, pkg_name],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn set_pkg_bridge_version(&self, pkg_name: &str, version: &str) -> Result<()> {
+        self.conn
+            .execute(sql::SET_PKG_BRIDGE_VERSION, [version, pkg_name])?;
+
+        Ok(())
+    }
+
+    // the `pre-remove` hooks of the pkg, run before it's removed
+    pub fn set_pkg_pre_remove(&self, pkg_name: &str, commands: &[String]) -> Result<()> {
+        self.conn
+            .execute(sql::SET_PKG_PRE_REMOVE, [&commands.join("\n"), pkg_name])?;
+
+        Ok(())
+    }
+
+    pub fn get_pkg_pre_remove(&self, pkg_name: &str) -> Result<Vec<String>> {
+        let commands: Option<String> = self
+            .conn
+            .query_row(sql::GET_PKG_PRE_REMOVE, [pkg_name], |row| row.get(0))
+            .optional()?;
+
+        Ok(commands
+            .unwrap_or_default()
+            .lines()
+            .map(String::from)
+            .collect())
     }
 
     pub fn install_bridge_pkgs(&self, pkgs: &[&Pkg], bridge: &String) -> Result<()> {
-        let mut stmt = self.conn.prepare(sql::INSERT_PKGS).into_diagnostic()?;
+        // all the rows or none of them
+        let tx = self.conn.unchecked_transaction()?;
+        let mut stmt = tx.prepare_cached(sql::INSERT_PKGS)?;
 
         for pkg in pkgs {
-            let pkg_version = format!(
-                "{}.{}.{}",
-                pkg.version.first_cell, pkg.version.second_cell, pkg.version.third_cell
-            );
-
-            let pkg_type = match &pkg.pkg_type {
-                PkgType::SingleExecutable => "SingleExecutable".to_string(),
-                PkgType::Directory(_) => "Directory".to_string(),
-            };
+            let row = pkg_row(pkg)?;
+            stmt.execute(rusqlite::params_from_iter(row.iter().chain([bridge])))?;
+        }
 
-            let pkg_path = pkg.path.to_str().ok_or(DbError::InvalidPath)?.to_string();
+        drop(stmt);
+        tx.commit()?;
 
-            let entry_point = match &pkg.pkg_type {
-                PkgType::SingleExecutable => pkg_path.to_string(), // Convert &str to String
-                PkgType::Directory(ep) => ep.to_string_lossy().into_owned(), // Handle path conversion
-            };
+        Ok(())
+    }
 
-            stmt.execute([
-                &pkg.name,
-                &pkg_version,
-                &pkg_path,
-                &pkg_type,
-                &entry_point,
-                bridge,
-            ])
-            .into_diagnostic()?;
-        }
+    // for an update or a reinstall, the old row becomes the new one in one
+    // statement, a crash can't lose the record of the pkg
+    pub fn update_pkg(&self, pkg: &Pkg, bridge: &str) -> Result<()> {
+        let row = pkg_row(pkg)?;
+
+        self.conn
+            .prepare_cached(sql::UPDATE_PKG)?
+            .execute(rusqlite::params_from_iter(
+                row.iter().map(String::as_str).chain([bridge]),
+            ))?;
 
         Ok(())
     }
 
     pub fn remove_pkgs(&self, pkgs_names: &[String]) -> Result<()> {
-        let mut stmt = self.conn.prepare(sql::DELETE_PKGS).into_diagnostic()?;
+        let tx = self.conn.unchecked_transaction()?;
+        let mut stmt = tx.prepare_cached(sql::DELETE_PKGS)?;
+        let mut cache_stmt = tx.prepare_cached(sql::DELETE_CACHE_KEYS)?;
 
         for pkg_name in pkgs_names {
-            stmt.execute([&pkg_name]).into_diagnostic()?;
+            stmt.execute([&pkg_name])?;
+            cache_stmt.execute([&pkg_name])?;
         }
 
+        drop(stmt);
+        drop(cache_stmt);
+        tx.commit()?;
+
         Ok(())
     }
 
     pub fn get_pkgs(&self) -> Result<Vec<Pkg>> {
-        let mut stmt = self.conn.prepare(sql::GET_PKGS).into_diagnostic()?;
-        let rows = stmt
-            .query_map([], |row| {
-                let name: String = row.get(0)?;
-                let version: String = row.get(1)?;
-                let path: String = row.get(2)?;
-                let pkg_type: String = row.get(3)?;
-                let entry_point: String = row.get(4)?;
-
-                // Parse version string into components
-                let version_parts: Vec<&str> = version.split('.').collect();
-                if version_parts.len() != 3 {
-                    return Err(RusqliteError::InvalidQuery);
-                }
-
-                // Parse package type
-                let pkg_type = match pkg_type.as_str() {
-                    "SingleExecutable" => PkgType::SingleExecutable,
-                    "Directory" => PkgType::Directory(PathBuf::from(&entry_point)),
-                    _ => return Err(RusqliteError::InvalidQuery),
-                };
-
-                Ok(Pkg {
-                    name,
-                    version: Version {
-                        first_cell: version_parts[0].to_string(),
-                        second_cell: version_parts[1].to_string(),
-                        third_cell: version_parts[2].to_string(),
-                    },
-                    path: PathBuf::from(path),
-                    pkg_type,
-                })
-            })
-            .into_diagnostic()?;
+        let mut stmt = self.conn.prepare_cached(sql::GET_PKGS)?;
+        let rows = stmt.query_map([], pkg_from_row)?;
 
         let mut pkgs = Vec::new();
         for pkg in rows {
-            pkgs.push(pkg.into_diagnostic()?);
+            pkgs.push(pkg?);
         }
 
         Ok(pkgs)
@@ -264,116 +1460,170 @@ impl Db {
         let placeholders = pkg_names.iter().map(|_| "?").collect::<Vec<_>>().join(",");
         let sql = sql::GET_PKGS_BY_NAMES.replace("{}", &placeholders);
 
-        let mut stmt = self.conn.prepare(&sql).into_diagnostic()?;
+        let mut stmt = self.conn.prepare(&sql)?;
 
         let params: Vec<&str> = pkg_names.iter().map(|s| s.as_str()).collect();
 
-        let rows = stmt
-            .query_map(rusqlite::params_from_iter(params.iter()), |row| {
-                let name: String = row.get(0)?;
-                let version: String = row.get(1)?;
-                let path: String = row.get(2)?;
-                let pkg_type: String = row.get(3)?;
-                let entry_point: String = row.get(4)?;
-
-                // Parse version string into components
-                let version_parts: Vec<&str> = version.split('.').collect();
-                if version_parts.len() != 3 {
-                    return Err(RusqliteError::InvalidQuery);
-                }
+        let rows = stmt.query_map(rusqlite::params_from_iter(params.iter()), pkg_from_row)?;
 
-                // Parse package type
-                let pkg_type = match pkg_type.as_str() {
-                    "SingleExecutable" => PkgType::SingleExecutable,
-                    "Directory" => PkgType::Directory(PathBuf::from(&entry_point)),
-                    _ => return Err(RusqliteError::InvalidQuery),
-                };
+        let mut pkgs = Vec::new();
+        for pkg in rows {
+            pkgs.push(pkg?);
+        }
 
-                Ok(Pkg {
-                    name,
-                    version: Version {
-                        first_cell: version_parts[0].to_string(),
-                        second_cell: version_parts[1].to_string(),
-                        third_cell: version_parts[2].to_string(),
-                    },
-                    path: PathBuf::from(path),
-                    pkg_type,
-                })
-            })
-            .into_diagnostic()?;
+        Ok(pkgs)
+    }
+
+    pub fn get_pkgs_by_bridge(&self, bridge_name: &String) -> Result<Vec<Pkg>> {
+        let mut stmt = self.conn.prepare_cached(sql::GET_PKGS_BY_BRIDGE)?;
+
+        let rows = stmt.query_map([&bridge_name], pkg_from_row)?;
 
         let mut pkgs = Vec::new();
         for pkg in rows {
-            pkgs.push(pkg.into_diagnostic()?);
+            pkgs.push(pkg?);
         }
 
         Ok(pkgs)
     }
 
-    pub fn get_pkgs_by_bridge(&self, bridge_name: &String) -> Result<Vec<Pkg>> {
-        let mut stmt = self
+    pub fn record_duration(
+        &self,
+        pkg_name: &str,
+        operation: &str,
+        duration: Duration,
+    ) -> Result<()> {
+        let duration_ms = duration.as_millis() as i64;
+
+        self.conn
+            .prepare_cached(sql::INSERT_DURATION)?
+            .execute(rusqlite::params![pkg_name, operation, duration_ms])?;
+        self.conn
+            .prepare_cached(sql::PRUNE_DURATIONS)?
+            .execute([pkg_name, operation])?;
+
+        Ok(())
+    }
+
+    pub fn get_average_durations(&self, operation: &str) -> Result<HashMap<String, Duration>> {
+        let mut stmt = self.conn.prepare_cached(sql::GET_AVERAGE_DURATIONS)?;
+
+        let rows = stmt.query_map([operation], |row| {
+            let name: String = row.get(0)?;
+            let duration_ms: f64 = row.get(1)?;
+            Ok((name, Duration::from_millis(duration_ms as u64)))
+        })?;
+
+        let mut durations = HashMap::new();
+        for row in rows {
+            let (name, duration) = row?;
+            durations.insert(name, duration);
+        }
+
+        Ok(durations)
+    }
+
+    // the `bridge::cache_key` of the last successful `install` or `update` of a pkg
+    pub fn set_cache_key(&self, pkg_name: &str, operation: &str, key: &str) -> Result<()> {
+        self.conn
+            .prepare_cached(sql::SET_CACHE_KEY)?
+            .execute([pkg_name, operation, key])?;
+
+        Ok(())
+    }
+
+    pub fn get_cache_key(&self, pkg_name: &str, operation: &str) -> Result<Option<String>> {
+        Ok(self
             .conn
-            .prepare(sql::GET_PKGS_BY_BRIDGE)
-            .into_diagnostic()?;
-
-        let rows = stmt
-            .query_map([&bridge_name], |row| {
-                let name: String = row.get(0)?;
-                let version: String = row.get(1)?;
-                let path: String = row.get(2)?;
-                let pkg_type: String = row.get(3)?;
-                let entry_point: String = row.get(4)?;
-
-                // Parse version string into components
-                let version_parts: Vec<&str> = version.split('.').collect();
-                if version_parts.len() != 3 {
-                    return Err(RusqliteError::InvalidQuery);
-                }
+            .prepare_cached(sql::GET_CACHE_KEY)?
+            .query_row([pkg_name, operation], |row| row.get(0))
+            .optional()?)
+    }
 
-                // Parse package type
-                let pkg_type = match pkg_type.as_str() {
-                    "SingleExecutable" => PkgType::SingleExecutable,
-                    "Directory" => PkgType::Directory(PathBuf::from(&entry_point)),
-                    _ => return Err(RusqliteError::InvalidQuery),
-                };
+    pub fn record_build(
+        &self,
+        installed: usize,
+        removed: usize,
+        failures: usize,
+        duration: Duration,
+    ) -> Result<()> {
+        self.conn
+            .prepare_cached(sql::INSERT_BUILD)?
+            .execute(rusqlite::params![
+                installed as i64,
+                removed as i64,
+                failures as i64,
+                duration.as_millis() as i64
+            ])?;
+        self.conn.execute(sql::PRUNE_BUILDS, [])?;
+
+        Ok(())
+    }
 
-                Ok(Pkg {
-                    name,
-                    version: Version {
-                        first_cell: version_parts[0].to_string(),
-                        second_cell: version_parts[1].to_string(),
-                        third_cell: version_parts[2].to_string(),
-                    },
-                    path: PathBuf::from(path),
-                    pkg_type,
+    // `None` before the first build
+    pub fn last_build(&self) -> Result<Option<BuildRecord>> {
+        Ok(self
+            .conn
+            .prepare_cached(sql::GET_LAST_BUILD)?
+            .query_row([], |row| {
+                Ok(BuildRecord {
+                    finished_at: row.get(0)?,
+                    installed: row.get::<_, i64>(1)? as usize,
+                    removed: row.get::<_, i64>(2)? as usize,
+                    failures: row.get::<_, i64>(3)? as usize,
+                    duration: Duration::from_millis(row.get::<_, i64>(4)? as u64),
                 })
             })
-            .into_diagnostic()?;
+            .optional()?)
+    }
 
-        let mut pkgs = Vec::new();
-        for pkg in rows {
-            pkgs.push(pkg.into_diagnostic()?);
-        }
+    pub fn set_bridge_source(
+        &self,
+        name: &str,
+        registry: &str,
+        version: &str,
+        revision: &str,
+    ) -> Result<()> {
+        self.conn
+            .prepare_cached(sql::SET_BRIDGE_SOURCE)?
+            .execute([name, registry, version, revision])?;
 
-        Ok(pkgs)
+        Ok(())
+    }
+
+    // the bridges installed from a registry, by name
+    pub fn get_bridge_sources(&self) -> Result<Vec<BridgeSource>> {
+        Ok(self
+            .conn
+            .prepare_cached(sql::GET_BRIDGE_SOURCES)?
+            .query_map([], |row| {
+                Ok(BridgeSource {
+                    name: row.get(0)?,
+                    registry: row.get(1)?,
+                    version: row.get(2)?,
+                    revision: row.get(3)?,
+                    installed_at: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<BridgeSource>>>()?)
+    }
+
+    pub fn get_bridge_source(&self, name: &str) -> Result<Option<BridgeSource>> {
+        Ok(self
+            .get_bridge_sources()?
+            .into_iter()
+            .find(|source| source.name == name))
     }
 
     pub fn which_pkgs_are_not_installed<'a>(
         &'a self,
         pkgs: &'a [String],
     ) -> Result<Vec<&'a String>> {
-        let installed_pkgs = self.get_pkgs()?;
-        let mut not_installed_pkgs = Vec::new();
-
-        for pkg in pkgs {
-            if !installed_pkgs
-                .iter()
-                .any(|installed| installed.name == pkg.clone())
-            {
-                not_installed_pkgs.push(pkg);
-            }
-        }
+        let installed_names = self.get_installed_names(pkgs)?;
 
-        Ok(not_installed_pkgs)
+        Ok(pkgs
+            .iter()
+            .filter(|pkg| !installed_names.contains(*pkg))
+            .collect())
     }
 }
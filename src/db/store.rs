@@ -0,0 +1,338 @@
+// where the installed pkgs are kept, behind `StateStore`. `Db` (sqlite) is the
+// one pkg uses, `MemoryStore` is for the tests and the library, `JsonStore`
+// for the embedders that can't ship sqlite. only the pkgs are in the trait,
+// the durations, the builds and the cache keys stay in the db
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use serde_json::{Value, json};
+
+use super::{
+    Artifact, Db, DbError, Pkg, PkgFilter, PkgMetadata, PkgRecord, PkgType, Result, Version,
+    pkg_row, sql,
+};
+
+// the format of the json state file, a newer one isn't read
+pub const STATE_FILE_VERSION: u64 = 1;
+
+pub trait StateStore {
+    // creates what's missing (the tables, the file) and updates what an older
+    // pkg wrote, it's called before the rest
+    fn migrate(&self) -> Result<()>;
+
+    fn get(&self, name: &str) -> Result<Option<PkgRecord>>;
+
+    // the record of a pkg of the same name is replaced
+    fn insert(&self, record: PkgRecord) -> Result<()>;
+
+    // false if it wasn't there
+    fn remove(&self, name: &str) -> Result<bool>;
+
+    // the records that match the filter, by name
+    fn query(&self, filter: &PkgFilter) -> Result<Vec<PkgRecord>>;
+}
+
+impl StateStore for Db {
+    fn migrate(&self) -> Result<()> {
+        super::migrate(&self.conn)
+    }
+
+    fn get(&self, name: &str) -> Result<Option<PkgRecord>> {
+        let filter = PkgFilter {
+            names: vec![name.to_string()],
+            ..Default::default()
+        };
+
+        Ok(Db::query(self, &filter)?.pop())
+    }
+
+    fn insert(&self, record: PkgRecord) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+
+        self.update_pkg(&record.pkg, &record.bridge)?;
+        tx.execute(
+            sql::SET_PKG_RECORD,
+            rusqlite::params![
+                record.installed_at,
+                record.tags.join(","),
+                record.deps.join(","),
+                record.auto,
+                record.bridge_version,
+                record.pkg.name,
+            ],
+        )?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> Result<bool> {
+        if self.get(name)?.is_none() {
+            return Ok(false);
+        }
+
+        self.remove_pkgs(&[name.to_string()])?;
+        Ok(true)
+    }
+
+    fn query(&self, filter: &PkgFilter) -> Result<Vec<PkgRecord>> {
+        Db::query(self, filter)
+    }
+}
+
+// nothing is written anywhere, it's gone with the store
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    records: Mutex<BTreeMap<String, PkgRecord>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn records(&self) -> MutexGuard<'_, BTreeMap<String, PkgRecord>> {
+        self.records
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn migrate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Result<Option<PkgRecord>> {
+        Ok(self.records().get(name).cloned())
+    }
+
+    fn insert(&self, record: PkgRecord) -> Result<()> {
+        self.records().insert(record.pkg.name.clone(), record);
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> Result<bool> {
+        Ok(self.records().remove(name).is_some())
+    }
+
+    fn query(&self, filter: &PkgFilter) -> Result<Vec<PkgRecord>> {
+        Ok(self
+            .records()
+            .values()
+            .filter(|record| filter.matches(record))
+            .cloned()
+            .collect())
+    }
+}
+
+// the records in one json file, read when it's opened and written again
+// (next to it, then renamed over it) after every change
+#[derive(Debug)]
+pub struct JsonStore {
+    path: PathBuf,
+    records: Mutex<BTreeMap<String, PkgRecord>>,
+}
+
+impl JsonStore {
+    // a missing file is an empty store, `migrate` writes it
+    pub fn open(path: &Path) -> Result<Self> {
+        let records = match std::fs::read_to_string(path) {
+            Ok(src) => parse_state_file(&src, path)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            records: Mutex::new(records),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn records(&self) -> MutexGuard<'_, BTreeMap<String, PkgRecord>> {
+        self.records
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn save(&self, records: &BTreeMap<String, PkgRecord>) -> Result<()> {
+        let pkgs = records
+            .values()
+            .map(record_to_json)
+            .collect::<Result<Vec<Value>>>()?;
+        let state = json!({ "version": STATE_FILE_VERSION, "packages": pkgs });
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, format!("{state:#}\n"))?;
+        std::fs::rename(&tmp, &self.path)?;
+
+        Ok(())
+    }
+}
+
+impl StateStore for JsonStore {
+    // there's one version of the file yet, it's written if it's missing
+    fn migrate(&self) -> Result<()> {
+        if self.path.exists() {
+            return Ok(());
+        }
+
+        self.save(&self.records())
+    }
+
+    fn get(&self, name: &str) -> Result<Option<PkgRecord>> {
+        Ok(self.records().get(name).cloned())
+    }
+
+    fn insert(&self, record: PkgRecord) -> Result<()> {
+        let mut records = self.records();
+        records.insert(record.pkg.name.clone(), record);
+        self.save(&records)
+    }
+
+    fn remove(&self, name: &str) -> Result<bool> {
+        let mut records = self.records();
+        if records.remove(name).is_none() {
+            return Ok(false);
+        }
+
+        self.save(&records)?;
+        Ok(true)
+    }
+
+    fn query(&self, filter: &PkgFilter) -> Result<Vec<PkgRecord>> {
+        Ok(self
+            .records()
+            .values()
+            .filter(|record| filter.matches(record))
+            .cloned()
+            .collect())
+    }
+}
+
+// the columns of the `packages` table, a record is a row
+fn record_to_json(record: &PkgRecord) -> Result<Value> {
+    let [
+        name,
+        version,
+        path,
+        pkg_type,
+        entry_point,
+        artifacts,
+        description,
+        homepage,
+        license,
+        source_url,
+    ] = pkg_row(&record.pkg)?;
+
+    Ok(json!({
+        "name": name,
+        "version": version,
+        "path": path,
+        "pkg_type": pkg_type,
+        "entry_point": entry_point,
+        "artifacts": artifacts.lines().collect::<Vec<&str>>(),
+        "description": description,
+        "homepage": homepage,
+        "license": license,
+        "source_url": source_url,
+        "bridge": record.bridge,
+        "installed_at": record.installed_at,
+        "tags": record.tags,
+        "deps": record.deps,
+        "auto": record.auto,
+        "bridge_version": record.bridge_version,
+    }))
+}
+
+fn parse_state_file(src: &str, path: &Path) -> Result<BTreeMap<String, PkgRecord>> {
+    let bad = |error: String| DbError::BadStateFile {
+        path: path.to_path_buf(),
+        error,
+    };
+
+    let state: Value = serde_json::from_str(src).map_err(|err| bad(err.to_string()))?;
+
+    let version = state["version"].as_u64().unwrap_or(0);
+    if version > STATE_FILE_VERSION {
+        return Err(bad(format!(
+            "it's version {version}, a newer pkg wrote it (this one reads {STATE_FILE_VERSION})"
+        )));
+    }
+
+    state["packages"]
+        .as_array()
+        .ok_or_else(|| bad("no `packages` list".to_string()))?
+        .iter()
+        .map(|pkg| {
+            let record = record_from_json(pkg).ok_or_else(|| {
+                bad(format!(
+                    "invalid package {}",
+                    pkg["name"].as_str().unwrap_or("without a name")
+                ))
+            })?;
+            Ok((record.pkg.name.clone(), record))
+        })
+        .collect()
+}
+
+// `None` if a field is missing or of another type, the optional ones (the
+// metadata, the tags...) can be missing
+fn record_from_json(pkg: &Value) -> Option<PkgRecord> {
+    let text = |key: &str| pkg[key].as_str().unwrap_or_default().to_string();
+    let optional = |key: &str| Some(text(key)).filter(|value| !value.is_empty());
+    let list = |key: &str| {
+        pkg[key]
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|item| item.as_str().map(String::from))
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default()
+    };
+
+    let path = PathBuf::from(pkg["path"].as_str()?);
+    let pkg_type = match pkg["pkg_type"].as_str()? {
+        "SingleExecutable" => PkgType::SingleExecutable,
+        "Directory" => PkgType::Directory(PathBuf::from(pkg["entry_point"].as_str()?)),
+        _ => return None,
+    };
+
+    Some(PkgRecord {
+        pkg: Pkg {
+            name: pkg["name"].as_str()?.to_string(),
+            version: Version::parse(pkg["version"].as_str()?)?,
+            path,
+            pkg_type,
+            artifacts: list("artifacts")
+                .iter()
+                .filter_map(|line| Artifact::parse(line))
+                .collect(),
+            metadata: PkgMetadata {
+                description: optional("description"),
+                homepage: optional("homepage"),
+                license: optional("license"),
+                source_url: optional("source_url"),
+            },
+        },
+        bridge: pkg["bridge"].as_str()?.to_string(),
+        installed_at: pkg["installed_at"].as_i64().unwrap_or(0),
+        tags: list("tags"),
+        deps: list("deps"),
+        auto: pkg["auto"].as_bool().unwrap_or(false),
+        bridge_version: text("bridge_version"),
+    })
+}
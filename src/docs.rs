@@ -0,0 +1,126 @@
+// the user docs (`docs/topics/*.md`), built in the binary and rendered for
+// the terminal by `pkg docs <topic>`
+use owo_colors::OwoColorize;
+
+use crate::cmd::DocsTopic;
+
+pub struct Topic {
+    pub name: &'static str,
+    pub summary: &'static str,
+    pub content: &'static str,
+}
+
+pub const TOPICS: [Topic; 5] = [
+    Topic {
+        name: "inputs",
+        summary: "how the packages are declared: attributes, tags, conditions, variables, includes",
+        content: include_str!("../docs/topics/inputs.md"),
+    },
+    Topic {
+        name: "bridges",
+        summary: "how to write a bridge: inline and wasm bridges, manifest, working dirs",
+        content: include_str!("../docs/topics/bridges.md"),
+    },
+    Topic {
+        name: "protocol",
+        summary: "how a bridge is run and what it prints: output, env vars, default impls",
+        content: include_str!("../docs/topics/protocol.md"),
+    },
+    Topic {
+        name: "store",
+        summary: "where the packages go: load paths, link strategies, adopt, systemd units, hooks",
+        content: include_str!("../docs/topics/store.md"),
+    },
+    Topic {
+        name: "db",
+        summary: "the db backups, the operations cache and the audit log",
+        content: include_str!("../docs/topics/db.md"),
+    },
+];
+
+pub fn topic(topic: DocsTopic) -> &'static Topic {
+    match topic {
+        DocsTopic::Inputs => &TOPICS[0],
+        DocsTopic::Bridges => &TOPICS[1],
+        DocsTopic::Protocol => &TOPICS[2],
+        DocsTopic::Store => &TOPICS[3],
+        DocsTopic::Db => &TOPICS[4],
+    }
+}
+
+// the list of the topics, what `pkg docs` shows
+pub fn index() -> String {
+    let mut index = String::from("# Docs\n\n`pkg docs <topic>` to read one of them:\n\n");
+    for topic in &TOPICS {
+        index.push_str(&format!("- `{}` - {}\n", topic.name, topic.summary));
+    }
+    index
+}
+
+// the markdown the docs use: headings, code blocks, lists, quotes and
+// inline code, without `color` it's only reindented
+pub fn render(markdown: &str, color: bool) -> String {
+    let mut out = String::new();
+    let mut in_code = false;
+
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+
+        let rendered = if in_code {
+            let code = format!("    {line}");
+            if color {
+                code.dimmed().to_string()
+            } else {
+                code
+            }
+        } else if let Some(title) = line.strip_prefix("# ") {
+            if color {
+                title.to_uppercase().green().bold().underline().to_string()
+            } else {
+                title.to_uppercase()
+            }
+        } else if let Some(title) = line.strip_prefix("## ") {
+            if color {
+                title.blue().bold().to_string()
+            } else {
+                title.to_string()
+            }
+        } else if let Some(item) = line.strip_prefix("- ") {
+            format!("  • {}", inline(item, color))
+        } else if let Some(quote) = line.strip_prefix("> ") {
+            format!("  │ {}", inline(quote, color))
+        } else {
+            inline(line, color)
+        };
+
+        out.push_str(&rendered);
+        out.push('\n');
+    }
+
+    out
+}
+
+// `code` spans
+fn inline(text: &str, color: bool) -> String {
+    let mut out = String::new();
+    let parts = text.split('`').collect::<Vec<&str>>();
+
+    for (i, part) in parts.iter().enumerate() {
+        // the odd parts are in backticks, an unclosed one is kept as it is
+        if i % 2 == 1 && i == parts.len() - 1 {
+            out.push('`');
+            out.push_str(part);
+        } else if i % 2 == 1 && color {
+            out.push_str(&part.yellow().to_string());
+        } else if i % 2 == 1 {
+            out.push_str(&format!("`{part}`"));
+        } else {
+            out.push_str(part);
+        }
+    }
+
+    out
+}
@@ -0,0 +1,94 @@
+// every error the library can return, so a crate using pkg can match on what
+// went wrong instead of a `miette::Report`, the cli still renders them with
+// the diagnostics of each module
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::{
+    audit::AuditError, bridge::BridgeApiError, config::ConfigError, db::DbError,
+    export::ExportError, failures::FailuresError, fs::FsError, git::GitError, hooks::HookError,
+    import::ImportError, input::InputError, lock::LockError, manifest::ManifestError,
+    metrics::MetricsError, privilege::PrivilegeError, registry::RegistryError,
+    schedule::ScheduleError, systemd::SystemdError, watch::WatchError,
+};
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum Error {
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Db(#[from] DbError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Bridge(#[from] BridgeApiError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Config(#[from] ConfigError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Input(#[from] InputError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Fs(#[from] FsError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Manifest(#[from] ManifestError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Git(#[from] GitError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Lock(#[from] LockError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Hook(#[from] HookError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Systemd(#[from] SystemdError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Audit(#[from] AuditError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Watch(#[from] WatchError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Schedule(#[from] ScheduleError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Metrics(#[from] MetricsError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Import(#[from] ImportError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Export(#[from] ExportError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Privilege(#[from] PrivilegeError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Registry(#[from] RegistryError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Failures(#[from] FailuresError),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,190 @@
+// what happens during a build, the cli renders it with progress bars, but
+// anything can listen to it (a gui, a log file, the tests)
+use std::{path::PathBuf, time::Duration};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    // before the jobs of a bridge, with what it's going to do
+    BridgeStarted {
+        bridge: String,
+        install: usize,
+        remove: usize,
+        update: usize,
+    },
+    // `install`, `update`, `remove` or `reinstall`
+    JobStarted {
+        job: String,
+        pkgs: usize,
+    },
+    // estimated from the past runs, before each pkg
+    Eta {
+        remaining: Duration,
+    },
+    PackageStarted {
+        name: String,
+        index: usize,
+        total: usize,
+    },
+    // the bridge is done, the pkg is being moved to the store or removed from it
+    PackageStoring {
+        name: String,
+    },
+    PackageInstalled {
+        name: String,
+    },
+    PackageRemoved {
+        name: String,
+    },
+    // declared for an other os or arch, left as it is
+    PackageSkipped {
+        name: String,
+        reason: String,
+    },
+    // nothing changed since its last install (or update), the bridge isn't run
+    PackageUnchanged {
+        name: String,
+    },
+    PackageFailed {
+        name: String,
+        step: Step,
+        error: String,
+        // of the bridge, when it ran for the pkg
+        exit_code: Option<i32>,
+    },
+    JobDone,
+    LinkStarted,
+    // with the stale links that were removed
+    LinkDone {
+        pruned: Vec<PathBuf>,
+    },
+    LinkFailed {
+        error: String,
+    },
+    // `daemon-reload` or `enable --now` after the link
+    UnitsFailed {
+        error: String,
+    },
+    // a `post-link` hook, the pkgs are linked anyway
+    HookFailed {
+        pkg: Option<String>,
+        error: String,
+    },
+    // a license the config doesn't allow, with `--allow-license-violations`
+    // (the pkg fails without it)
+    LicenseViolation {
+        name: String,
+        license: String,
+    },
+    // a record of the audit log couldn't be written
+    AuditFailed {
+        error: String,
+    },
+    // a notifier of the config, after the summary
+    NotifyFailed {
+        error: String,
+    },
+    Summary {
+        installed: usize,
+        removed: usize,
+    },
+}
+
+// where a pkg failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    BridgeOperation,
+    Store,
+    Unstore,
+    DbWrite,
+    DbRemove,
+    Hook,
+    Units,
+    License,
+}
+
+pub trait EventSink {
+    fn emit(&mut self, event: Event);
+}
+
+// drops everything
+#[derive(Debug, Default)]
+pub struct NoopSink;
+
+impl EventSink for NoopSink {
+    fn emit(&mut self, _event: Event) {}
+}
+
+// keeps everything, to look at it later
+impl EventSink for Vec<Event> {
+    fn emit(&mut self, event: Event) {
+        self.push(event);
+    }
+}
+
+// passes the events on and counts the failures, a build with some is a
+// partial failure
+#[derive(Debug, Default)]
+pub struct CountingSink<S> {
+    inner: S,
+    failures: usize,
+}
+
+impl<S: EventSink> CountingSink<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner, failures: 0 }
+    }
+
+    pub fn failures(&self) -> usize {
+        self.failures
+    }
+}
+
+impl<S: EventSink> EventSink for CountingSink<S> {
+    fn emit(&mut self, event: Event) {
+        if matches!(
+            event,
+            Event::PackageFailed { .. }
+                | Event::LinkFailed { .. }
+                | Event::UnitsFailed { .. }
+                | Event::HookFailed { .. }
+                | Event::AuditFailed { .. }
+        ) {
+            self.failures += 1;
+        }
+
+        self.inner.emit(event);
+    }
+}
+
+impl Step {
+    // for the failure report
+    pub fn name(&self) -> &'static str {
+        match self {
+            Step::BridgeOperation => "bridge-operation",
+            Step::Store => "store",
+            Step::Unstore => "unstore",
+            Step::DbWrite => "db-write",
+            Step::DbRemove => "db-remove",
+            Step::Hook => "hook",
+            Step::Units => "units",
+            Step::License => "license",
+        }
+    }
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let step = match self {
+            Step::BridgeOperation => "at bridge operation",
+            Step::Store => "at store the pkg",
+            Step::Unstore => "at remove the pkg",
+            Step::DbWrite => "at write pkg in db",
+            Step::DbRemove => "at remove pkg from db",
+            Step::Hook => "at run the hooks",
+            Step::Units => "at the systemd units",
+            Step::License => "at check the license",
+        };
+
+        write!(f, "{step}")
+    }
+}
@@ -0,0 +1,75 @@
+// the exit codes of pkg, so a script (ansible, a cron job, the ci...) can tell
+// a pkg that failed to install from a broken config or a missing sudo
+use miette::Report;
+
+use crate::{
+    bridge::BridgeApiError, config::ConfigError, error::Error, fs::FsError, input::InputError,
+    lock::LockError, manifest::ManifestError,
+};
+
+// everything is in sync
+pub const SUCCESS: u8 = 0;
+// the build went to the end, but some pkgs (or hooks, units) failed
+pub const PARTIAL_FAILURE: u8 = 1;
+// the config, the inputs or a bridge manifest are wrong, nothing was done
+// (it's the code of the wrong cli args too)
+pub const CONFIG_ERROR: u8 = 2;
+// pkg needs root and couldn't get it
+pub const PRIVILEGE_ERROR: u8 = 3;
+// another pkg is running
+pub const LOCKED: u8 = 4;
+// anything else stopped pkg on the way (the db, the fs, a bridge that can't run...)
+pub const FAILURE: u8 = 5;
+// the plan was declined at the prompt, nothing was done
+pub const DECLINED: u8 = 6;
+
+// the code of an error of the library, the errors of the cli have their own
+pub fn code(report: &Report) -> u8 {
+    if let Some(err) = report.downcast_ref::<Error>() {
+        return match err {
+            Error::Config(_) | Error::Input(_) | Error::Manifest(_) => CONFIG_ERROR,
+            Error::Bridge(err) => bridge_code(err),
+            Error::Fs(err) => fs_code(err),
+            Error::Lock(err) => lock_code(err),
+            _ => FAILURE,
+        };
+    }
+
+    if report.downcast_ref::<ConfigError>().is_some()
+        || report.downcast_ref::<InputError>().is_some()
+        || report.downcast_ref::<ManifestError>().is_some()
+    {
+        CONFIG_ERROR
+    } else if let Some(err) = report.downcast_ref::<BridgeApiError>() {
+        bridge_code(err)
+    } else if let Some(err) = report.downcast_ref::<FsError>() {
+        fs_code(err)
+    } else if let Some(err) = report.downcast_ref::<LockError>() {
+        lock_code(err)
+    } else {
+        FAILURE
+    }
+}
+
+fn bridge_code(err: &BridgeApiError) -> u8 {
+    match err {
+        BridgeApiError::BridgeNotFound(_)
+        | BridgeApiError::BridgeSetNotFound(_)
+        | BridgeApiError::BridgeSetPathAreNotADirectory(_) => CONFIG_ERROR,
+        _ => FAILURE,
+    }
+}
+
+fn fs_code(err: &FsError) -> u8 {
+    match err {
+        FsError::UnknownLinkStrategy { .. } | FsError::UnknownLoadPath { .. } => CONFIG_ERROR,
+        _ => FAILURE,
+    }
+}
+
+fn lock_code(err: &LockError) -> u8 {
+    match err {
+        LockError::AlreadyLocked { .. } => LOCKED,
+        _ => FAILURE,
+    }
+}
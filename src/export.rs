@@ -0,0 +1,206 @@
+// `pkg export --format bootstrap`, a shell script that sets up this machine's
+// pkg on a new one: it installs pkg (with cargo, and rustup without cargo),
+// writes the config, the inputs and the bridges, then runs `pkg build`. the
+// cloud-init flavour is the same script in a `#cloud-config`
+use std::path::{Path, PathBuf};
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+// the end of the heredocs, a file with this line is base64 encoded
+const DELIMITER: &str = "PKG_BOOTSTRAP_EOF";
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum ExportError {
+    #[error(transparent)]
+    #[diagnostic(code(export::io_error))]
+    IoError(#[from] std::io::Error),
+}
+
+type Result<T, E = ExportError> = std::result::Result<T, E>;
+
+// a file written by the script, its path is a shell word (`$config_dir/...`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct BootstrapFile {
+    pub path: String,
+    pub content: Vec<u8>,
+    pub executable: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bootstrap {
+    // the version of pkg that's installed, this one
+    pub version: String,
+    pub files: Vec<BootstrapFile>,
+    // after `pkg`, `--non-interactive build` and maybe a `--profile`
+    pub build_args: Vec<String>,
+}
+
+impl Bootstrap {
+    // the files of the dir and its sub dirs, the hidden ones (`.git`) aren't
+    // taken. they're written under `target`
+    pub fn add_dir(&mut self, dir: &Path, target: &str) -> Result<()> {
+        if !dir.is_dir() {
+            return Ok(());
+        }
+
+        let mut entries = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<PathBuf>>>()?;
+        entries.sort();
+
+        for path in entries {
+            let Some(name) = path.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+                continue;
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+
+            let target = format!("{target}/{}", shell_escape(&name));
+            if path.is_dir() {
+                self.add_dir(&path, &target)?;
+            } else if path.is_file() {
+                self.add_file(&path, target)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // a file that's there already (the same path) isn't taken twice
+    pub fn add_file(&mut self, file: &Path, target: String) -> Result<()> {
+        if self.files.iter().any(|f| f.path == target) {
+            return Ok(());
+        }
+
+        self.files.push(BootstrapFile {
+            path: target,
+            content: std::fs::read(file)?,
+            executable: is_executable(file),
+        });
+
+        Ok(())
+    }
+
+    pub fn script(&self) -> String {
+        let mut script = format!(
+            "#!/bin/sh\n\
+             # written by `pkg export --format bootstrap`, it installs pkg {version}, its config and its inputs, then builds\n\
+             set -eu\n\
+             \n\
+             config_dir=\"${{XDG_CONFIG_HOME:-$HOME/.config}}/pkg\"\n\
+             \n\
+             if ! command -v pkg >/dev/null 2>&1; then\n\
+             \x20 if ! command -v cargo >/dev/null 2>&1; then\n\
+             \x20   curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh -s -- -y --profile minimal\n\
+             \x20 fi\n\
+             \x20 PATH=\"$HOME/.cargo/bin:$PATH\"\n\
+             \x20 cargo install pkg-rs --version {version} --locked\n\
+             fi\n",
+            version = self.version
+        );
+
+        for file in &self.files {
+            script.push('\n');
+            script.push_str(&format!("mkdir -p \"$(dirname \"{}\")\"\n", file.path));
+
+            match std::str::from_utf8(&file.content) {
+                Ok(text) if text.ends_with('\n') && !text.lines().any(|line| line == DELIMITER) => {
+                    script.push_str(&format!(
+                        "cat > \"{}\" <<'{DELIMITER}'\n{text}{DELIMITER}\n",
+                        file.path
+                    ));
+                }
+                // binary, or a last line heredocs can't keep
+                _ => {
+                    script.push_str(&format!(
+                        "base64 -d > \"{}\" <<'{DELIMITER}'\n{}\n{DELIMITER}\n",
+                        file.path,
+                        base64(&file.content)
+                    ));
+                }
+            }
+
+            if file.executable {
+                script.push_str(&format!("chmod 755 \"{}\"\n", file.path));
+            }
+        }
+
+        script.push_str(&format!("\npkg {}\n", self.build_args.join(" ")));
+        script
+    }
+
+    // the script runs once, on the first boot
+    pub fn cloud_init(&self) -> String {
+        let script = self
+            .script()
+            .lines()
+            .map(|line| {
+                if line.is_empty() {
+                    String::new()
+                } else {
+                    format!("      {line}")
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        format!(
+            "#cloud-config\n\
+             write_files:\n\
+             \x20 - path: /var/lib/pkg/bootstrap.sh\n\
+             \x20   permissions: '0755'\n\
+             \x20   content: |\n\
+             {script}\n\
+             runcmd:\n\
+             \x20 - [ sh, -c, 'HOME=/root /var/lib/pkg/bootstrap.sh' ]\n"
+        )
+    }
+}
+
+// for the inside of double quotes
+pub fn shell_escape(word: &str) -> String {
+    let mut escaped = String::new();
+    for c in word.chars() {
+        if matches!(c, '"' | '$' | '`' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn is_executable(file: &Path) -> bool {
+    crate::fs::platform::is_executable(file)
+}
+
+// lines of 76, what `base64 -d` reads
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+        .as_bytes()
+        .chunks(76)
+        .map(|line| String::from_utf8_lossy(line).into_owned())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
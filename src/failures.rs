@@ -0,0 +1,239 @@
+// the failures of the last build, in `failures.json` of the log dir: each
+// failed pkg with its bridge, the step it failed at, the exit code of the
+// bridge and its log. `pkg build --retry-failed` runs these pkgs again
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use miette::Diagnostic;
+use serde_json::{Value, json};
+use thiserror::Error;
+
+use crate::event::{Event, EventSink};
+
+pub const FAILURES_FILE_NAME: &str = "failures.json";
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum FailuresError {
+    #[error(transparent)]
+    #[diagnostic(code(failures::io_error))]
+    IoError(#[from] std::io::Error),
+
+    #[error("No failure report at {0:?}")]
+    #[diagnostic(
+        code(failures::no_report),
+        help("The last build had no failures (or ran with another log dir)")
+    )]
+    NoReport(PathBuf),
+
+    #[error("Invalid failure report {path:?}: {error}")]
+    #[diagnostic(code(failures::bad_report))]
+    BadReport { path: PathBuf, error: String },
+}
+
+type Result<T, E = FailuresError> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedPkg {
+    pub name: String,
+    pub bridge: String,
+    // `bridge-operation`, `store`, `db-write`... (`Step::name`)
+    pub step: String,
+    // none when the bridge didn't run for it (or couldn't be started)
+    pub exit_code: Option<i32>,
+    pub error: String,
+    pub log: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FailureReport {
+    // `build`, `update`...
+    pub command: String,
+    // unix time
+    pub finished_at: i64,
+    pub pkgs: Vec<FailedPkg>,
+    // the failures that aren't of a pkg: the link, the units, the hooks of
+    // the config
+    pub others: Vec<String>,
+}
+
+impl FailureReport {
+    pub fn is_empty(&self) -> bool {
+        self.pkgs.is_empty() && self.others.is_empty()
+    }
+
+    // once, a pkg that failed twice (its hook and its units) is retried once
+    pub fn pkg_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for pkg in &self.pkgs {
+            if !names.contains(&pkg.name) {
+                names.push(pkg.name.clone());
+            }
+        }
+        names
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "command": self.command,
+            "finished_at": self.finished_at,
+            "packages": self
+                .pkgs
+                .iter()
+                .map(|pkg| json!({
+                    "name": pkg.name,
+                    "bridge": pkg.bridge,
+                    "step": pkg.step,
+                    "exit_code": pkg.exit_code,
+                    "error": pkg.error,
+                    "log": pkg.log,
+                }))
+                .collect::<Vec<Value>>(),
+            "others": self.others,
+        })
+    }
+
+    pub fn parse(src: &str, path: &Path) -> Result<Self> {
+        let bad = |error: String| FailuresError::BadReport {
+            path: path.to_path_buf(),
+            error,
+        };
+
+        let report: Value = serde_json::from_str(src).map_err(|err| bad(err.to_string()))?;
+        let text = |value: &Value, key: &str| value[key].as_str().unwrap_or_default().to_string();
+
+        let pkgs = report["packages"]
+            .as_array()
+            .ok_or_else(|| bad("no `packages` list".to_string()))?
+            .iter()
+            .map(|pkg| {
+                let name = pkg["name"]
+                    .as_str()
+                    .ok_or_else(|| bad("a package without a name".to_string()))?;
+
+                Ok(FailedPkg {
+                    name: name.to_string(),
+                    bridge: text(pkg, "bridge"),
+                    step: text(pkg, "step"),
+                    exit_code: pkg["exit_code"].as_i64().map(|code| code as i32),
+                    error: text(pkg, "error"),
+                    log: PathBuf::from(text(pkg, "log")),
+                })
+            })
+            .collect::<Result<Vec<FailedPkg>>>()?;
+
+        Ok(Self {
+            command: text(&report, "command"),
+            finished_at: report["finished_at"].as_i64().unwrap_or(0),
+            pkgs,
+            others: report["others"]
+                .as_array()
+                .map(|others| {
+                    others
+                        .iter()
+                        .filter_map(|other| other.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+
+    // in `log_dir/failures.json`, over the one of the last build
+    pub fn write(&self, log_dir: &Path) -> Result<PathBuf> {
+        std::fs::create_dir_all(log_dir)?;
+        let path = log_dir.join(FAILURES_FILE_NAME);
+        let json = serde_json::to_string_pretty(&self.to_json()).unwrap_or_default();
+        std::fs::write(&path, json + "\n")?;
+        Ok(path)
+    }
+
+    pub fn read(log_dir: &Path) -> Result<Self> {
+        let path = log_dir.join(FAILURES_FILE_NAME);
+        match std::fs::read_to_string(&path) {
+            Ok(src) => Self::parse(&src, &path),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Err(FailuresError::NoReport(path))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // a build without failures leaves no report, there's nothing to retry
+    pub fn remove(log_dir: &Path) -> Result<()> {
+        match std::fs::remove_file(log_dir.join(FAILURES_FILE_NAME)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// makes the failure report from the events and passes them on, the pkgs are
+// of the last `BridgeStarted` and their log is the one of the bridge
+#[derive(Debug)]
+pub struct FailureSink<S> {
+    log_dir: PathBuf,
+    bridge: String,
+    report: FailureReport,
+    inner: S,
+}
+
+impl<S: EventSink> FailureSink<S> {
+    pub fn new(command: &str, log_dir: PathBuf, inner: S) -> Self {
+        Self {
+            log_dir,
+            bridge: String::new(),
+            report: FailureReport {
+                command: command.to_string(),
+                ..Default::default()
+            },
+            inner,
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn report(&self) -> &FailureReport {
+        &self.report
+    }
+}
+
+impl<S: EventSink> EventSink for FailureSink<S> {
+    fn emit(&mut self, event: Event) {
+        match &event {
+            Event::BridgeStarted { bridge, .. } => self.bridge = bridge.clone(),
+            Event::PackageFailed {
+                name,
+                step,
+                error,
+                exit_code,
+            } => self.report.pkgs.push(FailedPkg {
+                name: name.clone(),
+                bridge: self.bridge.clone(),
+                step: step.name().to_string(),
+                exit_code: *exit_code,
+                error: error.clone(),
+                log: self.log_dir.join(format!("{}.log", self.bridge)),
+            }),
+            Event::Summary { .. } => {
+                self.report.finished_at = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs() as i64)
+                    .unwrap_or(0);
+            }
+            Event::HookFailed {
+                pkg: Some(pkg),
+                error,
+            } => self.report.others.push(format!("{pkg}: {error}")),
+            Event::HookFailed { pkg: None, error }
+            | Event::LinkFailed { error }
+            | Event::UnitsFailed { error }
+            | Event::AuditFailed { error } => self.report.others.push(error.clone()),
+            _ => {}
+        }
+
+        self.inner.emit(event);
+    }
+}
@@ -1,16 +1,90 @@
 use crate::{
-    Pkg,
-    db::{Db, PkgType},
+    ADOPTED_BRIDGE_NAME, Pkg, PkgVersion,
+    db::{Artifact, Db, DbError, PkgMetadata, PkgType},
+    event::{Event, EventSink},
+};
+use miette::Diagnostic;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Component, Path, PathBuf},
+    process::Command,
+    rc::Rc,
 };
-use miette::{Diagnostic, IntoDiagnostic, Result};
-use std::path::PathBuf;
 use thiserror::Error;
 
+pub mod platform;
+
 #[derive(Debug)]
 pub struct Fs {
     target_dir: PathBuf,
     load_path: PathBuf,
-    db: Db,
+    db: Rc<Db>,
+    link_strategy: LinkStrategy,
+    // the pkgs with a `link-strategy` attribute
+    pkg_link_strategies: HashMap<String, LinkStrategy>,
+    permissions: StorePermissions,
+    // `load-paths { user "~/.local/bin" }` in the config
+    extra_load_paths: BTreeMap<String, PathBuf>,
+    // the pkgs with a `load-path` attribute, to one of the extra load paths
+    pkg_load_paths: HashMap<String, String>,
+    // `unit-dir` in the config, where the systemd units of the pkgs are linked
+    unit_dir: Option<PathBuf>,
+    // the system is provisioned from the host (`--root`), its links point in it
+    root: Option<PathBuf>,
+}
+
+// what the stored pkgs are made to be, whatever user the bridge ran as and
+// whatever modes it left
+#[derive(Debug, Clone)]
+pub struct StorePermissions {
+    // owned by root (when pkg runs as root), 0755 dirs, 0755 or 0644 files
+    pub normalize: bool,
+    // the pkgs that keep their setuid and setgid bits
+    pub setuid_allowed: Vec<String>,
+}
+
+// how a pkg is put in the load path
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LinkStrategy {
+    #[default]
+    Symlink,
+    // the load path has to be on the same fs as the target dir
+    Hardlink,
+    // only the executable, the rest of a directory pkg stays in the target dir
+    Copy,
+    // a small sh script that `exec`s the pkg by its real path, for the
+    // directory pkgs that look for their files next to the executable
+    WrapperScript,
+}
+
+// the names of what `link` made in the load path, to know what's ours when
+// it's not a symlink
+const LINKS_FILE_NAME: &str = ".pkg-links";
+
+// what `link` did in the load path
+#[derive(Debug, Default)]
+pub struct LinkReport {
+    pub linked: usize,
+    // the links of the pkgs that aren't in the db anymore, and the dead ones
+    pub pruned: Vec<PathBuf>,
+}
+
+// what a pkg put on the disk, `pkg info --files`
+#[derive(Debug, Default, PartialEq)]
+pub struct PkgFiles {
+    // the files in the store (the pkg itself for an executable), the dirs
+    // aren't listed
+    pub store: Vec<PathBuf>,
+    // its links in the load paths and in the unit dir
+    pub links: Vec<PathBuf>,
+}
+
+// what a stored pkg takes on disk
+#[derive(Debug)]
+pub struct PkgUsage {
+    pub bridge: String,
+    pub name: String,
+    pub size: u64,
 }
 
 #[derive(Error, Debug, Diagnostic)]
@@ -22,81 +96,829 @@ pub enum FsError {
     #[error("The given load path is exist and is a file {0}")]
     #[diagnostic(code(fs::load_path_is_file))]
     LoadPathIsFile(PathBuf),
+
+    #[error("Nothing to adopt at {0}")]
+    #[diagnostic(code(fs::adopt_path_not_found))]
+    AdoptPathNotFound(PathBuf),
+
+    #[error("{0} is a directory, which file in it runs the package?")]
+    #[diagnostic(
+        code(fs::missing_entry_point),
+        help("Give it with `--entry-point <path in the directory>`")
+    )]
+    MissingEntryPoint(PathBuf),
+
+    #[error("The entry point {0} is not a file")]
+    #[diagnostic(code(fs::wrong_entry_point))]
+    WrongEntryPoint(PathBuf),
+
+    #[error("The entry point {0} is out of the adopted directory")]
+    #[diagnostic(
+        code(fs::entry_point_out_of_dir),
+        help("It's a path in the directory, without `..` and not absolute")
+    )]
+    EntryPointOutOfDir(PathBuf),
+
+    #[error("`{0}` can't be the name of a package")]
+    #[diagnostic(
+        code(fs::bad_pkg_name),
+        help("A package name is the name of its dir in the target dir, without `/` or `..`")
+    )]
+    BadPkgName(String),
+
+    #[error("Can't adopt {0}, it's in the target dir or holds it")]
+    #[diagnostic(code(fs::adopt_in_target_dir))]
+    AdoptInTargetDir(PathBuf),
+
+    #[error("Unknown link strategy `{strategy}` for {pkg}")]
+    #[diagnostic(
+        code(fs::unknown_link_strategy),
+        help("It's one of `symlink`, `hardlink`, `copy` or `wrapper-script`")
+    )]
+    UnknownLinkStrategy { pkg: String, strategy: String },
+
+    #[error("Unknown load path `{name}` for {pkg}")]
+    #[diagnostic(
+        code(fs::unknown_load_path),
+        help("Declare it in the config: `output {{ load-paths {{ {name} \"/some/bin\" }} }}`")
+    )]
+    UnknownLoadPath { pkg: String, name: String },
+
+    #[error("Refusing to remove {0}, it's not in the target dir")]
+    #[diagnostic(
+        code(fs::out_of_target_dir),
+        help("The path of the package in the db was changed by hand?")
+    )]
+    OutOfTargetDir(PathBuf),
+
+    #[error("Package already installed: {0}")]
+    #[diagnostic(code(fs::already_installed))]
+    AlreadyInstalled(String),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    DbError(#[from] DbError),
 }
 
-impl Fs {
-    pub fn new(target_dir: PathBuf, load_path: PathBuf, db_path: &PathBuf) -> Self {
-        let db = Db::new(db_path).unwrap();
+type Result<T, E = FsError> = std::result::Result<T, E>;
 
-        let _ = std::fs::create_dir_all(&target_dir);
-        let _ = std::fs::create_dir_all(&load_path);
+fn made_by_link(load_path: &Path, name: &str) -> bool {
+    std::fs::read_to_string(load_path.join(LINKS_FILE_NAME))
+        .is_ok_and(|links| links.lines().any(|l| l == name))
+}
+
+// one dir of the target dir: not `""`, `..`, `/etc` or `a/b`
+pub fn check_pkg_name(name: &str) -> Result<()> {
+    let mut components = Path::new(name).components();
+
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(dir)), None) if dir == name && !name.starts_with('.') => Ok(()),
+        _ => Err(FsError::BadPkgName(name.to_string())),
+    }
+}
+
+// `a/../b` is `b`, without looking at the fs (a dead link can't be
+// canonicalized)
+fn lexically_normal(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+    normal
+}
+
+// what the load path runs, the executable of a directory pkg
+fn entry_point(pkg: &Pkg) -> &PathBuf {
+    match &pkg.pkg_type {
+        PkgType::SingleExecutable => &pkg.path,
+        PkgType::Directory(entry_point) => entry_point,
+    }
+}
+
+// the command of `pkg run`: the entry point of the pkg, with the load paths
+// first in its PATH so it can call the other pkgs by their names too
+pub fn run_command(pkg: &Pkg, args: &[String], load_paths: &[PathBuf]) -> Result<Command> {
+    let paths = load_paths
+        .iter()
+        .cloned()
+        .chain(std::env::split_paths(
+            &std::env::var_os("PATH").unwrap_or_default(),
+        ))
+        .collect::<Vec<PathBuf>>();
+    let path = std::env::join_paths(paths).map_err(std::io::Error::other)?;
+
+    let mut command = Command::new(entry_point(pkg));
+    command.args(args).env("PATH", path);
+    Ok(command)
+}
+
+pub fn units(artifacts: &[Artifact]) -> impl Iterator<Item = &PathBuf> {
+    artifacts.iter().map(|artifact| match artifact {
+        Artifact::SystemdUnit(path) => path,
+    })
+}
+
+// puts the pkg in the load path unless it's already there, the new file is
+// made next to the old one and renamed over it, so the pkg is never missing
+// from the load path (a shell or an editor being updated)
+//
+// `points_to` is the source as the system of the load path sees it, what the
+// symlinks and the wrapper scripts use
+fn place(
+    strategy: LinkStrategy,
+    source: &Path,
+    points_to: &Path,
+    target: &Path,
+) -> std::io::Result<()> {
+    let wrapper = platform::wrapper_script(points_to);
+
+    let up_to_date = match strategy {
+        LinkStrategy::Symlink => std::fs::read_link(target).is_ok_and(|t| t == points_to),
+        LinkStrategy::Hardlink => platform::same_file(target, source),
+        // it may have changed in place
+        LinkStrategy::Copy => false,
+        LinkStrategy::WrapperScript => {
+            !target.is_symlink() && std::fs::read_to_string(target).is_ok_and(|c| c == wrapper)
+        }
+    };
+    if up_to_date {
+        return Ok(());
+    }
+
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = target.with_file_name(format!(".{name}.pkg-link"));
+
+    // a left over of an interrupted link
+    if tmp.symlink_metadata().is_ok() {
+        std::fs::remove_file(&tmp)?;
+    }
+
+    let made = match strategy {
+        LinkStrategy::Symlink => platform::symlink(points_to, &tmp),
+        LinkStrategy::Hardlink => std::fs::hard_link(source, &tmp),
+        LinkStrategy::Copy => std::fs::copy(source, &tmp).map(|_| ()),
+        LinkStrategy::WrapperScript => {
+            std::fs::write(&tmp, &wrapper).and_then(|_| platform::set_executable(&tmp))
+        }
+    };
+
+    made.and_then(|_| std::fs::rename(&tmp, target))
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&tmp);
+        })
+}
+
+// a file or a dir with all its content, the symlinks are copied as symlinks,
+// with their permissions and extended attributes, and synced to the disk
+pub fn copy_path(from: &Path, to: &Path) -> std::io::Result<()> {
+    let metadata = std::fs::symlink_metadata(from)?;
+
+    if metadata.is_symlink() {
+        return platform::symlink(&std::fs::read_link(from)?, to);
+    }
+
+    if metadata.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_path(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        std::fs::set_permissions(to, metadata.permissions())?;
+    } else {
+        std::fs::copy(from, to)?;
+    }
+
+    copy_xattrs(from, to)?;
+    std::fs::File::open(to)?.sync_all()
+}
+
+// `rename` can't move between two file systems (the working dir in /var/tmp
+// and the target dir on another disk), so it's copied then removed
+pub fn move_path(from: &Path, to: &Path) -> std::io::Result<()> {
+    match std::fs::rename(from, to) {
+        Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+            // copied next to `to` first, so `to` is never half copied
+            let name = to.file_name().unwrap_or_default().to_string_lossy();
+            let tmp = to.with_file_name(format!(".{name}.pkg-move"));
+            if tmp.symlink_metadata().is_ok() {
+                remove_path(&tmp).map_err(std::io::Error::other)?;
+            }
+
+            copy_path(from, &tmp)
+                .and_then(|_| std::fs::rename(&tmp, to))
+                .inspect_err(|_| {
+                    let _ = remove_path(&tmp);
+                })?;
+
+            remove_path(from).map_err(std::io::Error::other)?;
+            Ok(())
+        }
+        res => res,
+    }
+}
+
+// best effort, a file system without them (tmpfs...) is fine
+#[cfg(target_os = "linux")]
+fn copy_xattrs(from: &Path, to: &Path) -> std::io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let cpath =
+        |path: &Path| CString::new(path.as_os_str().as_bytes()).map_err(std::io::Error::other);
+    let (from, to) = (cpath(from)?, cpath(to)?);
+
+    // SAFETY: the buffers are as large as told to the calls
+    unsafe {
+        let size = libc::llistxattr(from.as_ptr(), std::ptr::null_mut(), 0);
+        if size <= 0 {
+            return Ok(());
+        }
 
+        let mut names = vec![0u8; size as usize];
+        let size = libc::llistxattr(from.as_ptr(), names.as_mut_ptr().cast(), names.len());
+        if size <= 0 {
+            return Ok(());
+        }
+        names.truncate(size as usize);
+
+        for name in names.split(|b| *b == 0).filter(|n| !n.is_empty()) {
+            let name = CString::new(name).map_err(std::io::Error::other)?;
+
+            let size = libc::lgetxattr(from.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0);
+            if size < 0 {
+                continue;
+            }
+            let mut value = vec![0u8; size as usize];
+            let size = libc::lgetxattr(
+                from.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            );
+            if size < 0 {
+                continue;
+            }
+
+            // the `security.` ones need privileges, they are skipped if refused
+            libc::lsetxattr(
+                to.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                size as usize,
+                0,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_xattrs(_from: &Path, _to: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+// what macos quarantines (the downloads) asks before it runs or is refused
+// by gatekeeper, a stored pkg is trusted like the bridge that got it. an
+// arm64 binary only runs signed, the unsigned ones (or the ones a patch
+// broke) are signed ad hoc. best effort, like the xattrs
+#[cfg(target_os = "macos")]
+fn clear_quarantine(path: &Path) -> std::io::Result<()> {
+    use std::{
+        ffi::CString,
+        os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+        process::{Command, Stdio},
+    };
+
+    let metadata = std::fs::symlink_metadata(path)?;
+    if metadata.is_symlink() {
+        return Ok(());
+    }
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            clear_quarantine(&entry?.path())?;
+        }
+    }
+
+    let cpath = CString::new(path.as_os_str().as_bytes()).map_err(std::io::Error::other)?;
+    // SAFETY: both are nul terminated, a file without it is fine
+    unsafe {
+        libc::removexattr(
+            cpath.as_ptr(),
+            c"com.apple.quarantine".as_ptr(),
+            libc::XATTR_NOFOLLOW,
+        );
+    }
+
+    if metadata.is_file()
+        && metadata.permissions().mode() & 0o111 != 0
+        && is_mach_o(path)
+        && let Some(codesign) = crate::host::find_command("codesign")
+    {
+        let run = |args: &[&str]| {
+            Command::new(&codesign)
+                .args(args)
+                .arg(path)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+        };
+
+        if !run(&["--verify", "--quiet"]) {
+            run(&["--force", "--sign", "-"]);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn clear_quarantine(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+// the magics of the thin (32 and 64 bits) and the fat binaries
+#[cfg(target_os = "macos")]
+fn is_mach_o(path: &Path) -> bool {
+    use std::io::Read;
+
+    let mut magic = [0u8; 4];
+    std::fs::File::open(path).is_ok_and(|mut file| file.read_exact(&mut magic).is_ok())
+        && matches!(
+            magic,
+            [0xcf, 0xfa, 0xed, 0xfe] | [0xce, 0xfa, 0xed, 0xfe] | [0xca, 0xfe, 0xba, 0xbe]
+        )
+}
+
+// the size of a file or a dir with all its content, symlinks aren't followed
+// the files and the symlinks under `path` (not followed), or `path` itself
+fn list_files(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(path)? {
+        list_files(&entry?.path(), files)?;
+    }
+    Ok(())
+}
+
+// the paths under `root` as a tree, like the `tree` command:
+//
+// /opt/pkg/github/nvim
+// ├── bin
+// │   └── nvim
+// └── share
+pub fn file_tree(root: &Path, paths: &[PathBuf]) -> String {
+    #[derive(Default)]
+    struct Node(BTreeMap<String, Node>);
+
+    fn render(node: &Node, prefix: &str, out: &mut String) {
+        let count = node.0.len();
+        for (i, (name, child)) in node.0.iter().enumerate() {
+            let last = i + 1 == count;
+            out.push_str(prefix);
+            out.push_str(if last { "└── " } else { "├── " });
+            out.push_str(name);
+            out.push('\n');
+            render(
+                child,
+                &format!("{prefix}{}", if last { "    " } else { "│   " }),
+                out,
+            );
+        }
+    }
+
+    let mut tree = Node::default();
+    for path in paths {
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+        let mut node = &mut tree;
+        for component in relative.components() {
+            node = node
+                .0
+                .entry(component.as_os_str().to_string_lossy().into_owned())
+                .or_default();
+        }
+    }
+
+    let mut out = format!("{}\n", root.display());
+    render(&tree, "", &mut out);
+    out
+}
+
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(metadata) = path.symlink_metadata() else {
+        return 0;
+    };
+
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    path.read_dir()
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| dir_size(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+pub fn remove_path(path: &Path) -> Result<bool> {
+    if path.symlink_metadata().is_err() {
+        return Ok(false);
+    }
+
+    if path.is_dir() && !path.is_symlink() {
+        std::fs::remove_dir_all(path)?;
+    } else {
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(true)
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes}{}", UNITS[0])
+    } else {
+        format!("{size:.1}{}", UNITS[unit])
+    }
+}
+
+impl Default for StorePermissions {
+    fn default() -> Self {
         Self {
+            normalize: true,
+            setuid_allowed: Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for LinkStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let strategy = match self {
+            Self::Symlink => "symlink",
+            Self::Hardlink => "hardlink",
+            Self::Copy => "copy",
+            Self::WrapperScript => "wrapper-script",
+        };
+
+        write!(f, "{strategy}")
+    }
+}
+
+impl std::str::FromStr for LinkStrategy {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "symlink" => Ok(Self::Symlink),
+            "hardlink" => Ok(Self::Hardlink),
+            "copy" => Ok(Self::Copy),
+            "wrapper-script" => Ok(Self::WrapperScript),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Fs {
+    // the db is the one of the `BridgeApi`, so they see the same pkgs
+    pub fn new(target_dir: PathBuf, load_path: PathBuf, db: Rc<Db>) -> Result<Self> {
+        std::fs::create_dir_all(&target_dir)?;
+        // a load path that is a file is reported by `link`
+        let _ = std::fs::create_dir_all(&load_path);
+
+        Ok(Self {
             target_dir,
             load_path,
             db,
+            link_strategy: LinkStrategy::default(),
+            pkg_link_strategies: HashMap::new(),
+            permissions: StorePermissions::default(),
+            extra_load_paths: BTreeMap::new(),
+            pkg_load_paths: HashMap::new(),
+            unit_dir: None,
+            root: None,
+        })
+    }
+
+    // without it the units of the pkgs aren't linked anywhere
+    pub fn with_unit_dir(mut self, unit_dir: Option<PathBuf>) -> Self {
+        self.unit_dir = unit_dir;
+        self
+    }
+
+    pub fn with_root(mut self, root: Option<PathBuf>) -> Self {
+        self.root = root;
+        self
+    }
+
+    // a path of the host as the system at the root sees it
+    fn in_root(&self, path: &Path) -> PathBuf {
+        match &self.root {
+            Some(root) => match path.strip_prefix(root) {
+                Ok(inside) => Path::new("/").join(inside),
+                Err(_) => path.to_path_buf(),
+            },
+            None => path.to_path_buf(),
+        }
+    }
+
+    // the other way, for what a link made in the root points to
+    fn on_host(&self, path: &Path) -> PathBuf {
+        match &self.root {
+            Some(root) if path.is_absolute() && !path.starts_with(root) => {
+                crate::config::under_root(root, path)
+            }
+            _ => path.to_path_buf(),
+        }
+    }
+
+    pub fn with_load_paths(
+        mut self,
+        extra: BTreeMap<String, PathBuf>,
+        per_pkg: HashMap<String, String>,
+    ) -> Result<Self> {
+        for (pkg, name) in &per_pkg {
+            if !extra.contains_key(name) {
+                return Err(FsError::UnknownLoadPath {
+                    pkg: pkg.clone(),
+                    name: name.clone(),
+                });
+            }
+        }
+
+        self.extra_load_paths = extra;
+        self.pkg_load_paths = per_pkg;
+        Ok(self)
+    }
+
+    pub fn with_permissions(mut self, permissions: StorePermissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    fn normalize_permissions(&self, pkg_name: &str, path: &Path) -> std::io::Result<()> {
+        if !self.permissions.normalize {
+            return Ok(());
         }
+
+        let keep_setuid = self
+            .permissions
+            .setuid_allowed
+            .iter()
+            .any(|p| p == pkg_name);
+        platform::normalize_permissions(path, keep_setuid)
+    }
+
+    pub fn with_link_strategies(
+        mut self,
+        default: LinkStrategy,
+        per_pkg: HashMap<String, LinkStrategy>,
+    ) -> Self {
+        self.link_strategy = default;
+        self.pkg_link_strategies = per_pkg;
+        self
     }
 
-    pub fn link(&self) -> Result<()> {
+    // links every pkg in its load path, and removes the links of the pkgs
+    // that are gone (or went to another load path), the other files in them
+    // aren't touched
+    pub fn link(&self) -> Result<LinkReport> {
         let pkgs = self.db.get_pkgs()?;
 
-        if !self.load_path.exists() {
-            std::fs::create_dir_all(&self.load_path).into_diagnostic()?;
-        } else if self.load_path.is_dir() {
-            std::fs::remove_dir_all(&self.load_path).into_diagnostic()?;
-            std::fs::create_dir_all(&self.load_path).into_diagnostic()?;
-        } else {
-            return Err(FsError::LoadPathIsFile(self.load_path.clone())).into_diagnostic()?;
+        // every load path is pruned, even the ones without pkgs anymore
+        let mut linked = self
+            .load_paths()
+            .map(|dir| (dir.clone(), HashSet::new()))
+            .collect::<BTreeMap<PathBuf, HashSet<PathBuf>>>();
+
+        for dir in linked.keys() {
+            if !dir.exists() {
+                std::fs::create_dir_all(dir)?;
+            } else if !dir.is_dir() {
+                return Err(FsError::LoadPathIsFile(dir.clone()));
+            }
         }
 
+        if let Some(unit_dir) = &self.unit_dir {
+            std::fs::create_dir_all(unit_dir)?;
+        }
+
+        let mut report = LinkReport::default();
+        let mut linked_units = HashSet::new();
+
         for pkg in pkgs {
-            let target = self.load_path.join(pkg.name);
+            let dir = self.load_path_of(&pkg.name);
+            let target = dir.join(self.link_name(&pkg));
+            let source = entry_point(&pkg);
+            let strategy = self.link_strategy_of(&pkg.name);
 
-            if target.exists() {
-                std::fs::remove_file(&target).into_diagnostic()?;
-            }
+            place(strategy, source, &self.in_root(source), &target)?;
+            linked.entry(dir.clone()).or_default().insert(target);
+            report.linked += 1;
 
-            match pkg.pkg_type {
-                PkgType::SingleExecutable => {
-                    std::os::unix::fs::symlink(&pkg.path, &target).into_diagnostic()?;
-                }
-                PkgType::Directory(ref entry_point) => {
-                    std::os::unix::fs::symlink(entry_point, &target).into_diagnostic()?;
+            if let Some(unit_dir) = &self.unit_dir {
+                for unit in units(&pkg.artifacts) {
+                    let target = unit_dir.join(unit.file_name().unwrap_or_default());
+                    place(LinkStrategy::Symlink, unit, &self.in_root(unit), &target)?;
+                    linked_units.insert(target);
                 }
             }
         }
 
+        for (dir, linked) in &linked {
+            self.prune_load_path(dir, linked, &mut report)?;
+        }
+        // the dead links in it may not be ours (a unit of a fs not mounted yet)
+        if let Some(unit_dir) = &self.unit_dir {
+            self.prune_unit_dir(unit_dir, &linked_units, &mut report)?;
+        }
+        report.pruned.sort();
+
+        Ok(report)
+    }
+
+    // the link step of a build, told to the sink
+    pub fn link_with_events(&self, sink: &mut dyn EventSink) -> Result<LinkReport> {
+        sink.emit(Event::LinkStarted);
+        match self.link() {
+            Ok(report) => {
+                sink.emit(Event::LinkDone {
+                    pruned: report.pruned.clone(),
+                });
+                Ok(report)
+            }
+            Err(err) => {
+                sink.emit(Event::LinkFailed {
+                    error: err.to_string(),
+                });
+                Err(err)
+            }
+        }
+    }
+
+    fn prune_load_path(
+        &self,
+        dir: &Path,
+        linked: &HashSet<PathBuf>,
+        report: &mut LinkReport,
+    ) -> Result<()> {
+        let links_file = dir.join(LINKS_FILE_NAME);
+        let previous = std::fs::read_to_string(&links_file)
+            .unwrap_or_default()
+            .lines()
+            .map(|name| dir.join(name))
+            .collect::<HashSet<PathBuf>>();
+
+        // ours if `link` made it, or if it points in the target dir. a dead
+        // link of the user or another tool is left alone, like in the unit dir
+        for entry in dir.read_dir()? {
+            let path = entry?.path();
+            if linked.contains(&path) || path == links_file {
+                continue;
+            }
+
+            let stale = previous.contains(&path) || self.points_in_target_dir(&path);
+
+            if stale {
+                remove_path(&path)?;
+                report.pruned.push(path);
+            }
+        }
+
+        let mut names = linked
+            .iter()
+            .filter_map(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect::<Vec<String>>();
+        names.sort();
+        std::fs::write(&links_file, names.join("\n"))?;
+
+        Ok(())
+    }
+
+    // only what `link` made in it (the `.pkg-links` file) is removed
+    fn prune_unit_dir(
+        &self,
+        dir: &Path,
+        linked: &HashSet<PathBuf>,
+        report: &mut LinkReport,
+    ) -> Result<()> {
+        let links_file = dir.join(LINKS_FILE_NAME);
+
+        for name in std::fs::read_to_string(&links_file)
+            .unwrap_or_default()
+            .lines()
+        {
+            let path = dir.join(name);
+            if !linked.contains(&path) && remove_path(&path)? {
+                report.pruned.push(path);
+            }
+        }
+
+        let mut names = linked
+            .iter()
+            .filter_map(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect::<Vec<String>>();
+        names.sort();
+        std::fs::write(&links_file, names.join("\n"))?;
+
         Ok(())
     }
 
+    // a link to a pkg (or to one that's gone), relative or not, resolved in
+    // the root when there's one
+    fn points_in_target_dir(&self, link: &Path) -> bool {
+        std::fs::read_link(link).is_ok_and(|points_to| {
+            let points_to = match link.parent() {
+                Some(dir) if points_to.is_relative() => dir.join(points_to),
+                _ => self.on_host(&points_to),
+            };
+            lexically_normal(&points_to).starts_with(&self.target_dir)
+        })
+    }
+
+    // the main load path and the named ones
+    fn load_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        std::iter::once(&self.load_path).chain(self.extra_load_paths.values())
+    }
+
+    fn link_strategy_of(&self, pkg_name: &str) -> LinkStrategy {
+        self.pkg_link_strategies
+            .get(pkg_name)
+            .copied()
+            .unwrap_or(self.link_strategy)
+    }
+
+    // its name in the load path, the pkg name but on windows
+    fn link_name(&self, pkg: &Pkg) -> String {
+        platform::link_name(
+            &pkg.name,
+            entry_point(pkg),
+            self.link_strategy_of(&pkg.name),
+        )
+    }
+
+    // the names are checked by `with_load_paths`
+    fn load_path_of(&self, pkg_name: &str) -> &PathBuf {
+        self.pkg_load_paths
+            .get(pkg_name)
+            .and_then(|name| self.extra_load_paths.get(name))
+            .unwrap_or(&self.load_path)
+    }
+
     pub fn store_or_overwrite(
         &self,
         pkgs: &mut [&mut Pkg],
         bridge_name: Option<&str>,
     ) -> Result<()> {
         if !self.target_dir.exists() {
-            std::fs::create_dir_all(&self.target_dir).into_diagnostic()?;
+            std::fs::create_dir_all(&self.target_dir)?;
         }
 
         for pkg in pkgs {
             let target_dir = self.target_dir.join(bridge_name.unwrap_or(""));
 
             if !target_dir.exists() {
-                std::fs::create_dir_all(&target_dir).into_diagnostic()?;
+                std::fs::create_dir_all(&target_dir)?;
             }
 
             let target = target_dir.join(&pkg.name);
 
             if target.exists() {
                 if target.is_dir() {
-                    std::fs::remove_dir_all(&target).into_diagnostic()?;
+                    std::fs::remove_dir_all(&target)?;
                 } else {
-                    std::fs::remove_file(&target).into_diagnostic()?;
+                    std::fs::remove_file(&target)?;
                 }
             }
 
-            std::fs::rename(&pkg.path, &target).into_diagnostic()?;
+            move_path(&pkg.path, &target)?;
+            self.normalize_permissions(&pkg.name, &target)?;
+            clear_quarantine(&target)?;
 
             if let PkgType::Directory(ref entry_point) = pkg.pkg_type {
                 // change the entry point parent to the target dir
@@ -108,32 +930,279 @@ impl Fs {
                 pkg.pkg_type = PkgType::Directory(PathBuf::from(new_entry_point_str))
             };
 
+            // they're checked to be in the pkg by the bridge api
+            pkg.artifacts = pkg
+                .artifacts
+                .iter()
+                .map(|artifact| match artifact.path().strip_prefix(&pkg.path) {
+                    Ok(relative) => artifact.with_path(target.join(relative)),
+                    Err(_) => artifact.clone(),
+                })
+                .collect();
+
             pkg.path = target;
         }
 
         Ok(())
     }
 
-    pub fn remove_pkgs(&self, pkgs: &[&String]) -> Result<bool> {
+    // takes a pkg installed by hand into `<target_dir>/adopted/<name>`, it's moved
+    // unless `copy`, the caller writes it in the db with the adopted bridge
+    pub fn adopt(
+        &self,
+        name: &str,
+        path: &Path,
+        entry_point: Option<&Path>,
+        copy: bool,
+    ) -> Result<Pkg> {
+        // the name is a dir of the target dir, anything else is removed
+        // somewhere else
+        check_pkg_name(name)?;
+
+        if !self
+            .db
+            .which_pkgs_are_installed(&[name.to_string()])?
+            .is_empty()
+        {
+            return Err(FsError::AlreadyInstalled(name.to_string()));
+        }
+
+        if !path.exists() {
+            return Err(FsError::AdoptPathNotFound(path.to_path_buf()));
+        }
+
+        let source = path.canonicalize()?;
+        let store = self
+            .target_dir
+            .canonicalize()
+            .unwrap_or_else(|_| self.target_dir.clone());
+        if source.starts_with(&store) || store.starts_with(&source) {
+            return Err(FsError::AdoptInTargetDir(path.to_path_buf()));
+        }
+
+        // relative to the dir, where it will be after the move
+        let entry_point = if path.is_dir() {
+            let entry_point = entry_point.ok_or(FsError::MissingEntryPoint(path.to_path_buf()))?;
+            if !entry_point
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(FsError::EntryPointOutOfDir(entry_point.to_path_buf()));
+            }
+            if !path.join(entry_point).is_file() {
+                return Err(FsError::WrongEntryPoint(path.join(entry_point)));
+            }
+            Some(entry_point.to_path_buf())
+        } else {
+            None
+        };
+
+        let target_dir = self.target_dir.join(ADOPTED_BRIDGE_NAME);
+        std::fs::create_dir_all(&target_dir)?;
+        let target = target_dir.join(name);
+
+        // a left over of a previous adopt that wasn't written in the db
+        remove_path(&target)?;
+
+        if copy {
+            copy_path(path, &target)?;
+        } else {
+            move_path(path, &target)?;
+        }
+        self.normalize_permissions(name, &target)?;
+
+        Ok(Pkg {
+            name: name.to_string(),
+            version: PkgVersion {
+                first_cell: "0".to_string(),
+                second_cell: "0".to_string(),
+                third_cell: "0".to_string(),
+            },
+            pkg_type: match entry_point {
+                Some(entry_point) => PkgType::Directory(target.join(entry_point)),
+                None => PkgType::SingleExecutable,
+            },
+            path: target,
+            artifacts: Vec::new(),
+            metadata: PkgMetadata::default(),
+        })
+    }
+
+    // every `<target_dir>/<bridge>/<pkg>`, the largest first
+    pub fn disk_usage(&self) -> Result<Vec<PkgUsage>> {
+        let mut usage = Vec::new();
+
+        if !self.target_dir.is_dir() {
+            return Ok(usage);
+        }
+
+        for bridge_dir in self.target_dir.read_dir()? {
+            let bridge_dir = bridge_dir?.path();
+
+            if !bridge_dir.is_dir() || bridge_dir.is_symlink() {
+                continue;
+            }
+
+            let bridge = bridge_dir
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+
+            for pkg in bridge_dir.read_dir()? {
+                let pkg = pkg?.path();
+
+                usage.push(PkgUsage {
+                    bridge: bridge.clone(),
+                    name: pkg
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned(),
+                    size: dir_size(&pkg),
+                });
+            }
+        }
+
+        usage.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+        Ok(usage)
+    }
+
+    pub fn pkg_files(&self, pkg: &Pkg) -> Result<PkgFiles> {
+        let mut files = PkgFiles::default();
+        list_files(&pkg.path, &mut files.store)?;
+        files.store.sort();
+
+        let name = self.link_name(pkg);
+        for dir in self.load_paths() {
+            let link = dir.join(&name);
+            // a link of the same name can be of the user or another tool
+            if (link.exists() && made_by_link(dir, &name)) || self.points_in_target_dir(&link) {
+                files.links.push(link);
+            }
+        }
+
+        if let Some(unit_dir) = &self.unit_dir {
+            for unit in units(&pkg.artifacts) {
+                let link = unit_dir.join(unit.file_name().unwrap_or_default());
+                if std::fs::read_link(&link).is_ok_and(|points_to| points_to == self.in_root(unit))
+                {
+                    files.links.push(link);
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    // the links of the installed pkgs that are missing or dead (the pkg was
+    // removed from the store by hand, a load path was cleaned...), `pkg link`
+    // makes them again
+    pub fn broken_links(&self) -> Result<Vec<PathBuf>> {
+        let mut broken = Vec::new();
+
+        for pkg in self.db.get_pkgs()? {
+            let link = self.load_path_of(&pkg.name).join(self.link_name(&pkg));
+            // follows the symlinks
+            if !link.exists() {
+                broken.push(link);
+            }
+
+            if let Some(unit_dir) = &self.unit_dir {
+                for unit in units(&pkg.artifacts) {
+                    let link = unit_dir.join(unit.file_name().unwrap_or_default());
+                    if !link.exists() {
+                        broken.push(link);
+                    }
+                }
+            }
+        }
+
+        broken.sort();
+        Ok(broken)
+    }
+
+    // what's in the target dir but not in the db (interrupted builds, manual copies...)
+    pub fn store_orphans(&self) -> Result<Vec<PathBuf>> {
+        let known = self
+            .db
+            .get_pkgs()?
+            .into_iter()
+            .map(|pkg| pkg.path)
+            .collect::<HashSet<PathBuf>>();
+
+        let mut orphans = Vec::new();
+
+        if !self.target_dir.is_dir() {
+            return Ok(orphans);
+        }
+
+        // the pkgs are in `<target_dir>/<bridge>/<pkg>`
+        for entry in self.target_dir.read_dir()? {
+            let path = entry?.path();
+
+            if known.contains(&path) {
+                continue;
+            }
+
+            if !path.is_dir() || path.is_symlink() {
+                orphans.push(path);
+                continue;
+            }
+
+            for entry in path.read_dir()? {
+                let path = entry?.path();
+
+                if !known.contains(&path) {
+                    orphans.push(path);
+                }
+            }
+        }
+
+        orphans.sort();
+        Ok(orphans)
+    }
+
+    // removes what the db says each pkg is (`<target_dir>/<bridge>/<pkg>`) and
+    // its link in the load path, each pkg with true if there was something
+    // to remove, a failed one doesn't stop the others
+    pub fn remove_pkgs(&self, pkgs: &[&String]) -> Result<Vec<(String, Result<bool>)>> {
         let pkgs = pkgs.iter().map(|s| s.to_string()).collect::<Vec<String>>();
-        let pkgs = pkgs.as_slice();
+        let pkgs = self.db.get_pkgs_by_name(&pkgs)?;
 
-        let mut removed = false;
+        Ok(pkgs
+            .into_iter()
+            .map(|pkg| {
+                let removed = self.remove_pkg(&pkg);
+                (pkg.name, removed)
+            })
+            .collect())
+    }
 
-        let pkgs = self.db.get_pkgs_by_name(pkgs)?;
+    fn remove_pkg(&self, pkg: &Pkg) -> Result<bool> {
+        // a db edited by hand shouldn't make pkg remove anything else
+        if !pkg.path.starts_with(&self.target_dir) {
+            return Err(FsError::OutOfTargetDir(pkg.path.clone()));
+        }
 
-        for pkg in pkgs {
-            let target = self.target_dir.join(&pkg.name);
+        let name = self.link_name(pkg);
+        let mut link_removed = false;
+        for dir in self.load_paths() {
+            let link = dir.join(&name);
+            if made_by_link(dir, &name) || self.points_in_target_dir(&link) {
+                link_removed |= remove_path(&link)?;
+            }
+        }
 
-            if target.exists() {
-                if target.is_dir() {
-                    std::fs::remove_dir_all(&target).into_diagnostic()?;
-                } else {
-                    std::fs::remove_file(&target).into_diagnostic()?;
+        if let Some(unit_dir) = &self.unit_dir {
+            for unit in units(&pkg.artifacts) {
+                let link = unit_dir.join(unit.file_name().unwrap_or_default());
+                if std::fs::read_link(&link).is_ok_and(|points_to| points_to == *unit) {
+                    link_removed |= remove_path(&link)?;
                 }
-                removed = true;
             }
         }
-        Ok(removed)
+
+        Ok(remove_path(&pkg.path)? || link_removed)
     }
 }
@@ -0,0 +1,190 @@
+// what the store and the load path do differently on unix and on windows,
+// the rest of fs.rs doesn't know which one it runs on
+use std::path::Path;
+
+#[cfg(windows)]
+use std::process::Command;
+
+use super::LinkStrategy;
+
+// the name of the pkg in the load path. windows finds the executables by their
+// extension (`PATHEXT`), so the link keeps the one of the source and the
+// wrapper is a `.cmd`
+#[cfg(unix)]
+pub fn link_name(name: &str, _source: &Path, _strategy: LinkStrategy) -> String {
+    name.to_string()
+}
+
+#[cfg(windows)]
+pub fn link_name(name: &str, source: &Path, strategy: LinkStrategy) -> String {
+    let extension = match strategy {
+        LinkStrategy::WrapperScript => Some("cmd".to_string()),
+        _ => source
+            .extension()
+            .map(|ext| ext.to_string_lossy().into_owned()),
+    };
+
+    match extension {
+        Some(ext)
+            if !name
+                .to_lowercase()
+                .ends_with(&format!(".{}", ext.to_lowercase())) =>
+        {
+            format!("{name}.{ext}")
+        }
+        _ => name.to_string(),
+    }
+}
+
+#[cfg(unix)]
+pub fn symlink(points_to: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(points_to, link)
+}
+
+// the symlinks need the developer mode (or an admin), without it a dir is
+// linked with a junction, they don't
+#[cfg(windows)]
+pub fn symlink(points_to: &Path, link: &Path) -> std::io::Result<()> {
+    // ERROR_PRIVILEGE_NOT_HELD
+    const NOT_HELD: i32 = 1314;
+
+    // a relative one is from the dir of the link
+    let resolved = link
+        .parent()
+        .map(|dir| dir.join(points_to))
+        .unwrap_or_else(|| points_to.to_path_buf());
+    if !resolved.is_dir() {
+        return std::os::windows::fs::symlink_file(points_to, link);
+    }
+
+    match std::os::windows::fs::symlink_dir(points_to, link) {
+        Err(err) if err.raw_os_error() == Some(NOT_HELD) => junction(points_to, link),
+        made => made,
+    }
+}
+
+// std has no junctions, `mklink` makes them
+#[cfg(windows)]
+fn junction(points_to: &Path, link: &Path) -> std::io::Result<()> {
+    let output = Command::new("cmd")
+        .arg("/C")
+        .arg("mklink")
+        .arg("/J")
+        .arg(link)
+        .arg(points_to)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "mklink /J {}: {}",
+            link.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(())
+}
+
+// the same file twice, a hard link of it
+#[cfg(unix)]
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (a.symlink_metadata(), b.metadata()) {
+        (Ok(a), Ok(b)) => a.ino() == b.ino() && a.dev() == b.dev(),
+        _ => false,
+    }
+}
+
+// the file index isn't in stable std, it's linked again
+#[cfg(windows)]
+pub fn same_file(_a: &Path, _b: &Path) -> bool {
+    false
+}
+
+// what runs the pkg by its real path
+#[cfg(unix)]
+pub fn wrapper_script(points_to: &Path) -> String {
+    format!(
+        "#!/bin/sh\nexec '{}' \"$@\"\n",
+        points_to.display().to_string().replace('\'', "'\\''")
+    )
+}
+
+#[cfg(windows)]
+pub fn wrapper_script(points_to: &Path) -> String {
+    format!("@echo off\r\n\"{}\" %*\r\n", points_to.display())
+}
+
+// a file the load path (or a bridge set) can run
+#[cfg(unix)]
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+// there are no exec bits, the extension makes it one (`find_command` looks
+// for them)
+#[cfg(windows)]
+pub fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(unix)]
+pub fn set_executable(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+}
+
+// the extension makes it one
+#[cfg(windows)]
+pub fn set_executable(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+// the exec bits are kept (all or nothing), the setuid and setgid only if
+// `keep_setuid`, the symlinks are only chowned
+#[cfg(unix)]
+pub fn normalize_permissions(path: &Path, keep_setuid: bool) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = std::fs::symlink_metadata(path)?;
+
+    // SAFETY: no arguments, it can't fail
+    if unsafe { libc::geteuid() } == 0 {
+        std::os::unix::fs::lchown(path, Some(0), Some(0))?;
+    }
+
+    if metadata.is_symlink() {
+        return Ok(());
+    }
+
+    let mode = metadata.permissions().mode();
+    let special = if keep_setuid { mode & 0o6000 } else { 0 };
+
+    let new_mode = if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            normalize_permissions(&entry?.path(), keep_setuid)?;
+        }
+        0o755
+    } else if mode & 0o111 != 0 {
+        0o755 | special
+    } else {
+        0o644
+    };
+
+    if mode & 0o7777 != new_mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(new_mode))?;
+    }
+
+    Ok(())
+}
+
+// the ACLs are inherited from the target dir, there are no modes to fix
+#[cfg(windows)]
+pub fn normalize_permissions(_path: &Path, _keep_setuid: bool) -> std::io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use miette::Diagnostic;
+use std::{path::Path, process::Command};
+use thiserror::Error;
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum GitError {
+    #[error(transparent)]
+    #[diagnostic(code(git::io_error), help("Is git installed and in the PATH?"))]
+    IoError(#[from] std::io::Error),
+
+    #[error("git {0} failed: {1}")]
+    #[diagnostic(code(git::command_failed))]
+    CommandFailed(&'static str, String),
+}
+
+fn run(subcommand: &'static str, command: &mut Command) -> Result<(), GitError> {
+    let output = command.output()?;
+
+    if !output.status.success() {
+        return Err(GitError::CommandFailed(
+            subcommand,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn clone(url: &str, dest: &Path, branch: Option<&str>) -> Result<(), GitError> {
+    let mut command = Command::new("git");
+    command.arg("clone").arg("--depth").arg("1");
+
+    if let Some(branch) = branch {
+        command.arg("--branch").arg(branch);
+    }
+
+    run("clone", command.arg(url).arg(dest))
+}
+
+pub fn pull(repo: &Path) -> Result<(), GitError> {
+    run(
+        "pull",
+        Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .arg("pull")
+            .arg("--ff-only"),
+    )
+}
+
+// the commit the checkout is at
+pub fn head(repo: &Path) -> Result<String, GitError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .arg("rev-parse")
+        .arg("HEAD")
+        .output()?;
+
+    if !output.status.success() {
+        return Err(GitError::CommandFailed(
+            "rev-parse",
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// `https://host/user/repo.git` -> `repo`
+pub fn repo_name(url: &str) -> String {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or(url);
+
+    name.strip_suffix(".git").unwrap_or(name).to_string()
+}
@@ -0,0 +1,134 @@
+// commands run by pkg around the link and the removal of the pkgs, from the
+// `hooks` section of the config (run once) and from the `post-link` and
+// `pre-remove` attributes of a pkg (run for it)
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::input::AttributeValue;
+
+pub const POST_LINK: &str = "post-link";
+pub const PRE_REMOVE: &str = "pre-remove";
+// for the hooks of the config, the pkg ones go to the log of their bridge
+pub const LOG_FILE_NAME: &str = "hooks.log";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hooks {
+    // after the pkg is linked (in a build), `fc-cache -f`...
+    pub post_link: Vec<String>,
+    // before the pkg is removed, they're kept in the db since a removed pkg
+    // isn't in the inputs anymore
+    pub pre_remove: Vec<String>,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum HookError {
+    #[error(transparent)]
+    #[diagnostic(code(hooks::io_error))]
+    IoError(#[from] std::io::Error),
+
+    #[error("The {hook} hook `{command}` failed with the exit code {code}")]
+    #[diagnostic(code(hooks::failed), help("Its output is in {log}"))]
+    Failed {
+        hook: &'static str,
+        command: String,
+        code: i32,
+        log: String,
+    },
+
+    #[error("The `{hook}` attribute of {pkg} should be a command or a list of commands")]
+    #[diagnostic(code(hooks::wrong_value))]
+    WrongValue { pkg: String, hook: &'static str },
+}
+
+type Result<T, E = HookError> = std::result::Result<T, E>;
+
+impl Hooks {
+    pub fn from_attributes(
+        pkg_name: &str,
+        attributes: &HashMap<String, AttributeValue>,
+    ) -> Result<Self> {
+        let commands = |hook: &'static str| -> Result<Vec<String>> {
+            let wrong_value = || HookError::WrongValue {
+                pkg: pkg_name.to_string(),
+                hook,
+            };
+
+            match attributes.get(hook) {
+                None => Ok(Vec::new()),
+                Some(AttributeValue::String(command)) => Ok(vec![command.clone()]),
+                Some(AttributeValue::List(commands)) => commands
+                    .iter()
+                    .map(|c| match c {
+                        AttributeValue::String(command) => Ok(command.clone()),
+                        _ => Err(wrong_value()),
+                    })
+                    .collect(),
+                Some(_) => Err(wrong_value()),
+            }
+        };
+
+        Ok(Self {
+            post_link: commands(POST_LINK)?,
+            pre_remove: commands(PRE_REMOVE)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.post_link.is_empty() && self.pre_remove.is_empty()
+    }
+}
+
+// runs the commands with `sh -c` one after the other, with `$pkg_name` when
+// it's for a pkg, their output goes to the log file under `|<label>|`, it
+// stops at the first one that fails
+pub fn run(
+    hook: &'static str,
+    commands: &[String],
+    pkg_name: Option<&str>,
+    label: &str,
+    log_file: &Path,
+) -> Result<()> {
+    if commands.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(parent) = log_file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)?;
+
+    for command in commands {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command).stdin(Stdio::null());
+        if let Some(pkg_name) = pkg_name {
+            cmd.env("pkg_name", pkg_name);
+        }
+        let output = cmd.output()?;
+
+        log.write_all(format!("\n|{label}|:::::::\n|HOOK={hook}| {command}\n").as_bytes())?;
+        log.write_all(&output.stdout)?;
+        log.write_all(&output.stderr)?;
+
+        if !output.status.success() {
+            return Err(HookError::Failed {
+                hook,
+                command: command.clone(),
+                code: output.status.code().unwrap_or(-1),
+                log: log_file.display().to_string(),
+            });
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,352 @@
+use std::path::{Path, PathBuf};
+
+use crate::{DEFAULT_CACHE_DIR, DEFAULT_LOG_DIR, DEFAULT_WORKING_DIR};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Container {
+    Docker,
+    Podman,
+    Kubernetes,
+    Lxc,
+    Chroot,
+    Other(String), // from the `container` env var (systemd-nspawn, flatpak...)
+}
+
+// how pkg runs a command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Privilege {
+    // as it is: as root, or for a command that doesn't need it
+    AsIs,
+    // root's state read by a user: the db read only and the logs in its home
+    Unprivileged,
+    // pkg runs itself again as root
+    Escalate,
+}
+
+// what pkg knows about the machine it's running on
+#[derive(Debug)]
+pub struct HostEnv {
+    pub container: Option<Container>,
+    pub systemd: bool,
+    pub var_writable: bool,
+    pub overlay_root: bool,
+}
+
+impl std::fmt::Display for Container {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Container::Docker => write!(f, "docker"),
+            Container::Podman => write!(f, "podman"),
+            Container::Kubernetes => write!(f, "kubernetes"),
+            Container::Lxc => write!(f, "lxc"),
+            Container::Chroot => write!(f, "chroot"),
+            Container::Other(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+fn detect_container() -> Option<Container> {
+    if Path::new("/.dockerenv").exists() {
+        return Some(Container::Docker);
+    }
+
+    if Path::new("/run/.containerenv").exists() {
+        return Some(Container::Podman);
+    }
+
+    let env = std::env::var("container").ok();
+    let cgroup = std::fs::read_to_string("/proc/1/cgroup").unwrap_or_default();
+    if let Some(container) = container_of(env.as_deref(), &cgroup) {
+        return Some(container);
+    }
+
+    // the root of pid 1 is not our root (only readable as root)
+    #[cfg(unix)]
+    if let (Ok(init_root), Ok(root)) = (std::fs::metadata("/proc/1/root/."), std::fs::metadata("/"))
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        if init_root.dev() != root.dev() || init_root.ino() != root.ino() {
+            return Some(Container::Chroot);
+        }
+    }
+
+    None
+}
+
+// from the `container` env var and the cgroup of pid 1
+pub fn container_of(env: Option<&str>, cgroup: &str) -> Option<Container> {
+    if let Some(name) = env
+        && !name.is_empty()
+    {
+        return Some(Container::Other(name.to_string()));
+    }
+
+    if cgroup.contains("kubepods") {
+        Some(Container::Kubernetes)
+    } else if cgroup.contains("docker") {
+        Some(Container::Docker)
+    } else if cgroup.contains("lxc") {
+        Some(Container::Lxc)
+    } else {
+        None
+    }
+}
+
+fn is_overlay_root() -> bool {
+    std::fs::read_to_string("/proc/mounts")
+        .map(|mounts| {
+            mounts.lines().any(|line| {
+                let mut fields = line.split_whitespace().skip(1);
+                fields.next() == Some("/") && fields.next() == Some("overlay")
+            })
+        })
+        .unwrap_or(false)
+}
+
+// NOTE: `access` and not the permissions bits, because a read only mount
+// is still 0755 for root
+#[cfg(unix)]
+fn is_writable(path: &str) -> bool {
+    let Ok(path) = std::ffi::CString::new(path) else {
+        return false;
+    };
+
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+// the read only attribute, there's no `access`
+#[cfg(windows)]
+fn is_writable(path: &str) -> bool {
+    std::fs::metadata(path).is_ok_and(|m| !m.permissions().readonly())
+}
+
+#[cfg(unix)]
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+
+    let res = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if res != 0 {
+        return String::new();
+    }
+
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(windows)]
+pub fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+// the os the bridges get in `pkg_os`, the name `uname` gives on macos
+// (`darwin`, what the release assets are named after), the rust one elsewhere
+pub fn os() -> &'static str {
+    match std::env::consts::OS {
+        "macos" => "darwin",
+        os => os,
+    }
+}
+
+// `darwin` and `macos` are the same os in the inputs
+pub fn canonical_os(os: &str) -> &str {
+    match os {
+        "darwin" => "macos",
+        os => os,
+    }
+}
+
+// the executable `name` in the PATH
+pub fn find_command(name: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default())
+        .flat_map(|dir| command_names(name).into_iter().map(move |n| dir.join(n)))
+        .find(|path| crate::fs::platform::is_executable(path))
+}
+
+#[cfg(unix)]
+fn command_names(name: &str) -> Vec<String> {
+    vec![name.to_string()]
+}
+
+// `name.exe`, `name.cmd`... what `PATHEXT` says windows runs, and `name`
+// itself if it has one already
+#[cfg(windows)]
+fn command_names(name: &str) -> Vec<String> {
+    std::env::var("PATHEXT")
+        .unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string())
+        .split(';')
+        .filter(|ext| !ext.is_empty())
+        .map(|ext| format!("{name}{}", ext.to_lowercase()))
+        .chain(std::iter::once(name.to_string()))
+        .collect()
+}
+
+// runs the command in place of pkg, it only returns if it couldn't run it.
+// windows can't replace a process, it's waited for and pkg exits with its code
+#[cfg(unix)]
+pub fn exec(command: &mut std::process::Command) -> std::io::Error {
+    use std::os::unix::process::CommandExt;
+
+    command.exec()
+}
+
+#[cfg(windows)]
+pub fn exec(command: &mut std::process::Command) -> std::io::Error {
+    match command.status() {
+        Ok(status) => std::process::exit(status.code().unwrap_or(crate::exit::FAILURE.into())),
+        Err(err) => err,
+    }
+}
+
+// a desktop to show a dialog on (the agent of polkit, a notification)
+pub fn has_graphical_session() -> bool {
+    ["WAYLAND_DISPLAY", "DISPLAY"]
+        .iter()
+        .any(|var| std::env::var_os(var).is_some_and(|v| !v.is_empty()))
+}
+
+// how the missing tools can be installed on this machine, `<cmd> <tools>`
+pub fn install_command() -> Option<&'static str> {
+    [
+        ("apt-get", "apt-get install"),
+        ("dnf", "dnf install"),
+        ("pacman", "pacman -S"),
+        ("apk", "apk add"),
+        ("zypper", "zypper install"),
+        ("brew", "brew install"),
+    ]
+    .into_iter()
+    .find(|(manager, _)| find_command(manager).is_some())
+    .map(|(_, install)| install)
+}
+
+// the libc of the host (not the one pkg was built with): `musl`, `glibc`,
+// or `unknown` off linux
+pub fn libc() -> &'static str {
+    if std::env::consts::OS != "linux" {
+        return "unknown";
+    }
+
+    let musl = ["/lib", "/usr/lib"].iter().any(|dir| {
+        std::fs::read_dir(dir).is_ok_and(|entries| {
+            entries
+                .flatten()
+                .any(|e| e.file_name().to_string_lossy().starts_with("ld-musl-"))
+        })
+    });
+
+    if musl { "musl" } else { "glibc" }
+}
+
+fn home_dir() -> PathBuf {
+    std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" })
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+// the dirs of a user in its home, for the user mode and for an unprivileged
+// user that reads the state of root's pkg (`pkg info`, `pkg outdated`...)
+pub fn user_log_dir() -> PathBuf {
+    home_dir().join(".local/state/pkg/log")
+}
+
+pub fn user_working_dir() -> PathBuf {
+    home_dir().join(".cache/pkg/tmp")
+}
+
+// a default dir of pkg, as it is on unix. on windows they're relative to
+// `%LOCALAPPDATA%` (there's no system wide place a user can write to)
+#[cfg(unix)]
+pub fn platform_dir(path: &str) -> PathBuf {
+    PathBuf::from(path)
+}
+
+#[cfg(windows)]
+pub fn platform_dir(path: &str) -> PathBuf {
+    std::env::var_os("LOCALAPPDATA")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home_dir().join("AppData/Local"))
+        .join(path)
+}
+
+// root on unix, an admin on windows
+#[cfg(unix)]
+pub fn is_elevated() -> bool {
+    // SAFETY: no arguments, it can't fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+// an admin token, what "run as administrator" gives
+#[cfg(windows)]
+pub fn is_elevated() -> bool {
+    #[link(name = "shell32")]
+    unsafe extern "system" {
+        fn IsUserAnAdmin() -> i32;
+    }
+
+    // SAFETY: no arguments, it only reads the token of the process
+    unsafe { IsUserAnAdmin() != 0 }
+}
+
+impl HostEnv {
+    pub fn detect() -> Self {
+        Self {
+            container: detect_container(),
+            systemd: Path::new("/run/systemd/system").is_dir(),
+            var_writable: is_writable("/var"),
+            overlay_root: is_overlay_root(),
+        }
+    }
+
+    pub fn is_container(&self) -> bool {
+        self.container.is_some() || self.overlay_root
+    }
+
+    // inside a container with a read only /var, keep every thing under $HOME
+    pub fn user_mode(&self) -> bool {
+        self.is_container() && !self.var_writable
+    }
+
+    // a user in a container keeps the system paths (the target dir, the db...),
+    // so it's asked for root like anywhere else, unless they're its own
+    pub fn privilege(&self, reads_only: bool, needs_root: bool, elevated: bool) -> Privilege {
+        if elevated {
+            Privilege::AsIs
+        } else if reads_only {
+            Privilege::Unprivileged
+        } else if needs_root && !(self.is_container() && self.var_writable) {
+            Privilege::Escalate
+        } else {
+            Privilege::AsIs
+        }
+    }
+
+    // daemons (watch, timers...) need a service manager to live in
+    pub fn supports_daemons(&self) -> bool {
+        self.systemd && !self.is_container()
+    }
+
+    pub fn log_dir(&self) -> PathBuf {
+        if self.user_mode() {
+            user_log_dir()
+        } else {
+            platform_dir(DEFAULT_LOG_DIR)
+        }
+    }
+
+    pub fn working_dir(&self) -> PathBuf {
+        if self.user_mode() {
+            user_working_dir()
+        } else {
+            platform_dir(DEFAULT_WORKING_DIR)
+        }
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        if self.user_mode() {
+            home_dir().join(".cache/pkg/cache")
+        } else {
+            platform_dir(DEFAULT_CACHE_DIR)
+        }
+    }
+}
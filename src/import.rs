@@ -0,0 +1,206 @@
+// `pkg import`, the pkgs of a machine that isn't on pkg yet (a Brewfile, an
+// `apt list --installed`, a `cargo install --list`) made into inputs, each kind
+// of pkg goes to the bridge it's mapped to
+use std::{collections::BTreeMap, path::PathBuf};
+
+use kdl::{KdlDocument, KdlEntry, KdlNode};
+use miette::Diagnostic;
+use thiserror::Error;
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum ImportError {
+    #[error(transparent)]
+    #[diagnostic(code(import::io_error))]
+    IoError(#[from] std::io::Error),
+
+    #[error("{0:?} exists already")]
+    #[diagnostic(
+        code(import::exists),
+        help("Choose another `--output`, pkg doesn't overwrite the inputs")
+    )]
+    Exists(PathBuf),
+
+    #[error("`{0}` should be `<kind>=<bridge>`")]
+    #[diagnostic(code(import::bad_map), help("e.g. `--map cask=brew-cask`"))]
+    BadMap(String),
+}
+
+type Result<T, E = ImportError> = std::result::Result<T, E>;
+
+// a pkg of the list, its kind chooses the bridge
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedPkg {
+    // `brew`, `cask`, `mas`, `apt`, `cargo`...
+    pub kind: String,
+    // the name in the load path
+    pub name: String,
+    // what the bridge gets
+    pub input: String,
+}
+
+impl ImportedPkg {
+    fn new(kind: &str, name: &str, input: &str) -> Self {
+        Self {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            input: input.to_string(),
+        }
+    }
+}
+
+// `brew "fd"`, `cask "firefox"`, `mas "Xcode", id: 497799835`..., the taps
+// aren't pkgs
+pub fn parse_brewfile(src: &str) -> Vec<ImportedPkg> {
+    let mut pkgs = Vec::new();
+
+    for line in src.lines().map(str::trim) {
+        let Some((kind, rest)) = line.split_once(char::is_whitespace) else {
+            continue;
+        };
+        if kind.starts_with('#') || kind == "tap" {
+            continue;
+        }
+        let Some(quoted) = rest
+            .trim()
+            .strip_prefix('"')
+            .and_then(|r| r.split('"').next())
+        else {
+            continue;
+        };
+
+        // `user/tap/fd` is `fd`
+        let name = quoted.rsplit('/').next().unwrap_or(quoted);
+        let input = match kind {
+            // the id is what installs it
+            "mas" => rest
+                .split_once("id:")
+                .map(|(_, id)| id.trim().trim_end_matches(',').to_string())
+                .unwrap_or_else(|| quoted.to_string()),
+            _ => quoted.to_string(),
+        };
+
+        pkgs.push(ImportedPkg::new(kind, name, &input));
+    }
+
+    pkgs
+}
+
+// `apt list --installed` (the automatic ones are deps, they're skipped),
+// `apt-mark showmanual` or `dpkg --get-selections`
+pub fn parse_apt(src: &str) -> Vec<ImportedPkg> {
+    let mut pkgs = Vec::new();
+
+    for line in src.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("Listing") || line.starts_with('#') {
+            continue;
+        }
+
+        let name = if let Some((name, rest)) = line.split_once('/') {
+            if rest.contains("automatic]") {
+                continue;
+            }
+            name
+        } else {
+            let mut fields = line.split_whitespace();
+            let name = fields.next().unwrap_or_default();
+            if fields.next().is_some_and(|state| state != "install") {
+                continue;
+            }
+            name
+        };
+        // `libc6:amd64`
+        let name = name.split(':').next().unwrap_or(name);
+
+        pkgs.push(ImportedPkg::new("apt", name, name));
+    }
+
+    pkgs
+}
+
+// `cargo install --list`, a crate with one binary is named after it
+// (`rg "ripgrep"`)
+pub fn parse_cargo(src: &str) -> Vec<ImportedPkg> {
+    let mut pkgs = Vec::new();
+    let mut current: Option<(String, Vec<String>)> = None;
+
+    let mut push = |current: Option<(String, Vec<String>)>| {
+        if let Some((krate, bins)) = current {
+            let name = match bins.as_slice() {
+                [bin] => bin.clone(),
+                _ => krate.clone(),
+            };
+            pkgs.push(ImportedPkg::new("cargo", &name, &krate));
+        }
+    };
+
+    for line in src.lines() {
+        if line.starts_with(char::is_whitespace) {
+            if let Some((_, bins)) = &mut current {
+                bins.push(line.trim().to_string());
+            }
+        } else if let Some(krate) = line.split_whitespace().next() {
+            push(current.take());
+            current = Some((krate.to_string(), Vec::new()));
+        }
+    }
+    push(current);
+
+    pkgs
+}
+
+// `--map cask=brew-cask`, the kinds go to the bridges of the same name by
+// default
+pub fn parse_map(maps: &[String]) -> Result<BTreeMap<String, String>> {
+    maps.iter()
+        .map(|map| match map.split_once('=') {
+            Some((kind, bridge)) if !kind.is_empty() && !bridge.is_empty() => {
+                Ok((kind.to_string(), bridge.to_string()))
+            }
+            _ => Err(ImportError::BadMap(map.clone())),
+        })
+        .collect()
+}
+
+// the inputs of the pkgs, a bridge block per kind, the blocks of the bridges
+// that aren't in the bridges set are commented out with a slashdash (like
+// `pkg inputs init` does). the unmapped pkgs are returned too
+pub fn to_kdl<'a>(
+    pkgs: &'a [ImportedPkg],
+    map: &BTreeMap<String, String>,
+    available_bridges: &[String],
+) -> (String, Vec<&'a ImportedPkg>) {
+    let mut by_bridge: BTreeMap<&str, Vec<&ImportedPkg>> = BTreeMap::new();
+    for pkg in pkgs {
+        let bridge = map.get(&pkg.kind).unwrap_or(&pkg.kind);
+        by_bridge.entry(bridge.as_str()).or_default().push(pkg);
+    }
+
+    let mut kdl = String::new();
+    let mut unmapped = Vec::new();
+
+    for (bridge, pkgs) in by_bridge {
+        let mut node = KdlNode::new(bridge);
+        for pkg in &pkgs {
+            let mut pkg_node = KdlNode::new(pkg.name.as_str());
+            if pkg.input != pkg.name {
+                pkg_node.push(KdlEntry::new(pkg.input.clone()));
+            }
+            node.ensure_children().nodes_mut().push(pkg_node);
+        }
+
+        let mut doc = KdlDocument::new();
+        doc.nodes_mut().push(node);
+        doc.autoformat();
+
+        if !available_bridges.iter().any(|b| b == bridge) {
+            kdl.push_str(&format!(
+                "// no `{bridge}` bridge in the bridges set, add one or `--map` its pkgs to another\n/-"
+            ));
+            unmapped.extend(pkgs);
+        }
+        kdl.push_str(&doc.to_string());
+        kdl.push('\n');
+    }
+
+    (kdl, unmapped)
+}
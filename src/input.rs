@@ -1,9 +1,16 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use kdl::{KdlDocument, KdlError, KdlNode};
-use miette::{Diagnostic, IntoDiagnostic, Report, Result};
+use kdl::{KdlDiagnostic, KdlDocument, KdlEntry, KdlError, KdlNode};
+use miette::{Diagnostic, NamedSource, SourceSpan};
 use thiserror::Error;
 
+mod format;
+
 #[derive(Debug)]
 pub enum PkgType {
     SingleExecutable, // so the entry point is the pkg path itself
@@ -16,6 +23,8 @@ pub enum AttributeValue {
     Integer(i64),
     Float(f64),
     Boolean(bool),
+    List(Vec<AttributeValue>),
+    Map(BTreeMap<String, AttributeValue>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -23,6 +32,42 @@ pub struct PkgDeclaration {
     pub name: String,
     pub input: String,
     pub attributes: HashMap<String, AttributeValue>,
+    pub tags: Vec<String>,
+    // runs the pkg operations instead of the bridge it's declared in
+    pub bridge: Option<BridgeOverride>,
+}
+
+// `bridge "./my-bridge"` (a bridge dir or a `run` file, relative to the input
+// file) or `exec "#!/bin/sh ..."` (the `run` file it self)
+#[derive(Debug, Clone, PartialEq)]
+pub enum BridgeOverride {
+    Path(PathBuf),
+    Exec(String),
+}
+
+// what the inputs are evaluated against, the `when` nodes and the `${VAR}`s
+#[derive(Debug, Clone, Default)]
+pub struct InputContext {
+    pub hostname: String,
+    pub os: String,
+    pub arch: String,
+    pub profile: Option<String>,
+    pub vars: HashMap<String, String>,
+}
+
+// which declarations a build takes, from `--tag` and `--exclude-tag`
+#[derive(Debug, Default)]
+pub struct TagFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+// which pkgs a build touches, from `--only` and `--exclude`, the others are
+// left as they are (not installed, not removed)
+#[derive(Debug, Default)]
+pub struct NameFilter {
+    pub only: Vec<String>,
+    pub exclude: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -35,6 +80,11 @@ pub struct Bridge {
 pub struct Input {
     pub path: PathBuf,
     pub bridges: Vec<Bridge>,
+    // the bridges left out by their `when` on this machine, they can lose
+    // all their pkgs on purpose
+    pub when_excluded: Vec<String>,
+    // the pkgs left out by their own `when`, the rest of their bridge isn't
+    pub when_excluded_pkgs: Vec<String>,
 }
 
 #[derive(Error, Debug, Diagnostic)]
@@ -47,32 +97,203 @@ pub enum InputError {
     #[diagnostic(code(input::parse_error))]
     KdlError(#[from] KdlError),
 
-    #[error("Unsupported attribute type {0}")]
-    #[diagnostic(code(input::wrong_value))]
-    UnSupportedAttributeType(String),
+    #[error("Failed to parse {path:?}")]
+    #[diagnostic(code(input::parse_error))]
+    ParseError {
+        path: PathBuf,
+        #[related]
+        errors: Vec<KdlDiagnostic>,
+    },
+
+    #[error("Failed to parse {path:?}: {message}")]
+    #[diagnostic(code(input::format_error))]
+    FormatError { path: PathBuf, message: String },
+
+    #[error("Unsupported attribute type {value}")]
+    #[diagnostic(
+        code(input::wrong_value),
+        help("Attributes can be strings, integers, floats or booleans")
+    )]
+    UnSupportedAttributeType {
+        value: String,
+        #[source_code]
+        src: NamedSource<Arc<String>>,
+        #[label("this attribute")]
+        span: SourceSpan,
+    },
 
     #[error("Missing required field")]
-    #[diagnostic(code(input::missing_field))]
-    MissingField,
+    #[diagnostic(
+        code(input::missing_field),
+        help("A bridge node needs a block with the packages: `bridge {{ pkg }}`")
+    )]
+    MissingField {
+        #[source_code]
+        src: NamedSource<Arc<String>>,
+        #[label("this bridge has no packages block")]
+        span: SourceSpan,
+    },
 
     #[error("Invalid attribute format")]
-    #[diagnostic(code(input::invalid_attribute))]
-    InvalidAttribute,
+    #[diagnostic(
+        code(input::invalid_attribute),
+        help(
+            "The input is the first argument and should be a string, the attributes are `key=value`"
+        )
+    )]
+    InvalidAttribute {
+        #[source_code]
+        src: NamedSource<Arc<String>>,
+        #[label("here")]
+        span: SourceSpan,
+    },
 
-    #[error("Duplicate package declaration: {0}")]
+    #[error("Duplicate package declaration: {name}")]
     #[diagnostic(code(input::duplicate_pkg))]
-    DuplicatePkgDeclaration(String),
+    DuplicatePkgDeclaration {
+        name: String,
+        #[source_code]
+        src: NamedSource<Arc<String>>,
+        #[label("declared again here")]
+        span: SourceSpan,
+        #[related]
+        first: Vec<FirstDeclaration>,
+    },
+
+    #[error("Unknown condition `{key}`")]
+    #[diagnostic(
+        code(input::unknown_condition),
+        help("`when` can match hostname, os, arch and profile, e.g. `when hostname=\"laptop\"`")
+    )]
+    UnknownCondition {
+        key: String,
+        #[source_code]
+        src: NamedSource<Arc<String>>,
+        #[label("this condition")]
+        span: SourceSpan,
+    },
+
+    #[error("Undefined variable `{name}`")]
+    #[diagnostic(
+        code(input::undefined_variable),
+        help("Declare it in a `vars` node of the inputs or of the config")
+    )]
+    UndefinedVariable {
+        name: String,
+        #[source_code]
+        src: NamedSource<Arc<String>>,
+        #[label("used here")]
+        span: SourceSpan,
+    },
+
+    #[error("No preset named `{name}`")]
+    #[diagnostic(
+        code(input::unknown_preset),
+        help("Declare it with a `preset \"{name}\" {{ .. }}` node in one of the inputs files")
+    )]
+    UnknownPreset {
+        name: String,
+        #[source_code]
+        src: NamedSource<Arc<String>>,
+        #[label("used here")]
+        span: SourceSpan,
+    },
+
+    #[error("Nothing matches the include {pattern:?}")]
+    #[diagnostic(
+        code(input::include_not_found),
+        help("The include paths are relative to the file that includes them")
+    )]
+    IncludeNotFound {
+        pattern: String,
+        #[source_code]
+        src: NamedSource<Arc<String>>,
+        #[label("this include")]
+        span: SourceSpan,
+    },
+
+    #[error("Include cycle: {path:?} includes itself")]
+    #[diagnostic(code(input::include_cycle))]
+    IncludeCycle {
+        path: PathBuf,
+        #[source_code]
+        src: NamedSource<Arc<String>>,
+        #[label("this include")]
+        span: SourceSpan,
+    },
+
+    #[error("Unknown bridge `{bridge}`, used by {pkgs} packages")]
+    #[diagnostic(
+        code(input::unknown_bridge),
+        help("Add it to the bridges set or remove its declarations")
+    )]
+    UnknownBridge { bridge: String, pkgs: usize },
+
+    #[error("`{pkg}` is missing the `{attribute}` attribute, required by the `{bridge}` bridge")]
+    #[diagnostic(code(input::missing_required_attribute))]
+    MissingRequiredAttribute {
+        pkg: String,
+        bridge: String,
+        attribute: String,
+    },
+
+    #[error("`{command}` is not in the PATH, it's needed by the `{bridge}` bridge")]
+    #[diagnostic(code(input::missing_host_command))]
+    MissingHostCommand { command: String, bridge: String },
+
+    #[error("Found {0} problems in the inputs")]
+    #[diagnostic(code(input::check_failed))]
+    CheckFailed(usize),
+
+    #[error("The inputs dir already exists: {0}")]
+    #[diagnostic(
+        code(input::inputs_dir_exists),
+        help("Remove it or pull it instead of initializing it again")
+    )]
+    InputsDirExists(PathBuf),
+}
+
+type Result<T, E = InputError> = std::result::Result<T, E>;
+
+// where a duplicated pkg was declared first, it can be in another file
+#[derive(Error, Debug, Diagnostic)]
+#[error("first declared here")]
+pub struct FirstDeclaration {
+    #[source_code]
+    src: NamedSource<Arc<String>>,
+    #[label("first declaration")]
+    span: SourceSpan,
+}
+
+// a parsed input file, the source is kept to point at it in the errors
+struct InputFile {
+    path: PathBuf,
+    src: Arc<String>,
+    doc: KdlDocument,
+    // a toml/yaml file, the src is the kdl it was converted to
+    converted: bool,
+}
+
+impl InputFile {
+    fn named_source(&self) -> NamedSource<Arc<String>> {
+        let name = if self.converted {
+            format!("{} (as kdl)", self.path.display())
+        } else {
+            self.path.display().to_string()
+        };
+
+        NamedSource::new(name, self.src.clone())
+    }
 }
 
 fn detect_pkg_kdl_files(path: &PathBuf) -> Result<Vec<PathBuf>> {
     let mut inputs_paths = Vec::new();
-    for entry in fs::read_dir(path).into_diagnostic()? {
-        let entry = entry.into_diagnostic()?;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
         let path = entry.path();
 
         if path.is_file() {
-            if let Some(ext) = path.extension().and_then(|e| e.to_str())
-                && ext.eq_ignore_ascii_case("kdl")
+            if format::is_input_file(&path)
                 && let Some(file_name) = path.file_name().and_then(|n| n.to_str())
                 && !file_name.starts_with('.')
             {
@@ -85,85 +306,674 @@ fn detect_pkg_kdl_files(path: &PathBuf) -> Result<Vec<PathBuf>> {
     Ok(inputs_paths)
 }
 
-fn parse_inputs_kdl(inputs_paths: &[PathBuf]) -> Result<Vec<KdlDocument>> {
-    inputs_paths
+// the files that failed to parse are reported and skipped, so the others
+// can still be checked, the `include`d files are loaded after the file that
+// includes them
+fn parse_inputs_kdl(inputs_paths: &[PathBuf]) -> Result<(Vec<InputFile>, Vec<InputError>)> {
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+
+    // with the chain of files that included it, to detect the cycles
+    let mut queue = inputs_paths
+        .iter()
+        .map(|path| (path.clone(), Vec::new()))
+        .collect::<std::collections::VecDeque<(PathBuf, Vec<PathBuf>)>>();
+    let mut seen = std::collections::HashSet::new();
+
+    while let Some((path, chain)) = queue.pop_front() {
+        let canonical = fs::canonicalize(&path)?;
+        if !seen.insert(canonical.clone()) {
+            continue;
+        }
+
+        let src = fs::read_to_string(&path)?;
+
+        let converted = format::for_path(&path);
+        let src = match converted {
+            Some(format) => match format::to_kdl(format, &src) {
+                Ok(src) => src,
+                Err(message) => {
+                    errors.push(InputError::FormatError {
+                        path: path.clone(),
+                        message,
+                    });
+                    continue;
+                }
+            },
+            None => src,
+        };
+
+        let doc = match src.parse::<KdlDocument>() {
+            Ok(doc) => doc,
+            Err(err) => {
+                errors.push(InputError::ParseError {
+                    path: path.clone(),
+                    errors: err.diagnostics,
+                });
+                continue;
+            }
+        };
+
+        let file = InputFile {
+            path: path.clone(),
+            src: Arc::new(src),
+            doc,
+            converted: converted.is_some(),
+        };
+
+        let mut chain = chain;
+        chain.push(canonical);
+
+        for included in resolve_includes(&file, &chain, &mut errors) {
+            queue.push_back((included, chain.clone()));
+        }
+
+        files.push(file);
+    }
+
+    Ok((files, errors))
+}
+
+// `include "../shared/*.kdl"` relative to the file, a dir includes all the
+// kdl files in it
+fn resolve_includes(
+    file: &InputFile,
+    chain: &[PathBuf],
+    errors: &mut Vec<InputError>,
+) -> Vec<PathBuf> {
+    let base = file
+        .path
+        .parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_default();
+    let mut includes = Vec::new();
+
+    for node in file
+        .doc
+        .nodes()
         .iter()
-        .map(|path| {
-            fs::read_to_string(path)
-                .into_diagnostic()?
-                .parse::<KdlDocument>()
-                .into_diagnostic()
+        .filter(|n| n.name().value() == "include")
+    {
+        for entry in node.entries() {
+            let Some(pattern) = entry.value().as_string() else {
+                errors.push(InputError::InvalidAttribute {
+                    src: file.named_source(),
+                    span: entry.span(),
+                });
+                continue;
+            };
+
+            let pattern = base.join(pattern).to_string_lossy().into_owned();
+            let matches = glob::glob(&pattern)
+                .map(|paths| paths.filter_map(|p| p.ok()).collect::<Vec<PathBuf>>())
+                .unwrap_or_default();
+
+            if matches.is_empty() {
+                errors.push(InputError::IncludeNotFound {
+                    pattern,
+                    src: file.named_source(),
+                    span: entry.span(),
+                });
+                continue;
+            }
+
+            for path in matches {
+                let paths = if path.is_dir() {
+                    detect_pkg_kdl_files(&path).unwrap_or_default()
+                } else {
+                    vec![path]
+                };
+
+                for path in paths {
+                    if fs::canonicalize(&path).is_ok_and(|p| chain.contains(&p)) {
+                        errors.push(InputError::IncludeCycle {
+                            path,
+                            src: file.named_source(),
+                            span: entry.span(),
+                        });
+                        continue;
+                    }
+
+                    includes.push(path);
+                }
+            }
+        }
+    }
+
+    includes
+}
+
+fn attribute_value(
+    entry: &KdlEntry,
+    file: &InputFile,
+    vars: &HashMap<String, String>,
+) -> Result<AttributeValue, InputError> {
+    let value = entry.value();
+
+    if value.is_string() {
+        Ok(AttributeValue::String(
+            expand(value.as_string().unwrap(), vars).map_err(|name| {
+                InputError::UndefinedVariable {
+                    name,
+                    src: file.named_source(),
+                    span: entry.span(),
+                }
+            })?,
+        ))
+    } else if value.is_integer() {
+        Ok(AttributeValue::Integer(value.as_integer().unwrap() as i64))
+    } else if value.is_bool() {
+        Ok(AttributeValue::Boolean(value.as_bool().unwrap()))
+    } else if value.is_float() {
+        Ok(AttributeValue::Float(value.as_float().unwrap()))
+    } else {
+        Err(InputError::UnSupportedAttributeType {
+            value: value.to_string(),
+            src: file.named_source(),
+            span: entry.span(),
         })
-        .collect()
+    }
+}
+
+// a child node is an attribute too: `assets "linux-x64" "musl"` is a list,
+// `build { cmd "make"; }` a map and a bare `static` is `true`
+fn child_attribute_value(
+    node: &KdlNode,
+    file: &InputFile,
+    vars: &HashMap<String, String>,
+) -> Result<AttributeValue, InputError> {
+    if let Some(children) = node.children() {
+        let mut map = BTreeMap::new();
+        for child in children.nodes() {
+            map.insert(
+                child.name().value().to_string(),
+                child_attribute_value(child, file, vars)?,
+            );
+        }
+        return Ok(AttributeValue::Map(map));
+    }
+
+    let mut values = Vec::new();
+    for entry in node.entries() {
+        if entry.name().is_some() {
+            return Err(InputError::InvalidAttribute {
+                src: file.named_source(),
+                span: entry.span(),
+            });
+        }
+        values.push(attribute_value(entry, file, vars)?);
+    }
+
+    Ok(match values.len() {
+        0 => AttributeValue::Boolean(true),
+        1 => values.remove(0),
+        _ => AttributeValue::List(values),
+    })
 }
 
-fn parse_attributes(node: &KdlNode) -> Result<HashMap<String, AttributeValue>, InputError> {
+fn parse_attributes(
+    node: &KdlNode,
+    file: &InputFile,
+    vars: &HashMap<String, String>,
+) -> Result<HashMap<String, AttributeValue>, InputError> {
     let mut attributes = HashMap::new();
 
     for entry in node.entries().iter().skip(1) {
         // Skip first entry which is the input
-        let name = entry.name().ok_or(InputError::MissingField)?;
-        let value = entry.value();
-
-        let attr_value = if value.is_string() {
-            AttributeValue::String(value.as_string().unwrap().to_string())
-        } else if value.is_integer() {
-            AttributeValue::Integer(value.as_integer().unwrap() as i64)
-        } else if value.is_bool() {
-            AttributeValue::Boolean(value.as_bool().unwrap())
-        } else if value.is_float() {
-            AttributeValue::Float(value.as_float().unwrap())
-        } else {
-            return Err(InputError::UnSupportedAttributeType(value.to_string()));
-        };
+        let name = entry.name().ok_or_else(|| InputError::InvalidAttribute {
+            src: file.named_source(),
+            span: entry.span(),
+        })?;
+
+        attributes.insert(name.to_string(), attribute_value(entry, file, vars)?);
+    }
 
-        attributes.insert(name.to_string(), attr_value);
+    // `tags`, `when`, `use`... are not attributes, they are handled on their own
+    for child in node.children().map(|c| c.nodes()).unwrap_or_default() {
+        if matches!(
+            child.name().value(),
+            "tags" | "when" | "bridge" | "exec" | "use"
+        ) {
+            continue;
+        }
+
+        attributes.insert(
+            child.name().value().to_string(),
+            child_attribute_value(child, file, vars)?,
+        );
     }
 
     Ok(attributes)
 }
 
-fn parse_bridges(kdl_docs: &[KdlDocument]) -> Result<Vec<Bridge>> {
+// the variables every input and config can use
+pub fn builtin_vars() -> HashMap<String, String> {
+    HashMap::from([
+        (
+            "HOME".to_string(),
+            std::env::var("HOME").unwrap_or_default(),
+        ),
+        ("OS".to_string(), std::env::consts::OS.to_string()),
+        ("ARCH".to_string(), std::env::consts::ARCH.to_string()),
+        ("HOSTNAME".to_string(), crate::host::hostname()),
+    ])
+}
+
+// replaces the `${NAME}`s in the value (`$${` for a literal `${`), the error
+// is the name of the undefined variable
+pub fn expand(value: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        expanded.push_str(&rest[..start]);
+
+        let after = &rest[start + 2..];
+        let end = after.find('}').ok_or_else(|| after.to_string())?;
+        let name = &after[..end];
+
+        expanded.push_str(vars.get(name).ok_or_else(|| name.to_string())?);
+        rest = &after[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+// the `vars { name "value"; }` nodes of all the files, they are global
+fn parse_vars(files: &[InputFile], errors: &mut Vec<InputError>) -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+
+    for file in files {
+        for node in file
+            .doc
+            .nodes()
+            .iter()
+            .filter(|n| n.name().value() == "vars")
+        {
+            for var in node.children().map(|c| c.nodes()).unwrap_or_default() {
+                match var.entries().first().and_then(|e| e.value().as_string()) {
+                    Some(value) => {
+                        vars.insert(var.name().value().to_string(), value.to_string());
+                    }
+                    None => errors.push(InputError::InvalidAttribute {
+                        src: file.named_source(),
+                        span: var.span(),
+                    }),
+                }
+            }
+        }
+    }
+
+    vars
+}
+
+// what a pkg gets from a `preset` it `use`s, under its own attributes
+struct Preset {
+    attributes: HashMap<String, AttributeValue>,
+    tags: Vec<String>,
+    bridge: Option<BridgeOverride>,
+}
+
+// `preset "rust-tool" { tags "rust"; locked; }` nodes of all the files, global
+// like the vars (the last one of a name wins)
+fn parse_presets(
+    files: &[InputFile],
+    vars: &HashMap<String, String>,
+    errors: &mut Vec<InputError>,
+) -> HashMap<String, Preset> {
+    let mut presets = HashMap::new();
+
+    for file in files {
+        for node in file
+            .doc
+            .nodes()
+            .iter()
+            .filter(|n| n.name().value() == "preset")
+        {
+            let Some(name) = node.entries().first().and_then(|e| e.value().as_string()) else {
+                errors.push(InputError::InvalidAttribute {
+                    src: file.named_source(),
+                    span: node.span(),
+                });
+                continue;
+            };
+
+            match (
+                parse_attributes(node, file, vars),
+                parse_tags(node, file),
+                parse_bridge_override(node, file, vars),
+            ) {
+                (Ok(attributes), Ok(tags), Ok(bridge)) => {
+                    presets.insert(
+                        name.to_string(),
+                        Preset {
+                            attributes,
+                            tags,
+                            bridge,
+                        },
+                    );
+                }
+                (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => errors.push(err),
+            }
+        }
+    }
+
+    presets
+}
+
+// `use "rust-tool" "github-release"`, the presets are applied in order (a
+// later one wins) and the pkg's own attributes, tags and bridge win over all
+fn apply_presets(
+    pkg: &mut PkgDeclaration,
+    node: &KdlNode,
+    file: &InputFile,
+    presets: &HashMap<String, Preset>,
+) -> Result<(), InputError> {
+    let Some(uses) = node.children().and_then(|c| c.get("use")) else {
+        return Ok(());
+    };
+
+    let mut attributes = HashMap::new();
+    let mut tags = Vec::new();
+    let mut bridge = None;
+    for entry in uses.entries() {
+        let Some(name) = entry.value().as_string() else {
+            return Err(InputError::InvalidAttribute {
+                src: file.named_source(),
+                span: entry.span(),
+            });
+        };
+        let Some(preset) = presets.get(name) else {
+            return Err(InputError::UnknownPreset {
+                name: name.to_string(),
+                src: file.named_source(),
+                span: entry.span(),
+            });
+        };
+
+        attributes.extend(preset.attributes.clone());
+        tags.extend(preset.tags.iter().cloned());
+        bridge = preset.bridge.clone().or(bridge);
+    }
+
+    attributes.extend(std::mem::take(&mut pkg.attributes));
+    pkg.attributes = attributes;
+    tags.extend(std::mem::take(&mut pkg.tags));
+    let mut seen = HashSet::new();
+    tags.retain(|tag| seen.insert(tag.clone()));
+    pkg.tags = tags;
+    pkg.bridge = pkg.bridge.take().or(bridge);
+
+    Ok(())
+}
+
+// `when hostname="laptop" os="linux"` matches if all its conditions do, and
+// a node matches if one of its `when` children does (or it has none)
+fn when_matches(
+    node: &KdlNode,
+    file: &InputFile,
+    context: &InputContext,
+) -> Result<bool, InputError> {
+    let Some(children) = node.children() else {
+        return Ok(true);
+    };
+
+    let whens = children
+        .nodes()
+        .iter()
+        .filter(|n| n.name().value() == "when")
+        .collect::<Vec<&KdlNode>>();
+
+    if whens.is_empty() {
+        return Ok(true);
+    }
+
+    for when in whens {
+        let mut matches = true;
+
+        for entry in when.entries() {
+            let (Some(key), Some(value)) = (entry.name(), entry.value().as_string()) else {
+                return Err(InputError::InvalidAttribute {
+                    src: file.named_source(),
+                    span: entry.span(),
+                });
+            };
+
+            let actual = match key.value() {
+                "hostname" => Some(&context.hostname),
+                "os" => Some(&context.os),
+                "arch" => Some(&context.arch),
+                "profile" => context.profile.as_ref(),
+                _ => {
+                    return Err(InputError::UnknownCondition {
+                        key: key.value().to_string(),
+                        src: file.named_source(),
+                        span: entry.span(),
+                    });
+                }
+            };
+
+            let value = if key.value() == "os" {
+                crate::host::canonical_os(value)
+            } else {
+                value
+            };
+            matches &= actual.is_some_and(|actual| actual == value);
+        }
+
+        if matches {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+// `pkg "input" { bridge "./bridges/custom"; }` or `pkg "input" { exec "..."; }`
+fn parse_bridge_override(
+    node: &KdlNode,
+    file: &InputFile,
+    vars: &HashMap<String, String>,
+) -> Result<Option<BridgeOverride>, InputError> {
+    let Some(children) = node.children() else {
+        return Ok(None);
+    };
+
+    let mut bridge_override = None;
+
+    for child in children.nodes() {
+        let name = child.name().value();
+        if !matches!(name, "bridge" | "exec") {
+            continue;
+        }
+
+        // exactly one string, and only one of them
+        let value = match (child.entries(), &bridge_override) {
+            ([entry], None) if entry.name().is_none() => entry,
+            _ => {
+                return Err(InputError::InvalidAttribute {
+                    src: file.named_source(),
+                    span: child.span(),
+                });
+            }
+        };
+
+        let AttributeValue::String(value) = attribute_value(value, file, vars)? else {
+            return Err(InputError::InvalidAttribute {
+                src: file.named_source(),
+                span: value.span(),
+            });
+        };
+
+        bridge_override = Some(if name == "bridge" {
+            let dir = file.path.parent().unwrap_or(Path::new("."));
+            BridgeOverride::Path(dir.join(value))
+        } else {
+            BridgeOverride::Exec(value)
+        });
+    }
+
+    Ok(bridge_override)
+}
+
+// `pkg "input" { tags "dev" "gui"; }`
+fn parse_tags(node: &KdlNode, file: &InputFile) -> Result<Vec<String>, InputError> {
+    let Some(tags) = node.children().and_then(|c| c.get("tags")) else {
+        return Ok(Vec::new());
+    };
+
+    tags.entries()
+        .iter()
+        .map(|entry| {
+            entry
+                .value()
+                .as_string()
+                .map(|tag| tag.to_string())
+                .ok_or_else(|| InputError::InvalidAttribute {
+                    src: file.named_source(),
+                    span: entry.span(),
+                })
+        })
+        .collect()
+}
+
+// a broken declaration is reported and skipped, not fatal
+fn parse_bridges(
+    files: &[InputFile],
+    context: &InputContext,
+) -> (Vec<Bridge>, Vec<String>, Vec<String>, Vec<InputError>) {
     let mut bridges = Vec::<Bridge>::new();
+    let mut when_excluded = Vec::<String>::new();
+    let mut when_excluded_pkgs = Vec::<String>::new();
+    let mut errors = Vec::new();
+    // where every pkg is declared, to point at both in the duplicate error
+    let mut declarations = HashMap::<String, (&InputFile, SourceSpan)>::new();
+
+    let mut vars = context.vars.clone();
+    vars.extend(parse_vars(files, &mut errors));
+    let presets = parse_presets(files, &vars, &mut errors);
+
+    for file in files {
+        for bridge_node in file.doc.nodes() {
+            if matches!(bridge_node.name().value(), "vars" | "include" | "preset") {
+                continue;
+            }
 
-    for doc in kdl_docs {
-        for bridge_node in doc.nodes() {
             let bridge_name = bridge_node.name().to_string();
             let mut bridge = Bridge {
                 name: bridge_name.clone(),
                 pkgs: Vec::new(),
             };
 
-            let children = bridge_node.children().ok_or(InputError::MissingField)?;
+            let Some(children) = bridge_node.children() else {
+                errors.push(InputError::MissingField {
+                    src: file.named_source(),
+                    span: bridge_node.name().span(),
+                });
+                continue;
+            };
+
+            match when_matches(bridge_node, file, context) {
+                Ok(true) => {}
+                Ok(false) => {
+                    when_excluded.push(bridge_name);
+                    continue;
+                }
+                Err(err) => {
+                    errors.push(err);
+                    continue;
+                }
+            }
 
             for pkg_decl_node in children.nodes() {
+                if pkg_decl_node.name().value() == "when" {
+                    continue;
+                }
+
+                match when_matches(pkg_decl_node, file, context) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        when_excluded_pkgs.push(pkg_decl_node.name().to_string());
+                        continue;
+                    }
+                    Err(err) => {
+                        errors.push(err);
+                        continue;
+                    }
+                }
+
                 let input = pkg_decl_node
                     .entries()
                     .first()
                     .map(|entry| {
-                        entry
-                            .value()
-                            .as_string()
-                            .ok_or(InputError::InvalidAttribute)
-                            .map(|s| s.to_string())
+                        let input = entry.value().as_string().ok_or_else(|| {
+                            InputError::InvalidAttribute {
+                                src: file.named_source(),
+                                span: entry.span(),
+                            }
+                        })?;
+
+                        expand(input, &vars).map_err(|name| InputError::UndefinedVariable {
+                            name,
+                            src: file.named_source(),
+                            span: entry.span(),
+                        })
                     })
-                    .unwrap_or_else(|| Ok(pkg_decl_node.name().to_string()))?;
+                    .unwrap_or_else(|| Ok(pkg_decl_node.name().to_string()));
 
-                let pkg_decl = PkgDeclaration {
+                let (input, attributes, tags, bridge_override) = match (
+                    input,
+                    parse_attributes(pkg_decl_node, file, &vars),
+                    parse_tags(pkg_decl_node, file),
+                    parse_bridge_override(pkg_decl_node, file, &vars),
+                ) {
+                    (Ok(input), Ok(attributes), Ok(tags), Ok(bridge_override)) => {
+                        (input, attributes, tags, bridge_override)
+                    }
+                    (Err(err), _, _, _)
+                    | (_, Err(err), _, _)
+                    | (_, _, Err(err), _)
+                    | (_, _, _, Err(err)) => {
+                        errors.push(err);
+                        continue;
+                    }
+                };
+
+                let mut pkg_decl = PkgDeclaration {
                     name: pkg_decl_node.name().to_string(),
                     input,
-                    attributes: parse_attributes(pkg_decl_node)?,
+                    attributes,
+                    tags,
+                    bridge: bridge_override,
                 };
+                if let Err(err) = apply_presets(&mut pkg_decl, pkg_decl_node, file, &presets) {
+                    errors.push(err);
+                    continue;
+                }
 
-                if bridges.iter().any(|b: &Bridge| {
-                    b.pkgs
-                        .iter()
-                        .any(|p: &PkgDeclaration| p.name == pkg_decl.name)
-                }) {
-                    return Err(Report::new(InputError::DuplicatePkgDeclaration(
-                        pkg_decl.name.clone(),
-                    )));
+                let span = pkg_decl_node.name().span();
+
+                if let Some((first_file, first_span)) = declarations.get(&pkg_decl.name) {
+                    errors.push(InputError::DuplicatePkgDeclaration {
+                        name: pkg_decl.name.clone(),
+                        src: file.named_source(),
+                        span,
+                        first: vec![FirstDeclaration {
+                            src: first_file.named_source(),
+                            span: *first_span,
+                        }],
+                    });
+                    continue;
                 }
+                declarations.insert(pkg_decl.name.clone(), (file, span));
 
                 bridge.pkgs.push(pkg_decl);
             }
@@ -175,18 +985,218 @@ fn parse_bridges(kdl_docs: &[KdlDocument]) -> Result<Vec<Bridge>> {
         }
     }
 
-    Ok(bridges)
+    when_excluded.sort();
+    when_excluded.dedup();
+    when_excluded_pkgs.sort();
+    when_excluded_pkgs.dedup();
+
+    (bridges, when_excluded, when_excluded_pkgs, errors)
+}
+
+// comments out (with a `/-` slashdash) every declaration of a bridge in the
+// kdl files under the path, returns how many bridge nodes it disabled
+pub fn disable_bridge(path: &PathBuf, bridge_name: &str) -> Result<usize> {
+    let mut disabled = 0;
+
+    for file in detect_pkg_kdl_files(path)? {
+        let mut src = fs::read_to_string(&file)?;
+        let doc = src.parse::<KdlDocument>()?;
+
+        let mut offsets = doc
+            .nodes()
+            .iter()
+            .filter(|n| n.name().value() == bridge_name)
+            .map(|n| n.span().offset())
+            .collect::<Vec<usize>>();
+
+        if offsets.is_empty() {
+            continue;
+        }
+
+        // from the end, so the offsets that are left stay valid
+        offsets.sort_unstable_by(|a, b| b.cmp(a));
+        for offset in &offsets {
+            src.insert_str(*offset, "/-");
+        }
+
+        fs::write(&file, src)?;
+        disabled += offsets.len();
+    }
+
+    Ok(disabled)
+}
+
+impl InputContext {
+    // this machine, with the profile and the vars from the config
+    pub fn host(profile: Option<String>, config_vars: &HashMap<String, String>) -> Self {
+        let mut vars = builtin_vars();
+        vars.extend(config_vars.clone());
+
+        Self {
+            hostname: crate::host::hostname(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            profile,
+            vars,
+        }
+    }
+}
+
+impl Bridge {
+    // false when every pkg brings its own bridge, so the bridge dir isn't needed
+    pub fn needs_bridge_dir(&self) -> bool {
+        self.pkgs.iter().any(|p| p.bridge.is_none())
+    }
+}
+
+impl PkgDeclaration {
+    // why the pkg is skipped on this machine, from its `os` and `arch`
+    // attributes (a value or a list of them). unlike `when`, the pkg is still
+    // declared: it's not installed here but not removed if it's installed
+    pub fn platform_mismatch(&self, context: &InputContext) -> Option<String> {
+        [("os", &context.os), ("arch", &context.arch)]
+            .into_iter()
+            .find_map(|(key, host)| {
+                let wanted = match self.attributes.get(key)? {
+                    AttributeValue::String(value) => vec![value.as_str()],
+                    AttributeValue::List(values) => values
+                        .iter()
+                        .filter_map(|v| match v {
+                            AttributeValue::String(value) => Some(value.as_str()),
+                            _ => None,
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                };
+
+                let matches = wanted
+                    .iter()
+                    .any(|w| crate::host::canonical_os(w) == host.as_str());
+                (!matches).then(|| format!("only for {key} {}, not {host}", wanted.join("/")))
+            })
+    }
+
+    // the pkgs it needs, its `deps` attribute (a name or a list of them)
+    pub fn deps(&self) -> Vec<String> {
+        match self.attributes.get("deps") {
+            Some(AttributeValue::String(dep)) => vec![dep.clone()],
+            Some(AttributeValue::List(deps)) => deps
+                .iter()
+                .filter_map(|d| match d {
+                    AttributeValue::String(dep) => Some(dep.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // `auto=#true`, it's only there because other pkgs need it
+    pub fn is_auto(&self) -> bool {
+        matches!(
+            self.attributes.get("auto"),
+            Some(AttributeValue::Boolean(true))
+        )
+    }
+}
+
+// the names of the pkgs the declared ones need, the deps of a needed `auto`
+// pkg are needed too. an `auto` pkg out of it isn't installed
+pub fn needed_deps(bridges: &[Bridge]) -> HashSet<String> {
+    let pkgs = bridges
+        .iter()
+        .flat_map(|b| &b.pkgs)
+        .collect::<Vec<&PkgDeclaration>>();
+
+    let mut needed = HashSet::new();
+    let mut queue = pkgs
+        .iter()
+        .filter(|p| !p.is_auto())
+        .copied()
+        .collect::<Vec<&PkgDeclaration>>();
+    while let Some(pkg) = queue.pop() {
+        for dep in pkg.deps() {
+            if needed.insert(dep.clone()) {
+                queue.extend(pkgs.iter().filter(|p| p.is_auto() && p.name == dep));
+            }
+        }
+    }
+
+    needed
+}
+
+impl TagFilter {
+    pub fn matches(&self, tags: &[String]) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|t| tags.contains(t)))
+            && !self.exclude.iter().any(|t| tags.contains(t))
+    }
+
+    pub fn is_excluded(&self, tags: &[String]) -> bool {
+        self.exclude.iter().any(|t| tags.contains(t))
+    }
+}
+
+impl NameFilter {
+    pub fn matches(&self, name: &str) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|n| n == name))
+            && !self.exclude.iter().any(|n| n == name)
+    }
 }
 
 impl Input {
     pub fn load(path: &PathBuf) -> Result<Self> {
+        Self::load_for(path, &InputContext::host(None, &HashMap::new()))
+    }
+
+    // only the declarations whose `when` matches the context
+    pub fn load_for(path: &PathBuf, context: &InputContext) -> Result<Self> {
+        let (input, errors) = Self::check_for(path, context)?;
+
+        if let Some(err) = errors.into_iter().next() {
+            return Err(err);
+        }
+
+        Ok(input)
+    }
+
+    // all the problems instead of the first one, with what could be parsed
+    pub fn check(path: &PathBuf) -> Result<(Self, Vec<InputError>)> {
+        Self::check_for(path, &InputContext::host(None, &HashMap::new()))
+    }
+
+    pub fn check_for(path: &PathBuf, context: &InputContext) -> Result<(Self, Vec<InputError>)> {
         let inputs_paths = detect_pkg_kdl_files(path)?;
-        let kdl_docs = parse_inputs_kdl(&inputs_paths)?;
-        let bridges = parse_bridges(&kdl_docs)?;
+        let (files, mut errors) = parse_inputs_kdl(&inputs_paths)?;
+        let (bridges, when_excluded, when_excluded_pkgs, bridges_errors) =
+            parse_bridges(&files, context);
+        errors.extend(bridges_errors);
 
-        Ok(Self {
-            path: path.clone(),
-            bridges,
-        })
+        Ok((
+            Self {
+                path: path.clone(),
+                bridges,
+                when_excluded,
+                when_excluded_pkgs,
+            },
+            errors,
+        ))
+    }
+
+    // the default attributes of the bridges (from the config), under the
+    // ones of the pkgs: a pkg that sets one keeps its own
+    pub fn apply_defaults(&mut self, defaults: &BTreeMap<String, HashMap<String, AttributeValue>>) {
+        for bridge in &mut self.bridges {
+            let Some(defaults) = defaults.get(&bridge.name) else {
+                continue;
+            };
+
+            for pkg in &mut bridge.pkgs {
+                for (name, value) in defaults {
+                    pkg.attributes
+                        .entry(name.clone())
+                        .or_insert_with(|| value.clone());
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,275 @@
+// the non kdl inputs, they are converted to the same kdl document a `.kdl`
+// input would be, so the rest of the input module only knows kdl:
+//
+// ```toml
+// include = ["shared.kdl"]
+// [vars]
+// version = "1.2"
+// [bridge1]
+// pkg1 = "pkg1-input"
+// [bridge1.pkg2]
+// input = "pkg2-input"
+// tags = ["dev"]
+// when = { os = "linux" }
+// assets = ["linux-x64", "musl"]
+// use = "rust-tool"
+// [preset.rust-tool]
+// tags = ["rust"]
+// ```
+use std::path::Path;
+
+use kdl::{KdlDocument, KdlEntry, KdlNode, KdlValue};
+use serde_json::Value;
+
+pub trait InputFormat: Sync {
+    // the file extensions of the format, lowercase
+    fn extensions(&self) -> &'static [&'static str];
+
+    fn parse(&self, src: &str) -> Result<Value, String>;
+}
+
+#[cfg(feature = "toml_inputs")]
+struct Toml;
+
+#[cfg(feature = "toml_inputs")]
+impl InputFormat for Toml {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["toml"]
+    }
+
+    fn parse(&self, src: &str) -> Result<Value, String> {
+        toml::from_str(src).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(feature = "yaml_inputs")]
+struct Yaml;
+
+#[cfg(feature = "yaml_inputs")]
+impl InputFormat for Yaml {
+    fn extensions(&self) -> &'static [&'static str] {
+        &["yaml", "yml"]
+    }
+
+    fn parse(&self, src: &str) -> Result<Value, String> {
+        serde_yaml_ng::from_str(src).map_err(|err| err.to_string())
+    }
+}
+
+static FORMATS: &[&dyn InputFormat] = &[
+    #[cfg(feature = "toml_inputs")]
+    &Toml,
+    #[cfg(feature = "yaml_inputs")]
+    &Yaml,
+];
+
+// `None` for the kdl files, they are parsed as they are
+pub fn for_path(path: &Path) -> Option<&'static dyn InputFormat> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+
+    FORMATS
+        .iter()
+        .find(|format| format.extensions().contains(&ext.as_str()))
+        .copied()
+}
+
+pub fn is_input_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("kdl"))
+        || for_path(path).is_some()
+}
+
+// the kdl source of the document, parsed again by the caller to get the spans
+pub fn to_kdl(format: &dyn InputFormat, src: &str) -> Result<String, String> {
+    let Value::Object(root) = format.parse(src)? else {
+        return Err("the inputs should be a table of bridges".to_string());
+    };
+
+    let mut doc = KdlDocument::new();
+
+    for (key, value) in root {
+        match key.as_str() {
+            "vars" => {
+                let mut node = KdlNode::new("vars");
+                push_children(&mut node, &value, "vars")?;
+                doc.nodes_mut().push(node);
+            }
+            "include" => {
+                for pattern in strings(&value, "include")? {
+                    let mut node = KdlNode::new("include");
+                    node.push(KdlEntry::new(pattern));
+                    doc.nodes_mut().push(node);
+                }
+            }
+            "preset" => {
+                let Value::Object(presets) = value else {
+                    return Err("`preset` should be a table of presets".to_string());
+                };
+                for (name, value) in presets {
+                    doc.nodes_mut().push(preset_node(&name, &value)?);
+                }
+            }
+            bridge => doc.nodes_mut().push(bridge_node(bridge, &value)?),
+        }
+    }
+
+    doc.autoformat();
+    Ok(doc.to_string())
+}
+
+fn bridge_node(name: &str, value: &Value) -> Result<KdlNode, String> {
+    let Value::Object(pkgs) = value else {
+        return Err(format!("`{name}` should be a table of pkgs"));
+    };
+
+    let mut node = KdlNode::new(name);
+    let children = node.ensure_children();
+
+    for (pkg, value) in pkgs {
+        if pkg == "when" {
+            children.nodes_mut().extend(when_nodes(value)?);
+        } else {
+            children.nodes_mut().push(pkg_node(pkg, value)?);
+        }
+    }
+
+    Ok(node)
+}
+
+// `pkg = "input"`, `pkg = {}` or `pkg = { input = "input", version = "1" }`
+fn pkg_node(name: &str, value: &Value) -> Result<KdlNode, String> {
+    let mut node = KdlNode::new(name);
+
+    let fields = match value {
+        Value::String(input) => {
+            node.push(KdlEntry::new(input.clone()));
+            return Ok(node);
+        }
+        Value::Object(fields) => fields,
+        _ => return Err(format!("`{name}` should be an input string or a table")),
+    };
+
+    match fields.get("input") {
+        Some(Value::String(input)) => node.push(KdlEntry::new(input.clone())),
+        Some(_) => return Err(format!("the input of `{name}` should be a string")),
+        // the input is the name, but the props need an input before them
+        None => node.push(KdlEntry::new(name)),
+    }
+
+    let mut children = Vec::new();
+
+    for (key, value) in fields {
+        match (key.as_str(), value) {
+            ("input", _) => {}
+            ("when", _) => children.extend(when_nodes(value)?),
+            ("tags" | "use", _) => {
+                let mut list = KdlNode::new(key.as_str());
+                for item in strings(value, key)? {
+                    list.push(KdlEntry::new(item));
+                }
+                children.push(list);
+            }
+            (_, Value::Array(_) | Value::Object(_)) => children.push(attribute_node(key, value)?),
+            (_, value) => node.push(KdlEntry::new_prop(key.as_str(), scalar(value, key)?)),
+        }
+    }
+
+    if !children.is_empty() {
+        node.ensure_children().nodes_mut().extend(children);
+    }
+
+    Ok(node)
+}
+
+// `preset "name" { .. }`, its fields are the ones of a pkg (without an input)
+fn preset_node(name: &str, value: &Value) -> Result<KdlNode, String> {
+    let Value::Object(fields) = value else {
+        return Err(format!("the preset `{name}` should be a table"));
+    };
+
+    let mut fields = fields.clone();
+    fields.insert("input".to_string(), Value::String(name.to_string()));
+    pkg_node("preset", &Value::Object(fields))
+}
+
+// `when = { os = "linux" }` or a list of them, they are or'ed
+fn when_nodes(value: &Value) -> Result<Vec<KdlNode>, String> {
+    let conditions = match value {
+        Value::Array(conditions) => conditions.iter().collect(),
+        value => vec![value],
+    };
+
+    conditions
+        .into_iter()
+        .map(|condition| {
+            let Value::Object(condition) = condition else {
+                return Err("`when` should be a table of conditions".to_string());
+            };
+
+            let mut node = KdlNode::new("when");
+            for (key, value) in condition {
+                node.push(KdlEntry::new_prop(key.as_str(), scalar(value, key)?));
+            }
+            Ok(node)
+        })
+        .collect()
+}
+
+// a list is the node args, a table its children
+fn attribute_node(name: &str, value: &Value) -> Result<KdlNode, String> {
+    let mut node = KdlNode::new(name);
+
+    match value {
+        Value::Array(values) => {
+            for value in values {
+                node.push(KdlEntry::new(scalar(value, name)?));
+            }
+        }
+        Value::Object(_) => push_children(&mut node, value, name)?,
+        value => node.push(KdlEntry::new(scalar(value, name)?)),
+    }
+
+    Ok(node)
+}
+
+fn push_children(node: &mut KdlNode, value: &Value, name: &str) -> Result<(), String> {
+    let Value::Object(fields) = value else {
+        return Err(format!("`{name}` should be a table"));
+    };
+
+    let children = node.ensure_children();
+    for (key, value) in fields {
+        children.nodes_mut().push(attribute_node(key, value)?);
+    }
+
+    Ok(())
+}
+
+fn scalar(value: &Value, name: &str) -> Result<KdlValue, String> {
+    match value {
+        Value::String(value) => Ok(KdlValue::String(value.clone())),
+        Value::Bool(value) => Ok(KdlValue::Bool(*value)),
+        Value::Number(number) => Ok(match number.as_i64() {
+            Some(value) => KdlValue::Integer(value.into()),
+            None => KdlValue::Float(number.as_f64().unwrap_or_default()),
+        }),
+        _ => Err(format!("`{name}` should be a string, a number or a bool")),
+    }
+}
+
+fn strings(value: &Value, name: &str) -> Result<Vec<String>, String> {
+    match value {
+        Value::String(value) => Ok(vec![value.clone()]),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| {
+                value
+                    .as_str()
+                    .map(|v| v.to_string())
+                    .ok_or_else(|| format!("`{name}` should be a list of strings"))
+            })
+            .collect(),
+        _ => Err(format!("`{name}` should be a list of strings")),
+    }
+}
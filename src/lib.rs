@@ -1,7 +1,28 @@
 pub const DEFAULT_CONFIG_FILE_NAME: &str = ".config";
 pub const DEFAULT_CONFIG_FILE_EXTENSION: &str = "kdl";
+#[cfg(all(unix, not(target_os = "macos")))]
 pub const DEFAULT_LOG_DIR: &str = "/var/log/pkg";
+#[cfg(all(unix, not(target_os = "macos")))]
+pub const DEFAULT_CACHE_DIR: &str = "/var/cache/pkg";
+// where macos keeps them, `/var/cache` isn't there
+#[cfg(target_os = "macos")]
+pub const DEFAULT_LOG_DIR: &str = "/Library/Logs/pkg";
+#[cfg(target_os = "macos")]
+pub const DEFAULT_CACHE_DIR: &str = "/Library/Caches/pkg";
+#[cfg(unix)]
 pub const DEFAULT_WORKING_DIR: &str = "/var/tmp/pkg";
+// in `%LOCALAPPDATA%` on windows (`host::platform_dir`)
+#[cfg(windows)]
+pub const DEFAULT_LOG_DIR: &str = "pkg/log";
+#[cfg(windows)]
+pub const DEFAULT_CACHE_DIR: &str = "pkg/cache";
+#[cfg(windows)]
+pub const DEFAULT_WORKING_DIR: &str = "pkg/tmp";
+// the pkgs installed by hand before pkg and adopted with `pkg adopt`
+pub const ADOPTED_BRIDGE_NAME: &str = "adopted";
+
+pub mod error;
+pub use error::{Error, Result};
 
 pub mod config;
 
@@ -13,9 +34,60 @@ use db::{Pkg, PkgType, Version as PkgVersion};
 
 pub mod bridge;
 
+pub mod manifest;
+
 pub mod fs;
 
 pub mod cmd;
 
+pub mod docs;
+
+#[cfg(feature = "cli_complation")]
+pub mod completions;
+
+pub mod lock;
+
+pub mod host;
+
+pub mod git;
+
+pub mod event;
+
+pub mod hooks;
+
+pub mod systemd;
+
+pub mod secrets;
+
+pub mod audit;
+
+pub mod license;
+
+pub mod watch;
+
+pub mod schedule;
+
+pub mod notify;
+
+pub mod metrics;
+
+pub mod import;
+
+pub mod export;
+
+pub mod output;
+
+pub mod exit;
+
+pub mod privilege;
+
+pub mod registry;
+
+pub mod failures;
+
+pub mod plan;
+
+pub mod build;
+
 #[cfg(test)]
 mod test;
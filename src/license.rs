@@ -0,0 +1,64 @@
+// the licenses the pkgs can have, the `licenses` section of the config. a
+// fleet can't take a pkg it isn't allowed to run, the license the bridge gives
+// (`license,MIT` in its output) is checked before the pkg is stored
+use miette::Diagnostic;
+use thiserror::Error;
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum LicenseError {
+    #[error("The license `{license}` of {pkg} is not allowed")]
+    #[diagnostic(
+        code(license::not_allowed),
+        help(
+            "Change the `licenses` section of the config, or pass `--allow-license-violations` to only warn"
+        )
+    )]
+    NotAllowed { pkg: String, license: String },
+}
+
+type Result<T, E = LicenseError> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LicensePolicy {
+    // only these spdx ids, all of them if it's empty
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl LicensePolicy {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    // a spdx expression, `MIT OR Apache-2.0` passes if one side does and
+    // `MIT AND Zlib` if both do. a pkg without a license passes, the bridge
+    // doesn't know it
+    pub fn check(&self, pkg: &str, license: Option<&str>) -> Result<()> {
+        let Some(license) = license else {
+            return Ok(());
+        };
+
+        let expression = license.replace(['(', ')'], " ");
+        let allowed = expression.split(" OR ").any(|choice| {
+            choice
+                .split(" AND ")
+                .all(|id| self.allows(id.split(" WITH ").next().unwrap_or(id).trim()))
+        });
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(LicenseError::NotAllowed {
+                pkg: pkg.to_string(),
+                license: license.to_string(),
+            })
+        }
+    }
+
+    // the spdx ids are matched without the case
+    fn allows(&self, id: &str) -> bool {
+        let listed = |ids: &[String]| ids.iter().any(|i| i.eq_ignore_ascii_case(id));
+
+        !listed(&self.deny) && (self.allow.is_empty() || listed(&self.allow))
+    }
+}
@@ -0,0 +1,84 @@
+use miette::Diagnostic;
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+/// An advisory (flock) lock held for the whole life of a mutating command,
+/// the kernel releases it if the process dies so a stale file is harmless.
+/// On windows it's a `LockFileEx` one, the holder can't be read while it's
+/// held.
+#[derive(Debug)]
+pub struct Lock {
+    file: File,
+    pub path: PathBuf,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum LockError {
+    #[error(transparent)]
+    #[diagnostic(code(lock::io_error))]
+    IoError(#[from] std::io::Error),
+
+    #[error("Another pkg process is already running: {holder}")]
+    #[diagnostic(
+        code(lock::already_locked),
+        help("Wait for it to finish, the lock file is {path:?}")
+    )]
+    AlreadyLocked { holder: String, path: PathBuf },
+}
+
+impl Lock {
+    pub fn acquire(path: &Path) -> Result<Self, LockError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path)?;
+
+        if let Err(err) = file.try_lock() {
+            if let std::fs::TryLockError::Error(err) = err {
+                return Err(LockError::IoError(err));
+            }
+
+            let mut holder = String::new();
+            let _ = file.read_to_string(&mut holder);
+            let holder = holder.trim();
+
+            return Err(LockError::AlreadyLocked {
+                holder: if holder.is_empty() {
+                    "unknown process".to_string()
+                } else {
+                    holder.to_string()
+                },
+                path: path.to_path_buf(),
+            });
+        }
+
+        // we own the lock, so record who we are for the next one that waits
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        let args = std::env::args().collect::<Vec<String>>().join(" ");
+        writeln!(file, "pid {} ({})", std::process::id(), args)?;
+        file.flush()?;
+
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
@@ -1,69 +1,438 @@
-use clap::{CommandFactory, Parser};
-#[cfg(feature = "cli_complation")]
-use clap_complete::Shell as ClapShell;
-#[cfg(feature = "cli_complation")]
-use clap_complete_nushell::Nushell;
-use cli_table::{Cell, Style, Table, print_stdout};
+use clap::Parser;
+use cli_table::{Cell, ColorChoice as TableColors, Style as _, Table, print_stdout};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use miette::{IntoDiagnostic, Result};
-use owo_colors::OwoColorize;
+use miette::{Diagnostic, IntoDiagnostic, Result};
+use owo_colors::Style;
 #[cfg(feature = "cli_complation")]
-use pkg_rs::cmd::Shell;
+use pkg_rs::completions;
 use pkg_rs::{
-    DEFAULT_CONFIG_FILE_EXTENSION, DEFAULT_CONFIG_FILE_NAME, DEFAULT_LOG_DIR, DEFAULT_WORKING_DIR,
-    bridge,
-    cmd::{Cli, Commands},
-    config::Config,
-    db::{self, Db, Pkg, PkgType},
-    fs,
-    input::{self, PkgDeclaration},
+    ADOPTED_BRIDGE_NAME, DEFAULT_CONFIG_FILE_EXTENSION, DEFAULT_CONFIG_FILE_NAME,
+    audit::{AuditLog, AuditSink},
+    bridge::{self, BridgeApiError, BridgeOptions},
+    build::{BridgeJobs, Build, BuildOutcome, Job},
+    cmd::{
+        AuditCommands, BridgeCommands, Cli, ColorMode, Commands, ConfigCommands, DbCommands,
+        DocsTopic, ExportFormat, ImportFormat, InfoSort, InputsCommands, PkgTypeFilter,
+        PolkitCommands, ScheduleCommands,
+    },
+    config::{self, Config, ConfigError, GitInputs},
+    db::{self, Db, DbError, DbSnapshot, PkgFilter, PkgKind, PkgType},
+    docs,
+    event::{CountingSink, Event, EventSink},
+    exit,
+    export::{self, Bootstrap},
+    failures::{FailureReport, FailureSink},
+    fs, git,
+    host::{self, HostEnv, Privilege},
+    import::{self, ImportError},
+    input::{self, InputContext, NameFilter, TagFilter},
+    lock::Lock,
+    manifest::BridgeManifest,
+    metrics::Metrics,
+    notify::{BuildReport, Notifiers, ReportSink},
+    output::{self, Paint},
+    plan::{Confirmation, Plan, PlanScope, filter_pkgs_by_statuses, missing_rebuild_targets},
+    privilege::{self, Escalation},
+    registry::{self, Registry, RegistryError},
+    schedule::{self, Frequency, Schedule, Scheduler},
+    systemd::{self, Systemctl},
+    watch::{self, Watcher},
 };
-use rpassword::read_password;
 use std::{
-    io::{self, Write},
-    path::PathBuf,
-    process::{Command, Stdio, exit},
-    time::Duration,
+    collections::HashMap,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitCode, Stdio},
+    rc::Rc,
+    sync::OnceLock,
+    time::{Duration, Instant},
 };
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
+
+// the errors of the cli itself, the ones of the library are classified by
+// `exit::code`
+#[derive(thiserror::Error, Debug, Diagnostic)]
+enum CliError {
+    #[error("pkg needs root and {0} can't be used without a password in the non-interactive mode")]
+    #[diagnostic(
+        code(pkg::needs_root),
+        help(
+            "Run pkg as root, or let {0} run it without a password (NOPASSWD in the sudoers, nopass in doas.conf)"
+        )
+    )]
+    NeedsRoot(Escalation),
+
+    #[error("pkg needs root and none of sudo, doas or run0 is in the PATH")]
+    #[diagnostic(code(pkg::no_escalation), help("Run pkg as root"))]
+    NoEscalation,
+
+    #[error("pkg needs root and `{0}`, the `escalate` of the config, isn't in the PATH")]
+    #[diagnostic(code(pkg::missing_escalation))]
+    MissingEscalation(Escalation),
+
+    #[error("pkg needs to run as an administrator")]
+    #[diagnostic(
+        code(pkg::needs_administrator),
+        help("Run it from a terminal opened with \"Run as administrator\"")
+    )]
+    NeedsAdministrator,
+
+    #[error("Incorrect password or {0} access denied")]
+    #[diagnostic(code(pkg::escalation_denied))]
+    EscalationDenied(Escalation),
+
+    #[error("{0} failures in the build, the rest is done")]
+    #[diagnostic(
+        code(pkg::partial_failure),
+        help(
+            "The logs of the bridges are in {1:?}, with the failures in `failures.json`, `pkg build --retry-failed` runs the failed packages again"
+        )
+    )]
+    PartialFailure(usize, PathBuf),
+
+    #[error("Not installed from the inputs: {0}")]
+    #[diagnostic(
+        code(pkg::not_installed),
+        help("`pkg info` lists the installed packages, `pkg build` installs the new ones")
+    )]
+    NotInstalled(String),
+
+    #[error("Every package of {0} would be removed, no declaration was loaded for it")]
+    #[diagnostic(
+        code(pkg::mass_remove),
+        help(
+            "Check the inputs (a missing, empty or renamed file...), or pass `--allow-mass-remove` if the bridge is deprecated"
+        )
+    )]
+    BridgesEmptied(String),
+
+    #[error("{0} of the {1} installed packages would be removed, nothing was done")]
+    #[diagnostic(
+        code(pkg::mass_remove),
+        help("Check the inputs, or pass `--allow-mass-remove` if it's what u want")
+    )]
+    MassRemove(usize, usize),
+
+    #[error("Not declared in the inputs: {0}")]
+    #[diagnostic(
+        code(pkg::not_declared),
+        help("`--remote` asks the bridge of the declaration, declare it first")
+    )]
+    NotDeclared(String),
+
+    #[error("The plan was declined, nothing was done")]
+    #[diagnostic(code(pkg::declined))]
+    Declined,
+}
+
+impl CliError {
+    fn exit_code(&self) -> u8 {
+        match self {
+            CliError::NeedsRoot(_)
+            | CliError::NoEscalation
+            | CliError::MissingEscalation(_)
+            | CliError::NeedsAdministrator
+            | CliError::EscalationDenied(_) => exit::PRIVILEGE_ERROR,
+            CliError::PartialFailure(..) => exit::PARTIAL_FAILURE,
+            CliError::NotInstalled(_)
+            | CliError::BridgesEmptied(_)
+            | CliError::MassRemove(..)
+            | CliError::NotDeclared(_) => exit::CONFIG_ERROR,
+            CliError::Declined => exit::DECLINED,
+        }
+    }
+}
+
+// the notifiers of a build or an update and its name, set once the config is
+// loaded, an error that stops it is sent from `main`
+static BUILD_NOTIFIERS: OnceLock<(Notifiers, &'static str)> = OnceLock::new();
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::from(exit::SUCCESS),
+        Err(report) => {
+            eprintln!("Error: {report:?}");
+
+            // a partial failure was sent with the rest of the summary
+            if let Some((notifiers, command)) = BUILD_NOTIFIERS.get()
+                && !matches!(
+                    report.downcast_ref::<CliError>(),
+                    Some(CliError::PartialFailure(..) | CliError::Declined)
+                )
+            {
+                let mut build_report = BuildReport::new(command);
+                build_report.error = Some(report.to_string());
+                for err in notifiers.send(&build_report) {
+                    eprintln!(
+                        "{} {}",
+                        "notify failed:".paint(Style::new().yellow().bold()),
+                        err.to_string().paint(Style::new().red())
+                    );
+                }
+            }
+
+            let code = match report.downcast_ref::<CliError>() {
+                Some(err) => err.exit_code(),
+                None => exit::code(&report),
+            };
+            ExitCode::from(code)
+        }
+    }
+}
 
-fn main() -> Result<()> {
+fn run() -> Result<()> {
     let cli = Cli::parse();
 
-    // Check if we need root privileges and prompt for password if needed
-    if !check_root_privileges() {
-        prompt_for_sudo()?;
+    // `NO_COLOR` (https://no-color.org) and the pipes get the plain text
+    let colors = match cli.color {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => {
+            io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        }
+    };
+    output::set_colors(colors);
+    miette::set_hook(Box::new(move |_| {
+        Box::new(miette::MietteHandlerOpts::new().color(colors).build())
+    }))
+    .into_diagnostic()?;
+
+    // spinners on a terminal, a line per step in the logs
+    let progress = if cli.quiet {
+        Progress::Quiet
+    } else if io::stdout().is_terminal() {
+        Progress::Spinners
+    } else {
+        Progress::Plain
+    };
+
+    // the docs are in the binary, they don't need root or the config
+    if let Commands::Docs { topic, no_pager } = &cli.command {
+        return show_docs(*topic, *no_pager);
+    }
+
+    // ansible, cloud-init, cron... nobody is there to answer
+    let interactive = !cli.non_interactive && io::stdin().is_terminal();
+
+    if !interactive {
+        // SAFETY: no other thread is running yet
+        unsafe {
+            // the bridges and the hooks can check it, git fails instead of
+            // asking for the credentials
+            std::env::set_var("PKG_NON_INTERACTIVE", "1");
+            std::env::set_var("GIT_TERMINAL_PROMPT", "0");
+        }
     }
 
+    let host = HostEnv::detect();
+
     let config_dir = get_valid_config_path()?;
 
     let config_path = config_dir
         .join(DEFAULT_CONFIG_FILE_NAME)
         .with_extension(DEFAULT_CONFIG_FILE_EXTENSION);
 
-    // load config
-    let config = Config::load(config_path)?;
+    if let Commands::Config { command } = &cli.command {
+        return config_command(command, config_path, &cli);
+    }
+
+    let mut config = load_config(config_path, &cli)?;
+
+    // the state of root's pkg read by a user: the db is opened read only and
+    // the bridges (`pkg outdated`) log in its home
+    let privilege = host.privilege(
+        cli.command.reads_only(),
+        cli.command.needs_root(),
+        host::is_elevated(),
+    );
+    let unprivileged = privilege == Privilege::Unprivileged;
+
+    // pkg runs itself again as root, a container without sudo gets an error
+    // that says so instead of failing on the first write
+    if privilege == Privilege::Escalate {
+        // no sudo to re-run with
+        if cfg!(windows) {
+            return Err(CliError::NeedsAdministrator.into());
+        }
+        re_run_as_root(config.escalate, interactive)?;
+    }
+
+    if let Some(command) = cli.command.build_name() {
+        let _ = BUILD_NOTIFIERS.set((config.notify.clone(), command));
+    }
+
+    if cli.trace_db || config.trace_db {
+        // the statements are traces of the `db` target, the rest isn't shown
+        tracing_subscriber::fmt()
+            .with_writer(io::stderr)
+            .with_ansi(output::colors())
+            .without_time()
+            .with_max_level(tracing::Level::TRACE)
+            .finish()
+            .with(Targets::new().with_target("db", tracing::Level::TRACE))
+            .init();
+        db::enable_tracing();
+    }
+
+    let cache_dir = config.cache_dir.clone().unwrap_or_else(|| host.cache_dir());
+
+    // held until main returns, so two builds can't corrupt each other's state
+    // (nor pull the git inputs under each other)
+    let _lock = if cli.command.is_mutating() {
+        Some(Lock::acquire(&config.db_path.with_extension("lock"))?)
+    } else {
+        None
+    };
+
+    // the inputs can live in a git repo, it's synced before anything reads them
+    if let Some(git_inputs) = &config.inputs_git
+        && !matches!(cli.command, Commands::Run { .. } | Commands::CompletePkgs)
+    {
+        let checkout = if config.source_dir.as_os_str().is_empty() {
+            cache_dir
+                .join("inputs")
+                .join(git::repo_name(&git_inputs.url))
+        } else {
+            config.source_dir.clone()
+        };
+
+        sync_git_inputs(git_inputs, &checkout, cli.command.is_mutating())?;
+        config.source_dir = checkout;
+    }
+
+    // commands that work without loading the inputs and the bridges
+    match &cli.command {
+        Commands::Inputs { command } => {
+            return match command {
+                InputsCommands::Init { from } => init_inputs(from, &config, interactive),
+            };
+        }
+        Commands::Run { package, args } => return run_pkg(package, args, &config),
+        Commands::CompletePkgs => return complete_pkgs(&config),
+        Commands::Check => return check(&config),
+        Commands::Import {
+            format,
+            file,
+            map,
+            output,
+        } => return import_pkgs(*format, file, map, output.as_deref(), &config),
+        Commands::Export { format, output } => {
+            return export_bootstrap(*format, output.as_deref(), &config, &cli);
+        }
+        Commands::Schedule { command } => return schedule_command(command, &config, &host, &cli),
+        Commands::Polkit { command } => return polkit_command(command, &config),
+        Commands::Watch { debounce } => {
+            return watch_inputs(&config, Duration::from_millis(*debounce), &cli);
+        }
+        _ => {}
+    }
 
     let db_path = config.db_path.clone();
     let target_dir = config.target_dir.clone();
     let load_path = config.load_path.clone();
     let bridges_set = config.bridges_set.clone();
     let inputs_path = config.source_dir.clone();
+    let (log_dir, working_dir) = if unprivileged {
+        (host::user_log_dir(), host::user_working_dir())
+    } else {
+        (
+            config
+                .log_dir
+                .clone()
+                .unwrap_or_else(|| config.rooted(&host.log_dir())),
+            config
+                .work_dir
+                .clone()
+                .unwrap_or_else(|| host.working_dir()),
+        )
+    };
+
+    // before the db is opened (and migrated), so a bad build or migration
+    // can be undone with `pkg db restore latest`
+    if cli.command.is_mutating() && !matches!(cli.command, Commands::Db { .. }) {
+        db::backup_rotating(&db_path, db::KEPT_BACKUPS)?;
+    }
+
+    if let Commands::Db { command } = &cli.command {
+        return db_command(command, &db_path);
+    }
+
+    // every bridge run and every change of the installed pkgs
+    let audit = AuditLog::new(config.audit_log.clone());
+    if let Commands::Audit { command } = &cli.command {
+        return audit_command(command, &audit);
+    }
+
+    // one connection for everything, the bridges and the fs see the same pkgs
+    let db = Rc::new(if unprivileged {
+        db::Db::open_read_only(&db_path)?
+    } else {
+        db::Db::new(&db_path)?
+    });
 
-    let db = db::Db::new(&db_path)?;
+    if let Commands::Bridge { command } = &cli.command {
+        return bridge_command(command, &config, &db, &cache_dir);
+    }
 
-    let input = input::Input::load(&inputs_path)?;
+    let input_context = InputContext::host(config.profile.clone(), &config.vars);
+    let mut input = input::Input::load_for(&inputs_path, &input_context)?;
+    input.apply_defaults(&config.bridge_defaults);
 
     let needed_bridges = input
         .bridges
         .iter()
+        .filter(|b| b.needs_bridge_dir())
         .map(|b| b.name.clone())
         .collect::<Vec<String>>();
 
-    let bridge_api = bridge::BridgeApi::new(bridges_set.to_path_buf(), &needed_bridges, &db_path)?;
+    let bridge_api = bridge::BridgeApi::new_with_aliases(
+        bridges_set.to_path_buf(),
+        &needed_bridges,
+        &config.bridge_aliases,
+        db.clone(),
+    )?
+    .with_options(BridgeOptions {
+        log_dir: log_dir.clone(),
+        working_dir: working_dir.clone(),
+        workdir_retention: config.workdir_retention,
+        workdir_max_size: config.workdir_max_size,
+        retry: config.retry,
+        env: config.bridge_env.clone(),
+        redact: config.redact.clone(),
+        // a user can't write the log of root, and what it runs changes nothing
+        audit: (!unprivileged).then(|| audit.clone()),
+    });
+
+    let mut pkg_link_strategies = HashMap::new();
+    let mut pkg_load_paths = HashMap::new();
+    for pkg in input.bridges.iter().flat_map(|b| &b.pkgs) {
+        if let Some(input::AttributeValue::String(name)) = pkg.attributes.get("load-path") {
+            pkg_load_paths.insert(pkg.name.clone(), name.clone());
+        }
+        if let Some(input::AttributeValue::String(strategy)) = pkg.attributes.get("link-strategy") {
+            let parsed = strategy
+                .parse()
+                .map_err(|_| fs::FsError::UnknownLinkStrategy {
+                    pkg: pkg.name.clone(),
+                    strategy: strategy.clone(),
+                })?;
+            pkg_link_strategies.insert(pkg.name.clone(), parsed);
+        }
+    }
 
-    let fs = fs::Fs::new(target_dir, load_path, &db_path);
+    let fs = fs::Fs::new(target_dir, load_path, db.clone())?
+        .with_link_strategies(config.link_strategy, pkg_link_strategies)
+        .with_permissions(config.store_permissions.clone())
+        .with_load_paths(config.load_paths.clone(), pkg_load_paths)?
+        .with_unit_dir(config.unit_dir.clone())
+        .with_root(config.root.clone());
 
-    let spinner_style = ProgressStyle::with_template("{prefix:.bold.dim} {spinner} {wide_msg}")
+    let spinner_template = if output::colors() {
+        "{prefix:.bold.dim} {spinner} {wide_msg}"
+    } else {
+        "{prefix} {spinner} {wide_msg}"
+    };
+    let spinner_style = ProgressStyle::with_template(spinner_template)
         .unwrap()
         .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ");
     let job_style = ProgressStyle::with_template("{wide_msg}")
@@ -71,599 +440,2429 @@ fn main() -> Result<()> {
         .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ");
 
     match &cli.command {
-        Commands::Clean => {
-            if PathBuf::from(DEFAULT_LOG_DIR).exists() {
-                std::fs::remove_dir_all(DEFAULT_LOG_DIR).into_diagnostic()?;
+        Commands::Clean {
+            logs,
+            workdir,
+            cache,
+            store_orphans,
+            dry_run,
+        } => {
+            // nothing asked means the usual (safe) ones
+            let all = !(*logs || *workdir || *cache || *store_orphans);
+
+            let mut categories = Vec::new();
+            if *logs || all {
+                categories.push(("logs", vec![log_dir.clone()]));
+            }
+            if *workdir || all {
+                categories.push(("working dirs", vec![working_dir.clone()]));
+            }
+            if *cache || all {
+                categories.push(("cache", vec![cache_dir.clone()]));
+            }
+            if *store_orphans {
+                categories.push(("store orphans", fs.store_orphans()?));
             }
-            if PathBuf::from(DEFAULT_WORKING_DIR).exists() {
-                std::fs::remove_dir_all(DEFAULT_WORKING_DIR).into_diagnostic()?;
+
+            let mut freed = 0;
+            for (category, paths) in &categories {
+                let size = paths.iter().map(|p| fs::dir_size(p)).sum::<u64>();
+                freed += size;
+
+                println!(
+                    "{} {}",
+                    format!("{category}:").paint(Style::new().green().bold()),
+                    fs::format_size(size)
+                );
+
+                if !*dry_run {
+                    for path in paths {
+                        fs::remove_path(path)?;
+                    }
+                }
             }
 
-            println!("🧹🗑️✨");
+            if *dry_run {
+                println!(
+                    "{} {}",
+                    "would free:".paint(Style::new().green().bold()),
+                    fs::format_size(freed)
+                );
+            } else {
+                println!("🧹🗑️✨ {}", fs::format_size(freed));
+            }
 
             Ok(())
         }
-        Commands::Link => perform_linking(&fs, job_style.clone()),
-        Commands::Info { package } => {
-            let pkgs = if let Some(packages) = package {
-                db.get_pkgs_by_name(packages)?
-            } else {
-                db.get_pkgs()?
-            };
+        Commands::Du { json } => {
+            let usage = fs.disk_usage()?;
+
+            let mut bridges = Vec::<(String, u64)>::new();
+            for pkg in &usage {
+                match bridges.iter_mut().find(|(name, _)| name == &pkg.bridge) {
+                    Some((_, size)) => *size += pkg.size,
+                    None => bridges.push((pkg.bridge.clone(), pkg.size)),
+                }
+            }
+            bridges.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+            let total = bridges.iter().map(|(_, size)| size).sum::<u64>();
+
+            if *json {
+                let report = serde_json::json!({
+                    "total": total,
+                    "bridges": bridges
+                        .iter()
+                        .map(|(name, size)| serde_json::json!({ "name": name, "size": size }))
+                        .collect::<Vec<_>>(),
+                    "pkgs": usage
+                        .iter()
+                        .map(|pkg| serde_json::json!({
+                            "name": pkg.name,
+                            "bridge": pkg.bridge,
+                            "size": pkg.size,
+                        }))
+                        .collect::<Vec<_>>(),
+                });
+
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).into_diagnostic()?
+                );
+                return Ok(());
+            }
 
-            let table = pkgs
+            let table = usage
                 .iter()
                 .map(|pkg| {
                     vec![
                         pkg.name.clone().cell(),
-                        format!(
-                            "{}.{}.{}",
-                            pkg.version.first_cell, pkg.version.second_cell, pkg.version.third_cell
-                        )
-                        .cell(),
-                        pkg.path.display().to_string().cell(),
-                        match &pkg.pkg_type {
-                            PkgType::SingleExecutable => "executable".to_string(),
-                            PkgType::Directory(entry_point) => {
-                                format!("directory: {}", entry_point.display())
-                            }
-                        }
-                        .cell(),
+                        pkg.bridge.clone().cell(),
+                        fs::format_size(pkg.size).cell(),
                     ]
                 })
                 .collect::<Vec<_>>()
                 .table()
                 .title(vec![
                     "Name".cell().bold(true),
-                    "Version".cell().bold(true),
-                    "Path".cell().bold(true),
-                    "Type".cell().bold(true),
-                ]);
+                    "Bridge".cell().bold(true),
+                    "Size".cell().bold(true),
+                ])
+                .color_choice(table_colors());
+            print_stdout(table).into_diagnostic()?;
 
+            let table = bridges
+                .iter()
+                .map(|(name, size)| vec![name.clone().cell(), fs::format_size(*size).cell()])
+                .collect::<Vec<_>>()
+                .table()
+                .title(vec!["Bridge".cell().bold(true), "Size".cell().bold(true)])
+                .color_choice(table_colors());
             print_stdout(table).into_diagnostic()?;
+
+            println!(
+                "{} {}",
+                "total:".paint(Style::new().green().bold()),
+                fs::format_size(total)
+            );
+
             Ok(())
         }
-        Commands::Docs => {
-            println!("in the name of Allah");
-            let docs = include_str!("../docs/user.md");
-            println!("{}", docs);
-
+        Commands::Link => {
+            fs.link_with_events(&mut AuditSink::new(
+                audit,
+                TerminalSink::new(spinner_style, job_style, progress),
+            ))?;
             Ok(())
         }
-        #[cfg(feature = "cli_complation")]
-        Commands::Completions { shell } => {
-            let mut cmd = Cli::command();
-
-            match shell {
-                Shell::Bash => {
-                    clap_complete::generate(
-                        ClapShell::Bash,
-                        &mut cmd,
-                        "pkg",
-                        &mut std::io::stdout(),
-                    );
-                }
-                Shell::Fish => {
-                    clap_complete::generate(
-                        ClapShell::Fish,
-                        &mut cmd,
-                        "pkg",
-                        &mut std::io::stdout(),
-                    );
-                }
-                Shell::Zsh => {
-                    clap_complete::generate(
-                        ClapShell::Zsh,
-                        &mut cmd,
-                        "pkg",
-                        &mut std::io::stdout(),
-                    );
-                }
-                Shell::Elvish => {
-                    clap_complete::generate(
-                        ClapShell::Elvish,
-                        &mut cmd,
-                        "pkg",
-                        &mut std::io::stdout(),
-                    );
-                }
-                Shell::Nushell => {
-                    clap_complete::generate(Nushell, &mut cmd, "pkg", &mut std::io::stdout());
-                }
-                Shell::PowerShell => {
-                    clap_complete::generate(
-                        ClapShell::PowerShell,
-                        &mut cmd,
-                        "pkg",
-                        &mut std::io::stdout(),
-                    );
-                }
-            }
+        Commands::Adopt {
+            name,
+            path,
+            entry_point,
+            copy,
+        } => {
+            let pkg = fs.adopt(name, path, entry_point.as_deref(), *copy)?;
+            db.install_bridge_pkgs(&[&pkg], &ADOPTED_BRIDGE_NAME.to_string())?;
+            audit.record("adopt", &pkg.name, None)?;
 
+            println!(
+                "{} {} {}",
+                "adopted:".paint(Style::new().green().bold()),
+                pkg.name.paint(Style::new().purple()),
+                pkg.path.display()
+            );
+
+            fs.link_with_events(&mut AuditSink::new(
+                audit,
+                TerminalSink::new(spinner_style, job_style, progress),
+            ))?;
             Ok(())
         }
-        _ => {
-            // Handle commands
-            let mut total_installed_pkgs_count_index = 0;
-            let mut total_removed_pkgs_count_index = 0;
+        Commands::Status { env } => {
+            let snapshot = db.snapshot()?;
+            let tag_filter = TagFilter::default();
 
-            enum Job {
-                Install,
-                Update,
-                Remove,
-                Reinstall,
+            // what a `pkg build` would do, by the same planning
+            let mut rows = Vec::new();
+            for bridge in &input.bridges {
+                let (_, to_install, to_remove) =
+                    filter_pkgs_by_statuses(&snapshot, &bridge.pkgs, &bridge.name, &tag_filter);
+                rows.push((
+                    bridge.name.clone(),
+                    snapshot.pkgs_by_bridge(&bridge.name).len(),
+                    to_install.len(),
+                    to_remove.len(),
+                ));
             }
-
-            enum Action {
-                Add(Result<Pkg>),
-                Remove(Result<bool>),
+            // the bridges that aren't in the inputs anymore lose all their pkgs,
+            // the adopted ones are kept
+            for bridge in snapshot.bridges() {
+                if !input.bridges.iter().any(|b| b.name == bridge) {
+                    let installed = snapshot.pkgs_by_bridge(&bridge).len();
+                    let to_remove = if bridge == ADOPTED_BRIDGE_NAME {
+                        0
+                    } else {
+                        installed
+                    };
+                    rows.push((bridge, installed, 0, to_remove));
+                }
             }
 
-            for bridge in &input.bridges {
-                let pkgs = bridge
-                    .pkgs
+            let usage = fs.disk_usage()?;
+            let bridge_size = |name: &str| {
+                usage
                     .iter()
-                    .map(|p| p.name.clone())
-                    .collect::<Vec<String>>();
-
-                let (
-                    installed_pkgs_in_input,
-                    not_installed_pkgs_in_input,
-                    installed_pkgs_not_in_input,
-                ) = filter_pkgs_by_statuses(&db, &pkgs, &bridge.pkgs, bridge.name.as_str())?;
-                let mut installed_pkgs_in_input = installed_pkgs_in_input;
-
-                let pkgs_to_remove_count = installed_pkgs_not_in_input.len();
-                let pkgs_to_install_count = not_installed_pkgs_in_input.len();
-                let mut pkgs_to_update_count = 0;
-
-                let m = MultiProgress::new();
+                    .filter(|pkg| pkg.bridge == name)
+                    .map(|pkg| pkg.size)
+                    .sum::<u64>()
+            };
 
-                let mut jobs = vec![];
-                if let Commands::Build { update } = &cli.command {
-                    if *update {
-                        jobs.push(Job::Update);
-                    }
-                    jobs.push(Job::Install);
-                    jobs.push(Job::Remove);
-                } else if let Commands::Rebuild = cli.command {
-                    jobs.push(Job::Install);
-                    jobs.push(Job::Remove);
-                    jobs.push(Job::Reinstall);
-                } else if let Commands::Update { packages } = &cli.command {
-                    if let Some(packages) = packages {
-                        let mut pkgs = Vec::new();
-                        installed_pkgs_in_input.iter().for_each(|pkg| {
-                            if packages.contains(&pkg.name) {
-                                pkgs.push(pkg.clone());
-                            }
-                        });
-                        installed_pkgs_in_input = pkgs.clone();
-                        pkgs_to_update_count = pkgs.len();
-                    }
+            let table = rows
+                .iter()
+                .map(|(name, installed, to_install, to_remove)| {
+                    vec![
+                        name.clone().cell(),
+                        installed.cell(),
+                        to_install.cell(),
+                        to_remove.cell(),
+                        fs::format_size(bridge_size(name)).cell(),
+                    ]
+                })
+                .collect::<Vec<_>>()
+                .table()
+                .title(vec![
+                    "Bridge".cell().bold(true),
+                    "Installed".cell().bold(true),
+                    "To install".cell().bold(true),
+                    "To remove".cell().bold(true),
+                    "Size".cell().bold(true),
+                ])
+                .color_choice(table_colors());
+            print_stdout(table).into_diagnostic()?;
 
-                    jobs.push(Job::Update);
+            println!(
+                "{} {}",
+                "installed pkgs:".paint(Style::new().green().bold()),
+                snapshot.records.len()
+            );
+            println!(
+                "{} {}",
+                "disk usage:".paint(Style::new().green().bold()),
+                fs::format_size(usage.iter().map(|pkg| pkg.size).sum())
+            );
+            println!(
+                "{} {}",
+                "last build:".paint(Style::new().green().bold()),
+                match db.last_build()? {
+                    Some(build) => format!(
+                        "{} (📦{} 🗑️ {})",
+                        format_date(build.finished_at),
+                        build.installed,
+                        build.removed
+                    ),
+                    None => "never".to_string(),
                 }
+            );
 
-                print_bridge_header(
-                    &bridge.name,
-                    pkgs_to_install_count,
-                    pkgs_to_remove_count,
-                    pkgs_to_update_count,
+            let broken_links = fs.broken_links()?;
+            for link in &broken_links {
+                println!(
+                    "{} {}",
+                    "broken link:".paint(Style::new().yellow().bold()),
+                    link.display()
                 );
+            }
 
-                for job in jobs {
-                    let pkgs = match job {
-                        Job::Install => &not_installed_pkgs_in_input,
-                        Job::Update => &installed_pkgs_in_input,
-                        Job::Remove => &installed_pkgs_not_in_input,
-                        Job::Reinstall => &installed_pkgs_in_input,
-                    };
-
-                    let pkgs_count = pkgs.len();
+            // the warnings only, a build doesn't fix them
+            let orphans = snapshot.orphans();
+            for record in &orphans {
+                println!(
+                    "{} {} {}",
+                    "orphan:".paint(Style::new().yellow().bold()),
+                    record.pkg.name,
+                    format!("({})", record.bridge).paint(Style::new().dimmed())
+                );
+            }
+            if !orphans.is_empty() {
+                println!("  `pkg autoremove` removes them");
+            }
+            warn_bridge_major_changes(&snapshot, &input.bridges, &bridge_api);
+            for (pkg, dep) in snapshot.broken_deps() {
+                println!(
+                    "{} {pkg} needs {dep}, it's not installed",
+                    "broken dep:".paint(Style::new().yellow().bold())
+                );
+            }
 
-                    if pkgs_count == 0 {
-                        continue;
-                    }
+            let pending = rows.iter().map(|row| row.2 + row.3).sum::<usize>();
+            if pending == 0 && broken_links.is_empty() {
+                println!("{}", "In sync 🌻".paint(Style::new().green().bold()));
+            } else {
+                println!(
+                    "{} `pkg build` (or `pkg link` for the links) fixes it",
+                    "Not in sync:".paint(Style::new().yellow().bold())
+                );
+            }
 
-                    match job {
-                        Job::Install => print_job_header("install"),
-                        Job::Update => print_job_header("update"),
-                        Job::Remove => print_job_header("remove"),
-                        Job::Reinstall => print_job_header("reinstall"),
-                    }
+            if *env {
+                let yes_no = |value: bool| if value { "yes" } else { "no" };
 
-                    for (i, pkg) in pkgs.iter().enumerate() {
-                        let pb = m.add(ProgressBar::new(100));
-                        pb.set_style(spinner_style.clone());
-                        pb.set_prefix(format!("[{}/{}]", i + 1, pkgs_count));
-                        pb.set_message(format!("🚚 {}", pkg.name));
-                        pb.enable_steady_tick(Duration::from_millis(100));
-
-                        let pkg_name = pkg.name.clone();
-
-                        let action_result = match job {
-                            Job::Install => Action::Add(bridge_api.install(&bridge.name, pkg)),
-                            Job::Update => Action::Add(bridge_api.update(&bridge.name, pkg)),
-                            Job::Remove => Action::Remove(bridge_api.remove(&bridge.name, pkg)),
-                            Job::Reinstall => {
-                                let install_result = bridge_api.install(&bridge.name, pkg);
-
-                                if install_result.is_err() {
-                                    return Err(install_result.err().unwrap());
-                                }
-
-                                let remove_result = bridge_api.remove(&bridge.name, pkg);
-
-                                if remove_result.is_err() {
-                                    return Err(remove_result.err().unwrap());
-                                }
-
-                                let db_remove_result =
-                                    db.remove_pkgs(std::slice::from_ref(&pkg.name));
-                                if let Err(db_err) = db_remove_result {
-                                    pb.finish_with_message(format!(
-                                        "❌ {},{}: {}",
-                                        pkg.name.red().bold(),
-                                        "at remove pkg from db".red().underline(),
-                                        db_err.red()
-                                    ));
-                                }
-
-                                Action::Add(install_result)
-                            }
-                        };
-
-                        if let Action::Add(Err(err)) | Action::Remove(Err(err)) = action_result {
-                            pb.finish_with_message(format!(
-                                "❌ {},{}: {}",
-                                pkg.name.red().bold(),
-                                "at bridge operation".red().underline(),
-                                err.red()
-                            ));
-                            continue;
-                        }
+                println!(
+                    "{} {}",
+                    "container:".paint(Style::new().green().bold()),
+                    host.container
+                        .as_ref()
+                        .map(|c| c.to_string())
+                        .unwrap_or_else(|| yes_no(host.overlay_root).to_string())
+                );
+                println!(
+                    "{} {}",
+                    "systemd:".paint(Style::new().green().bold()),
+                    yes_no(host.systemd)
+                );
+                println!(
+                    "{} {}",
+                    "writable /var:".paint(Style::new().green().bold()),
+                    yes_no(host.var_writable)
+                );
+                println!(
+                    "{} {}",
+                    "overlay root:".paint(Style::new().green().bold()),
+                    yes_no(host.overlay_root)
+                );
+                println!(
+                    "{} {}",
+                    "user mode:".paint(Style::new().green().bold()),
+                    yes_no(host.user_mode())
+                );
+                println!(
+                    "{} {}",
+                    "log dir:".paint(Style::new().green().bold()),
+                    log_dir.display()
+                );
+                println!(
+                    "{} {}",
+                    "working dir:".paint(Style::new().green().bold()),
+                    working_dir.display()
+                );
+                println!(
+                    "{} {}",
+                    "cache dir:".paint(Style::new().green().bold()),
+                    cache_dir.display()
+                );
+            }
 
-                        match action_result {
-                            Action::Add(Ok(mut pkg)) => {
-                                pb.set_message(format!("🗃️ {}", pkg.name));
-
-                                let fs_res = fs
-                                    .store_or_overwrite(&mut [&mut pkg], Some(bridge.name.as_str()))
-                                    .inspect_err(|err| {
-                                        pb.finish_with_message(format!(
-                                            "❌ {}, {}: {}",
-                                            pkg.name.red().bold(),
-                                            "at store the pkg".red().underline(),
-                                            err.red()
-                                        ));
-                                    });
-
-                                if fs_res.is_err() {
-                                    continue;
-                                }
-
-                                if matches!(job, Job::Update) {
-                                    let db_res =
-                                        db.remove_pkgs(&[pkg.name.clone()]).inspect_err(|err| {
-                                            pb.finish_with_message(format!(
-                                                "❌ {}, {}: {}",
-                                                pkg.name.red().bold(),
-                                                "at remove pkg from db".red().underline(),
-                                                err.red()
-                                            ));
-                                        });
-
-                                    if db_res.is_err() {
-                                        continue;
-                                    }
-                                }
-
-                                let db_res = db
-                                    .install_bridge_pkgs(&[&pkg], &bridge.name)
-                                    .inspect_err(|err| {
-                                        pb.finish_with_message(format!(
-                                            "❌ {}, {}: {}",
-                                            pkg.name.red().bold(),
-                                            "at write pkg in db".red().underline(),
-                                            err.red()
-                                        ));
-                                    });
-
-                                if db_res.is_err() {
-                                    continue;
-                                }
-
-                                total_installed_pkgs_count_index += 1;
-                                pb.finish_with_message(format!("📦 {}.", pkg.name.green().bold()));
-                            }
-                            Action::Remove(Ok(true)) => {
-                                pb.set_message(format!("🗃️ {}", &pkg_name));
-
-                                let fs_res = fs.remove_pkgs(&[&pkg_name]).inspect_err(|err| {
-                                    pb.finish_with_message(format!(
-                                        "❌ {}, {}: {}",
-                                        &pkg_name.red().bold(),
-                                        "at remove the pkg".red().underline(),
-                                        err.red()
-                                    ));
-                                });
-
-                                if fs_res.is_err() {
-                                    continue;
-                                }
-
-                                let db_res = db
-                                    .remove_pkgs(std::slice::from_ref(&pkg_name))
-                                    .inspect_err(|err| {
-                                        pb.finish_with_message(format!(
-                                            "❌ {}, {}: {}",
-                                            &pkg_name.red().bold(),
-                                            "at remove pkg from db".red().underline(),
-                                            err.red()
-                                        ));
-                                    });
-
-                                if db_res.is_err() {
-                                    continue;
-                                }
-
-                                total_removed_pkgs_count_index += 1;
-                                pb.finish_with_message(format!("🗑️ {}.", &pkg_name.green().bold()));
-                            }
-                            Action::Add(Err(err)) | Action::Remove(Err(err)) => {
-                                // Error already handled in the map_err above
-                                return Err(err);
-                            }
-                            Action::Remove(Ok(false)) => {
-                                pb.finish_with_message(format!(
-                                    "❌ {}, {}: {}",
-                                    &pkg_name.red().bold(),
-                                    "at bridge operation".red().underline(),
-                                    "the remove operation returned false".red().bold()
-                                ));
-                            }
-                        }
+            Ok(())
+        }
+        Commands::Diff { json } => {
+            let snapshot = db.snapshot()?;
 
-                        pb.inc(1);
-                    }
-                }
+            // the pkgs for an other os or arch aren't missing, a build skips them
+            let diff = snapshot.diff_inputs(input.bridges.iter().flat_map(|bridge| {
+                bridge
+                    .pkgs
+                    .iter()
+                    .filter(|pkg| pkg.platform_mismatch(&input_context).is_none())
+                    .map(|pkg| (bridge.name.as_str(), pkg))
+            }));
+
+            if *json {
+                let report = serde_json::json!({
+                    "missing": diff
+                        .missing
+                        .iter()
+                        .map(|(bridge, name)| serde_json::json!({ "name": name, "bridge": bridge }))
+                        .collect::<Vec<_>>(),
+                    "undeclared": diff
+                        .undeclared
+                        .iter()
+                        .map(|(bridge, name, version)| serde_json::json!({
+                            "name": name,
+                            "bridge": bridge,
+                            "version": version,
+                        }))
+                        .collect::<Vec<_>>(),
+                    "bridge_differs": diff
+                        .bridge_differs
+                        .iter()
+                        .map(|(name, installed, declared)| serde_json::json!({
+                            "name": name,
+                            "installed": installed,
+                            "declared": declared,
+                        }))
+                        .collect::<Vec<_>>(),
+                    "version_drift": diff
+                        .version_drift
+                        .iter()
+                        .map(|(name, installed, pinned)| serde_json::json!({
+                            "name": name,
+                            "installed": installed,
+                            "pinned": pinned,
+                        }))
+                        .collect::<Vec<_>>(),
+                });
+
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).into_diagnostic()?
+                );
+                return Ok(());
             }
 
-            // hundle the out th serves bridge's pkgs
-            let bridges_in_db = db.get_bridges()?;
-            let bridges_in_input = input
-                .bridges
-                .iter()
-                .map(|b| b.name.clone())
-                .collect::<Vec<String>>();
-
-            let bridges_out_of_service_names = bridges_in_db
-                .iter()
-                .filter(|b| !bridges_in_input.contains(b))
-                .collect::<Vec<&String>>();
+            if diff.is_empty() {
+                println!(
+                    "{}",
+                    "The installed packages match the inputs 🌻".paint(Style::new().green().bold())
+                );
+                return Ok(());
+            }
 
-            if !bridges_out_of_service_names.is_empty() {
-                hint("Looks like u deprecate some bridges...");
+            let mut rows = Vec::new();
+            for (bridge, name) in &diff.missing {
+                rows.push(vec![
+                    name.clone().cell(),
+                    "-".cell(),
+                    bridge.clone().cell(),
+                    "missing".cell(),
+                ]);
+            }
+            for (bridge, name, version) in &diff.undeclared {
+                rows.push(vec![
+                    name.clone().cell(),
+                    format!("{bridge} {version}").cell(),
+                    "-".cell(),
+                    "undeclared".cell(),
+                ]);
+            }
+            for (name, installed, declared) in &diff.bridge_differs {
+                rows.push(vec![
+                    name.clone().cell(),
+                    installed.clone().cell(),
+                    declared.clone().cell(),
+                    "other bridge".cell(),
+                ]);
+            }
+            for (name, installed, pinned) in &diff.version_drift {
+                rows.push(vec![
+                    name.clone().cell(),
+                    installed.clone().cell(),
+                    pinned.clone().cell(),
+                    "version drift".cell(),
+                ]);
+            }
 
-                let mut any_bridge_remove_impl_failed = false;
+            let table = rows
+                .table()
+                .title(vec![
+                    "Name".cell().bold(true),
+                    "Installed".cell().bold(true),
+                    "Declared".cell().bold(true),
+                    "Change".cell().bold(true),
+                ])
+                .color_choice(table_colors());
 
-                for bridge in bridges_out_of_service_names {
-                    let pkgs_to_remove = db.get_pkgs_by_bridge(bridge)?;
-
-                    let m = MultiProgress::new();
-
-                    print_bridge_header(bridge, 0, pkgs_to_remove.len(), 0);
-                    print_job_header("remove");
-
-                    let mut i = 1;
-                    pkgs_to_remove.iter().for_each(|pkg| {
-                        let pb = m.add(ProgressBar::new(100));
-                        pb.set_style(spinner_style.clone());
-                        pb.set_prefix(format!("[{}/{}]", i, pkgs_to_remove.len()));
-                        pb.set_message(format!("🗃️ {}", pkg.name));
-                        pb.enable_steady_tick(Duration::from_millis(100));
-
-                        let removed = if let Ok(bridge_api) = bridge::BridgeApi::new(
-                            bridges_set.clone(),
-                            std::slice::from_ref(bridge),
-                            &db_path,
-                        ) {
-                            bridge_api
-                                .remove(bridge, &pkg.to_pkg_declaration_with_empty_attributes())
-                                .inspect_err(|_| {
-                                    any_bridge_remove_impl_failed = true;
-                                })
-                        } else {
-                            bridge_api.default_impls_remove(&pkg.name)
-                        };
-
-                        if let Err(err) = removed {
-                            pb.finish_with_message(format!(
-                                "❌ {}, {}: {}",
-                                &pkg.name.red().bold(),
-                                "at bridge operation".red().underline(),
-                                err.red()
-                            ));
-                        } else {
-                            let db_res = db.remove_pkgs(std::slice::from_ref(&pkg.name));
-
-                            let _ = fs.remove_pkgs(std::slice::from_ref(&&pkg.name));
-                            if db_res.is_err() {
-                                pb.finish_with_message(format!(
-                                    "❌ {}, {}: {}",
-                                    &pkg.name.red().bold(),
-                                    "at remove pkg from db".red().underline(),
-                                    db_res.err().unwrap().red()
-                                ));
-                            }
+            print_stdout(table).into_diagnostic()?;
+            Ok(())
+        }
+        Commands::Metrics { textfile } => {
+            let snapshot = db.snapshot()?;
+            let mut metrics = Metrics::new();
+
+            let installed = snapshot
+                .bridges()
+                .into_iter()
+                .map(|bridge| {
+                    let count = snapshot.pkgs_by_bridge(&bridge).len() as f64;
+                    (bridge, count)
+                })
+                .collect::<Vec<(String, f64)>>();
+            metrics.gauge(
+                "pkg_installed_packages",
+                "The installed packages, by bridge",
+                &installed
+                    .iter()
+                    .map(|(bridge, count)| (vec![("bridge", bridge.as_str())], *count))
+                    .collect::<Vec<_>>(),
+            );
 
-                            i += 1;
-                            pb.inc(1);
-                            pb.finish_with_message(format!("🗑️ {}.", &pkg.name.green().bold()));
-                        }
-                    });
-                }
-                if any_bridge_remove_impl_failed {
-                    hint(
-                        "to use the default remove function impl try to remove the bridge it self or for more info run `pkg docs`",
-                    );
+            // nothing before the first build
+            if let Some(build) = db.last_build()? {
+                for (name, help, value) in [
+                    (
+                        "pkg_last_build_timestamp_seconds",
+                        "When the last build (or update) finished, in unix time",
+                        build.finished_at as f64,
+                    ),
+                    (
+                        "pkg_last_build_duration_seconds",
+                        "How long the last build took",
+                        build.duration.as_secs_f64(),
+                    ),
+                    (
+                        "pkg_last_build_installed_packages",
+                        "The packages installed or updated by the last build",
+                        build.installed as f64,
+                    ),
+                    (
+                        "pkg_last_build_removed_packages",
+                        "The packages removed by the last build",
+                        build.removed as f64,
+                    ),
+                    (
+                        "pkg_last_build_failures",
+                        "The failures of the last build (packages, links, hooks...)",
+                        build.failures as f64,
+                    ),
+                ] {
+                    metrics.gauge(name, help, &[(Vec::new(), value)]);
                 }
             }
 
-            perform_linking(&fs, job_style.clone())?;
-
-            println!(
-                "{}\n📦{} 🗑️ {}",
-                "Summary:".green().bold(),
-                total_installed_pkgs_count_index,
-                total_removed_pkgs_count_index,
+            // like `pkg diff`, the pkgs for an other os or arch aren't missing
+            let diff = snapshot.diff_inputs(input.bridges.iter().flat_map(|bridge| {
+                bridge
+                    .pkgs
+                    .iter()
+                    .filter(|pkg| pkg.platform_mismatch(&input_context).is_none())
+                    .map(|pkg| (bridge.name.as_str(), pkg))
+            }));
+            metrics.gauge(
+                "pkg_outdated_packages",
+                "The installed packages that aren't at the version pinned in the inputs",
+                &[(Vec::new(), diff.version_drift.len() as f64)],
+            );
+            metrics.gauge(
+                "pkg_missing_packages",
+                "The packages declared in the inputs that aren't installed",
+                &[(Vec::new(), diff.missing.len() as f64)],
             );
-            println!("{}", "Done 🌻, thanks to Allah".green().bold());
+            metrics.gauge(
+                "pkg_undeclared_packages",
+                "The installed packages that aren't in the inputs anymore",
+                &[(Vec::new(), diff.undeclared.len() as f64)],
+            );
+
+            match textfile {
+                Some(path) => metrics.write_textfile(path)?,
+                None => print!("{}", metrics.as_str()),
+            }
 
             Ok(())
         }
-    }
-}
-
-fn check_root_privileges() -> bool {
-    let output = Command::new("id")
-        .arg("-u")
-        .output()
-        .expect("Failed to check user ID");
-
-    if output.status.success() {
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        let uid = output_str.trim();
-        uid == "0"
-    } else {
-        false
-    }
-}
+        Commands::Compare { state } => {
+            let other = Db::open_read_only(state)?;
+            let diff = db.compare(&other)?;
+
+            if diff.only_local.is_empty()
+                && diff.only_other.is_empty()
+                && diff.version_differs.is_empty()
+            {
+                println!(
+                    "{}",
+                    "Both machines have the same packages 🌻".paint(Style::new().green().bold())
+                );
+                return Ok(());
+            }
 
-fn prompt_for_sudo() -> Result<()> {
-    print!("{}: ", "password".blue().bold());
-    io::stdout().flush().into_diagnostic()?;
+            let mut rows = Vec::new();
+            for pkg in &diff.only_local {
+                rows.push(vec![
+                    pkg.name.clone().cell(),
+                    pkg.version.to_string().cell(),
+                    "-".cell(),
+                ]);
+            }
+            for pkg in &diff.only_other {
+                rows.push(vec![
+                    pkg.name.clone().cell(),
+                    "-".cell(),
+                    pkg.version.to_string().cell(),
+                ]);
+            }
+            for (name, local_version, other_version) in &diff.version_differs {
+                rows.push(vec![
+                    name.clone().cell(),
+                    local_version.clone().cell(),
+                    other_version.clone().cell(),
+                ]);
+            }
+
+            let table = rows
+                .table()
+                .title(vec![
+                    "Name".cell().bold(true),
+                    "Local".cell().bold(true),
+                    "Other".cell().bold(true),
+                ])
+                .color_choice(table_colors());
+
+            print_stdout(table).into_diagnostic()?;
+            Ok(())
+        }
+        Commands::Info {
+            package,
+            bridge,
+            pkg_type,
+            glob,
+            sort,
+            long,
+            files,
+            tree,
+            remote,
+            json,
+        } => {
+            if *remote {
+                return print_remote_info(
+                    package.as_deref().unwrap_or_default(),
+                    &input.bridges,
+                    &bridge_api,
+                    *json,
+                );
+            }
+
+            let records = db.query(&PkgFilter {
+                bridge: bridge.clone(),
+                names: package.clone().unwrap_or_default(),
+                name_glob: glob.clone(),
+                pkg_type: pkg_type.map(|pkg_type| match pkg_type {
+                    PkgTypeFilter::Executable => PkgKind::SingleExecutable,
+                    PkgTypeFilter::Directory => PkgKind::Directory,
+                }),
+                ..Default::default()
+            })?;
+            let mut records = records.iter().collect::<Vec<&db::PkgRecord>>();
+
+            if *files {
+                records.sort_by(|a, b| a.pkg.name.cmp(&b.pkg.name));
+                return print_pkg_files(&records, &fs, *tree, *json);
+            }
+
+            // walking the pkgs dirs is slow, only when it's needed
+            let sizes = if *long || *sort == InfoSort::Size {
+                records
+                    .iter()
+                    .map(|r| (r.pkg.name.clone(), fs::dir_size(&r.pkg.path)))
+                    .collect::<HashMap<String, u64>>()
+            } else {
+                HashMap::new()
+            };
+
+            records.sort_by(|a, b| match sort {
+                InfoSort::Name => a.pkg.name.cmp(&b.pkg.name),
+                InfoSort::Version => a.pkg.version.compare(&b.pkg.version),
+                // the largest and the newest first
+                InfoSort::Size => sizes[&b.pkg.name].cmp(&sizes[&a.pkg.name]),
+                InfoSort::InstalledAt => b.installed_at.cmp(&a.installed_at),
+            });
+
+            if *json {
+                let pkgs = records
+                    .iter()
+                    .map(|r| {
+                        let pkg = &r.pkg;
+                        let mut value = serde_json::json!({
+                            "name": pkg.name,
+                            "version": pkg.version.to_string(),
+                            "path": pkg.path,
+                            "entry_point": match &pkg.pkg_type {
+                                PkgType::SingleExecutable => None,
+                                PkgType::Directory(entry_point) => Some(entry_point),
+                            },
+                            "bridge": r.bridge,
+                            "installed_at": r.installed_at,
+                            "tags": r.tags,
+                            "description": pkg.metadata.description,
+                            "homepage": pkg.metadata.homepage,
+                            "license": pkg.metadata.license,
+                            "source_url": pkg.metadata.source_url,
+                        });
+                        if *long {
+                            value["size"] = sizes[&pkg.name].into();
+                        }
+                        value
+                    })
+                    .collect::<Vec<_>>();
+
+                println!("{}", serde_json::to_string_pretty(&pkgs).into_diagnostic()?);
+                return Ok(());
+            }
+
+            let table = records
+                .iter()
+                .map(|r| {
+                    let pkg = &r.pkg;
+                    let mut row = vec![
+                        pkg.name.clone().cell(),
+                        pkg.version.to_string().cell(),
+                        pkg.path.display().to_string().cell(),
+                        match &pkg.pkg_type {
+                            PkgType::SingleExecutable => "executable".to_string(),
+                            PkgType::Directory(entry_point) => {
+                                format!("directory: {}", entry_point.display())
+                            }
+                        }
+                        .cell(),
+                    ];
+
+                    if *long {
+                        row.push(r.bridge.clone().cell());
+                        row.push(fs::format_size(sizes[&pkg.name]).cell());
+                        row.push(format_date(r.installed_at).cell());
+                        for field in pkg.metadata.fields() {
+                            row.push(field.unwrap_or("-").cell());
+                        }
+                    }
+
+                    row
+                })
+                .collect::<Vec<_>>();
+
+            let mut title = vec![
+                "Name".cell().bold(true),
+                "Version".cell().bold(true),
+                "Path".cell().bold(true),
+                "Type".cell().bold(true),
+            ];
+            if *long {
+                title.push("Bridge".cell().bold(true));
+                title.push("Size".cell().bold(true));
+                title.push("Installed".cell().bold(true));
+                title.push("Description".cell().bold(true));
+                title.push("Homepage".cell().bold(true));
+                title.push("License".cell().bold(true));
+                title.push("Source".cell().bold(true));
+            }
+
+            print_stdout(table.table().title(title).color_choice(table_colors()))
+                .into_diagnostic()?;
+            Ok(())
+        }
+        Commands::Outdated {
+            packages,
+            bridge,
+            json,
+        } => print_outdated(
+            &db.snapshot()?,
+            &input.bridges,
+            &bridge_api,
+            packages,
+            bridge.as_ref(),
+            *json,
+        ),
+        #[cfg(feature = "cli_complation")]
+        Commands::Completions { shell } => {
+            completions::generate(shell, &mut std::io::stdout()).into_diagnostic()
+        }
+        _ => {
+            // Handle commands
+            let build_started_at = Instant::now();
+
+            let command_name = cli.command.build_name().unwrap_or("build");
+            let mut sink = AuditSink::new(
+                audit,
+                FailureSink::new(
+                    command_name,
+                    log_dir.clone(),
+                    ReportSink::new(
+                        command_name,
+                        CountingSink::new(TerminalSink::new(spinner_style, job_style, progress)),
+                    ),
+                ),
+            );
+
+            let systemctl = Systemctl::default();
+            let mut outcome = BuildOutcome::default();
+
+            let tag_filter = match &cli.command {
+                Commands::Build {
+                    tags, exclude_tags, ..
+                } => TagFilter {
+                    include: tags.clone(),
+                    exclude: exclude_tags.clone(),
+                },
+                _ => TagFilter::default(),
+            };
+
+            let name_filter = match &cli.command {
+                // the pkgs of the failure report of the last build, and only them
+                Commands::Build {
+                    retry_failed: true, ..
+                } => {
+                    let failed = FailureReport::read(&log_dir)?.pkg_names();
+                    if failed.is_empty() {
+                        println!("No failed packages to retry 🌻");
+                        return Ok(());
+                    }
+                    NameFilter {
+                        only: failed,
+                        exclude: Vec::new(),
+                    }
+                }
+                Commands::Build { only, exclude, .. } => NameFilter {
+                    only: only.clone(),
+                    exclude: exclude.clone(),
+                },
+                _ => NameFilter::default(),
+            };
+
+            // the other bridges are skipped, their pkgs are left as they are
+            let only_bridge = match &cli.command {
+                Commands::Build { bridge, .. } | Commands::Rebuild { bridge, .. } => {
+                    bridge.as_ref()
+                }
+                _ => None,
+            };
+            if let Some(name) = only_bridge
+                && !input.bridges.iter().any(|bridge| &bridge.name == name)
+            {
+                return Err(BridgeApiError::BridgeNotFound(name.clone()).into());
+            }
+
+            // the bridges are run again even for what didn't change, the pkgs
+            // named to `pkg rebuild` are the ones that are broken
+            let force = match &cli.command {
+                Commands::Rebuild {
+                    packages, force, ..
+                } => *force || !packages.is_empty(),
+                Commands::Update { force, .. } => *force,
+                _ => false,
+            };
+
+            // `pkg rebuild <pkg...> --bridge <bridge>` only reinstalls these, nothing
+            // is installed or removed
+            let rebuild_targets = match &cli.command {
+                Commands::Rebuild {
+                    packages, bridge, ..
+                } if !packages.is_empty() || bridge.is_some() => {
+                    check_rebuild_targets(&db.snapshot()?, &input.bridges, packages, bridge)?;
+                    Some(packages)
+                }
+                _ => None,
+            };
+
+            // the bridges that aren't in the inputs anymore, all their pkgs are
+            // removed (unless a filter leaves them as they are)
+            let autoremove = matches!(cli.command, Commands::Autoremove);
+            let bridges_out_of_service_names = if rebuild_targets.is_some()
+                || only_bridge.is_some()
+                || autoremove
+            {
+                Vec::new()
+            } else {
+                let bridges_in_input = input
+                    .bridges
+                    .iter()
+                    .map(|b| b.name.clone())
+                    .collect::<Vec<String>>();
+
+                // the adopted pkgs have no bridge to be removed by
+                db.get_bridges()?
+                    .into_iter()
+                    .filter(|b| !bridges_in_input.contains(b) && b.as_str() != ADOPTED_BRIDGE_NAME)
+                    .collect::<Vec<String>>()
+            };
+
+            // an empty or missing inputs (or a bridge file that was renamed...)
+            // shouldn't wipe the machine, and a typo shouldn't remove a pkg silently
+            let updated = match &cli.command {
+                Commands::Build { update: true, .. } => Some(&[][..]),
+                Commands::Update { packages, .. } => Some(packages.as_deref().unwrap_or_default()),
+                Commands::Rebuild { packages, .. } => Some(&packages[..]),
+                _ => None,
+            };
+            let plan = if autoremove {
+                Plan::autoremove(&db.snapshot()?, &input.bridges)
+            } else {
+                Plan::new(
+                    &db.snapshot()?,
+                    &input.bridges,
+                    &bridges_out_of_service_names,
+                    PlanScope {
+                        only_bridge,
+                        name_filter: &name_filter,
+                        tag_filter: &tag_filter,
+                        removes_declared: rebuild_targets.is_none()
+                            && matches!(
+                                cli.command,
+                                Commands::Build { .. } | Commands::Rebuild { .. }
+                            ),
+                        when_excluded: &input.when_excluded,
+                        when_excluded_pkgs: &input.when_excluded_pkgs,
+                        updated,
+                    },
+                )
+            };
+            warn_bridge_major_changes(&db.snapshot()?, &input.bridges, &bridge_api);
+            if autoremove && plan.removed.is_empty() {
+                println!("No orphans to remove 🌻");
+                return Ok(());
+            }
+            confirm_plan(&plan, cli.allow_mass_remove, cli.yes, interactive)?;
+
+            let build = Build {
+                db: &db,
+                fs: &fs,
+                bridge_api: &bridge_api,
+                systemctl: &systemctl,
+                hooks: &config.hooks,
+                licenses: &config.licenses,
+                allow_license_violations: cli.allow_license_violations,
+                force,
+                log_dir: &log_dir,
+            };
+
+            // the `auto` pkgs no declared pkg needs aren't installed
+            let needed_deps = input::needed_deps(&input.bridges);
+
+            for bridge in &input.bridges {
+                if only_bridge.is_some_and(|name| name != &bridge.name) {
+                    continue;
+                }
+
+                // taken for every bridge, because the previous one may has changed the db
+                let snapshot = db.snapshot()?;
+
+                let (
+                    installed_pkgs_in_input,
+                    not_installed_pkgs_in_input,
+                    installed_pkgs_not_in_input,
+                ) = filter_pkgs_by_statuses(
+                    &snapshot,
+                    &bridge.pkgs,
+                    bridge.name.as_str(),
+                    &tag_filter,
+                );
+                let mut installed_pkgs_in_input = installed_pkgs_in_input;
+
+                // what's filtered out by name isn't installed, updated or removed
+                let mut not_installed_pkgs_in_input = not_installed_pkgs_in_input;
+                let mut installed_pkgs_not_in_input = installed_pkgs_not_in_input;
+                for pkgs in [
+                    &mut installed_pkgs_in_input,
+                    &mut not_installed_pkgs_in_input,
+                    &mut installed_pkgs_not_in_input,
+                ] {
+                    pkgs.retain(|pkg| name_filter.matches(&pkg.name));
+                }
+
+                // the pkgs for an other os or arch are left as they are
+                let mut skipped = Vec::new();
+                for pkgs in [
+                    &mut installed_pkgs_in_input,
+                    &mut not_installed_pkgs_in_input,
+                ] {
+                    pkgs.retain(|pkg| match pkg.platform_mismatch(&input_context) {
+                        Some(reason) => {
+                            skipped.push((pkg.name.clone(), reason));
+                            false
+                        }
+                        None => true,
+                    });
+                }
+                not_installed_pkgs_in_input.retain(|pkg| {
+                    if pkg.is_auto() && !needed_deps.contains(&pkg.name) {
+                        skipped.push((pkg.name.clone(), "no pkg needs it".to_string()));
+                        return false;
+                    }
+                    true
+                });
+
+                // `pkg autoremove` removes the orphans, declared or not, and
+                // nothing else
+                if autoremove {
+                    installed_pkgs_not_in_input = installed_pkgs_in_input
+                        .drain(..)
+                        .chain(installed_pkgs_not_in_input)
+                        .filter(|pkg| plan.removed.iter().any(|(name, _)| name == &pkg.name))
+                        .collect();
+                    not_installed_pkgs_in_input.clear();
+                    skipped.clear();
+                }
+
+                let (pkgs_to_remove_count, pkgs_to_install_count) = match rebuild_targets {
+                    Some(_) => (0, 0),
+                    None => (
+                        installed_pkgs_not_in_input.len(),
+                        not_installed_pkgs_in_input.len(),
+                    ),
+                };
+                let mut pkgs_to_update_count = 0;
+
+                let mut jobs = vec![];
+                if let Commands::Build { update, .. } = &cli.command {
+                    if *update {
+                        jobs.push(Job::Update);
+                    }
+                    jobs.push(Job::Install);
+                    jobs.push(Job::Remove);
+                } else if autoremove {
+                    jobs.push(Job::Remove);
+                } else if let Some(packages) = rebuild_targets {
+                    if !packages.is_empty() {
+                        installed_pkgs_in_input.retain(|pkg| packages.contains(&pkg.name));
+                    }
+
+                    jobs.push(Job::Reinstall);
+                } else if let Commands::Rebuild { .. } = cli.command {
+                    jobs.push(Job::Install);
+                    jobs.push(Job::Remove);
+                    jobs.push(Job::Reinstall);
+                } else if let Commands::Update {
+                    packages, unpinned, ..
+                } = &cli.command
+                {
+                    if let Some(packages) = packages {
+                        let mut pkgs = Vec::new();
+                        installed_pkgs_in_input.iter().for_each(|pkg| {
+                            if packages.contains(&pkg.name) {
+                                pkgs.push(pkg.clone());
+                            }
+                        });
+                        installed_pkgs_in_input = pkgs.clone();
+                        pkgs_to_update_count = pkgs.len();
+                    }
+
+                    // the pinned ones wait for the inputs to change their version
+                    if *unpinned {
+                        installed_pkgs_in_input
+                            .retain(|pkg| !pkg.attributes.contains_key("version"));
+                        pkgs_to_update_count = installed_pkgs_in_input.len();
+                    }
+
+                    jobs.push(Job::Update);
+                }
+
+                let pkgs_of = |job: &Job| match job {
+                    Job::Install => &not_installed_pkgs_in_input[..],
+                    Job::Update | Job::Reinstall => &installed_pkgs_in_input[..],
+                    Job::Remove => &installed_pkgs_not_in_input[..],
+                };
+                build.run_bridge(
+                    BridgeJobs {
+                        bridge: &bridge.name,
+                        jobs: jobs.iter().map(|job| (*job, pkgs_of(job))).collect(),
+                        skipped,
+                        install: pkgs_to_install_count,
+                        remove: pkgs_to_remove_count,
+                        update: pkgs_to_update_count,
+                    },
+                    &mut outcome,
+                    &mut sink,
+                )?;
+            }
+
+            // hundle the out th serves bridge's pkgs
+            if !bridges_out_of_service_names.is_empty() {
+                if bridges_out_of_service_names
+                    .iter()
+                    .any(|bridge| !input.when_excluded.contains(bridge))
+                {
+                    hint("Looks like u deprecate some bridges...");
+                }
+
+                let mut any_bridge_remove_impl_failed = false;
+
+                for bridge in &bridges_out_of_service_names {
+                    let mut pkgs_to_remove = db.get_pkgs_by_bridge(bridge)?;
+                    pkgs_to_remove.retain(|pkg| name_filter.matches(&pkg.name));
+
+                    // its own remove while its dir is still in the bridges set
+                    let own_api = bridge::BridgeApi::new_with_aliases(
+                        bridges_set.clone(),
+                        std::slice::from_ref(bridge),
+                        &config.bridge_aliases,
+                        db.clone(),
+                    )
+                    .ok();
+
+                    any_bridge_remove_impl_failed |= build.remove_out_of_service(
+                        bridge,
+                        &pkgs_to_remove,
+                        own_api.as_ref(),
+                        &mut outcome,
+                        &mut sink,
+                    );
+                }
+                if any_bridge_remove_impl_failed {
+                    hint(
+                        "to use the default remove function impl try to remove the bridge it self or for more info run `pkg docs`",
+                    );
+                }
+            }
+
+            build.finish(&outcome, &mut sink)?;
+
+            db.record_build(
+                outcome.installed,
+                outcome.removed,
+                sink.inner().inner().inner().failures(),
+                build_started_at.elapsed(),
+            )?;
+
+            sink.emit(Event::Summary {
+                installed: outcome.installed,
+                removed: outcome.removed,
+            });
+
+            for err in config.notify.send(sink.inner().inner().report()) {
+                sink.emit(Event::NotifyFailed {
+                    error: err.to_string(),
+                });
+            }
+
+            let failures = sink.inner().inner().inner().failures();
+            if failures > 0 {
+                match sink.inner().report().write(&log_dir) {
+                    Ok(path) => println!(
+                        "{} {}",
+                        "failure report:".paint(Style::new().yellow().bold()),
+                        path.display()
+                    ),
+                    Err(err) => hint(&format!("the failure report wasn't written: {err}")),
+                }
+                return Err(CliError::PartialFailure(failures, log_dir).into());
+            }
+            // nothing left to retry
+            let _ = FailureReport::remove(&log_dir);
+
+            Ok(())
+        }
+    }
+}
+
+// through sudo, doas or run0, on the same terminal: they ask for the password
+// themselves and the exit status is the one of pkg as root
+fn re_run_as_root(escalate: Option<Escalation>, interactive: bool) -> Result<()> {
+    let escalation = match escalate {
+        Some(escalation) if !escalation.is_available() => {
+            return Err(CliError::MissingEscalation(escalation).into());
+        }
+        Some(escalation) => escalation,
+        None => Escalation::detect().ok_or(CliError::NoEscalation)?,
+    };
+
+    if !escalation.authenticate(interactive).into_diagnostic()? {
+        return Err(if interactive {
+            CliError::EscalationDenied(escalation)
+        } else {
+            CliError::NeedsRoot(escalation)
+        }
+        .into());
+    }
+
+    let current_exe = std::env::current_exe().into_diagnostic()?;
+    let args = std::env::args_os().skip(1).collect::<Vec<_>>();
+
+    // only returns if it couldn't run
+    let err = host::exec(&mut escalation.command_for(&current_exe, &args, interactive));
+    Err(err).into_diagnostic()
+}
+
+fn get_valid_config_path() -> Result<PathBuf> {
+    let xdg_config_home: String = std::env::var("XDG_CONFIG_HOME").unwrap_or_else(|_| {
+        // `%APPDATA%\pkg` on windows
+        if cfg!(windows)
+            && let Ok(app_data) = std::env::var("APPDATA")
+        {
+            return app_data;
+        }
+        let home_dir = std::env::var("HOME").expect("HOME environment variable not set");
+        format!("{home_dir}/.config")
+    });
+
+    let xdg_config_home = format!("{xdg_config_home}/pkg");
+
+    let xdg_config_home = PathBuf::from(&xdg_config_home);
+
+    std::fs::create_dir_all(&xdg_config_home).expect("Failed to make the defuld config dir");
+
+    Ok(xdg_config_home)
+}
+
+// refuses a bridge that would lose all its pkgs (no declaration loaded for
+// it), asks before removing more than a quarter of the pkgs even with `--yes`,
+// and before any removal or downgrade on a terminal. an error when it's declined
+fn confirm_plan(plan: &Plan, allow_mass_remove: bool, yes: bool, interactive: bool) -> Result<()> {
+    let mass_remove = match plan.confirmation(allow_mass_remove, yes, interactive) {
+        Confirmation::Proceed => return Ok(()),
+        Confirmation::BridgesEmptied(bridges) => {
+            return Err(CliError::BridgesEmptied(bridges.join(", ")).into());
+        }
+        Confirmation::Ask { mass_remove } => mass_remove,
+    };
+
+    print_plan(plan);
+
+    let question = if mass_remove {
+        format!(
+            "{} of the {} installed pkgs would be removed, continue?",
+            plan.removed.len(),
+            plan.installed
+        )
+    } else {
+        "Continue?".to_string()
+    };
+
+    if confirm_destructive(&question, interactive)? {
+        Ok(())
+    } else if mass_remove {
+        Err(CliError::MassRemove(plan.removed.len(), plan.installed).into())
+    } else {
+        Err(CliError::Declined.into())
+    }
+}
+
+fn print_plan(plan: &Plan) {
+    println!("{}", "The plan:".paint(Style::new().bold()));
+    for (name, bridge) in &plan.removed {
+        println!(
+            "  🗑️ {name} {}",
+            format!("({bridge})").paint(Style::new().dimmed())
+        );
+    }
+    for (name, installed, pinned) in &plan.downgraded {
+        println!(
+            "  ⬇️ {name} {}",
+            format!("{installed} -> {pinned}").paint(Style::new().yellow())
+        );
+    }
+}
+
+// the bridges whose major version changed since they installed their pkgs,
+// the pkgs may need a `pkg rebuild --bridge`
+fn warn_bridge_major_changes(
+    snapshot: &DbSnapshot,
+    bridges: &[input::Bridge],
+    bridge_api: &bridge::BridgeApi,
+) {
+    for bridge in bridges {
+        let Some(version) = bridge_api.bridge_version(&bridge.name) else {
+            continue;
+        };
+        let records = snapshot.installed_by_other_major(&bridge.name, version);
+        let Some(first) = records.first() else {
+            continue;
+        };
+
+        println!(
+            "{} `{}` is {version} now, {} of its pkgs were installed by {}",
+            "bridge changed:".paint(Style::new().yellow().bold()),
+            bridge.name,
+            records.len(),
+            first.bridge_version
+        );
+        println!("  `pkg rebuild --bridge {}` reinstalls them", bridge.name);
+    }
+}
+
+// the pkgs named to `pkg rebuild` have to be installed from the inputs
+fn check_rebuild_targets(
+    snapshot: &DbSnapshot,
+    bridges: &[input::Bridge],
+    packages: &[String],
+    only_bridge: &Option<String>,
+) -> Result<()> {
+    let not_installed =
+        missing_rebuild_targets(snapshot, bridges, packages, only_bridge.as_deref());
+
+    if not_installed.is_empty() {
+        Ok(())
+    } else {
+        Err(CliError::NotInstalled(not_installed.join(", ")).into())
+    }
+}
+
+// the config with the profile and the root of the command line
+fn load_config(path: PathBuf, cli: &Cli) -> Result<Config> {
+    let mut config = Config::load_profile(path, cli.profile.clone())?;
+    if let Some(root) = cli.root.clone().or(config.root.take()) {
+        config.set_root(root);
+    }
+
+    Ok(config)
+}
+
+fn config_command(command: &ConfigCommands, path: PathBuf, cli: &Cli) -> Result<()> {
+    // a broken config can be fixed with it, it only has to load after
+    if let ConfigCommands::Set { key, values } = command {
+        config::set(&path, key, values)?;
+        println!(
+            "{} {key} = {}",
+            "set:".paint(Style::new().green().bold()),
+            values.join(" ")
+        );
+        return Ok(());
+    }
+
+    let config = load_config(path, cli)?;
+    match command {
+        ConfigCommands::Check => println!(
+            "{} {}",
+            "all good:".paint(Style::new().green().bold()),
+            config.path.display()
+        ),
+        ConfigCommands::Show => print!("{}", config.to_kdl()),
+        ConfigCommands::Get { key } => match config.get(key) {
+            Some(value) => println!("{value}"),
+            None => return Err(ConfigError::UnknownKey(key.clone()).into()),
+        },
+        ConfigCommands::Set { .. } => {}
+    }
+
+    Ok(())
+}
+
+fn db_command(command: &DbCommands, db_path: &Path) -> Result<()> {
+    match command {
+        DbCommands::Backup { path } => {
+            let db = db::Db::new(&db_path.to_path_buf())?;
+
+            let dest = match path {
+                Some(path) => {
+                    db.backup(path)?;
+                    path.clone()
+                }
+                None => db::backup_rotating(db_path, db::KEPT_BACKUPS)?
+                    .ok_or(DbError::BackupNotFound(db_path.to_path_buf()))?,
+            };
+
+            println!(
+                "{} {}",
+                "backed up to:".paint(Style::new().green().bold()),
+                dest.display()
+            );
+        }
+        DbCommands::Restore { path } => {
+            let src = if path.as_os_str() == "latest" {
+                db::list_backups(db_path)?
+                    .pop()
+                    .ok_or(DbError::BackupNotFound(db::backups_dir(db_path)))?
+            } else {
+                path.clone()
+            };
+
+            // a wrong path shouldn't rotate out a good backup
+            if !src.is_file() {
+                return Err(DbError::BackupNotFound(src).into());
+            }
+
+            // taken after `latest` is picked, so it's not restoring itself. a
+            // corrupted db has nothing to keep
+            match db::backup_rotating(db_path, db::KEPT_BACKUPS) {
+                Ok(Some(current)) => hint(&format!(
+                    "the current db is backed up to {}",
+                    current.display()
+                )),
+                Ok(None) => {}
+                Err(DbError::Corrupted { .. }) => {
+                    hint("the current db is corrupted, it's not backed up")
+                }
+                Err(err) => return Err(err.into()),
+            }
+
+            db::Db::open_unchecked(&db_path.to_path_buf())?.restore(&src)?;
+
+            println!(
+                "{} {}",
+                "restored from:".paint(Style::new().green().bold()),
+                src.display()
+            );
+        }
+        DbCommands::Vacuum => {
+            let size = || std::fs::metadata(db_path).map(|m| m.len()).unwrap_or(0);
+            let before = size();
+
+            db::Db::new(&db_path.to_path_buf())?.vacuum()?;
+
+            println!(
+                "{} {} ({} -> {})",
+                "vacuumed:".paint(Style::new().green().bold()),
+                db_path.display(),
+                fs::format_size(before),
+                fs::format_size(size())
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn audit_command(command: &AuditCommands, audit: &AuditLog) -> Result<()> {
+    match command {
+        AuditCommands::Show { last, json } => {
+            // a broken chain is an error, nothing after it is shown
+            let records = audit.read()?;
+            let skip = last.map_or(0, |last| records.len().saturating_sub(last));
+            let shown = &records[skip..];
+
+            if *json {
+                let records = shown
+                    .iter()
+                    .map(|record| {
+                        serde_json::json!({
+                            "seq": record.seq,
+                            "time": record.time,
+                            "user": record.user,
+                            "command": record.command,
+                            "action": record.action,
+                            "target": record.target,
+                            "code": record.code,
+                            "hash": record.hash,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&records).into_diagnostic()?
+                );
+                return Ok(());
+            }
+
+            let table = shown
+                .iter()
+                .map(|record| {
+                    vec![
+                        record.seq.cell(),
+                        format_date(record.time as i64).cell(),
+                        record.user.clone().cell(),
+                        record.action.clone().cell(),
+                        record.target.clone().cell(),
+                        record
+                            .code
+                            .map(|code| code.to_string())
+                            .unwrap_or_else(|| "-".to_string())
+                            .cell(),
+                        record.command.clone().cell(),
+                    ]
+                })
+                .collect::<Vec<_>>()
+                .table()
+                .title(vec![
+                    "#".cell().bold(true),
+                    "Time".cell().bold(true),
+                    "User".cell().bold(true),
+                    "Action".cell().bold(true),
+                    "Target".cell().bold(true),
+                    "Code".cell().bold(true),
+                    "Command".cell().bold(true),
+                ])
+                .color_choice(table_colors());
+            print_stdout(table).into_diagnostic()?;
+
+            println!(
+                "{} {} records, {}",
+                "chain ok:".paint(Style::new().green().bold()),
+                records.len(),
+                audit.path().display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// clones the repo the first time, then pulls it for the commands that
+// change the system, a failed pull (offline...) keeps the last checkout
+fn sync_git_inputs(git_inputs: &GitInputs, checkout: &Path, pull: bool) -> Result<()> {
+    if !checkout.exists() {
+        println!(
+            "📥 {} {}",
+            "cloning".paint(Style::new().blue().bold()),
+            git_inputs.url
+        );
+        if let Some(parent) = checkout.parent() {
+            std::fs::create_dir_all(parent).into_diagnostic()?;
+        }
+        git::clone(&git_inputs.url, checkout, git_inputs.branch.as_deref())?;
+    } else if pull && let Err(err) = git::pull(checkout) {
+        hint(&format!(
+            "failed to pull the inputs, using the last checkout: {err}"
+        ));
+    }
+
+    Ok(())
+}
+
+// a systemd timer on the systems that run systemd, cron on the others (the
+// containers, the systems with another init)
+fn schedule_command(
+    command: &ScheduleCommands,
+    config: &Config,
+    host: &HostEnv,
+    cli: &Cli,
+) -> Result<()> {
+    let systemd_dir = config.rooted(Path::new(schedule::SYSTEMD_DIR));
+    // the systemctl of the host only manages the host
+    let systemctl = (config.root.is_none() && host.supports_daemons()).then(Systemctl::default);
+    let scheduler = if systemctl.is_some() || (config.root.is_some() && systemd_dir.is_dir()) {
+        Scheduler::SystemdTimer(systemd_dir)
+    } else {
+        Scheduler::Cron(config.rooted(Path::new(schedule::CRON_DIR)))
+    };
+    let timer = scheduler
+        .timer_name()
+        .map(|name| scheduler.files().into_iter().find(|f| f.ends_with(&name)))
+        .unwrap_or_default();
+
+    match command {
+        ScheduleCommands::Install { weekly, .. } => {
+            let mut command = vec![
+                std::env::current_exe()
+                    .into_diagnostic()?
+                    .display()
+                    .to_string(),
+                "--non-interactive".to_string(),
+            ];
+            if let Some(profile) = &cli.profile {
+                command.extend(["--profile".to_string(), profile.clone()]);
+            }
+            command.push("update".to_string());
+            if config.schedule.only_unpinned {
+                command.push("--unpinned".to_string());
+            }
+
+            // the run finds the config of the one who scheduled it
+            let env = config
+                .path
+                .parent()
+                .and_then(Path::parent)
+                .map(|dir| vec![("XDG_CONFIG_HOME".to_string(), dir.display().to_string())])
+                .unwrap_or_default();
+
+            let files = scheduler.install(&Schedule {
+                frequency: if *weekly {
+                    Frequency::Weekly
+                } else {
+                    Frequency::Daily
+                },
+                jitter: config.schedule.jitter,
+                env,
+                command,
+            })?;
+
+            if let (Some(systemctl), Some(timer)) = (&systemctl, &timer) {
+                systemctl.daemon_reload()?;
+                systemctl.enable_now(std::slice::from_ref(timer))?;
+            }
+
+            for file in files {
+                println!(
+                    "{} {}",
+                    "written:".paint(Style::new().green().bold()),
+                    file.display()
+                );
+            }
+        }
+        ScheduleCommands::Status => {
+            let installed = scheduler.installed()?;
+            if installed.is_empty() {
+                println!("not scheduled, see `pkg schedule install`");
+                return Ok(());
+            }
+
+            for (file, content) in installed {
+                println!("{}", file.display().paint(Style::new().green().bold()));
+                // without the header
+                for line in content.lines().skip(1) {
+                    println!("  {line}");
+                }
+            }
+
+            if let (Some(systemctl), Some(timer)) = (&systemctl, &timer) {
+                let name = systemd::unit_name(timer);
+                for (label, property) in [
+                    ("next run:", "NextElapseUSecRealtime"),
+                    ("last run:", "LastTriggerUSec"),
+                ] {
+                    let value = systemctl.property(&name, property)?;
+                    println!(
+                        "{} {}",
+                        label.paint(Style::new().green().bold()),
+                        if value.is_empty() || value == "n/a" {
+                            "never"
+                        } else {
+                            &value
+                        }
+                    );
+                }
+            }
+        }
+        ScheduleCommands::Remove => {
+            if let (Some(systemctl), Some(timer)) = (&systemctl, &timer)
+                && timer.exists()
+            {
+                systemctl.disable_now(std::slice::from_ref(timer))?;
+            }
+
+            let removed = scheduler.remove()?;
+            if removed.is_empty() {
+                println!("not scheduled");
+                return Ok(());
+            }
+
+            if let Some(systemctl) = &systemctl
+                && timer.is_some()
+            {
+                systemctl.daemon_reload()?;
+            }
+
+            for file in removed {
+                println!(
+                    "{} {}",
+                    "removed:".paint(Style::new().green().bold()),
+                    file.display()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// `pkg info --files`, the store files then the links of every pkg, with a
+// header when there's more than one
+// `pkg info --remote`, what the bridges of the declarations tell of the
+// pkgs, installed or not
+fn print_remote_info(
+    names: &[String],
+    bridges: &[input::Bridge],
+    bridge_api: &bridge::BridgeApi,
+    json: bool,
+) -> Result<()> {
+    let mut infos = Vec::new();
+    for name in names {
+        let Some((bridge, pkg)) = bridges.iter().find_map(|bridge| {
+            bridge
+                .pkgs
+                .iter()
+                .find(|pkg| &pkg.name == name)
+                .map(|pkg| (bridge, pkg))
+        }) else {
+            return Err(CliError::NotDeclared(name.clone()).into());
+        };
+
+        infos.push((name, &bridge.name, bridge_api.info(&bridge.name, pkg)?));
+    }
+
+    if json {
+        let pkgs = infos
+            .iter()
+            .map(|(name, bridge, info)| {
+                serde_json::json!({
+                    "name": name,
+                    "bridge": bridge,
+                    "version": info.version.to_string(),
+                    "description": info.metadata.description,
+                    "homepage": info.metadata.homepage,
+                    "license": info.metadata.license,
+                    "source_url": info.metadata.source_url,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        println!("{}", serde_json::to_string_pretty(&pkgs).into_diagnostic()?);
+        return Ok(());
+    }
+
+    let table = infos
+        .iter()
+        .map(|(name, bridge, info)| {
+            let mut row = vec![name.cell(), bridge.cell(), info.version.to_string().cell()];
+            for field in info.metadata.fields() {
+                row.push(field.unwrap_or("-").cell());
+            }
+            row
+        })
+        .collect::<Vec<_>>();
+
+    print_stdout(
+        table
+            .table()
+            .title(vec![
+                "Name".cell().bold(true),
+                "Bridge".cell().bold(true),
+                "Latest".cell().bold(true),
+                "Description".cell().bold(true),
+                "Homepage".cell().bold(true),
+                "License".cell().bold(true),
+                "Source".cell().bold(true),
+            ])
+            .color_choice(table_colors()),
+    )
+    .into_diagnostic()
+}
+
+// `pkg outdated`, the installed pkgs the `info` of their bridge has a newer
+// version of. a bridge without `info` is asked once, its pkgs aren't checked
+fn print_outdated(
+    snapshot: &DbSnapshot,
+    bridges: &[input::Bridge],
+    bridge_api: &bridge::BridgeApi,
+    packages: &[String],
+    only_bridge: Option<&String>,
+    json: bool,
+) -> Result<()> {
+    // name, bridge, installed, latest, pinned
+    let mut outdated = Vec::new();
+    let mut unsupported = Vec::new();
+
+    for bridge in bridges
+        .iter()
+        .filter(|bridge| only_bridge.is_none_or(|name| &bridge.name == name))
+    {
+        for pkg in &bridge.pkgs {
+            if !packages.is_empty() && !packages.contains(&pkg.name) {
+                continue;
+            }
+            let Some(record) = snapshot.get(&pkg.name) else {
+                continue;
+            };
+
+            match bridge_api.info(&bridge.name, pkg) {
+                Ok(info) if info.version.compare(&record.pkg.version).is_gt() => {
+                    outdated.push((
+                        pkg.name.clone(),
+                        bridge.name.clone(),
+                        record.pkg.version.to_string(),
+                        info.version.to_string(),
+                        pkg.attributes.contains_key("version"),
+                    ));
+                }
+                Ok(_) => {}
+                Err(BridgeApiError::UnsupportedOperation(_)) => {
+                    unsupported.push(bridge.name.clone());
+                    break;
+                }
+                // the others are still checked
+                Err(err) => eprintln!(
+                    "{} {}: {err}",
+                    "failed:".paint(Style::new().red().bold()),
+                    pkg.name
+                ),
+            }
+        }
+    }
 
-    let password = read_password().into_diagnostic()?;
+    if json {
+        let pkgs = outdated
+            .iter()
+            .map(|(name, bridge, installed, latest, pinned)| {
+                serde_json::json!({
+                    "name": name,
+                    "bridge": bridge,
+                    "installed": installed,
+                    "latest": latest,
+                    "pinned": pinned,
+                })
+            })
+            .collect::<Vec<_>>();
 
-    // Validate the password by trying to run a simple sudo command
-    let mut validation = Command::new("sudo")
-        .arg("-S")
-        .arg("echo")
-        .arg("password_valid")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()
+        println!("{}", serde_json::to_string_pretty(&pkgs).into_diagnostic()?);
+        return Ok(());
+    }
+
+    if outdated.is_empty() {
+        println!(
+            "{}",
+            "Everything is up to date 🌻".paint(Style::new().green().bold())
+        );
+    } else {
+        let table = outdated
+            .iter()
+            .map(|(name, bridge, installed, latest, pinned)| {
+                vec![
+                    name.cell(),
+                    bridge.cell(),
+                    installed.cell(),
+                    if *pinned {
+                        format!("{latest} (pinned)")
+                    } else {
+                        latest.clone()
+                    }
+                    .cell(),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        print_stdout(
+            table
+                .table()
+                .title(vec![
+                    "Name".cell().bold(true),
+                    "Bridge".cell().bold(true),
+                    "Installed".cell().bold(true),
+                    "Latest".cell().bold(true),
+                ])
+                .color_choice(table_colors()),
+        )
         .into_diagnostic()?;
+        println!("`pkg update` updates them, the pinned ones with `--force` or a new `version`");
+    }
 
-    let stdin = validation.stdin.as_mut();
+    for bridge in unsupported {
+        println!(
+            "{}",
+            format!("`{bridge}` has no `info` operation, its pkgs weren't checked")
+                .paint(Style::new().dimmed())
+        );
+    }
+
+    Ok(())
+}
 
-    // Send the password to sudo
-    if let Some(stdin) = stdin {
-        stdin.write_all(password.as_bytes()).into_diagnostic()?;
-        stdin.write_all(b"\n").into_diagnostic()?;
+fn print_pkg_files(records: &[&db::PkgRecord], fs: &fs::Fs, tree: bool, json: bool) -> Result<()> {
+    let listed = records
+        .iter()
+        .map(|r| Ok((&r.pkg, fs.pkg_files(&r.pkg)?)))
+        .collect::<Result<Vec<_>, fs::FsError>>()?;
+
+    if json {
+        let pkgs = listed
+            .iter()
+            .map(|(pkg, files)| {
+                serde_json::json!({
+                    "name": pkg.name,
+                    "files": files.store,
+                    "links": files.links,
+                })
+            })
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&pkgs).into_diagnostic()?);
+        return Ok(());
     }
 
-    let status = validation.wait().into_diagnostic()?;
+    for (i, (pkg, files)) in listed.iter().enumerate() {
+        if listed.len() > 1 {
+            if i > 0 {
+                println!();
+            }
+            println!("{}", pkg.name.paint(Style::new().bold()));
+        }
 
-    if status.success() {
-        // Password is valid, re-run the command with sudo
-        re_run_with_sudo()?;
-    } else {
-        eprintln!("Incorrect password or sudo access denied.");
-        std::process::exit(1);
+        if tree && matches!(pkg.pkg_type, PkgType::Directory(_)) {
+            print!("{}", fs::file_tree(&pkg.path, &files.store));
+        } else {
+            for file in &files.store {
+                println!("{}", file.display());
+            }
+        }
+        for link in &files.links {
+            println!("{}", link.display().paint(Style::new().cyan()));
+        }
     }
 
     Ok(())
 }
 
-fn re_run_with_sudo() -> Result<()> {
+// the policy names this binary, pkexec only uses it to run this one
+fn polkit_command(command: &PolkitCommands, config: &Config) -> Result<()> {
+    let dir = config.rooted(Path::new(privilege::POLKIT_ACTIONS_DIR));
     let current_exe = std::env::current_exe().into_diagnostic()?;
-    let args: Vec<String> = std::env::args().collect();
 
-    let status = Command::new("sudo")
-        .arg(&current_exe)
-        .args(&args[1..]) // Skip the program name
-        .status()
-        .into_diagnostic()?;
+    match command {
+        PolkitCommands::Install => {
+            let path = privilege::install_polkit_policy(&dir, &current_exe)?;
+            println!(
+                "{} {}",
+                "written:".paint(Style::new().green().bold()),
+                path.display()
+            );
+        }
+        PolkitCommands::Show => print!("{}", privilege::polkit_policy(&current_exe)),
+        PolkitCommands::Remove => {
+            if privilege::remove_polkit_policy(&dir)? {
+                println!(
+                    "{} {}",
+                    "removed:".paint(Style::new().green().bold()),
+                    dir.join(privilege::POLKIT_POLICY_NAME).display()
+                );
+            } else {
+                println!("no policy in {}", dir.display());
+            }
+        }
+    }
 
-    std::process::exit(status.code().unwrap_or(1));
+    Ok(())
 }
 
-fn get_valid_config_path() -> Result<PathBuf> {
-    let xdg_config_home: String = std::env::var("XDG_CONFIG_HOME").unwrap_or_else(|_| {
-        let home_dir = std::env::var("HOME").expect("HOME environment variable not set");
-        format!("{home_dir}/.config")
-    });
+// the bridges of a registry, fetched into the bridges set. where each one
+// came from is in the db, `update` fetches it again from there
+fn bridge_command(
+    command: &BridgeCommands,
+    config: &Config,
+    db: &Db,
+    cache_dir: &Path,
+) -> Result<()> {
+    // the version of the registry, or the start of the revision
+    let version = |version: &str, revision: &str| {
+        if version.is_empty() {
+            revision.chars().take(12).collect()
+        } else {
+            version.to_string()
+        }
+    };
+
+    match command {
+        BridgeCommands::Install {
+            name,
+            registry: url,
+            force,
+        } => {
+            registry::check_name(name)?;
+            let Some(url) = url.as_ref().or(config.bridge_registry.as_ref()) else {
+                return Err(RegistryError::NoRegistry.into());
+            };
+            if config.bridges_set.join(name).exists()
+                && db.get_bridge_source(name)?.is_none()
+                && !force
+            {
+                return Err(RegistryError::AlreadyInstalled(name.clone()).into());
+            }
 
-    let xdg_config_home = format!("{xdg_config_home}/pkg");
+            let fetched = registry::fetch(&Registry::parse(url), name, cache_dir)?;
+            registry::install(&fetched, &config.bridges_set, name)?;
+            db.set_bridge_source(name, url, &fetched.version, &fetched.revision)?;
 
-    let xdg_config_home = PathBuf::from(&xdg_config_home);
+            println!(
+                "{} {name} {} (from {url})",
+                "installed:".paint(Style::new().green().bold()),
+                version(&fetched.version, &fetched.revision)
+            );
+        }
+        BridgeCommands::Update { names } => {
+            let mut sources = db.get_bridge_sources()?;
+            if let Some(name) = names
+                .iter()
+                .find(|name| !sources.iter().any(|s| &s.name == *name))
+            {
+                return Err(RegistryError::NotFromRegistry(name.clone()).into());
+            }
+            if !names.is_empty() {
+                sources.retain(|s| names.contains(&s.name));
+            }
+            if sources.is_empty() {
+                println!("No bridge installed from a registry");
+            }
 
-    std::fs::create_dir_all(&xdg_config_home).expect("Failed to make the defuld config dir");
+            for source in sources {
+                let fetched =
+                    registry::fetch(&Registry::parse(&source.registry), &source.name, cache_dir)?;
+                if fetched.revision == source.revision {
+                    println!("{} is up to date", source.name);
+                    continue;
+                }
 
-    Ok(xdg_config_home)
+                registry::install(&fetched, &config.bridges_set, &source.name)?;
+                db.set_bridge_source(
+                    &source.name,
+                    &source.registry,
+                    &fetched.version,
+                    &fetched.revision,
+                )?;
+
+                println!(
+                    "{} {} {} -> {}",
+                    "updated:".paint(Style::new().green().bold()),
+                    source.name,
+                    version(&source.version, &source.revision),
+                    version(&fetched.version, &fetched.revision)
+                );
+            }
+        }
+    }
+
+    Ok(())
 }
 
-fn filter_pkgs_by_statuses(
-    db: &Db,
-    inputs_pkgs: &[String],
-    pkgs_declarations: &[PkgDeclaration],
-    bridge_name: &str,
-) -> Result<(
-    Vec<PkgDeclaration>,
-    Vec<PkgDeclaration>,
-    Vec<PkgDeclaration>,
-)> {
-    let all_installed_pkgs = db.get_pkgs()?;
-
-    let installed_pkgs_in_input_names = db.which_pkgs_are_installed(inputs_pkgs)?;
-    let not_installed_pkgs_in_input_names = db.which_pkgs_are_not_installed(inputs_pkgs)?;
-    let installed_pkgs_not_in_input_names: Vec<String> = all_installed_pkgs
-        .iter()
-        .filter(|p| !inputs_pkgs.contains(&p.name))
-        .map(|p| p.name.clone())
-        .collect();
+// a `pkg build` in its own process for every change, it loads the config and
+// the inputs again and takes the lock like any build
+fn watch_inputs(config: &Config, debounce: Duration, cli: &Cli) -> Result<()> {
+    let mut watcher = Watcher::new()?;
+    watcher.add_dir(&config.source_dir)?;
+    watcher.add_file(&config.path)?;
+
+    // nobody is there to answer while it watches, the flags given to
+    // `pkg watch` are for the builds
+    let mut args = vec!["--non-interactive".to_string()];
+    for (flag, set) in [
+        ("--quiet", cli.quiet),
+        ("--allow-mass-remove", cli.allow_mass_remove),
+        ("--allow-license-violations", cli.allow_license_violations),
+    ] {
+        if set {
+            args.push(flag.to_string());
+        }
+    }
+    if let Some(profile) = &cli.profile {
+        args.extend(["--profile".to_string(), profile.clone()]);
+    }
+    if let Some(root) = &cli.root {
+        args.extend(["--root".to_string(), root.display().to_string()]);
+    }
+    args.push("build".to_string());
 
-    let installed_pkgs_in_input = pkgs_declarations
-        .iter()
-        .filter(|p| installed_pkgs_in_input_names.iter().any(|n| **n == p.name))
-        .cloned()
-        .collect();
+    let current_exe = std::env::current_exe().into_diagnostic()?;
 
-    let not_installed_pkgs_in_input = pkgs_declarations
-        .iter()
-        .filter(|p| {
-            not_installed_pkgs_in_input_names
-                .iter()
-                .any(|n| **n == p.name)
-        })
-        .cloned()
-        .collect();
+    println!(
+        "{} {}",
+        "watching:".paint(Style::new().green().bold()),
+        config.source_dir.display()
+    );
 
-    let installed_pkgs_not_in_input = all_installed_pkgs
-        .iter()
-        .filter(|p| {
-            installed_pkgs_not_in_input_names.contains(&p.name)
-                && db
-                    .get_pkg_bridge_by_name(&p.name)
-                    .expect("Failed to get pkg bridge")
-                    == bridge_name
-        })
-        .map(|p| p.to_pkg_declaration_with_empty_attributes())
-        .collect();
+    loop {
+        let changed = watcher.wait(debounce)?;
+        if !cli.quiet {
+            for path in &changed {
+                println!(
+                    "{} {}",
+                    "changed:".paint(Style::new().blue().bold()),
+                    path.display()
+                );
+            }
+        }
+
+        let status = Command::new(&current_exe)
+            .args(&args)
+            .stdin(Stdio::null())
+            .status()
+            .into_diagnostic()?;
+
+        if !status.success() {
+            let message = match status.code() {
+                Some(code) => format!("the build failed with the exit code {code}"),
+                None => "the build was killed".to_string(),
+            };
+            eprintln!("{} {message}", "watch:".paint(Style::new().red().bold()));
+            watch::notify_failure(&message);
+        }
+    }
+}
+
+// every problem in the inputs at once, nothing is installed or removed
+fn check(config: &Config) -> Result<()> {
+    let (mut input, problems) = input::Input::check_for(
+        &config.source_dir,
+        &InputContext::host(config.profile.clone(), &config.vars),
+    )?;
+    input.apply_defaults(&config.bridge_defaults);
+    // the manifest errors are reported with the inputs ones
+    let mut problems: Vec<miette::Report> = problems.into_iter().map(miette::Report::new).collect();
+
+    let available_bridges = bridge::available_bridges(&config.bridges_set)?;
+
+    for bridge in &input.bridges {
+        if !bridge.needs_bridge_dir() {
+            continue;
+        }
+
+        // an instance of the config is checked with the bridge it runs
+        let bridge_dir = bridge::resolve_alias(&config.bridge_aliases, &bridge.name);
+        if !available_bridges.iter().any(|b| b == bridge_dir) {
+            problems.push(miette::Report::new(input::InputError::UnknownBridge {
+                bridge: bridge.name.clone(),
+                pkgs: bridge.pkgs.len(),
+            }));
+            continue;
+        }
+
+        let manifest = match BridgeManifest::load(&config.bridges_set.join(bridge_dir)) {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                problems.push(miette::Report::new(err).wrap_err(format!(
+                    "The manifest of the {} bridge is wrong",
+                    bridge.name
+                )));
+                continue;
+            }
+        };
+
+        for command in &manifest.needs {
+            if host::find_command(command).is_none() {
+                problems.push(miette::Report::new(input::InputError::MissingHostCommand {
+                    command: command.clone(),
+                    bridge: bridge.name.clone(),
+                }));
+            }
+        }
+
+        for pkg in bridge.pkgs.iter().filter(|p| p.bridge.is_none()) {
+            for attribute in &manifest.required_attributes {
+                if !pkg.attributes.contains_key(attribute) {
+                    problems.push(miette::Report::new(
+                        input::InputError::MissingRequiredAttribute {
+                            pkg: pkg.name.clone(),
+                            bridge: bridge.name.clone(),
+                            attribute: attribute.clone(),
+                        },
+                    ));
+                }
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        let pkgs = input.bridges.iter().map(|b| b.pkgs.len()).sum::<usize>();
+        println!(
+            "{} {} pkgs in {} bridges",
+            "all good:".paint(Style::new().green().bold()),
+            pkgs,
+            input.bridges.len()
+        );
+        return Ok(());
+    }
+
+    let count = problems.len();
+    for problem in problems {
+        eprintln!("{problem:?}");
+    }
+
+    Err(input::InputError::CheckFailed(count).into())
+}
+
+fn show_docs(topic: Option<DocsTopic>, no_pager: bool) -> Result<()> {
+    let markdown = match topic {
+        Some(topic) => docs::topic(topic).content.to_string(),
+        None => docs::index(),
+    };
+
+    let text = format!(
+        "in the name of Allah\n\n{}",
+        docs::render(&markdown, output::colors())
+    );
+
+    if io::stdout().is_terminal() && !no_pager {
+        page(&text)
+    } else {
+        print!("{text}");
+        Ok(())
+    }
+}
+
+// `$PAGER` (`less -R` by default), printed as it is if it can't be run
+fn page(text: &str) -> Result<()> {
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less -R".to_string());
+
+    let Ok(mut child) = Command::new("sh")
+        .arg("-c")
+        .arg(&pager)
+        .stdin(Stdio::piped())
+        .spawn()
+    else {
+        print!("{text}");
+        return Ok(());
+    };
+
+    // the pager quit before the end, nothing to report
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait().into_diagnostic()?;
+
+    Ok(())
+}
+
+// the db isn't created if it's not there yet, nothing is installed anyway
+fn complete_pkgs(config: &Config) -> Result<()> {
+    if !config.db_path.exists() {
+        return Ok(());
+    }
+
+    let mut names = Db::open_read_only(&config.db_path)?
+        .get_pkgs()?
+        .into_iter()
+        .map(|pkg| pkg.name)
+        .collect::<Vec<String>>();
+    names.sort();
+
+    for name in names {
+        println!("{name}");
+    }
+
+    Ok(())
+}
+
+// replaces pkg with the pkg process, so the exit status is the pkg one
+fn run_pkg(name: &str, args: &[String], config: &Config) -> Result<()> {
+    let db = Db::open_read_only(&config.db_path)?;
+
+    let pkg = db
+        .get_pkgs_by_name(&[name.to_string()])?
+        .pop()
+        .ok_or_else(|| db::DbError::PkgNotFound(name.to_string()))?;
+
+    let load_paths = std::iter::once(config.load_path.clone())
+        .chain(config.load_paths.values().cloned())
+        .collect::<Vec<PathBuf>>();
+
+    // only returns if it failed
+    let err = host::exec(&mut fs::run_command(&pkg, args, &load_paths)?);
+
+    Err(err).into_diagnostic()
+}
+
+fn init_inputs(url: &str, config: &Config, interactive: bool) -> Result<()> {
+    let dest = config.source_dir.join(git::repo_name(url));
+
+    if dest.exists() {
+        return Err(input::InputError::InputsDirExists(dest).into());
+    }
+
+    println!("📥 {} {}", "cloning".paint(Style::new().blue().bold()), url);
+    git::clone(url, &dest, None)?;
+
+    // a broken template shouldn't break the next build
+    let template = match input::Input::load(&dest) {
+        Ok(template) => template,
+        Err(err) => {
+            std::fs::remove_dir_all(&dest).into_diagnostic()?;
+            return Err(err.into());
+        }
+    };
+
+    let available_bridges = bridge::available_bridges(&config.bridges_set)?;
+
+    for bridge in &template.bridges {
+        let bridge_dir = bridge::resolve_alias(&config.bridge_aliases, &bridge.name);
+        if available_bridges.iter().any(|b| b == bridge_dir) {
+            continue;
+        }
+
+        hint(&format!(
+            "the bridge `{}` ({} pkgs) is not in your bridges set",
+            bridge.name,
+            bridge.pkgs.len()
+        ));
+
+        if confirm(
+            &format!("disable the `{}` declarations?", bridge.name),
+            interactive,
+        )? {
+            let disabled = input::disable_bridge(&dest, &bridge.name)?;
+            println!(
+                "🔕 {} {}",
+                disabled,
+                "declarations disabled".paint(Style::new().yellow())
+            );
+        }
+    }
+
+    println!(
+        "{} {}",
+        "inputs ready in".paint(Style::new().green().bold()),
+        dest.display()
+    );
+
+    Ok(())
+}
+
+// the kdl goes to the stdout (or the output file), what couldn't be mapped to a
+// bridge of the bridges set to the stderr
+fn import_pkgs(
+    format: ImportFormat,
+    file: &Path,
+    map: &[String],
+    output: Option<&Path>,
+    config: &Config,
+) -> Result<()> {
+    let map = import::parse_map(map)?;
+    if let Some(output) = output
+        && output.exists()
+    {
+        return Err(ImportError::Exists(output.to_path_buf()).into());
+    }
+
+    let src = if file == Path::new("-") {
+        io::read_to_string(io::stdin()).into_diagnostic()?
+    } else {
+        std::fs::read_to_string(file).into_diagnostic()?
+    };
+    let pkgs = match format {
+        ImportFormat::Brewfile => import::parse_brewfile(&src),
+        ImportFormat::Apt => import::parse_apt(&src),
+        ImportFormat::Cargo => import::parse_cargo(&src),
+    };
+
+    // the instances of the config can be mapped to too
+    let mut available_bridges = bridge::available_bridges(&config.bridges_set)?;
+    available_bridges.extend(
+        config
+            .bridge_aliases
+            .iter()
+            .filter(|(_, bridge)| available_bridges.contains(bridge))
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<String>>(),
+    );
+    let (kdl, unmapped) = import::to_kdl(&pkgs, &map, &available_bridges);
+
+    match output {
+        Some(output) => {
+            std::fs::write(output, &kdl).into_diagnostic()?;
+            println!(
+                "{} {} pkgs in {}",
+                "imported:".paint(Style::new().green().bold()),
+                pkgs.len() - unmapped.len(),
+                output.display()
+            );
+        }
+        None => print!("{kdl}"),
+    }
+
+    for pkg in &unmapped {
+        eprintln!(
+            "{} {} ({}, no `{}` bridge)",
+            "not mapped:".paint(Style::new().yellow().bold()),
+            pkg.name,
+            pkg.kind,
+            map.get(&pkg.kind).unwrap_or(&pkg.kind)
+        );
+    }
+
+    Ok(())
+}
+
+// the files under the config dir go under the config dir of the new machine,
+// the ones under the home under its home
+fn export_bootstrap(
+    format: ExportFormat,
+    output: Option<&Path>,
+    config: &Config,
+    cli: &Cli,
+) -> Result<()> {
+    let config_dir = config.path.parent().unwrap_or(Path::new("/"));
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let target = |path: &Path| {
+        let (base, rest) = if let Ok(rest) = path.strip_prefix(config_dir) {
+            ("$config_dir", rest)
+        } else if let Some(rest) = home.as_deref().and_then(|h| path.strip_prefix(h).ok()) {
+            ("$HOME", rest)
+        } else {
+            ("", path.strip_prefix("/").unwrap_or(path))
+        };
+
+        let rest = export::shell_escape(&rest.display().to_string());
+        if rest.is_empty() {
+            base.to_string()
+        } else {
+            format!("{base}/{rest}")
+        }
+    };
+
+    let mut bootstrap = Bootstrap {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        build_args: vec!["--non-interactive".to_string()],
+        ..Default::default()
+    };
+    if let Some(profile) = &cli.profile {
+        bootstrap
+            .build_args
+            .extend(["--profile".to_string(), profile.clone()]);
+    }
+    bootstrap.build_args.push("build".to_string());
+
+    bootstrap.add_file(&config.path, target(&config.path))?;
+    // the git inputs are cloned by the first build
+    if config.inputs_git.is_none() {
+        bootstrap.add_dir(&config.source_dir, &target(&config.source_dir))?;
+    }
+    bootstrap.add_dir(&config.bridges_set, &target(&config.bridges_set))?;
+
+    let script = match format {
+        ExportFormat::Bootstrap => bootstrap.script(),
+        ExportFormat::CloudInit => bootstrap.cloud_init(),
+    };
+
+    match output {
+        Some(output) => {
+            std::fs::write(output, script).into_diagnostic()?;
+            if format == ExportFormat::Bootstrap {
+                pkg_rs::fs::platform::set_executable(output).into_diagnostic()?;
+            }
+            println!(
+                "{} {} files in {}",
+                "exported:".paint(Style::new().green().bold()),
+                bootstrap.files.len(),
+                output.display()
+            );
+        }
+        None => print!("{script}"),
+    }
+
+    Ok(())
+}
+
+// yes is the default answer, and the answer without a terminal
+fn confirm(question: &str, interactive: bool) -> Result<bool> {
+    output::ask(
+        question,
+        true,
+        interactive,
+        &mut io::stdin().lock(),
+        &mut io::stdout(),
+    )
+    .into_diagnostic()
+}
 
-    Ok((
-        installed_pkgs_in_input,
-        not_installed_pkgs_in_input,
-        installed_pkgs_not_in_input,
-    ))
+// no is the default answer, and the answer without a terminal
+fn confirm_destructive(question: &str, interactive: bool) -> Result<bool> {
+    output::ask(
+        question,
+        false,
+        interactive,
+        &mut io::stdin().lock(),
+        &mut io::stdout(),
+    )
+    .into_diagnostic()
+}
+
+// `YYYY-MM-DD HH:MM` in UTC, from a unix time
+fn format_date(timestamp: i64) -> String {
+    if timestamp <= 0 {
+        return "-".to_string();
+    }
+
+    let days = timestamp.div_euclid(86400);
+    let seconds = timestamp.rem_euclid(86400);
+
+    // the days to civil date algorithm of Howard Hinnant
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}",
+        seconds / 3600,
+        seconds % 3600 / 60
+    )
 }
 
 fn hint(msg: &str) {
-    println!("💡 {}", msg.cyan());
+    println!("💡 {}", msg.paint(Style::new().cyan()));
 }
 
 fn print_bridge_header(
@@ -678,8 +2877,8 @@ fn print_bridge_header(
 
     print!(
         "{} {}: ",
-        "bridge:".green().bold(),
-        bridge_name.underline().blue()
+        "bridge:".paint(Style::new().green().bold()),
+        bridge_name.paint(Style::new().underline().blue())
     );
 
     for count in [
@@ -688,25 +2887,271 @@ fn print_bridge_header(
         (pkgs_to_update_count, "🔄"),
     ] {
         if count.0 > 0 {
-            print!(" {} {}", &count.0.blue().bold(), &count.1);
+            print!(
+                " {} {}",
+                &count.0.paint(Style::new().blue().bold()),
+                &count.1
+            );
         }
     }
     println!();
 }
 
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+
+    if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
 fn print_job_header(job_name: &str) {
-    println!("{} {}", "job:".green().bold(), job_name.purple());
+    println!(
+        "{} {}",
+        "job:".paint(Style::new().green().bold()),
+        job_name.paint(Style::new().purple())
+    );
 }
 
-fn perform_linking(fs: &fs::Fs, pb_style: ProgressStyle) -> Result<()> {
-    let pb = ProgressBar::new(100);
-    pb.set_style(pb_style);
-    pb.set_message(format!("🔌 {}", "linking...".blue().bold()));
-    let res = fs.link().map_err(|err| {
-        pb.finish_with_message(format!("🔌 {}", "failed".red().bold()));
-        println!("{}", err.red().bold());
-        exit(1);
-    });
-    pb.finish_with_message(format!("🔌 {}", "done.".green().bold()));
-    res
+// how the build shows what it's doing
+#[derive(Clone, Copy, PartialEq)]
+enum Progress {
+    Spinners,
+    // a line per step, for the cron jobs and the ci logs
+    Plain,
+    // only the failures
+    Quiet,
+}
+
+fn table_colors() -> TableColors {
+    if output::colors() {
+        TableColors::Always
+    } else {
+        TableColors::Never
+    }
+}
+
+// renders the build events with progress bars
+struct TerminalSink {
+    progress: Progress,
+    spinner_style: ProgressStyle,
+    job_style: ProgressStyle,
+    m: MultiProgress,
+    eta: Option<ProgressBar>,
+    pkg: Option<ProgressBar>,
+    link: Option<ProgressBar>,
+}
+
+impl TerminalSink {
+    fn new(spinner_style: ProgressStyle, job_style: ProgressStyle, progress: Progress) -> Self {
+        Self {
+            progress,
+            spinner_style,
+            job_style,
+            m: MultiProgress::new(),
+            eta: None,
+            pkg: None,
+            link: None,
+        }
+    }
+
+    fn finish_pkg(&mut self, msg: String) {
+        if let Some(pb) = self.pkg.take() {
+            pb.finish_with_message(msg);
+        } else if self.progress == Progress::Plain {
+            println!("{msg}");
+        }
+    }
+}
+
+impl EventSink for TerminalSink {
+    fn emit(&mut self, event: Event) {
+        let quiet = self.progress == Progress::Quiet;
+        let spinners = self.progress == Progress::Spinners;
+
+        match event {
+            Event::BridgeStarted {
+                bridge,
+                install,
+                remove,
+                update,
+            } => {
+                if !quiet {
+                    self.m = MultiProgress::new();
+                    print_bridge_header(&bridge, install, remove, update);
+                }
+            }
+            Event::JobStarted { job, .. } => {
+                if quiet {
+                    return;
+                }
+                print_job_header(&job);
+
+                if spinners {
+                    let eta = self.m.add(ProgressBar::new(100));
+                    eta.set_style(self.job_style.clone());
+                    self.eta = Some(eta);
+                }
+            }
+            Event::Eta { remaining } => {
+                if let Some(eta) = &self.eta {
+                    eta.set_message(format!(
+                        "⏳ ~{} left",
+                        format_duration(remaining).paint(Style::new().blue().bold())
+                    ));
+                }
+            }
+            Event::PackageStarted { name, index, total } => match self.progress {
+                Progress::Spinners => {
+                    let pb = self.m.add(ProgressBar::new(100));
+                    pb.set_style(self.spinner_style.clone());
+                    pb.set_prefix(format!("[{index}/{total}]"));
+                    pb.set_message(format!("🚚 {name}"));
+                    pb.enable_steady_tick(Duration::from_millis(100));
+                    self.pkg = Some(pb);
+                }
+                Progress::Plain => println!("[{index}/{total}] 🚚 {name}"),
+                Progress::Quiet => {}
+            },
+            Event::PackageStoring { name } => {
+                if let Some(pb) = &self.pkg {
+                    pb.set_message(format!("🗃️ {name}"));
+                }
+            }
+            Event::PackageInstalled { name } => {
+                self.finish_pkg(format!("📦 {}.", name.paint(Style::new().green().bold())));
+            }
+            Event::PackageRemoved { name } => {
+                self.finish_pkg(format!("🗑️ {}.", name.paint(Style::new().green().bold())));
+            }
+            Event::PackageSkipped { name, reason } => {
+                if !quiet {
+                    println!(
+                        "⏭️ {} {}",
+                        name.paint(Style::new().yellow()),
+                        format!("skipped: {reason}.").paint(Style::new().dimmed())
+                    );
+                }
+            }
+            Event::PackageUnchanged { name } => {
+                self.finish_pkg(format!(
+                    "⏭️ {} {}",
+                    name.paint(Style::new().green()),
+                    "unchanged.".paint(Style::new().dimmed())
+                ));
+            }
+            Event::PackageFailed {
+                name, step, error, ..
+            } => {
+                let msg = format!(
+                    "❌ {}, {}: {}",
+                    name.paint(Style::new().red().bold()),
+                    step.to_string().paint(Style::new().red().underline()),
+                    error.paint(Style::new().red())
+                );
+                if quiet {
+                    eprintln!("{msg}");
+                } else {
+                    self.finish_pkg(msg);
+                }
+            }
+            Event::JobDone => {
+                if let Some(eta) = self.eta.take() {
+                    eta.finish_and_clear();
+                }
+            }
+            Event::LinkStarted => match self.progress {
+                Progress::Spinners => {
+                    let pb = ProgressBar::new(100);
+                    pb.set_style(self.job_style.clone());
+                    pb.set_message(format!(
+                        "🔌 {}",
+                        "linking...".paint(Style::new().blue().bold())
+                    ));
+                    self.link = Some(pb);
+                }
+                Progress::Plain => println!("🔌 linking..."),
+                Progress::Quiet => {}
+            },
+            Event::LinkDone { pruned } => {
+                let done = format!("🔌 {}", "done.".paint(Style::new().green().bold()));
+                if let Some(pb) = self.link.take() {
+                    pb.finish_with_message(done);
+                } else if !quiet {
+                    println!("{done}");
+                }
+                if !quiet {
+                    for link in pruned {
+                        println!(
+                            "{} {}",
+                            "pruned:".paint(Style::new().yellow().bold()),
+                            link.display()
+                        );
+                    }
+                }
+            }
+            Event::UnitsFailed { error } => {
+                eprintln!(
+                    "{} {}",
+                    "systemctl failed:".paint(Style::new().yellow().bold()),
+                    error.paint(Style::new().red())
+                );
+            }
+            Event::HookFailed { pkg, error } => {
+                let pkg = pkg.map(|p| format!("{p}: ")).unwrap_or_default();
+                eprintln!(
+                    "{} {pkg}{}",
+                    "hook failed:".paint(Style::new().yellow().bold()),
+                    error.paint(Style::new().red())
+                );
+            }
+            Event::LicenseViolation { name, license } => {
+                eprintln!(
+                    "{} {name}: {}",
+                    "license not allowed:".paint(Style::new().yellow().bold()),
+                    license.paint(Style::new().red())
+                );
+            }
+            Event::AuditFailed { error } => {
+                eprintln!(
+                    "{} {}",
+                    "audit log:".paint(Style::new().yellow().bold()),
+                    error.paint(Style::new().red())
+                );
+            }
+            Event::NotifyFailed { error } => {
+                eprintln!(
+                    "{} {}",
+                    "notify failed:".paint(Style::new().yellow().bold()),
+                    error.paint(Style::new().red())
+                );
+            }
+            // the error itself is returned and rendered as a diagnostic
+            Event::LinkFailed { .. } => {
+                if let Some(pb) = self.link.take() {
+                    pb.finish_with_message(format!(
+                        "🔌 {}",
+                        "failed".paint(Style::new().red().bold())
+                    ));
+                }
+            }
+            Event::Summary { installed, removed } => {
+                if quiet {
+                    return;
+                }
+                println!(
+                    "{}\n📦{} 🗑️ {}",
+                    "Summary:".paint(Style::new().green().bold()),
+                    installed,
+                    removed,
+                );
+                println!(
+                    "{}",
+                    "Done 🌻, thanks to Allah".paint(Style::new().green().bold())
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,215 @@
+use kdl::{KdlDocument, KdlError};
+use miette::Diagnostic;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use thiserror::Error;
+
+use crate::bridge::RetryOverride;
+
+pub const MANIFEST_FILE_NAME: &str = "bridge.kdl";
+
+// 1: the attributes are env vars, 2: they are only in the `$pkg_opts` json file
+pub const LATEST_PROTOCOL: u32 = 2;
+
+// what a bridge says about itself in `<bridge>/bridge.kdl`, the file and all
+// its nodes are optional
+#[derive(Debug)]
+pub struct BridgeManifest {
+    // the attributes every pkg of the bridge has to declare
+    pub required_attributes: Vec<String>,
+    // how pkg talks to the bridge, `protocol 2`
+    pub protocol: u32,
+    // the most operations of the bridge running at the same time (by the
+    // async api), for the apis with strict rate limits
+    pub max_parallel: Option<usize>,
+    // the least time between the starts of two operations of the bridge
+    pub min_interval: Option<Duration>,
+    // `retries`, `retry-backoff` and `retry-on`, over the ones of the config
+    pub retry: RetryOverride,
+    // the bridge has a `reinstall` operation, pkg removes and installs the
+    // pkg without it
+    pub reinstall: bool,
+    // `run daemon` is started once and gets the operations on its stdin
+    pub daemon: bool,
+    // the commands the bridge runs, they should be in the PATH
+    pub needs: Vec<String>,
+    // `version "1.2.0"`, recorded with every pkg the bridge installs
+    pub version: Option<String>,
+}
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum ManifestError {
+    #[error(transparent)]
+    #[diagnostic(code(manifest::io_error))]
+    IoError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    KdlError(#[from] KdlError),
+
+    #[error("Invalid value for {0} in {1:?}")]
+    #[diagnostic(code(manifest::wrong_value))]
+    WrongValue(&'static str, PathBuf),
+}
+
+impl Default for BridgeManifest {
+    fn default() -> Self {
+        Self {
+            required_attributes: Vec::new(),
+            protocol: 1,
+            max_parallel: None,
+            min_interval: None,
+            retry: RetryOverride::default(),
+            reinstall: false,
+            daemon: false,
+            needs: Vec::new(),
+            version: None,
+        }
+    }
+}
+
+impl BridgeManifest {
+    pub fn load(bridge_dir: &Path) -> Result<Self, ManifestError> {
+        let path = bridge_dir.join(MANIFEST_FILE_NAME);
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let doc = std::fs::read_to_string(&path)?.parse::<KdlDocument>()?;
+
+        let strings = |node_name: &'static str| -> Result<Vec<String>, ManifestError> {
+            let Some(node) = doc.get(node_name) else {
+                return Ok(Vec::new());
+            };
+
+            node.entries()
+                .iter()
+                .map(|entry| {
+                    entry
+                        .value()
+                        .as_string()
+                        .map(|v| v.to_string())
+                        .ok_or_else(|| ManifestError::WrongValue(node_name, path.clone()))
+                })
+                .collect()
+        };
+
+        let protocol = match doc.get_arg("protocol") {
+            None => 1,
+            Some(value) => value
+                .as_integer()
+                .and_then(|v| u32::try_from(v).ok())
+                .filter(|v| (1..=LATEST_PROTOCOL).contains(v))
+                .ok_or_else(|| ManifestError::WrongValue("protocol", path.clone()))?,
+        };
+
+        let max_parallel = match doc.get_arg("max-parallel") {
+            None => None,
+            Some(value) => Some(
+                value
+                    .as_integer()
+                    .and_then(|v| usize::try_from(v).ok())
+                    .filter(|v| *v > 0)
+                    .ok_or_else(|| ManifestError::WrongValue("max-parallel", path.clone()))?,
+            ),
+        };
+
+        let duration = |node_name: &'static str| match doc.get_arg(node_name) {
+            None => Ok(None),
+            Some(value) => value
+                .as_string()
+                .and_then(parse_duration)
+                .map(Some)
+                .ok_or_else(|| ManifestError::WrongValue(node_name, path.clone())),
+        };
+
+        let flag = |node_name: &'static str| match doc.get_arg(node_name) {
+            None => Ok(false),
+            Some(value) => value
+                .as_bool()
+                .ok_or_else(|| ManifestError::WrongValue(node_name, path.clone())),
+        };
+
+        let retry = RetryOverride {
+            retries: match doc.get_arg("retries") {
+                None => None,
+                Some(value) => Some(
+                    value
+                        .as_integer()
+                        .and_then(|v| u32::try_from(v).ok())
+                        .ok_or_else(|| ManifestError::WrongValue("retries", path.clone()))?,
+                ),
+            },
+            backoff: duration("retry-backoff")?,
+            on: match doc.get_arg("retry-on") {
+                None => None,
+                Some(value) => Some(
+                    value
+                        .as_string()
+                        .and_then(|v| v.parse().ok())
+                        .ok_or_else(|| ManifestError::WrongValue("retry-on", path.clone()))?,
+                ),
+            },
+        };
+
+        Ok(Self {
+            required_attributes: strings("required-attributes")?,
+            protocol,
+            max_parallel,
+            min_interval: duration("min-interval")?,
+            retry,
+            reinstall: flag("reinstall")?,
+            daemon: flag("daemon")?,
+            needs: strings("needs")?,
+            version: match doc.get_arg("version") {
+                None => None,
+                Some(value) => Some(
+                    value
+                        .as_string()
+                        .filter(|v| !v.is_empty())
+                        .map(String::from)
+                        .ok_or_else(|| ManifestError::WrongValue("version", path.clone()))?,
+                ),
+            },
+        })
+    }
+}
+
+// a bridge that went from `1.x` to `2.0` may install its pkgs differently,
+// for the `0.x` ones the second number is the major one. a version that
+// isn't a number isn't compared
+pub fn is_major_change(old: &str, new: &str) -> bool {
+    let major = |version: &str| {
+        let mut numbers = version
+            .trim_start_matches('v')
+            .split('.')
+            .map(|n| n.parse::<u64>().ok());
+        match numbers.next().flatten()? {
+            0 => Some((0, numbers.next().flatten()?)),
+            major => Some((major, 0)),
+        }
+    };
+
+    match (major(old), major(new)) {
+        (Some(old), Some(new)) => old != new,
+        _ => false,
+    }
+}
+
+// `500ms`, `2s`, `1m`
+pub(crate) fn parse_duration(duration: &str) -> Option<Duration> {
+    let duration = duration.trim();
+    let (number, unit) = duration.split_at(duration.find(|c: char| !c.is_ascii_digit())?);
+    let number = number.parse::<u64>().ok()?;
+
+    match unit.trim() {
+        "ms" => Some(Duration::from_millis(number)),
+        "s" => Some(Duration::from_secs(number)),
+        "m" => Some(Duration::from_secs(number.checked_mul(60)?)),
+        "h" => Some(Duration::from_secs(number.checked_mul(60 * 60)?)),
+        _ => None,
+    }
+}
@@ -0,0 +1,84 @@
+// `pkg metrics`, the state of the pkgs in the prometheus text format, printed
+// for a scrape or written for the textfile collector of the node exporter
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum MetricsError {
+    #[error(transparent)]
+    #[diagnostic(code(metrics::io_error))]
+    IoError(#[from] std::io::Error),
+
+    #[error("{0:?} isn't a .prom file")]
+    #[diagnostic(
+        code(metrics::not_prom),
+        help("The textfile collector of the node exporter only reads the *.prom files")
+    )]
+    NotProm(PathBuf),
+}
+
+type Result<T, E = MetricsError> = std::result::Result<T, E>;
+
+// the metrics one after the other, each with its help and type lines
+#[derive(Debug, Default)]
+pub struct Metrics {
+    text: String,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // a value that goes up and down, with its labels
+    pub fn gauge(&mut self, name: &str, help: &str, samples: &[(Vec<(&str, &str)>, f64)]) {
+        let _ = writeln!(self.text, "# HELP {name} {help}");
+        let _ = writeln!(self.text, "# TYPE {name} gauge");
+
+        for (labels, value) in samples {
+            let labels = labels
+                .iter()
+                .map(|(label, value)| format!("{label}=\"{}\"", escape(value)))
+                .collect::<Vec<String>>();
+
+            if labels.is_empty() {
+                let _ = writeln!(self.text, "{name} {value}");
+            } else {
+                let _ = writeln!(self.text, "{name}{{{}}} {value}", labels.join(","));
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    // next to it then renamed, the collector never reads half of it
+    pub fn write_textfile(&self, path: &Path) -> Result<()> {
+        if path.extension().is_none_or(|ext| ext != "prom") {
+            return Err(MetricsError::NotProm(path.to_path_buf()));
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("prom.tmp");
+        std::fs::write(&tmp, &self.text)?;
+        std::fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+}
+
+// the label values are quoted, `\`, `"` and the new lines are escaped
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
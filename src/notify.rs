@@ -0,0 +1,312 @@
+// what a build or an update tells when it's done, from the `notify` section of
+// the config: a command, a desktop notification and webhooks, all of them get
+// the summary (the json one on their stdin or in the POST body)
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::{
+    event::{Event, EventSink},
+    host,
+};
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum NotifyError {
+    #[error(transparent)]
+    #[diagnostic(code(notify::io_error))]
+    IoError(#[from] std::io::Error),
+
+    #[error("The {notifier} notifier failed: {error}")]
+    #[diagnostic(code(notify::failed))]
+    Failed {
+        notifier: &'static str,
+        error: String,
+    },
+
+    #[error("The {notifier} notifier needs `{command}`, it's not in the PATH")]
+    #[diagnostic(code(notify::missing_command))]
+    MissingCommand {
+        notifier: &'static str,
+        command: &'static str,
+    },
+}
+
+type Result<T, E = NotifyError> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum NotifyOn {
+    #[default]
+    Always,
+    // only the builds with a failure, a quiet machine is a fine one
+    Failure,
+}
+
+impl std::str::FromStr for NotifyOn {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Self::Always),
+            "failure" => Ok(Self::Failure),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for NotifyOn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Always => write!(f, "always"),
+            Self::Failure => write!(f, "failure"),
+        }
+    }
+}
+
+// `notify { on "failure"; exec "mail -s pkg me@host"; desktop #true; webhook "https://..."; }`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Notifiers {
+    pub on: NotifyOn,
+    // run with `sh -c`, the json summary on their stdin and `$PKG_STATUS`
+    pub exec: Vec<String>,
+    // `notify-send`
+    pub desktop: bool,
+    // the json summary is POSTed to them (with curl)
+    pub webhooks: Vec<String>,
+}
+
+impl Notifiers {
+    pub fn is_empty(&self) -> bool {
+        self.exec.is_empty() && !self.desktop && self.webhooks.is_empty()
+    }
+
+    // every notifier is tried, the errors are returned together
+    pub fn send(&self, report: &BuildReport) -> Vec<NotifyError> {
+        if self.is_empty() || (self.on == NotifyOn::Failure && report.success()) {
+            return Vec::new();
+        }
+
+        let json = serde_json::to_string_pretty(&report.to_json()).unwrap_or_default();
+        let mut errors = Vec::new();
+
+        for command in &self.exec {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c")
+                .arg(command)
+                .env("PKG_STATUS", report.status());
+            if let Err(err) = run_with_stdin("exec", &mut cmd, &json) {
+                errors.push(err);
+            }
+        }
+
+        if self.desktop
+            && let Err(err) = desktop(report)
+        {
+            errors.push(err);
+        }
+
+        for url in &self.webhooks {
+            if let Err(err) = webhook(url, &json) {
+                errors.push(err);
+            }
+        }
+
+        errors
+    }
+}
+
+fn run_with_stdin(notifier: &'static str, command: &mut Command, stdin: &str) -> Result<()> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut input) = child.stdin.take() {
+        // a command that doesn't read it is fine
+        let _ = input.write_all(stdin.as_bytes());
+    }
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(NotifyError::Failed {
+            notifier,
+            error: format!(
+                "exit code {}: {}",
+                output.status.code().unwrap_or(-1),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+fn desktop(report: &BuildReport) -> Result<()> {
+    let Some(notify_send) = host::find_command("notify-send") else {
+        return Err(NotifyError::MissingCommand {
+            notifier: "desktop",
+            command: "notify-send",
+        });
+    };
+
+    let urgency = if report.success() {
+        "--urgency=normal"
+    } else {
+        "--urgency=critical"
+    };
+
+    run_with_stdin(
+        "desktop",
+        Command::new(notify_send)
+            .arg("--app-name=pkg")
+            .arg(urgency)
+            .arg(format!("pkg {}", report.command))
+            .arg(report.message()),
+        "",
+    )
+}
+
+fn webhook(url: &str, json: &str) -> Result<()> {
+    let Some(curl) = host::find_command("curl") else {
+        return Err(NotifyError::MissingCommand {
+            notifier: "webhook",
+            command: "curl",
+        });
+    };
+
+    run_with_stdin(
+        "webhook",
+        Command::new(curl)
+            .args(["--silent", "--show-error", "--fail", "--max-time", "30"])
+            .args(["--header", "Content-Type: application/json"])
+            .args(["--data-binary", "@-"])
+            .arg(url),
+        json,
+    )
+}
+
+// a failure of the build, with its pkg when it's one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub pkg: Option<String>,
+    pub error: String,
+}
+
+// what the build did, made from its events
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BuildReport {
+    // `build`, `update`...
+    pub command: String,
+    pub installed: Vec<String>,
+    pub removed: Vec<String>,
+    pub failures: Vec<Failure>,
+    // what stopped it, the rest wasn't done
+    pub error: Option<String>,
+}
+
+impl BuildReport {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.failures.is_empty() && self.error.is_none()
+    }
+
+    pub fn status(&self) -> &'static str {
+        if self.success() { "success" } else { "failure" }
+    }
+
+    // one line, for the desktop
+    pub fn message(&self) -> String {
+        if let Some(error) = &self.error {
+            return format!("failed on {}: {error}", host::hostname());
+        }
+
+        let mut message = format!(
+            "{} installed, {} removed on {}",
+            self.installed.len(),
+            self.removed.len(),
+            host::hostname()
+        );
+        if !self.failures.is_empty() {
+            message.push_str(&format!(", {} failures", self.failures.len()));
+        }
+        message
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "command": self.command,
+            "status": self.status(),
+            "hostname": host::hostname(),
+            "installed": self.installed,
+            "removed": self.removed,
+            "failures": self
+                .failures
+                .iter()
+                .map(|f| serde_json::json!({ "pkg": f.pkg, "error": f.error }))
+                .collect::<Vec<_>>(),
+            "error": self.error,
+        })
+    }
+}
+
+// passes the events on and keeps what the report needs
+#[derive(Debug)]
+pub struct ReportSink<S> {
+    report: BuildReport,
+    inner: S,
+}
+
+impl<S: EventSink> ReportSink<S> {
+    pub fn new(command: &str, inner: S) -> Self {
+        Self {
+            report: BuildReport::new(command),
+            inner,
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn report(&self) -> &BuildReport {
+        &self.report
+    }
+}
+
+impl<S: EventSink> EventSink for ReportSink<S> {
+    fn emit(&mut self, event: Event) {
+        let failure = |pkg: Option<&String>, error: &String| Failure {
+            pkg: pkg.cloned(),
+            error: error.clone(),
+        };
+
+        match &event {
+            Event::PackageInstalled { name } => self.report.installed.push(name.clone()),
+            Event::PackageRemoved { name } => self.report.removed.push(name.clone()),
+            Event::PackageFailed { name, error, .. } => {
+                self.report.failures.push(failure(Some(name), error));
+            }
+            Event::HookFailed { pkg, error } => {
+                self.report.failures.push(failure(pkg.as_ref(), error));
+            }
+            Event::LinkFailed { error }
+            | Event::UnitsFailed { error }
+            | Event::AuditFailed { error } => {
+                self.report.failures.push(failure(None, error));
+            }
+            _ => {}
+        }
+
+        self.inner.emit(event);
+    }
+}
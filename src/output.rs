@@ -0,0 +1,68 @@
+// the colors of what pkg prints, they're chosen once by the cli (`--color`,
+// `NO_COLOR`, a terminal or not) and every colored text goes through `paint`
+use std::{
+    fmt::Display,
+    io::{self, BufRead, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use owo_colors::{OwoColorize, Style};
+
+static COLORS: AtomicBool = AtomicBool::new(true);
+
+pub fn set_colors(enabled: bool) {
+    COLORS.store(enabled, Ordering::Relaxed);
+}
+
+pub fn colors() -> bool {
+    COLORS.load(Ordering::Relaxed)
+}
+
+// `"done".paint(Style::new().green().bold())`, the plain text when the colors
+// are off
+pub trait Paint: Display {
+    fn paint(&self, style: Style) -> String {
+        if colors() {
+            self.style(style).to_string()
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+impl<T: Display + ?Sized> Paint for T {}
+
+// a yes or no question, `default` is the answer of anything else than yes or
+// no, and the answer without a terminal (it's printed after the question, for
+// the logs)
+pub fn ask(
+    question: &str,
+    default: bool,
+    interactive: bool,
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+) -> io::Result<bool> {
+    let choices = if default { "[Y/n]:" } else { "[y/N]:" };
+    write!(
+        out,
+        "{} {} ",
+        question.paint(Style::new().bold()),
+        choices.paint(Style::new().dimmed())
+    )?;
+
+    if !interactive {
+        writeln!(out, "{}", if default { "y" } else { "n" })?;
+        return Ok(default);
+    }
+
+    out.flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+
+    Ok(match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => default,
+    })
+}
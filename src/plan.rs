@@ -0,0 +1,246 @@
+// what a build would remove or downgrade, it's printed and confirmed before
+// anything is done (`pkg build`, `update`, `rebuild` and `autoremove`)
+use crate::{
+    ADOPTED_BRIDGE_NAME,
+    db::{self, DbSnapshot},
+    input::{self, NameFilter, PkgDeclaration, TagFilter},
+};
+
+// what the cli does with a plan
+#[derive(Debug, PartialEq)]
+pub enum Confirmation {
+    // nothing to ask
+    Proceed,
+    // these bridges would lose all their pkgs
+    BridgesEmptied(Vec<String>),
+    // the plan is printed and asked, a declined mass remove is an error
+    Ask { mass_remove: bool },
+}
+
+// splits the bridge's pkgs to: (installed and in input, not installed and
+// in input, installed by this bridge but not in input)
+pub fn filter_pkgs_by_statuses(
+    snapshot: &DbSnapshot,
+    pkgs_declarations: &[PkgDeclaration],
+    bridge_name: &str,
+    tag_filter: &TagFilter,
+) -> (
+    Vec<PkgDeclaration>,
+    Vec<PkgDeclaration>,
+    Vec<PkgDeclaration>,
+) {
+    let (selected, filtered_out): (Vec<&PkgDeclaration>, Vec<&PkgDeclaration>) = pkgs_declarations
+        .iter()
+        .partition(|p| tag_filter.matches(&p.tags));
+
+    let (installed_pkgs_in_input, not_installed_pkgs_in_input) = selected
+        .into_iter()
+        .cloned()
+        .partition(|p| snapshot.is_installed(&p.name));
+
+    // a filtered out pkg is left as it is, unless it was installed with an excluded tag
+    let installed_pkgs_not_in_input = snapshot
+        .pkgs_by_bridge(bridge_name)
+        .iter()
+        .filter(|r| {
+            let declared = pkgs_declarations.iter().any(|p| p.name == r.pkg.name);
+            let excluded = filtered_out.iter().any(|p| p.name == r.pkg.name)
+                && tag_filter.is_excluded(&r.tags);
+
+            !declared || excluded
+        })
+        .map(|r| r.pkg.to_pkg_declaration_with_empty_attributes())
+        .collect();
+
+    (
+        installed_pkgs_in_input,
+        not_installed_pkgs_in_input,
+        installed_pkgs_not_in_input,
+    )
+}
+
+// the pkgs named to `pkg rebuild` that aren't installed from the inputs (of
+// the `--bridge` if it's given), a typo shouldn't pass as "nothing to do"
+pub fn missing_rebuild_targets(
+    snapshot: &DbSnapshot,
+    bridges: &[input::Bridge],
+    packages: &[String],
+    only_bridge: Option<&str>,
+) -> Vec<String> {
+    let bridges = bridges
+        .iter()
+        .filter(|bridge| only_bridge.is_none_or(|name| bridge.name == name))
+        .collect::<Vec<_>>();
+
+    packages
+        .iter()
+        .filter(|name| {
+            !bridges.iter().any(|bridge| {
+                bridge.pkgs.iter().any(|pkg| &pkg.name == *name) && snapshot.is_installed(name)
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+// above it a removal plan asks first, with at least this many pkgs
+pub const MASS_REMOVE_RATIO: f64 = 0.25;
+pub const MASS_REMOVE_MIN: usize = 5;
+
+// what the build would touch
+pub struct PlanScope<'a> {
+    pub only_bridge: Option<&'a String>,
+    pub name_filter: &'a NameFilter,
+    pub tag_filter: &'a TagFilter,
+    // false when only the out of service bridges can lose pkgs (update...)
+    pub removes_declared: bool,
+    // the bridges a `when` empties on purpose, they aren't refused
+    pub when_excluded: &'a [String],
+    // the pkgs a `when` leaves out, a bridge that loses only them isn't
+    // refused either
+    pub when_excluded_pkgs: &'a [String],
+    // the installed pkgs that are updated or reinstalled (all of them when
+    // it's empty), none without it
+    pub updated: Option<&'a [String]>,
+}
+
+// the destructive part of a build
+#[derive(Debug)]
+pub struct Plan {
+    // the pkgs and their bridges
+    pub removed: Vec<(String, String)>,
+    // the pkgs pinned to an older version than the installed one
+    pub downgraded: Vec<(String, db::Version, db::Version)>,
+    // the bridges that would lose all their pkgs
+    pub emptied_bridges: Vec<String>,
+    pub installed: usize,
+}
+
+impl Plan {
+    pub fn new(
+        snapshot: &DbSnapshot,
+        bridges: &[input::Bridge],
+        bridges_out_of_service: &[String],
+        scope: PlanScope,
+    ) -> Self {
+        let mut plan = Plan {
+            removed: Vec::new(),
+            downgraded: Vec::new(),
+            emptied_bridges: Vec::new(),
+            installed: snapshot
+                .bridges()
+                .iter()
+                .filter(|b| b.as_str() != ADOPTED_BRIDGE_NAME)
+                .map(|b| snapshot.pkgs_by_bridge(b).len())
+                .sum(),
+        };
+
+        for bridge in bridges_out_of_service {
+            let pkgs = snapshot
+                .pkgs_by_bridge(bridge)
+                .into_iter()
+                .filter(|r| scope.name_filter.matches(&r.pkg.name))
+                .map(|r| (r.pkg.name.clone(), bridge.clone()))
+                .collect::<Vec<_>>();
+
+            if !pkgs.is_empty() && !scope.when_excluded.contains(bridge) {
+                plan.emptied_bridges.push(bridge.clone());
+            }
+            plan.removed.extend(pkgs);
+        }
+
+        for bridge in bridges {
+            if scope.only_bridge.is_some_and(|name| name != &bridge.name) {
+                continue;
+            }
+
+            let (installed_pkgs_in_input, _, mut installed_pkgs_not_in_input) =
+                filter_pkgs_by_statuses(snapshot, &bridge.pkgs, &bridge.name, scope.tag_filter);
+            installed_pkgs_not_in_input.retain(|pkg| scope.name_filter.matches(&pkg.name));
+
+            if scope.removes_declared {
+                if bridge.pkgs.is_empty()
+                    && installed_pkgs_not_in_input
+                        .iter()
+                        .any(|pkg| !scope.when_excluded_pkgs.contains(&pkg.name))
+                    && !scope.when_excluded.contains(&bridge.name)
+                {
+                    plan.emptied_bridges.push(bridge.name.clone());
+                }
+                plan.removed.extend(
+                    installed_pkgs_not_in_input
+                        .into_iter()
+                        .map(|pkg| (pkg.name, bridge.name.clone())),
+                );
+            }
+
+            let Some(updated) = scope.updated else {
+                continue;
+            };
+            for pkg in installed_pkgs_in_input {
+                if !scope.name_filter.matches(&pkg.name)
+                    || !(updated.is_empty() || updated.contains(&pkg.name))
+                {
+                    continue;
+                }
+
+                if let Some(input::AttributeValue::String(pinned)) = pkg.attributes.get("version")
+                    && let Some(pinned) = db::Version::parse(pinned)
+                    && let Some(record) = snapshot.get(&pkg.name)
+                    && pinned.compare(&record.pkg.version).is_lt()
+                {
+                    plan.downgraded
+                        .push((pkg.name.clone(), record.pkg.version.clone(), pinned));
+                }
+            }
+        }
+
+        plan
+    }
+
+    // `pkg autoremove`, the orphans of the bridges in the inputs and nothing
+    // else (a build removes the pkgs of the other bridges)
+    pub fn autoremove(snapshot: &DbSnapshot, bridges: &[input::Bridge]) -> Self {
+        Plan {
+            removed: snapshot
+                .orphans()
+                .into_iter()
+                .filter(|r| bridges.iter().any(|b| b.name == r.bridge))
+                .map(|r| (r.pkg.name.clone(), r.bridge.clone()))
+                .collect(),
+            downgraded: Vec::new(),
+            emptied_bridges: Vec::new(),
+            installed: snapshot.records.len(),
+        }
+    }
+
+    pub fn is_mass_remove(&self) -> bool {
+        self.removed.len() >= MASS_REMOVE_MIN
+            && self.removed.len() as f64 > self.installed as f64 * MASS_REMOVE_RATIO
+    }
+
+    // a bridge that would lose all its pkgs is refused (no declaration was
+    // loaded for it), a mass remove is asked even with `--yes`, and the rest
+    // is asked on a terminal only
+    pub fn confirmation(
+        &self,
+        allow_mass_remove: bool,
+        yes: bool,
+        interactive: bool,
+    ) -> Confirmation {
+        if !allow_mass_remove && !self.emptied_bridges.is_empty() {
+            return Confirmation::BridgesEmptied(self.emptied_bridges.clone());
+        }
+
+        if self.removed.is_empty() && self.downgraded.is_empty() {
+            return Confirmation::Proceed;
+        }
+
+        let mass_remove = !allow_mass_remove && self.is_mass_remove();
+        if !mass_remove && (yes || !interactive) {
+            return Confirmation::Proceed;
+        }
+
+        Confirmation::Ask { mass_remove }
+    }
+}
@@ -0,0 +1,230 @@
+// how pkg becomes root when a user runs it: it runs itself again through
+// sudo, doas, run0 or pkexec (the `escalate` of the config, the first one in
+// the PATH by default). they ask for the password on the terminal of pkg, or
+// in a dialog of the desktop for pkexec, pkg never reads it
+use std::{
+    ffi::OsString,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::host;
+
+// the polkit action of pkg, what pkexec asks for when it runs pkg (with the
+// policy installed, the generic "run a program as root" without it)
+pub const POLKIT_ACTION: &str = "io.github.abdelkadouss.pkg.run";
+// where polkit reads the policies, the one of pkg is `<vendor>.policy`
+pub const POLKIT_ACTIONS_DIR: &str = "/usr/share/polkit-1/actions";
+pub const POLKIT_POLICY_NAME: &str = "io.github.abdelkadouss.pkg.policy";
+// the action of pkexec for the programs without a policy
+const PKEXEC_ACTION: &str = "org.freedesktop.policykit.exec";
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum PrivilegeError {
+    #[error(transparent)]
+    #[diagnostic(code(privilege::io_error))]
+    IoError(#[from] std::io::Error),
+
+    #[error("`{0}` isn't in the PATH")]
+    #[diagnostic(
+        code(privilege::missing_command),
+        help("It comes with polkit (the `polkit` package)")
+    )]
+    MissingCommand(&'static str),
+}
+
+type Result<T, E = PrivilegeError> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Escalation {
+    Sudo,
+    Doas,
+    // systemd's, through polkit
+    Run0,
+    // polkit's, a dialog of the desktop asks for the password
+    Pkexec,
+}
+
+impl Escalation {
+    // the order they're looked for in the PATH
+    pub const ALL: [Escalation; 4] = [Self::Sudo, Self::Doas, Self::Run0, Self::Pkexec];
+
+    pub fn command(&self) -> &'static str {
+        match self {
+            Self::Sudo => "sudo",
+            Self::Doas => "doas",
+            Self::Run0 => "run0",
+            Self::Pkexec => "pkexec",
+        }
+    }
+
+    // the first one in the PATH. pkg started from the desktop (a gui, a
+    // launcher) has no terminal to ask on, the dialog of pkexec goes first
+    pub fn detect() -> Option<Self> {
+        if host::has_graphical_session()
+            && !std::io::stdin().is_terminal()
+            && Self::Pkexec.is_available()
+        {
+            return Some(Self::Pkexec);
+        }
+
+        Self::ALL
+            .into_iter()
+            .find(|escalation| escalation.is_available())
+    }
+
+    pub fn is_available(&self) -> bool {
+        host::find_command(self.command()).is_some()
+    }
+
+    // it fails instead of asking for a password on the terminal, pkexec still
+    // asks with the agent of the desktop
+    fn non_interactive_flag(&self) -> &'static str {
+        match self {
+            Self::Sudo | Self::Doas => "-n",
+            Self::Run0 => "--no-ask-password",
+            Self::Pkexec => "--disable-internal-agent",
+        }
+    }
+
+    // false if pkg can't be run as root with it: a wrong password, or one
+    // that's needed in the non-interactive mode. only sudo can ask for it
+    // without running something (`sudo -v`), doas, run0 and pkexec ask when
+    // pkg runs
+    pub fn authenticate(&self, interactive: bool) -> std::io::Result<bool> {
+        let mut command = Command::new(self.command());
+        if interactive {
+            match self {
+                Self::Sudo => command.arg("-v"),
+                Self::Doas | Self::Run0 | Self::Pkexec => return Ok(true),
+            };
+        } else if *self == Self::Pkexec {
+            // no one at a terminal, but maybe at the desktop
+            if host::has_graphical_session() {
+                return Ok(true);
+            }
+            return Ok(authorized(pkexec_action(), std::process::id(), false).unwrap_or(false));
+        } else {
+            command
+                .arg(self.non_interactive_flag())
+                .arg("true")
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null());
+        }
+
+        Ok(command.status()?.success())
+    }
+
+    // `program args` as root, it inherits the stdin, stdout and stderr
+    pub fn command_for(&self, program: &Path, args: &[OsString], interactive: bool) -> Command {
+        let mut command = Command::new(self.command());
+        if !interactive {
+            command.arg(self.non_interactive_flag());
+        }
+        command.arg(program).args(args);
+        command
+    }
+}
+
+impl std::str::FromStr for Escalation {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|escalation| escalation.command() == s)
+            .ok_or(())
+    }
+}
+
+impl std::fmt::Display for Escalation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.command())
+    }
+}
+
+// the action pkexec checks when it runs pkg
+fn pkexec_action() -> &'static str {
+    if Path::new(POLKIT_ACTIONS_DIR)
+        .join(POLKIT_POLICY_NAME)
+        .is_file()
+    {
+        POLKIT_ACTION
+    } else {
+        PKEXEC_ACTION
+    }
+}
+
+// if polkit lets the user of the process `pid` do `action` (`POLKIT_ACTION`
+// to run pkg), a frontend asks it before running pkg with pkexec. with
+// `allow_interaction` the agent of the desktop asks for the password when
+// it's needed
+pub fn authorized(action: &str, pid: u32, allow_interaction: bool) -> Result<bool> {
+    let Some(pkcheck) = host::find_command("pkcheck") else {
+        return Err(PrivilegeError::MissingCommand("pkcheck"));
+    };
+
+    let mut command = Command::new(pkcheck);
+    command
+        .arg("--action-id")
+        .arg(action)
+        .arg("--process")
+        .arg(pid.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    if allow_interaction {
+        command.arg("--allow-user-interaction");
+    }
+
+    Ok(command.status()?.success())
+}
+
+// the policy of `POLKIT_ACTION` for pkg at `program`: an admin has to
+// authenticate, once per session on the desktop
+pub fn polkit_policy(program: &Path) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE policyconfig PUBLIC "-//freedesktop//DTD PolicyKit Policy Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/PolicyKit/1/policyconfig.dtd">
+<policyconfig>
+  <vendor>pkg</vendor>
+  <vendor_url>https://github.com/abdelkadouss/pkg</vendor_url>
+  <action id="{POLKIT_ACTION}">
+    <description>Manage the packages of the system</description>
+    <message>Authentication is required to install, update or remove packages with pkg</message>
+    <icon_name>package-x-generic</icon_name>
+    <defaults>
+      <allow_any>auth_admin</allow_any>
+      <allow_inactive>auth_admin</allow_inactive>
+      <allow_active>auth_admin_keep</allow_active>
+    </defaults>
+    <annotate key="org.freedesktop.policykit.exec.path">{}</annotate>
+  </action>
+</policyconfig>
+"#,
+        program.display()
+    )
+}
+
+// writes the policy in `dir` (`POLKIT_ACTIONS_DIR`), polkit picks it up
+pub fn install_polkit_policy(dir: &Path, program: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(POLKIT_POLICY_NAME);
+    std::fs::write(&path, polkit_policy(program))?;
+    Ok(path)
+}
+
+// false if it wasn't there
+pub fn remove_polkit_policy(dir: &Path) -> Result<bool> {
+    match std::fs::remove_file(dir.join(POLKIT_POLICY_NAME)) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
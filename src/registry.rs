@@ -0,0 +1,368 @@
+// `pkg bridge install|update`, the bridges fetched from a registry instead of
+// written by hand. a registry is a git repo with a dir per bridge, or an index
+// of bridge tarballs with their sha256:
+//
+// ```kdl
+// bridge "github" version="1.2.0" url="github-1.2.0.tar.gz" sha256="9f86d0..."
+// ```
+//
+// the urls of the index are relative to it, a tarball has the bridge at its
+// root or in a dir of its name
+use std::{
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+
+use kdl::KdlDocument;
+use miette::Diagnostic;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{
+    bridge::{BRIDGE_ENTRY_POINT_NAME, WASM_ENTRY_POINT_NAME},
+    git::{self, GitError},
+    host,
+};
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum RegistryError {
+    #[error(transparent)]
+    #[diagnostic(code(registry::io_error))]
+    IoError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Git(#[from] GitError),
+
+    #[error("`{0}` isn't in the PATH")]
+    #[diagnostic(code(registry::missing_command))]
+    MissingCommand(&'static str),
+
+    #[error("Couldn't download {url}: {error}")]
+    #[diagnostic(code(registry::download_failed))]
+    DownloadFailed { url: String, error: String },
+
+    #[error("Invalid registry index {url}: {error}")]
+    #[diagnostic(
+        code(registry::bad_index),
+        help("Every bridge is `bridge \"name\" version=\"..\" url=\"..\" sha256=\"..\"`")
+    )]
+    BadIndex { url: String, error: String },
+
+    #[error("No bridge `{name}` in the registry {registry}")]
+    #[diagnostic(code(registry::not_found))]
+    NotFound { name: String, registry: String },
+
+    #[error(
+        "The tarball of `{name}` doesn't match its sha256: {actual}, the index says {expected}"
+    )]
+    #[diagnostic(
+        code(registry::checksum_mismatch),
+        help("The tarball changed since the index was written, it's not installed")
+    )]
+    ChecksumMismatch {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("No bridge registry")]
+    #[diagnostic(
+        code(registry::no_registry),
+        help("Set `bridges {{ registry \"..\" }}` in the config or pass `--registry`")
+    )]
+    NoRegistry,
+
+    #[error("`{0}` is in the bridges set already")]
+    #[diagnostic(
+        code(registry::already_installed),
+        help("It wasn't installed from a registry, `--force` replaces it")
+    )]
+    AlreadyInstalled(String),
+
+    #[error("`{0}` wasn't installed from a registry")]
+    #[diagnostic(
+        code(registry::not_from_registry),
+        help("`pkg bridge install {0}` fetches it from one")
+    )]
+    NotFromRegistry(String),
+
+    #[error("`{0}` has no `run` or `run.wasm`, it's not a bridge")]
+    #[diagnostic(code(registry::not_a_bridge))]
+    NotABridge(String),
+
+    #[error("`{0}` isn't a bridge name")]
+    #[diagnostic(
+        code(registry::bad_bridge_name),
+        help("A bridge name is the name of its dir in the bridges set, without `/` or `..`")
+    )]
+    BadBridgeName(String),
+}
+
+type Result<T, E = RegistryError> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Registry {
+    // a repo with a dir per bridge
+    Git(String),
+    // the url (or the path) of an index
+    Index(String),
+}
+
+impl Registry {
+    // `git+https://..` or a `.git` url is a git repo, the rest an index
+    pub fn parse(url: &str) -> Self {
+        match url.strip_prefix("git+") {
+            Some(url) => Self::Git(url.to_string()),
+            None if url.trim_end_matches('/').ends_with(".git") => Self::Git(url.to_string()),
+            None => Self::Index(url.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Git(url) if !url.ends_with(".git") => write!(f, "git+{url}"),
+            Self::Git(url) | Self::Index(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+// a bridge of an index
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub name: String,
+    pub version: String,
+    pub url: String,
+    pub sha256: String,
+}
+
+// a bridge fetched from its registry, not installed yet
+#[derive(Debug)]
+pub struct Fetched {
+    pub dir: PathBuf,
+    // empty when the registry doesn't say
+    pub version: String,
+    // the sha256 of the tarball or the commit of the git repo, what tells a
+    // new fetch it changed
+    pub revision: String,
+}
+
+pub fn parse_index(src: &str, url: &str) -> Result<Vec<IndexEntry>> {
+    let bad = |error: String| RegistryError::BadIndex {
+        url: url.to_string(),
+        error,
+    };
+
+    let doc = src
+        .parse::<KdlDocument>()
+        .map_err(|err| bad(err.to_string()))?;
+
+    doc.nodes()
+        .iter()
+        .filter(|node| node.name().value() == "bridge")
+        .map(|node| {
+            let name = node
+                .entries()
+                .first()
+                .filter(|e| e.name().is_none())
+                .and_then(|e| e.value().as_string())
+                .ok_or_else(|| bad("a bridge without a name".to_string()))?;
+            let prop = |key: &str| node.get(key).and_then(|v| v.as_string()).map(String::from);
+
+            Ok(IndexEntry {
+                name: name.to_string(),
+                version: prop("version").unwrap_or_default(),
+                url: prop("url").ok_or_else(|| bad(format!("`{name}` has no url")))?,
+                sha256: prop("sha256")
+                    .ok_or_else(|| bad(format!("`{name}` has no sha256")))?
+                    .to_lowercase(),
+            })
+        })
+        .collect()
+}
+
+// a dir of the bridges set (and of a git registry), `""`, `..` or `a/../../x`
+// would be the set itself or out of it, and the hidden ones are pkg's
+pub fn check_name(name: &str) -> Result<()> {
+    let mut components = Path::new(name).components();
+
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(dir)), None) if dir == name && !name.starts_with('.') => Ok(()),
+        _ => Err(RegistryError::BadBridgeName(name.to_string())),
+    }
+}
+
+// the bridge `name` of the registry, in `cache_dir` (the checkout of a git
+// registry is kept there and pulled the next time)
+pub fn fetch(registry: &Registry, name: &str, cache_dir: &Path) -> Result<Fetched> {
+    check_name(name)?;
+
+    let not_found = || RegistryError::NotFound {
+        name: name.to_string(),
+        registry: registry.to_string(),
+    };
+
+    match registry {
+        Registry::Git(url) => {
+            let checkout = cache_dir.join("registries").join(git::repo_name(url));
+            if checkout.join(".git").is_dir() {
+                git::pull(&checkout)?;
+            } else {
+                std::fs::create_dir_all(cache_dir.join("registries"))?;
+                git::clone(url, &checkout, None)?;
+            }
+
+            let dir = checkout.join(name);
+            if !dir.is_dir() {
+                return Err(not_found());
+            }
+
+            Ok(Fetched {
+                dir,
+                version: String::new(),
+                revision: git::head(&checkout)?,
+            })
+        }
+        Registry::Index(url) => {
+            let index = String::from_utf8_lossy(&download(url)?).into_owned();
+            let entry = parse_index(&index, url)?
+                .into_iter()
+                .find(|entry| entry.name == name)
+                .ok_or_else(not_found)?;
+
+            let tarball = download(&resolve_url(url, &entry.url))?;
+            let actual = sha256(&tarball);
+            if actual != entry.sha256 {
+                return Err(RegistryError::ChecksumMismatch {
+                    name: name.to_string(),
+                    expected: entry.sha256,
+                    actual,
+                });
+            }
+
+            let dir = cache_dir.join("registries").join(format!("{name}.fetch"));
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir)?;
+            }
+            std::fs::create_dir_all(&dir)?;
+            let tarball_path = dir.with_extension("tar.gz");
+            std::fs::write(&tarball_path, &tarball)?;
+            let extracted = extract(&tarball_path, &dir);
+            let _ = std::fs::remove_file(&tarball_path);
+            extracted?;
+
+            // in a dir of its name, or at the root
+            let dir = match dir.join(name) {
+                nested if nested.is_dir() => nested,
+                _ => dir,
+            };
+
+            Ok(Fetched {
+                dir,
+                version: entry.version,
+                revision: entry.sha256,
+            })
+        }
+    }
+}
+
+// in `bridges_set/name`, over the one that's there. it's copied next to it
+// first, so a failed copy leaves the old one
+pub fn install(fetched: &Fetched, bridges_set: &Path, name: &str) -> Result<PathBuf> {
+    check_name(name)?;
+
+    if !fetched.dir.join(BRIDGE_ENTRY_POINT_NAME).is_file()
+        && !fetched.dir.join(WASM_ENTRY_POINT_NAME).is_file()
+    {
+        return Err(RegistryError::NotABridge(name.to_string()));
+    }
+
+    std::fs::create_dir_all(bridges_set)?;
+    let target = bridges_set.join(name);
+    let tmp = bridges_set.join(format!(".{name}.pkg-install"));
+    if tmp.exists() {
+        std::fs::remove_dir_all(&tmp)?;
+    }
+
+    crate::fs::copy_path(&fetched.dir, &tmp).inspect_err(|_| {
+        let _ = std::fs::remove_dir_all(&tmp);
+    })?;
+    // a `.git` of the registry isn't part of the bridge
+    let _ = std::fs::remove_dir_all(tmp.join(".git"));
+
+    if target.exists() {
+        std::fs::remove_dir_all(&target)?;
+    }
+    std::fs::rename(&tmp, &target)?;
+
+    Ok(target)
+}
+
+// the url of a tarball, relative to the index
+pub fn resolve_url(index_url: &str, url: &str) -> String {
+    if url.contains("://") || url.starts_with('/') {
+        return url.to_string();
+    }
+
+    match index_url.rsplit_once('/') {
+        Some((base, _)) => format!("{base}/{url}"),
+        None => url.to_string(),
+    }
+}
+
+pub fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+// with curl, a path (a mounted registry) is read as it is
+fn download(url: &str) -> Result<Vec<u8>> {
+    if !url.contains("://") {
+        return Ok(std::fs::read(url)?);
+    }
+
+    let Some(curl) = host::find_command("curl") else {
+        return Err(RegistryError::MissingCommand("curl"));
+    };
+
+    let output = Command::new(curl)
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        .args(["--max-time", "300"])
+        .arg(url)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(RegistryError::DownloadFailed {
+            url: url.to_string(),
+            error: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    Ok(output.stdout)
+}
+
+fn extract(tarball: &Path, dir: &Path) -> Result<()> {
+    let Some(tar) = host::find_command("tar") else {
+        return Err(RegistryError::MissingCommand("tar"));
+    };
+
+    let output = Command::new(tar)
+        .arg("-xzf")
+        .arg(tarball)
+        .arg("-C")
+        .arg(dir)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(RegistryError::DownloadFailed {
+            url: tarball.display().to_string(),
+            error: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    Ok(())
+}
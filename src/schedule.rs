@@ -0,0 +1,232 @@
+// `pkg schedule`, a `pkg update` run by a systemd timer (or cron without
+// systemd), the `schedule` section of the config gives the jitter and which
+// pkgs it updates
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+// `pkg-update.service` and `pkg-update.timer`, or `/etc/cron.d/pkg-update`
+pub const NAME: &str = "pkg-update";
+pub const SYSTEMD_DIR: &str = "/etc/systemd/system";
+pub const CRON_DIR: &str = "/etc/cron.d";
+
+// the first line of the files pkg writes, the others are never touched
+const HEADER: &str = "# written by `pkg schedule install`, removed by `pkg schedule remove`";
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum ScheduleError {
+    #[error(transparent)]
+    #[diagnostic(code(schedule::io_error))]
+    IoError(#[from] std::io::Error),
+
+    #[error("{0:?} wasn't written by pkg")]
+    #[diagnostic(
+        code(schedule::not_ours),
+        help("Move it away, pkg doesn't overwrite or remove what it didn't write")
+    )]
+    NotOurs(PathBuf),
+}
+
+type Result<T, E = ScheduleError> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Frequency {
+    #[default]
+    Daily,
+    Weekly,
+}
+
+impl std::fmt::Display for Frequency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Daily => write!(f, "daily"),
+            Self::Weekly => write!(f, "weekly"),
+        }
+    }
+}
+
+// `schedule { jitter "1h"; only-unpinned #true; }` in the config
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScheduleOptions {
+    // the runs are spread on this, a fleet doesn't hit the mirrors at once
+    pub jitter: Duration,
+    // `pkg update --unpinned`, the pkgs with a `version` attribute wait for
+    // the inputs to change it
+    pub only_unpinned: bool,
+}
+
+impl Default for ScheduleOptions {
+    fn default() -> Self {
+        Self {
+            jitter: Duration::from_secs(60 * 60),
+            only_unpinned: false,
+        }
+    }
+}
+
+// what the scheduled run is
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub frequency: Frequency,
+    pub jitter: Duration,
+    // the env of the run (the config dir of the one who scheduled it)
+    pub env: Vec<(String, String)>,
+    // the pkg binary and its args
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scheduler {
+    // the dir of the units, they're enabled by the caller
+    SystemdTimer(PathBuf),
+    // the dir of the cron file
+    Cron(PathBuf),
+}
+
+impl Scheduler {
+    pub fn files(&self) -> Vec<PathBuf> {
+        match self {
+            Self::SystemdTimer(dir) => vec![
+                dir.join(format!("{NAME}.service")),
+                dir.join(format!("{NAME}.timer")),
+            ],
+            Self::Cron(dir) => vec![dir.join(NAME)],
+        }
+    }
+
+    pub fn timer_name(&self) -> Option<String> {
+        match self {
+            Self::SystemdTimer(_) => Some(format!("{NAME}.timer")),
+            Self::Cron(_) => None,
+        }
+    }
+
+    pub fn install(&self, schedule: &Schedule) -> Result<Vec<PathBuf>> {
+        let files = self.files();
+        let contents = match self {
+            Self::SystemdTimer(_) => vec![service(schedule), timer(schedule)],
+            Self::Cron(_) => vec![cron(schedule, random_offset(schedule.jitter))],
+        };
+
+        for file in &files {
+            check_ours(file)?;
+        }
+        for (file, content) in files.iter().zip(contents) {
+            if let Some(parent) = file.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(file, content)?;
+        }
+
+        Ok(files)
+    }
+
+    // the files that were there
+    pub fn remove(&self) -> Result<Vec<PathBuf>> {
+        let mut removed = Vec::new();
+
+        for file in self.files() {
+            if file.exists() {
+                check_ours(&file)?;
+                std::fs::remove_file(&file)?;
+                removed.push(file);
+            }
+        }
+
+        Ok(removed)
+    }
+
+    // the files that are there, with their content
+    pub fn installed(&self) -> Result<Vec<(PathBuf, String)>> {
+        let mut installed = Vec::new();
+
+        for file in self.files() {
+            if file.exists() {
+                check_ours(&file)?;
+                installed.push((file.clone(), std::fs::read_to_string(&file)?));
+            }
+        }
+
+        Ok(installed)
+    }
+}
+
+fn check_ours(file: &Path) -> Result<()> {
+    match std::fs::read_to_string(file) {
+        Ok(content) if !content.starts_with(HEADER) => {
+            Err(ScheduleError::NotOurs(file.to_path_buf()))
+        }
+        _ => Ok(()),
+    }
+}
+
+// systemd splits the args on spaces, they're quoted
+fn quoted(command: &[String]) -> String {
+    command
+        .iter()
+        .map(|arg| {
+            if arg.contains([' ', '"', '\\']) {
+                format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+            } else {
+                arg.clone()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn service(schedule: &Schedule) -> String {
+    let env = schedule
+        .env
+        .iter()
+        .map(|(name, value)| format!("Environment=\"{name}={value}\"\n"))
+        .collect::<String>();
+
+    format!(
+        "{HEADER}\n[Unit]\nDescription=pkg update\nWants=network-online.target\nAfter=network-online.target\n\n[Service]\nType=oneshot\n{env}ExecStart={}\n",
+        quoted(&schedule.command)
+    )
+}
+
+fn timer(schedule: &Schedule) -> String {
+    format!(
+        "{HEADER}\n[Unit]\nDescription=pkg update, {}\n\n[Timer]\nOnCalendar={}\nRandomizedDelaySec={}\nPersistent=true\n\n[Install]\nWantedBy=timers.target\n",
+        schedule.frequency,
+        schedule.frequency,
+        schedule.jitter.as_secs()
+    )
+}
+
+// cron has no jitter, the run is at a random time in it, chosen once
+fn cron(schedule: &Schedule, offset: Duration) -> String {
+    let minutes = offset.as_secs() / 60;
+    let day = match schedule.frequency {
+        Frequency::Daily => "*",
+        Frequency::Weekly => "0",
+    };
+    let env = schedule
+        .env
+        .iter()
+        .map(|(name, value)| format!("{name}={value}\n"))
+        .collect::<String>();
+
+    format!(
+        "{HEADER}\n{env}{} {} * * {day} root {} >/dev/null 2>&1\n",
+        minutes % 60,
+        minutes / 60 % 24,
+        quoted(&schedule.command)
+    )
+}
+
+fn random_offset(jitter: Duration) -> Duration {
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64 ^ d.as_secs())
+        .unwrap_or_default();
+
+    Duration::from_secs(seed % jitter.as_secs().max(1))
+}
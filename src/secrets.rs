@@ -0,0 +1,210 @@
+// the attributes that hold a secret (the token of a private registry...) are
+// references in the inputs, resolved only when the bridge runs, so they're
+// never in the kdl files, the db or the cache keys, and their values are
+// redacted from the bridge logs, with the attributes that have a sensitive name
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::input::{AttributeValue, PkgDeclaration};
+
+// `token="secret:GH_TOKEN"`, an env var of pkg
+pub const ENV_PREFIX: &str = "secret:";
+// `token="secret-file:/etc/pkg/gh-token"`, the file without its last new line
+pub const FILE_PREFIX: &str = "secret-file:";
+// `token="secret-keyring:gh"`, looked up in the keyring with libsecret's
+// `secret-tool lookup pkg gh`
+pub const KEYRING_PREFIX: &str = "secret-keyring:";
+
+// what the secrets are replaced with in the logs
+pub const REDACTED: &str = "<redacted>";
+
+// the attribute names redacted when the config doesn't say (`redact` in the
+// `bridges` section), matched without the case
+const SENSITIVE_ATTRIBUTES: &[&str] = &["*token*", "*password*", "*secret*"];
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum SecretError {
+    #[error("The secret `{reference}` of {pkg} is not set")]
+    #[diagnostic(
+        code(secrets::not_found),
+        help(
+            "Export the env var, write the file or store it with `secret-tool store --label=pkg pkg <name>`"
+        )
+    )]
+    NotFound { pkg: String, reference: String },
+
+    #[error("Couldn't read the secret `{reference}` of {pkg}: {message}")]
+    #[diagnostic(code(secrets::unreadable))]
+    Unreadable {
+        pkg: String,
+        reference: String,
+        message: String,
+    },
+}
+
+type Result<T, E = SecretError> = std::result::Result<T, E>;
+
+// the values of the secrets of an operation, to redact them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Secrets {
+    values: Vec<String>,
+}
+
+impl Secrets {
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        self.values.iter().fold(text.to_string(), |text, value| {
+            text.replace(value, REDACTED)
+        })
+    }
+
+    // the plain values of the attributes with a sensitive name, a token
+    // written in the inputs is still kept out of the logs
+    pub fn add_sensitive(&mut self, pkg: &PkgDeclaration, patterns: &[String]) {
+        for (key, value) in &pkg.attributes {
+            let key = key.to_lowercase();
+            if patterns
+                .iter()
+                .any(|pattern| name_matches(&pattern.to_lowercase(), &key))
+            {
+                self.add_value(value);
+            }
+        }
+    }
+
+    fn add_value(&mut self, value: &AttributeValue) {
+        match value {
+            AttributeValue::String(value) if !value.is_empty() && !self.values.contains(value) => {
+                self.values.push(value.clone())
+            }
+            AttributeValue::List(values) => values.iter().for_each(|value| self.add_value(value)),
+            AttributeValue::Map(map) => map.values().for_each(|value| self.add_value(value)),
+            _ => {}
+        }
+    }
+
+    // the output of a bridge, untouched if there is nothing to redact
+    pub fn redact_bytes(&self, bytes: &[u8]) -> Vec<u8> {
+        if self.is_empty() {
+            return bytes.to_vec();
+        }
+
+        self.redact(&String::from_utf8_lossy(bytes)).into_bytes()
+    }
+}
+
+pub fn default_sensitive_attributes() -> Vec<String> {
+    SENSITIVE_ATTRIBUTES.iter().map(|p| p.to_string()).collect()
+}
+
+// `*token*` is in the name, `LC_*` a prefix, `*_TOKEN` a suffix, the rest the
+// whole name
+pub fn name_matches(pattern: &str, name: &str) -> bool {
+    if let Some(part) = pattern.strip_prefix('*').and_then(|p| p.strip_suffix('*')) {
+        name.contains(part)
+    } else if let Some(prefix) = pattern.strip_suffix('*') {
+        name.starts_with(prefix)
+    } else if let Some(suffix) = pattern.strip_prefix('*') {
+        name.ends_with(suffix)
+    } else {
+        pattern == name
+    }
+}
+
+pub fn is_reference(value: &str) -> bool {
+    value.starts_with(ENV_PREFIX)
+        || value.starts_with(FILE_PREFIX)
+        || value.starts_with(KEYRING_PREFIX)
+}
+
+// the pkg with the values of its secrets, what the bridge gets
+pub fn resolve(pkg: &PkgDeclaration) -> Result<(PkgDeclaration, Secrets)> {
+    let mut secrets = Secrets::default();
+
+    let attributes = pkg
+        .attributes
+        .iter()
+        .map(|(key, value)| Ok((key.clone(), resolve_value(&pkg.name, value, &mut secrets)?)))
+        .collect::<Result<HashMap<_, _>>>()?;
+
+    Ok((
+        PkgDeclaration {
+            attributes,
+            ..pkg.clone()
+        },
+        secrets,
+    ))
+}
+
+fn resolve_value(
+    pkg: &str,
+    value: &AttributeValue,
+    secrets: &mut Secrets,
+) -> Result<AttributeValue> {
+    Ok(match value {
+        AttributeValue::String(value) if is_reference(value) => {
+            let secret = AttributeValue::String(lookup(pkg, value)?);
+            secrets.add_value(&secret);
+            secret
+        }
+        AttributeValue::List(values) => AttributeValue::List(
+            values
+                .iter()
+                .map(|value| resolve_value(pkg, value, secrets))
+                .collect::<Result<Vec<_>>>()?,
+        ),
+        AttributeValue::Map(map) => AttributeValue::Map(
+            map.iter()
+                .map(|(key, value)| Ok((key.clone(), resolve_value(pkg, value, secrets)?)))
+                .collect::<Result<BTreeMap<_, _>>>()?,
+        ),
+        value => value.clone(),
+    })
+}
+
+fn lookup(pkg: &str, reference: &str) -> Result<String> {
+    let not_found = || SecretError::NotFound {
+        pkg: pkg.to_string(),
+        reference: reference.to_string(),
+    };
+    let unreadable = |message: String| SecretError::Unreadable {
+        pkg: pkg.to_string(),
+        reference: reference.to_string(),
+        message,
+    };
+
+    if let Some(path) = reference.strip_prefix(FILE_PREFIX) {
+        let path = PathBuf::from(path);
+        if !path.is_file() {
+            return Err(not_found());
+        }
+
+        let content = std::fs::read_to_string(&path).map_err(|err| unreadable(err.to_string()))?;
+        Ok(content.trim_end_matches(['\n', '\r']).to_string())
+    } else if let Some(name) = reference.strip_prefix(KEYRING_PREFIX) {
+        let output = Command::new("secret-tool")
+            .args(["lookup", "pkg", name])
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|err| unreadable(format!("secret-tool: {err}")))?;
+
+        // it exits with 1 and prints nothing when there's no such secret
+        if !output.status.success() || output.stdout.is_empty() {
+            return Err(not_found());
+        }
+
+        String::from_utf8(output.stdout).map_err(|err| unreadable(err.to_string()))
+    } else {
+        let name = reference.strip_prefix(ENV_PREFIX).unwrap_or(reference);
+        std::env::var(name).map_err(|_| not_found())
+    }
+}
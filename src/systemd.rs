@@ -0,0 +1,153 @@
+// the systemd units given by the bridges (`systemd-unit,<path>` artifacts) are
+// linked in the `unit-dir` of the config by `Fs::link`, this runs `systemctl`
+// for them as the pkg attributes ask
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::input::AttributeValue;
+
+// `systemd-enable=#true`, `enable --now` the units once they're linked
+pub const ENABLE_ATTRIBUTE: &str = "systemd-enable";
+// `systemd-reload=#true`, `daemon-reload` when the pkg is installed, updated
+// or removed (the enabled ones always do)
+pub const RELOAD_ATTRIBUTE: &str = "systemd-reload";
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum SystemdError {
+    #[error(transparent)]
+    #[diagnostic(code(systemd::io_error))]
+    IoError(#[from] std::io::Error),
+
+    #[error("`systemctl {args}` failed: {stderr}")]
+    #[diagnostic(code(systemd::failed))]
+    Failed { args: String, stderr: String },
+
+    #[error("The `{attribute}` attribute of {pkg} should be a boolean")]
+    #[diagnostic(code(systemd::wrong_value))]
+    WrongValue {
+        pkg: String,
+        attribute: &'static str,
+    },
+}
+
+type Result<T, E = SystemdError> = std::result::Result<T, E>;
+
+// what the attributes of a pkg ask for its units
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct UnitOptions {
+    pub enable: bool,
+    pub reload: bool,
+}
+
+impl UnitOptions {
+    pub fn from_attributes(
+        pkg_name: &str,
+        attributes: &HashMap<String, AttributeValue>,
+    ) -> Result<Self> {
+        let flag = |attribute: &'static str| match attributes.get(attribute) {
+            None => Ok(false),
+            Some(AttributeValue::Boolean(value)) => Ok(*value),
+            Some(_) => Err(SystemdError::WrongValue {
+                pkg: pkg_name.to_string(),
+                attribute,
+            }),
+        };
+
+        let enable = flag(ENABLE_ATTRIBUTE)?;
+        Ok(Self {
+            enable,
+            reload: enable || flag(RELOAD_ATTRIBUTE)?,
+        })
+    }
+}
+
+pub fn unit_name(unit: &Path) -> String {
+    unit.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[derive(Debug, Clone)]
+pub struct Systemctl {
+    program: PathBuf,
+}
+
+impl Default for Systemctl {
+    fn default() -> Self {
+        Self {
+            program: PathBuf::from("systemctl"),
+        }
+    }
+}
+
+impl Systemctl {
+    // another `systemctl` (`systemctl --user` in a wrapper...)
+    pub fn new(program: PathBuf) -> Self {
+        Self { program }
+    }
+
+    fn run(&self, args: &[&str]) -> Result<()> {
+        self.output(args).map(|_| ())
+    }
+
+    fn output(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new(&self.program)
+            .args(args)
+            .stdin(Stdio::null())
+            .output()?;
+
+        if !output.status.success() {
+            return Err(SystemdError::Failed {
+                args: args.join(" "),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    // `NextElapseUSecRealtime` of a timer...
+    pub fn property(&self, unit: &str, property: &str) -> Result<String> {
+        self.output(&["show", "--value", "--property", property, unit])
+    }
+
+    pub fn daemon_reload(&self) -> Result<()> {
+        self.run(&["daemon-reload"])
+    }
+
+    pub fn enable_now(&self, units: &[PathBuf]) -> Result<()> {
+        if units.is_empty() {
+            return Ok(());
+        }
+
+        let names = units.iter().map(|u| unit_name(u)).collect::<Vec<String>>();
+        let mut args = vec!["enable", "--now"];
+        args.extend(names.iter().map(String::as_str));
+        self.run(&args)
+    }
+
+    // only the enabled ones, so a pkg whose units were never enabled (or a
+    // system without systemd) is removed without errors, true if some were
+    pub fn disable_now(&self, units: &[PathBuf]) -> Result<bool> {
+        let names = units
+            .iter()
+            .map(|u| unit_name(u))
+            .filter(|name| self.run(&["is-enabled", "--quiet", name]).is_ok())
+            .collect::<Vec<String>>();
+
+        if names.is_empty() {
+            return Ok(false);
+        }
+
+        let mut args = vec!["disable", "--now"];
+        args.extend(names.iter().map(String::as_str));
+        self.run(&args).map(|_| true)
+    }
+}
@@ -0,0 +1,50 @@
+use crate::{
+    audit::*,
+    event::{Event, EventSink},
+};
+
+#[test]
+fn the_records_are_chained_and_a_change_breaks_the_chain() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db").join(FILE_NAME);
+    let log = AuditLog::new(path.clone());
+
+    assert!(log.read().unwrap().is_empty());
+
+    log.record("bridge install", "bridge1/tool", Some(0))
+        .unwrap();
+    let mut sink = AuditSink::new(log.clone(), Vec::<Event>::new());
+    sink.emit(Event::PackageInstalled {
+        name: "tool".into(),
+    });
+    sink.emit(Event::LinkStarted);
+
+    let records = log.read().unwrap();
+    assert_eq!(
+        records
+            .iter()
+            .map(|r| (r.seq, r.action.as_str(), r.target.as_str(), r.code))
+            .collect::<Vec<_>>(),
+        vec![
+            (1, "bridge install", "bridge1/tool", Some(0)),
+            (2, "install", "tool", None),
+        ]
+    );
+    assert_eq!(records[1].prev, records[0].hash);
+    assert_eq!(sink.inner().len(), 2);
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    std::fs::write(&path, content.replacen("bridge1/tool", "bridge2/tool", 1)).unwrap();
+    assert!(matches!(
+        log.read(),
+        Err(AuditError::Tampered { seq: 1, .. })
+    ));
+
+    // dropping the first record breaks it too
+    let second = content.lines().nth(1).unwrap();
+    std::fs::write(&path, format!("{second}\n")).unwrap();
+    assert!(matches!(
+        log.read(),
+        Err(AuditError::Tampered { seq: 1, .. })
+    ));
+}
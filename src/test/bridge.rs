@@ -1,15 +1,1244 @@
-use std::path::PathBuf;
+use tempfile::NamedTempFile;
 
 use crate::bridge::*;
 
 #[test]
 fn init_a_bridge_api() {
     let bridge_set_path = std::path::PathBuf::from("examples/assets/bridges");
+    let db_file = NamedTempFile::new().unwrap();
 
     let _bridge_api = BridgeApi::new(
         bridge_set_path,
-        &vec!["bridge1".to_string()],
-        &PathBuf::from("some/where"),
+        &["bridge1".to_string()],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
     )
     .unwrap();
 }
+
+#[test]
+fn an_instance_runs_the_bridge_it_is_an_alias_of() {
+    let bridge_set_path = std::path::PathBuf::from("examples/assets/bridges");
+    let db = || {
+        let db_file = NamedTempFile::new().unwrap();
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap())
+    };
+    let aliases = std::collections::BTreeMap::from([
+        ("bridge1-work".to_string(), "bridge1".to_string()),
+        ("nope-work".to_string(), "nope".to_string()),
+    ]);
+
+    let bridge_api = BridgeApi::new_with_aliases(
+        bridge_set_path.clone(),
+        &["bridge1".to_string(), "bridge1-work".to_string()],
+        &aliases,
+        db(),
+    )
+    .unwrap();
+    assert!(bridge_api.has_bridge("bridge1"));
+    assert!(bridge_api.has_bridge("bridge1-work"));
+
+    // the bridge that's missing is the one of the set
+    let Err(BridgeApiError::BridgeNotFound(missing)) =
+        BridgeApi::new_with_aliases(bridge_set_path, &["nope-work".to_string()], &aliases, db())
+    else {
+        panic!("expected a missing bridge");
+    };
+    assert_eq!(missing, "nope");
+}
+
+#[test]
+fn only_the_last_failed_working_dir_is_kept() {
+    let bridge_set_path = std::path::PathBuf::from("examples/assets/bridges");
+    let db_file = NamedTempFile::new().unwrap();
+    let working_dir = tempfile::tempdir().unwrap();
+    let log_dir = tempfile::tempdir().unwrap();
+
+    let bridge_api = BridgeApi::new(
+        bridge_set_path,
+        &["bridge1".to_string()],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
+    )
+    .unwrap()
+    .with_options(BridgeOptions {
+        log_dir: log_dir.path().to_path_buf(),
+        working_dir: working_dir.path().to_path_buf(),
+        ..Default::default()
+    });
+
+    // the bridge can't write in a dir that doesn't exist, so it fails
+    let pkg = crate::input::PkgDeclaration {
+        name: "broken".to_string(),
+        input: "missing/broken".to_string(),
+        attributes: Default::default(),
+        tags: Vec::new(),
+        bridge: None,
+    };
+
+    for _ in 0..3 {
+        assert!(bridge_api.install("bridge1", &pkg).is_err());
+    }
+
+    let kept = working_dir
+        .path()
+        .join("bridge1/broken")
+        .read_dir()
+        .unwrap()
+        .count();
+    assert_eq!(kept, 1);
+}
+
+#[test]
+fn protocol_2_bridges_get_the_attributes_in_the_opts_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let bridge_set = tempfile::tempdir().unwrap();
+    let bridge_dir = bridge_set.path().join("opts");
+    std::fs::create_dir_all(&bridge_dir).unwrap();
+    std::fs::write(bridge_dir.join("bridge.kdl"), "protocol 2\n").unwrap();
+    std::fs::write(
+        bridge_dir.join("run"),
+        "#!/bin/sh\ncp \"$pkg_opts\" opts.json\nenv > env.txt\nprintf '#!/bin/sh\\n' > \"$2\"\nchmod +x \"$2\"\necho \"./$2,0.1.0\"\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(
+        bridge_dir.join("run"),
+        std::fs::Permissions::from_mode(0o755),
+    )
+    .unwrap();
+
+    let db_file = NamedTempFile::new().unwrap();
+    let working_dir = tempfile::tempdir().unwrap();
+    let log_dir = tempfile::tempdir().unwrap();
+
+    let bridge_api = BridgeApi::new(
+        bridge_set.path().to_path_buf(),
+        &["opts".to_string()],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
+    )
+    .unwrap()
+    .with_options(BridgeOptions {
+        log_dir: log_dir.path().to_path_buf(),
+        working_dir: working_dir.path().to_path_buf(),
+        workdir_retention: WorkdirRetention::KeepAlways,
+        ..Default::default()
+    });
+
+    let pkg = crate::input::PkgDeclaration {
+        name: "pkg1".to_string(),
+        input: "pkg1".to_string(),
+        attributes: std::collections::HashMap::from([(
+            "assets".to_string(),
+            crate::input::AttributeValue::List(vec![
+                crate::input::AttributeValue::String("linux-x64".to_string()),
+                crate::input::AttributeValue::Integer(2),
+            ]),
+        )]),
+        tags: Vec::new(),
+        bridge: None,
+    };
+
+    let (_, work_dir) = bridge_api.install("opts", &pkg).unwrap();
+
+    let opts: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(work_dir.join("opts.json")).unwrap())
+            .unwrap();
+    assert_eq!(opts["operation"], "install");
+    assert_eq!(opts["input"], "pkg1");
+    assert_eq!(
+        opts["attributes"]["assets"],
+        serde_json::json!(["linux-x64", 2])
+    );
+
+    let env = std::fs::read_to_string(work_dir.join("env.txt")).unwrap();
+    assert!(env.contains("pkg_opts="));
+    assert!(!env.contains("assets"));
+}
+
+#[test]
+fn a_kept_failed_working_dir_has_no_secret_in_it() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let bridge_set = tempfile::tempdir().unwrap();
+    let bridge_dir = bridge_set.path().join("failing");
+    std::fs::create_dir_all(&bridge_dir).unwrap();
+    std::fs::write(
+        bridge_dir.join("run"),
+        "#!/bin/sh\nstat -c %a \"$pkg_opts\" > opts_mode.txt\nstat -c %a . > dir_mode.txt\nexit 1\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(
+        bridge_dir.join("run"),
+        std::fs::Permissions::from_mode(0o755),
+    )
+    .unwrap();
+
+    let secret_dir = tempfile::tempdir().unwrap();
+    let token_file = secret_dir.path().join("token");
+    std::fs::write(&token_file, "s3cr3t").unwrap();
+
+    let db_file = NamedTempFile::new().unwrap();
+    let working_dir = tempfile::tempdir().unwrap();
+    let log_dir = tempfile::tempdir().unwrap();
+
+    let bridge_api = BridgeApi::new(
+        bridge_set.path().to_path_buf(),
+        &["failing".to_string()],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
+    )
+    .unwrap()
+    .with_options(BridgeOptions {
+        log_dir: log_dir.path().to_path_buf(),
+        working_dir: working_dir.path().to_path_buf(),
+        workdir_retention: WorkdirRetention::KeepOnFailure,
+        ..Default::default()
+    });
+
+    let pkg = crate::input::PkgDeclaration {
+        name: "pkg1".to_string(),
+        input: "pkg1".to_string(),
+        attributes: std::collections::HashMap::from([(
+            "token".to_string(),
+            crate::input::AttributeValue::String(format!(
+                "{}{}",
+                crate::secrets::FILE_PREFIX,
+                token_file.display()
+            )),
+        )]),
+        tags: Vec::new(),
+        bridge: None,
+    };
+
+    assert!(bridge_api.install("failing", &pkg).is_err());
+
+    let work_dir = std::fs::read_dir(working_dir.path().join("failing/pkg1"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    assert_eq!(
+        std::fs::read_to_string(work_dir.join("opts_mode.txt")).unwrap(),
+        "600\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(work_dir.join("dir_mode.txt")).unwrap(),
+        "700\n"
+    );
+
+    // the working dir is kept to debug the failure, without the opts file
+    assert!(!work_dir.join("pkg_opts.json").exists());
+    for entry in std::fs::read_dir(&work_dir).unwrap() {
+        let content = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+        assert!(!content.contains("s3cr3t"));
+    }
+}
+
+#[test]
+fn an_inline_exec_replaces_the_bridge() {
+    let db_file = NamedTempFile::new().unwrap();
+    let working_dir = tempfile::tempdir().unwrap();
+    let log_dir = tempfile::tempdir().unwrap();
+
+    // no `dotfiles` bridge in the bridge set
+    let bridge_api = BridgeApi::new(
+        std::path::PathBuf::from("examples/assets/bridges"),
+        &[],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
+    )
+    .unwrap()
+    .with_options(BridgeOptions {
+        log_dir: log_dir.path().to_path_buf(),
+        working_dir: working_dir.path().to_path_buf(),
+        ..Default::default()
+    });
+
+    let pkg = crate::input::PkgDeclaration {
+        name: "tool".to_string(),
+        input: "tool".to_string(),
+        attributes: Default::default(),
+        tags: Vec::new(),
+        bridge: Some(crate::input::BridgeOverride::Exec(
+            "printf '#!/bin/sh\\n' > \"$2\"\nchmod +x \"$2\"\necho \"./$2,1.2.3\"\n".to_string(),
+        )),
+    };
+
+    let (pkg, _) = bridge_api.install("dotfiles", &pkg).unwrap();
+
+    assert_eq!(pkg.name, "tool");
+    assert_eq!(pkg.version.first_cell, "1");
+    assert!(pkg.path.ends_with("tool"));
+}
+
+#[test]
+fn a_mock_backend_runs_the_operations() {
+    #[derive(Debug)]
+    struct Mock;
+
+    impl BridgeBackend for Mock {
+        fn execute(
+            &self,
+            operation: &Operation,
+            declaration: &crate::input::PkgDeclaration,
+            ctx: &OperationContext,
+        ) -> std::io::Result<OperationOutcome> {
+            use std::os::unix::fs::PermissionsExt;
+
+            assert_eq!(operation, &Operation::Install);
+
+            let pkg = ctx.work_dir.join(&declaration.input);
+            std::fs::write(&pkg, "#!/bin/sh\n")?;
+            std::fs::set_permissions(&pkg, std::fs::Permissions::from_mode(0o755))?;
+
+            Ok(OperationOutcome {
+                code: 0,
+                stdout: format!("./{},2.0.0", declaration.input).into_bytes(),
+                stderr: Vec::new(),
+            })
+        }
+    }
+
+    let db_file = NamedTempFile::new().unwrap();
+    let working_dir = tempfile::tempdir().unwrap();
+    let log_dir = tempfile::tempdir().unwrap();
+
+    let bridge_api = BridgeApi::new(
+        std::path::PathBuf::from("examples/assets/bridges"),
+        &[],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
+    )
+    .unwrap()
+    .with_options(BridgeOptions {
+        log_dir: log_dir.path().to_path_buf(),
+        working_dir: working_dir.path().to_path_buf(),
+        ..Default::default()
+    })
+    .with_backend("mock", Mock);
+
+    let pkg = crate::input::PkgDeclaration {
+        name: "pkg1".to_string(),
+        input: "pkg1".to_string(),
+        attributes: Default::default(),
+        tags: Vec::new(),
+        bridge: None,
+    };
+
+    let (pkg, _) = bridge_api.install("mock", &pkg).unwrap();
+
+    assert_eq!(pkg.version.first_cell, "2");
+    assert!(bridge_api.has_bridge("mock"));
+}
+
+#[test]
+fn removing_a_pkg_that_is_not_installed_is_an_error() {
+    let db_file = NamedTempFile::new().unwrap();
+
+    let bridge_api = BridgeApi::new(
+        std::path::PathBuf::from("examples/assets/bridges"),
+        &[],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
+    )
+    .unwrap();
+
+    let err = bridge_api.default_impls_remove("pkg1").unwrap_err();
+
+    assert!(matches!(
+        err,
+        BridgeApiError::DbError(crate::db::DbError::PkgNotFound(name)) if name == "pkg1"
+    ));
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn the_async_api_installs_pkgs_concurrently() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // how many operations run at the same time, and the most seen
+    #[derive(Debug, Default)]
+    struct Slow {
+        running: AtomicUsize,
+        max: std::sync::Arc<AtomicUsize>,
+    }
+
+    impl BridgeBackend for Slow {
+        fn execute(
+            &self,
+            _operation: &Operation,
+            declaration: &crate::input::PkgDeclaration,
+            ctx: &OperationContext,
+        ) -> std::io::Result<OperationOutcome> {
+            use std::os::unix::fs::PermissionsExt;
+
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(running, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+
+            let pkg = ctx.work_dir.join(&declaration.input);
+            std::fs::write(&pkg, "#!/bin/sh\n")?;
+            std::fs::set_permissions(&pkg, std::fs::Permissions::from_mode(0o755))?;
+
+            Ok(OperationOutcome {
+                code: 0,
+                stdout: format!("./{},1.0.0", declaration.input).into_bytes(),
+                stderr: Vec::new(),
+            })
+        }
+    }
+
+    let db_file = NamedTempFile::new().unwrap();
+    let working_dir = tempfile::tempdir().unwrap();
+    let log_dir = tempfile::tempdir().unwrap();
+    let max = std::sync::Arc::new(AtomicUsize::new(0));
+
+    let bridge_api = BridgeApi::new(
+        std::path::PathBuf::from("examples/assets/bridges"),
+        &[],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
+    )
+    .unwrap()
+    .with_options(BridgeOptions {
+        log_dir: log_dir.path().to_path_buf(),
+        working_dir: working_dir.path().to_path_buf(),
+        ..Default::default()
+    })
+    .with_backend(
+        "slow",
+        Slow {
+            max: max.clone(),
+            ..Default::default()
+        },
+    );
+    let async_api = AsyncBridgeApi::new(bridge_api, 2);
+
+    let pkgs = (1..=4)
+        .map(|i| crate::input::PkgDeclaration {
+            name: format!("pkg{i}"),
+            input: format!("pkg{i}"),
+            attributes: Default::default(),
+            tags: Vec::new(),
+            bridge: None,
+        })
+        .collect::<Vec<_>>();
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let results = runtime.block_on(async_api.install_all("slow", pkgs));
+    let installed = runtime
+        .block_on(async_api.with_db(|db| db.get_pkgs()))
+        .unwrap();
+
+    let names = results
+        .into_iter()
+        .map(|r| r.unwrap().0.name)
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["pkg1", "pkg2", "pkg3", "pkg4"]);
+    // two at a time, no more
+    assert_eq!(max.load(Ordering::SeqCst), 2);
+    // the operations don't write the db, the caller does
+    assert!(installed.is_empty());
+}
+
+#[test]
+fn the_bridge_output_can_give_systemd_units() {
+    #[derive(Debug)]
+    struct Mock;
+
+    impl BridgeBackend for Mock {
+        fn execute(
+            &self,
+            _operation: &Operation,
+            declaration: &crate::input::PkgDeclaration,
+            ctx: &OperationContext,
+        ) -> std::io::Result<OperationOutcome> {
+            use std::os::unix::fs::PermissionsExt;
+
+            let dir = ctx.work_dir.join("app");
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(dir.join("app"), "#!/bin/sh\n")?;
+            std::fs::set_permissions(dir.join("app"), std::fs::Permissions::from_mode(0o755))?;
+            std::fs::write(dir.join("app.service"), "[Service]\n")?;
+            std::fs::write(ctx.work_dir.join("out.service"), "[Service]\n")?;
+
+            Ok(OperationOutcome {
+                code: 0,
+                stdout: format!(
+                    "./app,1.0.0,./app/app\nbuilding...\nsystemd-unit,{}\nlicense,MIT\n",
+                    declaration.input
+                )
+                .into_bytes(),
+                stderr: Vec::new(),
+            })
+        }
+    }
+
+    let db_file = NamedTempFile::new().unwrap();
+    let working_dir = tempfile::tempdir().unwrap();
+    let log_dir = tempfile::tempdir().unwrap();
+
+    let bridge_api = BridgeApi::new(
+        std::path::PathBuf::from("examples/assets/bridges"),
+        &[],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
+    )
+    .unwrap()
+    .with_options(BridgeOptions {
+        log_dir: log_dir.path().to_path_buf(),
+        working_dir: working_dir.path().to_path_buf(),
+        ..Default::default()
+    })
+    .with_backend("mock", Mock);
+
+    let declaration = |unit: &str| crate::input::PkgDeclaration {
+        name: "app".to_string(),
+        input: unit.to_string(),
+        attributes: Default::default(),
+        tags: Vec::new(),
+        bridge: None,
+    };
+
+    let (pkg, work_dir) = bridge_api
+        .install("mock", &declaration("./app/app.service"))
+        .unwrap();
+    assert_eq!(
+        pkg.artifacts,
+        [crate::db::Artifact::SystemdUnit(
+            work_dir.join("./app/app.service")
+        )]
+    );
+    // a metadata line isn't an artifact
+    assert_eq!(pkg.metadata.license.as_deref(), Some("MIT"));
+
+    assert!(matches!(
+        bridge_api.install("mock", &declaration("./out.service")),
+        Err(BridgeApiError::ArtifactOutOfPkg(_))
+    ));
+}
+
+#[test]
+fn the_min_interval_of_a_bridge_spaces_its_operations() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let bridge_set = tempfile::tempdir().unwrap();
+    let bridge_dir = bridge_set.path().join("limited");
+    std::fs::create_dir_all(&bridge_dir).unwrap();
+    std::fs::write(
+        bridge_dir.join("bridge.kdl"),
+        "max-parallel 1\nmin-interval \"150ms\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        bridge_dir.join("run"),
+        "#!/bin/sh\nprintf '#!/bin/sh\\n' > \"$2\"\nchmod +x \"$2\"\necho \"./$2,0.1.0\"\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(
+        bridge_dir.join("run"),
+        std::fs::Permissions::from_mode(0o755),
+    )
+    .unwrap();
+
+    let manifest = crate::manifest::BridgeManifest::load(&bridge_dir).unwrap();
+    assert_eq!(manifest.max_parallel, Some(1));
+    assert_eq!(
+        manifest.min_interval,
+        Some(std::time::Duration::from_millis(150))
+    );
+
+    let db_file = NamedTempFile::new().unwrap();
+    let working_dir = tempfile::tempdir().unwrap();
+    let log_dir = tempfile::tempdir().unwrap();
+
+    let bridge_api = BridgeApi::new(
+        bridge_set.path().to_path_buf(),
+        &["limited".to_string()],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
+    )
+    .unwrap()
+    .with_options(BridgeOptions {
+        log_dir: log_dir.path().to_path_buf(),
+        working_dir: working_dir.path().to_path_buf(),
+        ..Default::default()
+    });
+
+    let start = std::time::Instant::now();
+    for name in ["pkg1", "pkg2", "pkg3"] {
+        let pkg = crate::input::PkgDeclaration {
+            name: name.to_string(),
+            input: name.to_string(),
+            attributes: Default::default(),
+            tags: Vec::new(),
+            bridge: None,
+        };
+        bridge_api.install("limited", &pkg).unwrap();
+    }
+
+    // the first one doesn't wait
+    assert!(start.elapsed() >= std::time::Duration::from_millis(300));
+
+    std::fs::write(bridge_dir.join("bridge.kdl"), "min-interval \"soon\"\n").unwrap();
+    assert!(crate::manifest::BridgeManifest::load(&bridge_dir).is_err());
+}
+
+#[test]
+fn the_transient_failures_are_retried() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let bridge_set = tempfile::tempdir().unwrap();
+    let bridge_dir = bridge_set.path().join("flaky");
+    std::fs::create_dir_all(&bridge_dir).unwrap();
+    // fails with the sentinel twice, then works
+    let tries = bridge_set.path().join("tries");
+    std::fs::write(
+        bridge_dir.join("run"),
+        format!(
+            "#!/bin/sh\necho x >> {tries}\nif [ $(wc -l < {tries}) -le 2 ]; then echo __RETRY >&2; exit 3; fi\nprintf '#!/bin/sh\\n' > \"$2\"\nchmod +x \"$2\"\necho \"./$2,0.1.0\"\n",
+            tries = tries.display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(
+        bridge_dir.join("run"),
+        std::fs::Permissions::from_mode(0o755),
+    )
+    .unwrap();
+
+    let db_file = NamedTempFile::new().unwrap();
+    let working_dir = tempfile::tempdir().unwrap();
+    let log_dir = tempfile::tempdir().unwrap();
+
+    let bridge_api = BridgeApi::new(
+        bridge_set.path().to_path_buf(),
+        &["flaky".to_string()],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
+    )
+    .unwrap()
+    .with_options(BridgeOptions {
+        log_dir: log_dir.path().to_path_buf(),
+        working_dir: working_dir.path().to_path_buf(),
+        retry: RetryPolicy {
+            retries: 2,
+            backoff: std::time::Duration::from_millis(10),
+            on: RetryOn::Sentinel,
+        },
+        ..Default::default()
+    });
+
+    let declaration = |retries: Option<i64>| crate::input::PkgDeclaration {
+        name: "pkg1".to_string(),
+        input: "pkg1".to_string(),
+        attributes: retries
+            .map(|n| {
+                std::collections::HashMap::from([(
+                    "retries".to_string(),
+                    crate::input::AttributeValue::Integer(n),
+                )])
+            })
+            .unwrap_or_default(),
+        tags: Vec::new(),
+        bridge: None,
+    };
+
+    // the pkg allows one retry only
+    assert!(bridge_api.install("flaky", &declaration(Some(1))).is_err());
+    std::fs::remove_file(&tries).unwrap();
+
+    bridge_api.install("flaky", &declaration(None)).unwrap();
+
+    let log = std::fs::read_to_string(log_dir.path().join("flaky.log")).unwrap();
+    assert!(log.contains("|RETRY|::::::::: 1/1 in 10ms"));
+    assert!(log.contains("|RETRY|::::::::: 2/2 in 20ms"));
+}
+
+#[test]
+fn the_cache_key_changes_only_with_what_the_bridge_gets() {
+    use crate::input::{AttributeValue, PkgDeclaration};
+
+    let version = crate::PkgVersion {
+        first_cell: "1".to_string(),
+        second_cell: "0".to_string(),
+        third_cell: "0".to_string(),
+    };
+    let pkg = |input: &str, attributes: &[(&str, i64)]| PkgDeclaration {
+        name: "pkg1".to_string(),
+        input: input.to_string(),
+        attributes: attributes
+            .iter()
+            .map(|(key, value)| (key.to_string(), AttributeValue::Integer(*value)))
+            .collect(),
+        tags: Vec::new(),
+        bridge: None,
+    };
+
+    let key = cache_key(
+        "bridge1",
+        &pkg("pkg1", &[("a", 1), ("b", 2)]),
+        "install",
+        &version,
+    );
+
+    // the order of the attributes doesn't matter
+    assert_eq!(
+        key,
+        cache_key(
+            "bridge1",
+            &pkg("pkg1", &[("b", 2), ("a", 1)]),
+            "install",
+            &version
+        )
+    );
+
+    assert_ne!(
+        key,
+        cache_key(
+            "bridge2",
+            &pkg("pkg1", &[("a", 1), ("b", 2)]),
+            "install",
+            &version
+        )
+    );
+    assert_ne!(
+        key,
+        cache_key(
+            "bridge1",
+            &pkg("pkg2", &[("a", 1), ("b", 2)]),
+            "install",
+            &version
+        )
+    );
+    assert_ne!(
+        key,
+        cache_key(
+            "bridge1",
+            &pkg("pkg1", &[("a", 1), ("b", 3)]),
+            "install",
+            &version
+        )
+    );
+    assert_ne!(
+        key,
+        cache_key(
+            "bridge1",
+            &pkg("pkg1", &[("a", 1), ("b", 2)]),
+            "update",
+            &version
+        )
+    );
+
+    let newer = crate::PkgVersion {
+        third_cell: "1".to_string(),
+        ..version
+    };
+    assert_ne!(
+        key,
+        cache_key(
+            "bridge1",
+            &pkg("pkg1", &[("a", 1), ("b", 2)]),
+            "install",
+            &newer
+        )
+    );
+}
+
+#[test]
+fn the_default_impls_replace_the_old_pkg() {
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Default)]
+    struct Mock {
+        operations: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl BridgeBackend for Mock {
+        fn execute(
+            &self,
+            operation: &Operation,
+            declaration: &crate::input::PkgDeclaration,
+            ctx: &OperationContext,
+        ) -> std::io::Result<OperationOutcome> {
+            self.operations.lock().unwrap().push(operation.display());
+
+            if operation != &Operation::Install {
+                return Ok(default_impls::requested());
+            }
+
+            use std::os::unix::fs::PermissionsExt;
+
+            let pkg = ctx.work_dir.join(&declaration.input);
+            std::fs::write(&pkg, "new")?;
+            std::fs::set_permissions(&pkg, std::fs::Permissions::from_mode(0o755))?;
+            Ok(OperationOutcome {
+                code: 0,
+                stdout: format!("./{},2.0.0", declaration.input).into_bytes(),
+                stderr: Vec::new(),
+            })
+        }
+    }
+
+    let db_file = NamedTempFile::new().unwrap();
+    let working_dir = tempfile::tempdir().unwrap();
+    let log_dir = tempfile::tempdir().unwrap();
+    let target = tempfile::tempdir().unwrap();
+
+    let db = std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap());
+    let old_path = target.path().join("pkg1");
+    std::fs::write(&old_path, "old").unwrap();
+    db.install_bridge_pkgs(
+        &[&crate::Pkg {
+            name: "pkg1".to_string(),
+            version: crate::PkgVersion::parse("1.0.0").unwrap(),
+            path: old_path.clone(),
+            pkg_type: crate::PkgType::SingleExecutable,
+            artifacts: Vec::new(),
+            metadata: crate::db::PkgMetadata::default(),
+        }],
+        &"mock".to_string(),
+    )
+    .unwrap();
+
+    let mock = Mock::default();
+    let operations = mock.operations.clone();
+    let bridge_api = BridgeApi::new(std::path::PathBuf::from("examples/assets/bridges"), &[], db)
+        .unwrap()
+        .with_options(BridgeOptions {
+            log_dir: log_dir.path().to_path_buf(),
+            working_dir: working_dir.path().to_path_buf(),
+            ..Default::default()
+        })
+        .with_backend("mock", mock);
+
+    let pkg = crate::input::PkgDeclaration {
+        name: "pkg1".to_string(),
+        input: "pkg1".to_string(),
+        attributes: Default::default(),
+        tags: Vec::new(),
+        bridge: None,
+    };
+
+    let (new_pkg, work_dir) = bridge_api.reinstall("mock", &pkg).unwrap();
+
+    assert_eq!(*operations.lock().unwrap(), vec!["remove", "install"]);
+    assert!(!old_path.exists());
+    // the new pkg is still there to be stored
+    assert!(work_dir.exists());
+    assert_eq!(std::fs::read_to_string(&new_pkg.path).unwrap(), "new");
+    assert_eq!(new_pkg.version.to_string(), "2.0.0");
+
+    // the default update is the install, then the old pkg is removed
+    operations.lock().unwrap().clear();
+    std::fs::write(&old_path, "old").unwrap();
+
+    let (new_pkg, _) = bridge_api.update("mock", &pkg).unwrap();
+
+    assert_eq!(*operations.lock().unwrap(), vec!["update", "install"]);
+    assert!(!old_path.exists());
+    assert_eq!(std::fs::read_to_string(&new_pkg.path).unwrap(), "new");
+}
+
+#[test]
+fn the_structured_errors_are_typed() {
+    let failed = |stderr: &str| OperationOutcome {
+        code: 2,
+        stdout: Vec::new(),
+        stderr: stderr.as_bytes().to_vec(),
+    };
+
+    assert!(matches!(
+        BridgeApiError::from_failure(&failed("downloading...\n__ERR network connection reset\n")),
+        BridgeApiError::BridgeNetworkError(message) if message == "connection reset"
+    ));
+    assert!(matches!(
+        BridgeApiError::from_failure(&failed("__ERR not-found no release v9")),
+        BridgeApiError::PkgNotFoundUpstream(_)
+    ));
+    assert!(matches!(
+        BridgeApiError::from_failure(&failed("__ERR checksum-mismatch sha256")),
+        BridgeApiError::ChecksumMismatch(_)
+    ));
+    assert!(matches!(
+        BridgeApiError::from_failure(&failed("__ERR unsupported-op")),
+        BridgeApiError::UnsupportedOperation(message) if message.is_empty()
+    ));
+    // only the last line counts
+    assert!(matches!(
+        BridgeApiError::from_failure(&failed("__ERR network x\nsomething else")),
+        BridgeApiError::BridgeError(_)
+    ));
+
+    // a network error can pass, a checksum mismatch can't
+    let on = |on| RetryPolicy {
+        retries: 1,
+        on,
+        ..Default::default()
+    };
+    assert!(on(RetryOn::Sentinel).is_retryable(&failed("__ERR network timeout")));
+    assert!(!on(RetryOn::Sentinel).is_retryable(&failed("__ERR checksum-mismatch")));
+    assert!(!on(RetryOn::Failure).is_retryable(&failed("__ERR checksum-mismatch")));
+    assert!(on(RetryOn::Failure).is_retryable(&failed("it broke")));
+}
+
+#[test]
+fn a_daemon_bridge_is_started_once() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let bridge_set = tempfile::tempdir().unwrap();
+    let bridge_dir = bridge_set.path().join("daemon");
+    std::fs::create_dir_all(&bridge_dir).unwrap();
+    std::fs::write(bridge_dir.join("bridge.kdl"), "daemon #true\n").unwrap();
+
+    let starts = bridge_set.path().join("starts");
+    std::fs::write(
+        bridge_dir.join("run"),
+        format!(
+            r#"#!/bin/sh
+[ "$1" = daemon ] || exit 2
+echo started >> {starts}
+while IFS= read -r line; do
+  id=$(printf '%s' "$line" | sed 's/.*"id":\([0-9]*\).*/\1/')
+  dir=$(printf '%s' "$line" | sed 's/.*"work_dir":"\([^"]*\)".*/\1/')
+  input=$(printf '%s' "$line" | sed 's/.*"input":"\([^"]*\)".*/\1/')
+  if [ "$input" = missing ]; then
+    printf '{{"jsonrpc":"2.0","id":%s,"error":{{"code":1,"message":"__ERR not-found %s"}}}}\n' "$id" "$input"
+    continue
+  fi
+  printf '#!/bin/sh\n' > "$dir/$input"
+  chmod +x "$dir/$input"
+  printf '{{"jsonrpc":"2.0","id":%s,"result":{{"code":0,"stdout":"./%s,0.1.0","stderr":""}}}}\n' "$id" "$input"
+done
+"#,
+            starts = starts.display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(
+        bridge_dir.join("run"),
+        std::fs::Permissions::from_mode(0o755),
+    )
+    .unwrap();
+
+    let db_file = NamedTempFile::new().unwrap();
+    let working_dir = tempfile::tempdir().unwrap();
+    let log_dir = tempfile::tempdir().unwrap();
+
+    let bridge_api = BridgeApi::new(
+        bridge_set.path().to_path_buf(),
+        &["daemon".to_string()],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
+    )
+    .unwrap()
+    .with_options(BridgeOptions {
+        log_dir: log_dir.path().to_path_buf(),
+        working_dir: working_dir.path().to_path_buf(),
+        ..Default::default()
+    });
+
+    let declaration = |name: &str| crate::input::PkgDeclaration {
+        name: name.to_string(),
+        input: name.to_string(),
+        attributes: Default::default(),
+        tags: Vec::new(),
+        bridge: None,
+    };
+
+    let (pkg1, _) = bridge_api.install("daemon", &declaration("pkg1")).unwrap();
+    let (pkg2, _) = bridge_api.install("daemon", &declaration("pkg2")).unwrap();
+    assert!(pkg1.path.ends_with("pkg1") && pkg2.path.ends_with("pkg2"));
+
+    assert!(matches!(
+        bridge_api.install("daemon", &declaration("missing")),
+        Err(BridgeApiError::PkgNotFoundUpstream(_))
+    ));
+
+    assert_eq!(std::fs::read_to_string(&starts).unwrap().lines().count(), 1);
+}
+
+#[test]
+fn the_missing_host_commands_are_reported_at_once() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let bridge_set = tempfile::tempdir().unwrap();
+    for (bridge, needs) in [
+        ("b1", r#""sh" "pkg-missing-a""#),
+        ("b2", r#""pkg-missing-a" "pkg-missing-b""#),
+    ] {
+        let dir = bridge_set.path().join(bridge);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("run"), "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(dir.join("run"), std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(dir.join("bridge.kdl"), format!("needs {needs}\n")).unwrap();
+    }
+
+    let db_file = NamedTempFile::new().unwrap();
+    let err = BridgeApi::new(
+        bridge_set.path().to_path_buf(),
+        &["b1".to_string(), "b2".to_string()],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
+    )
+    .unwrap_err();
+
+    match err {
+        BridgeApiError::MissingHostCommands { missing, hint } => {
+            assert_eq!(missing, "pkg-missing-a (b1, b2), pkg-missing-b (b2)");
+            assert!(hint.contains("pkg-missing-a pkg-missing-b"));
+        }
+        err => panic!("unexpected error: {err}"),
+    }
+}
+
+#[test]
+fn the_bridges_get_only_the_allowed_env() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let policy = EnvPolicy {
+        allow: vec!["PATH".to_string(), "LC_*".to_string()],
+        deny: vec!["LC_SECRET".to_string(), "*_TOKEN".to_string()],
+        set: vec![("HTTP_PROXY".to_string(), "http://proxy:3128".to_string())],
+    };
+    assert!(policy.allows("PATH"));
+    assert!(policy.allows("LC_ALL"));
+    assert!(!policy.allows("LC_SECRET"));
+    assert!(!policy.allows("HOME"));
+    assert!(!policy.allows("LC_GH_TOKEN"));
+    assert!(policy.allows("PKG_NON_INTERACTIVE"));
+    assert_eq!(EnvPolicy::default().bridge_env(), None);
+
+    let dir = tempfile::tempdir().unwrap();
+    let run = dir.path().join("run");
+    std::fs::write(&run, "#!/bin/sh\nenv\n").unwrap();
+    std::fs::set_permissions(&run, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let declaration = crate::input::PkgDeclaration {
+        name: "pkg1".to_string(),
+        input: "pkg1".to_string(),
+        attributes: Default::default(),
+        tags: Vec::new(),
+        bridge: None,
+    };
+    let base_env = policy.bridge_env().unwrap();
+    let outcome = ProcessBackend::new(run)
+        .execute(
+            &Operation::Install,
+            &declaration,
+            &OperationContext {
+                envs: &[("pkg_work_dir".to_string(), "/tmp".to_string())],
+                base_env: Some(&base_env),
+                work_dir: dir.path(),
+            },
+        )
+        .unwrap();
+
+    let env = String::from_utf8(outcome.stdout).unwrap();
+    let env = env.lines().collect::<Vec<_>>();
+    assert!(env.contains(&"HTTP_PROXY=http://proxy:3128"));
+    assert!(env.contains(&"pkg_work_dir=/tmp"));
+    assert!(!env.iter().any(|line| line.starts_with("HOME=")));
+}
+
+#[test]
+fn the_sensitive_attributes_are_kept_out_of_the_logs_and_errors() {
+    let db_file = NamedTempFile::new().unwrap();
+    let working_dir = tempfile::tempdir().unwrap();
+    let log_dir = tempfile::tempdir().unwrap();
+
+    let bridge_api = BridgeApi::new(
+        std::path::PathBuf::from("examples/assets/bridges"),
+        &[],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
+    )
+    .unwrap()
+    .with_options(BridgeOptions {
+        log_dir: log_dir.path().to_path_buf(),
+        working_dir: working_dir.path().to_path_buf(),
+        ..Default::default()
+    });
+
+    // a protocol 1 bridge, it gets the attributes as env vars
+    let pkg = crate::input::PkgDeclaration {
+        name: "tool".to_string(),
+        input: "tool".to_string(),
+        attributes: std::collections::HashMap::from([(
+            "API_Token".to_string(),
+            crate::input::AttributeValue::String("hunter2".to_string()),
+        )]),
+        tags: Vec::new(),
+        bridge: Some(crate::input::BridgeOverride::Exec(
+            "echo \"using $API_Token\" >&2\necho \"no output for $API_Token\"\n".to_string(),
+        )),
+    };
+
+    let err = bridge_api.install("dotfiles", &pkg).unwrap_err();
+    let BridgeApiError::BridgeWrongOutput(output) = err else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(
+        output.trim(),
+        format!("no output for {}", crate::secrets::REDACTED)
+    );
+
+    let log = std::fs::read_to_string(log_dir.path().join("dotfiles.log")).unwrap();
+    assert!(log.contains("using <redacted>"));
+    assert!(!log.contains("hunter2"));
+}
+
+#[test]
+fn the_info_of_a_bridge_installs_nothing() {
+    #[derive(Debug)]
+    struct Mock;
+
+    impl BridgeBackend for Mock {
+        fn execute(
+            &self,
+            operation: &Operation,
+            declaration: &crate::input::PkgDeclaration,
+            _ctx: &OperationContext,
+        ) -> std::io::Result<OperationOutcome> {
+            assert_eq!(operation, &Operation::Info);
+
+            Ok(match declaration.input.as_str() {
+                "fd" => OperationOutcome {
+                    code: 0,
+                    stdout:
+                        b"10.2.0\ndescription,a simple, fast alternative to find\nlicense,MIT\n"
+                            .to_vec(),
+                    stderr: Vec::new(),
+                },
+                _ => OperationOutcome {
+                    code: 1,
+                    stdout: Vec::new(),
+                    stderr: b"__ERR unsupported-op no info here\n".to_vec(),
+                },
+            })
+        }
+    }
+
+    let db_file = NamedTempFile::new().unwrap();
+    let working_dir = tempfile::tempdir().unwrap();
+    let log_dir = tempfile::tempdir().unwrap();
+
+    let bridge_api = BridgeApi::new(
+        std::path::PathBuf::from("examples/assets/bridges"),
+        &[],
+        std::rc::Rc::new(crate::db::Db::new(&db_file.path().to_path_buf()).unwrap()),
+    )
+    .unwrap()
+    .with_options(BridgeOptions {
+        log_dir: log_dir.path().to_path_buf(),
+        working_dir: working_dir.path().to_path_buf(),
+        ..Default::default()
+    })
+    .with_backend("mock", Mock);
+
+    let pkg = |input: &str| crate::input::PkgDeclaration {
+        name: input.to_string(),
+        input: input.to_string(),
+        attributes: Default::default(),
+        tags: Vec::new(),
+        bridge: None,
+    };
+
+    let info = bridge_api.info("mock", &pkg("fd")).unwrap();
+    assert_eq!(info.version.to_string(), "10.2.0");
+    assert_eq!(
+        info.metadata.description.as_deref(),
+        Some("a simple, fast alternative to find")
+    );
+    assert_eq!(info.metadata.license.as_deref(), Some("MIT"));
+    assert!(
+        !working_dir
+            .path()
+            .join("mock")
+            .join("fd")
+            .read_dir()
+            .unwrap()
+            .any(|_| true)
+    );
+
+    assert!(matches!(
+        bridge_api.info("mock", &pkg("rg")),
+        Err(BridgeApiError::UnsupportedOperation(_))
+    ));
+}
+
+#[test]
+fn a_sandbox_path_cant_leave_the_work_dir() {
+    let work_dir = tempfile::tempdir().unwrap();
+    let outside = tempfile::tempdir().unwrap();
+    std::fs::create_dir(work_dir.path().join("pkg")).unwrap();
+    std::fs::write(work_dir.path().join("pkg/bin"), "").unwrap();
+    let root = work_dir.path().canonicalize().unwrap();
+
+    assert_eq!(
+        sandbox_path(work_dir.path(), "/pkg/bin").unwrap(),
+        root.join("pkg/bin")
+    );
+    assert_eq!(
+        sandbox_path(work_dir.path(), "pkg/bin").unwrap(),
+        root.join("pkg/bin")
+    );
+    // it's checked later that it's there
+    assert_eq!(
+        sandbox_path(work_dir.path(), "/missing").unwrap(),
+        root.join("missing")
+    );
+
+    for path in ["/../etc/passwd", "pkg/../../x", ".."] {
+        assert!(matches!(
+            sandbox_path(work_dir.path(), path),
+            Err(SandboxEscape(escaped)) if escaped == std::path::Path::new(path)
+        ));
+    }
+
+    // a link the bridge made to the outside
+    #[cfg(unix)]
+    {
+        std::fs::write(outside.path().join("file"), "").unwrap();
+        std::os::unix::fs::symlink(outside.path(), work_dir.path().join("out")).unwrap();
+        assert!(sandbox_path(work_dir.path(), "/out/file").is_err());
+    }
+    #[cfg(not(unix))]
+    let _ = outside;
+}
+
+#[cfg(feature = "wasm_bridges")]
+#[test]
+fn the_wasm_output_cant_chmod_out_of_the_sandbox() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = tempfile::tempdir().unwrap();
+    let work_dir = root.path().join("work");
+    std::fs::create_dir(&work_dir).unwrap();
+    let outside = root.path().join("outside");
+    std::fs::write(&outside, "").unwrap();
+    std::fs::set_permissions(&outside, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+    let err = crate::bridge::wasm::to_host_output("/../outside,1.0.0\n", &work_dir).unwrap_err();
+    assert!(
+        err.get_ref()
+            .and_then(|inner| inner.downcast_ref::<SandboxEscape>())
+            .is_some()
+    );
+    assert_eq!(
+        outside.metadata().unwrap().permissions().mode() & 0o777,
+        0o644
+    );
+
+    std::fs::write(work_dir.join("bin"), "").unwrap();
+    let output = crate::bridge::wasm::to_host_output("/bin,1.0.0\n", &work_dir).unwrap();
+    let bin = work_dir.canonicalize().unwrap().join("bin");
+    assert_eq!(output, format!("{},1.0.0", bin.display()));
+    assert_eq!(bin.metadata().unwrap().permissions().mode() & 0o755, 0o755);
+}
+
+#[test]
+fn an_install_that_left_no_pkg_is_an_error() {
+    let work_dir = std::path::PathBuf::from("/var/lib/pkg/work/cargo-bat");
+    let output = OperationOutput {
+        pkg: None,
+        work_dir: work_dir.clone(),
+    };
+
+    assert!(matches!(
+        output.into_pkg(),
+        Err(BridgeApiError::NoPkgReturned(dir)) if dir == work_dir
+    ));
+}
@@ -0,0 +1,230 @@
+use std::rc::Rc;
+
+use tempfile::NamedTempFile;
+
+use crate::{
+    bridge::{
+        BridgeApi, BridgeBackend, BridgeOptions, Operation, OperationContext, OperationOutcome,
+    },
+    build::*,
+    db::Db,
+    event::{Event, Step},
+    fs::Fs,
+    hooks::Hooks,
+    input::PkgDeclaration,
+    license::LicensePolicy,
+    systemd::Systemctl,
+};
+
+// installs anything but `broken`, and removes
+#[derive(Debug)]
+struct Mock;
+
+impl BridgeBackend for Mock {
+    fn execute(
+        &self,
+        operation: &Operation,
+        declaration: &PkgDeclaration,
+        ctx: &OperationContext,
+    ) -> std::io::Result<OperationOutcome> {
+        use std::os::unix::fs::PermissionsExt;
+
+        if declaration.name == "broken" {
+            return Ok(OperationOutcome {
+                code: 3,
+                stdout: Vec::new(),
+                stderr: b"no such pkg".to_vec(),
+            });
+        }
+
+        let stdout = match operation {
+            Operation::Remove => Vec::new(),
+            _ => {
+                let pkg = ctx.work_dir.join(&declaration.input);
+                std::fs::write(&pkg, "#!/bin/sh\n")?;
+                std::fs::set_permissions(&pkg, std::fs::Permissions::from_mode(0o755))?;
+                format!("./{},1.0.0", declaration.input).into_bytes()
+            }
+        };
+
+        Ok(OperationOutcome {
+            code: 0,
+            stdout,
+            stderr: Vec::new(),
+        })
+    }
+}
+
+fn declaration(name: &str) -> PkgDeclaration {
+    PkgDeclaration {
+        name: name.to_string(),
+        input: name.to_string(),
+        attributes: Default::default(),
+        tags: Vec::new(),
+        bridge: None,
+    }
+}
+
+#[test]
+fn the_jobs_of_a_bridge_are_told_to_the_sink() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let log_dir = root.path().join("log");
+
+    let bridge_api = BridgeApi::new(
+        std::path::PathBuf::from("examples/assets/bridges"),
+        &[],
+        db.clone(),
+    )
+    .unwrap()
+    .with_options(BridgeOptions {
+        log_dir: log_dir.clone(),
+        working_dir: root.path().join("work"),
+        ..Default::default()
+    })
+    .with_backend("mock", Mock);
+    let fs = Fs::new(root.path().join("opt"), root.path().join("bin"), db.clone()).unwrap();
+    let systemctl = Systemctl::default();
+    let hooks = Hooks::default();
+    let licenses = LicensePolicy::default();
+
+    let build = Build {
+        db: &db,
+        fs: &fs,
+        bridge_api: &bridge_api,
+        systemctl: &systemctl,
+        hooks: &hooks,
+        licenses: &licenses,
+        allow_license_violations: false,
+        force: false,
+        log_dir: &log_dir,
+    };
+
+    let to_install = [declaration("tool"), declaration("broken")];
+    let mut outcome = BuildOutcome::default();
+    let mut events: Vec<Event> = Vec::new();
+    build
+        .run_bridge(
+            BridgeJobs {
+                bridge: "mock",
+                jobs: vec![(Job::Install, &to_install), (Job::Remove, &[])],
+                skipped: vec![("arm-tool".to_string(), "for aarch64".to_string())],
+                install: 2,
+                remove: 0,
+                update: 0,
+            },
+            &mut outcome,
+            &mut events,
+        )
+        .unwrap();
+    build.finish(&outcome, &mut events).unwrap();
+
+    let Event::PackageFailed {
+        step, exit_code, ..
+    } = &events[7]
+    else {
+        panic!("expected the failure of broken, got {:?}", events[7]);
+    };
+    assert_eq!((*step, *exit_code), (Step::BridgeOperation, Some(3)));
+    events.remove(7);
+    assert_eq!(
+        events,
+        [
+            Event::BridgeStarted {
+                bridge: "mock".to_string(),
+                install: 2,
+                remove: 0,
+                update: 0,
+            },
+            Event::PackageSkipped {
+                name: "arm-tool".to_string(),
+                reason: "for aarch64".to_string(),
+            },
+            Event::JobStarted {
+                job: "install".to_string(),
+                pkgs: 2,
+            },
+            Event::PackageStarted {
+                name: "tool".to_string(),
+                index: 1,
+                total: 2,
+            },
+            Event::PackageStoring {
+                name: "tool".to_string(),
+            },
+            Event::PackageInstalled {
+                name: "tool".to_string(),
+            },
+            Event::PackageStarted {
+                name: "broken".to_string(),
+                index: 2,
+                total: 2,
+            },
+            // the empty remove job isn't told
+            Event::JobDone,
+            Event::LinkStarted,
+            Event::LinkDone { pruned: Vec::new() },
+        ]
+    );
+    assert_eq!((outcome.installed, outcome.removed), (1, 0));
+    assert!(root.path().join("opt/mock/tool").is_file());
+    assert!(root.path().join("bin/tool").exists());
+    assert_eq!(db.get_pkgs().unwrap().len(), 1);
+
+    // the pkg isn't declared anymore
+    let to_remove = [declaration("tool")];
+    let mut outcome = BuildOutcome::default();
+    let mut events: Vec<Event> = Vec::new();
+    build
+        .run_bridge(
+            BridgeJobs {
+                bridge: "mock",
+                jobs: vec![(Job::Remove, &to_remove)],
+                skipped: Vec::new(),
+                install: 0,
+                remove: 1,
+                update: 0,
+            },
+            &mut outcome,
+            &mut events,
+        )
+        .unwrap();
+
+    assert_eq!(
+        events[2..],
+        [
+            Event::PackageStarted {
+                name: "tool".to_string(),
+                index: 1,
+                total: 1,
+            },
+            Event::PackageStoring {
+                name: "tool".to_string(),
+            },
+            Event::PackageRemoved {
+                name: "tool".to_string(),
+            },
+            Event::JobDone,
+        ]
+    );
+    assert_eq!((outcome.installed, outcome.removed), (0, 1));
+    assert!(!root.path().join("opt/mock/tool").exists());
+    assert!(db.get_pkgs().unwrap().is_empty());
+}
+
+#[test]
+fn the_remaining_time_is_estimated_from_the_known_durations() {
+    use std::time::Duration;
+
+    assert_eq!(estimate_remaining(&[None, None]), None);
+    // the unknown one takes the average of the known ones
+    assert_eq!(
+        estimate_remaining(&[
+            Some(Duration::from_secs(2)),
+            None,
+            Some(Duration::from_secs(4))
+        ]),
+        Some(Duration::from_secs(9))
+    );
+}
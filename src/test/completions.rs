@@ -0,0 +1,25 @@
+use crate::{cmd::Shell, completions::*};
+
+fn script(shell: Shell) -> String {
+    let mut out = Vec::new();
+    generate(&shell, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+// the scripts of clap are patched, so a new clap shouldn't silently drop it
+#[test]
+fn the_scripts_complete_the_installed_pkgs() {
+    let names = format!("pkg {PKG_NAMES_COMMAND}");
+
+    for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::Nushell] {
+        assert!(script(shell).contains(&names));
+    }
+
+    let zsh = script(Shell::Zsh);
+    assert!(zsh.contains("compdef _pkg_with_pkg_names pkg"));
+    assert!(!zsh.contains("compdef _pkg pkg"));
+
+    let nushell = script(Shell::Nushell);
+    assert!(nushell.contains("...packages: string@\"nu-complete pkg installed\""));
+    assert!(nushell.contains("package: string@\"nu-complete pkg installed\""));
+}
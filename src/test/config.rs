@@ -0,0 +1,277 @@
+use std::path::PathBuf;
+
+use crate::config::*;
+
+const CONFIG: &str = r#"
+config {
+  inputs {
+    path "/etc/pkg"
+    bridges-set "/etc/pkg/.bridges"
+  }
+  output {
+    target-dir "/opt/pkg"
+    load-path "/usr/local/pkg"
+  }
+  db {
+    path "/var/db/pkg/packages.db"
+  }
+  profiles {
+    work
+    user {
+      inputs {
+        path "/home/me/pkg"
+      }
+      output {
+        load-path "/home/me/.local/bin"
+      }
+      db {
+        path "/home/me/.local/state/pkg.db"
+      }
+    }
+  }
+}
+"#;
+
+#[test]
+fn a_profile_overrides_the_config_node_by_node() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".config");
+    std::fs::write(&path, CONFIG).unwrap();
+
+    let system = Config::load(path.clone()).unwrap();
+    assert_eq!(system.load_path, PathBuf::from("/usr/local/pkg"));
+    assert_eq!(system.profile, None);
+
+    let user = Config::load_profile(path.clone(), Some("user".to_string())).unwrap();
+    assert_eq!(user.source_dir, PathBuf::from("/home/me/pkg"));
+    assert_eq!(user.bridges_set, PathBuf::from("/etc/pkg/.bridges"));
+    assert_eq!(user.target_dir, PathBuf::from("/opt/pkg"));
+    assert_eq!(user.load_path, PathBuf::from("/home/me/.local/bin"));
+    assert_eq!(user.db_path, PathBuf::from("/home/me/.local/state/pkg.db"));
+    assert_eq!(user.profile.as_deref(), Some("user"));
+
+    // only for the `when` of the inputs
+    let work = Config::load_profile(path.clone(), Some("work".to_string())).unwrap();
+    assert_eq!(work.db_path, system.db_path);
+
+    let Err(ConfigError::UnknownProfile { known, .. }) =
+        Config::load_profile(path, Some("home".to_string()))
+    else {
+        panic!("expected an unknown profile");
+    };
+    assert_eq!(known, "work, user");
+}
+
+#[test]
+fn the_problems_are_reported_at_once_and_the_paths_have_defaults() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".config");
+
+    std::fs::write(&path, "config {\n  db {\n    trace #true\n  }\n}\n").unwrap();
+    let config = Config::load(path.clone()).unwrap();
+    assert_eq!(config.source_dir, dir.path());
+    assert_eq!(config.bridges_set, dir.path().join(".bridges"));
+    assert_eq!(config.load_path, PathBuf::from("/usr/local/pkg"));
+    assert_eq!(config.db_path, PathBuf::from("/var/db/pkg/packages.db"));
+    // the host picks them
+    assert_eq!(config.log_dir, None);
+    assert_eq!(config.work_dir, None);
+    assert_eq!(config.cache_dir, None);
+    assert!(config.licenses.is_empty());
+
+    // what `pkg config show` prints is loaded back the same
+    std::fs::write(&path, config.to_kdl().to_string()).unwrap();
+    let shown = Config::load(path.clone()).unwrap();
+    assert_eq!(shown.source_dir, config.source_dir);
+    assert!(shown.trace_db);
+
+    std::fs::write(
+        &path,
+        "config {\n  licenses {\n    allow \"MIT\" \"Apache-2.0\"\n  }\n}\n",
+    )
+    .unwrap();
+    let config = Config::load(path.clone()).unwrap();
+    assert_eq!(config.licenses.allow, ["MIT", "Apache-2.0"]);
+    std::fs::write(&path, config.to_kdl().to_string()).unwrap();
+    assert_eq!(
+        Config::load(path.clone()).unwrap().licenses,
+        config.licenses
+    );
+
+    std::fs::write(
+        &path,
+        "config {\n  schedule {\n    jitter \"2h\"\n    only-unpinned #true\n  }\n}\n",
+    )
+    .unwrap();
+    let config = Config::load(path.clone()).unwrap();
+    assert_eq!(
+        config.schedule.jitter,
+        std::time::Duration::from_secs(2 * 60 * 60)
+    );
+    assert!(config.schedule.only_unpinned);
+    std::fs::write(&path, config.to_kdl().to_string()).unwrap();
+    assert_eq!(
+        Config::load(path.clone()).unwrap().schedule,
+        config.schedule
+    );
+
+    std::fs::write(
+        &path,
+        "config {\n  notify {\n    on \"failure\"\n    webhook \"https://a\"\n    webhook \"https://b\"\n  }\n}\n",
+    )
+    .unwrap();
+    let config = Config::load(path.clone()).unwrap();
+    assert_eq!(config.notify.on, crate::notify::NotifyOn::Failure);
+    assert_eq!(config.notify.webhooks, ["https://a", "https://b"]);
+    std::fs::write(&path, config.to_kdl().to_string()).unwrap();
+    assert_eq!(Config::load(path.clone()).unwrap().notify, config.notify);
+
+    std::fs::write(&path, "config {\n  escalate \"doas\"\n}\n").unwrap();
+    let config = Config::load(path.clone()).unwrap();
+    assert_eq!(config.escalate, Some(crate::privilege::Escalation::Doas));
+    std::fs::write(&path, config.to_kdl().to_string()).unwrap();
+    assert_eq!(
+        Config::load(path.clone()).unwrap().escalate,
+        config.escalate
+    );
+    std::fs::write(&path, "config {\n  escalate \"su\"\n}\n").unwrap();
+    assert!(Config::load(path.clone()).is_err());
+
+    std::fs::write(&path, "config {\n  log-dir \"~/pkg/log\"\n}\n").unwrap();
+    let mut config = Config::load(path.clone()).unwrap();
+    let log_dir = PathBuf::from(std::env::var("HOME").unwrap()).join("pkg/log");
+    assert_eq!(config.log_dir.as_ref(), Some(&log_dir));
+    config.set_root(PathBuf::from("/mnt/new"));
+    assert_eq!(
+        config.log_dir,
+        Some(under_root(std::path::Path::new("/mnt/new"), &log_dir))
+    );
+
+    std::fs::write(
+        &path,
+        "config {\n  output {\n    load-pth \"/x\"\n    link-strategy \"hard\"\n  }\n  db {\n    path 1\n  }\n}\n",
+    )
+    .unwrap();
+    let Err(ConfigError::Invalid { count, problems }) = Config::load(path) else {
+        panic!("expected all the problems");
+    };
+    assert_eq!(count, 3);
+    assert!(matches!(
+        problems.as_slice(),
+        [
+            ConfigError::UnknownNode { .. },
+            ConfigError::InvalidValue {
+                name: "link-strategy",
+                ..
+            },
+            ConfigError::InvalidPath { .. },
+        ]
+    ));
+}
+
+#[test]
+fn set_keeps_the_rest_of_the_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".config");
+    std::fs::write(
+        &path,
+        "// my config\nconfig {\n  output {\n    target-dir \"/opt/pkg\" // here\n  }\n  db {\n    path \"/x.db\"\n  }\n}\n",
+    )
+    .unwrap();
+
+    set(&path, "output.target-dir", &["/srv/pkg".to_string()]).unwrap();
+    set(&path, "output.load-path", &["/srv/bin".to_string()]).unwrap();
+    set(&path, "bridges.retries", &["2".to_string()]).unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "// my config\nconfig {\n  output {\n    target-dir \"/srv/pkg\" // here\n    load-path \"/srv/bin\"\n  }\n  db {\n    path \"/x.db\"\n  }\n  bridges {\n    retries 2\n  }\n}\n"
+    );
+    let config = Config::load(path.clone()).unwrap();
+    assert_eq!(config.get("output.target-dir").as_deref(), Some("/srv/pkg"));
+    assert_eq!(config.get("bridges.retries").as_deref(), Some("2"));
+    assert_eq!(config.get("output.nope"), None);
+
+    // what doesn't load isn't written
+    let before = std::fs::read_to_string(&path).unwrap();
+    assert!(set(&path, "output.link-strategy", &["hard".to_string()]).is_err());
+    assert!(set(&path, "bridges.retries", &["two".to_string()]).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), before);
+}
+
+#[test]
+fn the_bridge_defaults_go_under_the_pkg_attributes() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.kdl");
+    std::fs::write(
+        &path,
+        "config {\n  vars {\n    token \"t0k3n\"\n  }\n  bridges {\n    defaults {\n      github {\n        asset-pattern \"*linux*\"\n        token \"${token}\"\n        assets \"musl\" \"x64\"\n      }\n    }\n  }\n}\n",
+    )
+    .unwrap();
+    let config = Config::load(path.clone()).unwrap();
+    let github = &config.bridge_defaults["github"];
+    assert_eq!(
+        github["token"],
+        crate::input::AttributeValue::String("t0k3n".to_string())
+    );
+    std::fs::write(&path, config.to_kdl().to_string()).unwrap();
+    assert_eq!(
+        Config::load(path.clone()).unwrap().bridge_defaults,
+        config.bridge_defaults
+    );
+
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(
+        inputs.path().join("a.kdl"),
+        "github {\n  fd \"sharkdp/fd\" asset-pattern=\"*musl*\"\n}\ncargo {\n  bat\n}\n",
+    )
+    .unwrap();
+    let mut input = crate::input::Input::load(&inputs.path().to_path_buf()).unwrap();
+    input.apply_defaults(&config.bridge_defaults);
+
+    let pkg = |bridge: &str| {
+        &input
+            .bridges
+            .iter()
+            .find(|b| b.name == bridge)
+            .unwrap()
+            .pkgs[0]
+            .attributes
+    };
+
+    // the pkg's own attribute wins
+    let fd = pkg("github");
+    assert_eq!(
+        fd["asset-pattern"],
+        crate::input::AttributeValue::String("*musl*".to_string())
+    );
+    assert_eq!(fd["token"], github["token"]);
+    assert!(pkg("cargo").is_empty());
+}
+
+#[test]
+fn an_instance_has_the_defaults_of_its_bridge_under_its_own() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.kdl");
+    std::fs::write(
+        &path,
+        "config {\n  bridges {\n    defaults {\n      github {\n        asset-pattern \"*linux*\"\n        token \"public\"\n      }\n    }\n    instances {\n      github-work \"github\" {\n        token \"work\"\n        proxy \"http://proxy:3128\"\n      }\n      github-public \"github\"\n    }\n  }\n}\n",
+    )
+    .unwrap();
+    let config = Config::load(path.clone()).unwrap();
+    assert_eq!(config.bridge_aliases["github-work"], "github");
+
+    let string = |s: &str| crate::input::AttributeValue::String(s.to_string());
+    let work = &config.bridge_defaults["github-work"];
+    assert_eq!(work["token"], string("work"));
+    assert_eq!(work["asset-pattern"], string("*linux*"));
+    assert_eq!(
+        config.bridge_defaults["github-public"],
+        config.bridge_defaults["github"]
+    );
+
+    std::fs::write(&path, config.to_kdl().to_string()).unwrap();
+    let reloaded = Config::load(path).unwrap();
+    assert_eq!(reloaded.bridge_aliases, config.bridge_aliases);
+    assert_eq!(reloaded.bridge_defaults, config.bridge_defaults);
+}
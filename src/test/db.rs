@@ -3,6 +3,44 @@ use tempfile::NamedTempFile;
 #[cfg(test)]
 use crate::db::*;
 
+#[test]
+fn a_reopened_db_is_not_checked_or_migrated_again() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("pkg.db");
+    let db = Db::new(&db_path).unwrap();
+    let pkg = Pkg {
+        name: "pkg1".into(),
+        version: Version::parse("1.0.0").unwrap(),
+        path: "some/path".into(),
+        pkg_type: PkgType::SingleExecutable,
+        artifacts: Vec::new(),
+        metadata: PkgMetadata::default(),
+    };
+    db.install_bridge_pkgs(&[&pkg], &"bridge".to_string())
+        .unwrap();
+
+    let reopened = Db::reopen(&db_path).unwrap();
+    assert_eq!(reopened.get_pkgs().unwrap().len(), 1);
+    let foreign_keys: bool = reopened
+        .conn
+        .query_row("PRAGMA foreign_keys;", [], |row| row.get(0))
+        .unwrap();
+    assert!(foreign_keys);
+
+    // nothing is created in a db that isn't one of pkg
+    let bare = dir.path().join("bare.db");
+    rusqlite::Connection::open(&bare)
+        .unwrap()
+        .execute_batch("CREATE TABLE other (x);")
+        .unwrap();
+    let reopened = Db::reopen(&bare).unwrap();
+    let tables: i64 = reopened
+        .conn
+        .query_row("SELECT count(*) FROM sqlite_master;", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(tables, 1);
+}
+
 #[test]
 fn init_and_install() {
     let db_file = NamedTempFile::new().unwrap();
@@ -16,6 +54,8 @@ fn init_and_install() {
         },
         path: "some/path".into(),
         pkg_type: PkgType::SingleExecutable,
+        artifacts: Vec::new(),
+        metadata: PkgMetadata::default(),
     }];
 
     assert!(db.install_bridge_pkgs(&pkgs, &"bridge".to_string()).is_ok());
@@ -46,6 +86,8 @@ fn remove_pkgs() {
             },
             path: "some/path".into(),
             pkg_type: PkgType::SingleExecutable,
+            artifacts: Vec::new(),
+            metadata: PkgMetadata::default(),
         },
         &Pkg {
             name: "pkg2".into(),
@@ -56,6 +98,8 @@ fn remove_pkgs() {
             },
             path: "some/path".into(),
             pkg_type: PkgType::SingleExecutable,
+            artifacts: Vec::new(),
+            metadata: PkgMetadata::default(),
         },
     ];
 
@@ -77,3 +121,692 @@ fn remove_pkgs() {
     let installed = db.get_pkgs().unwrap();
     assert_eq!(installed.len(), 0);
 }
+
+#[test]
+fn average_durations() {
+    use std::time::Duration;
+
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Db::new(&db_file.path().to_path_buf()).unwrap();
+
+    db.record_duration("pkg1", "install", Duration::from_millis(1000))
+        .unwrap();
+    db.record_duration("pkg1", "install", Duration::from_millis(3000))
+        .unwrap();
+    db.record_duration("pkg1", "remove", Duration::from_millis(10))
+        .unwrap();
+
+    let durations = db.get_average_durations("install").unwrap();
+    assert_eq!(durations.get("pkg1"), Some(&Duration::from_millis(2000)));
+    assert_eq!(durations.len(), 1);
+}
+
+#[test]
+fn which_pkgs_are_not_installed() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Db::new(&db_file.path().to_path_buf()).unwrap();
+    let pkgs = [&Pkg {
+        name: "pkg1".into(),
+        version: Version {
+            first_cell: "1".into(),
+            second_cell: "2".into(),
+            third_cell: "3".into(),
+        },
+        path: "some/path".into(),
+        pkg_type: PkgType::SingleExecutable,
+        artifacts: Vec::new(),
+        metadata: PkgMetadata::default(),
+    }];
+
+    db.install_bridge_pkgs(&pkgs, &"bridge".to_string())
+        .unwrap();
+
+    let names = ["pkg1".to_string(), "pkg2".to_string()];
+    let not_installed = db.which_pkgs_are_not_installed(&names).unwrap();
+
+    assert_eq!(not_installed, vec![&"pkg2".to_string()]);
+}
+
+#[test]
+fn snapshot() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Db::new(&db_file.path().to_path_buf()).unwrap();
+    let pkg = |name: &str| Pkg {
+        name: name.into(),
+        version: Version {
+            first_cell: "1".into(),
+            second_cell: "2".into(),
+            third_cell: "3".into(),
+        },
+        path: "some/path".into(),
+        pkg_type: PkgType::SingleExecutable,
+        artifacts: Vec::new(),
+        metadata: PkgMetadata::default(),
+    };
+
+    db.install_bridge_pkgs(&[&pkg("pkg2"), &pkg("pkg1")], &"bridge".to_string())
+        .unwrap();
+    db.install_bridge_pkgs(&[&pkg("pkg3")], &"other".to_string())
+        .unwrap();
+
+    let snapshot = db.snapshot().unwrap();
+
+    assert!(snapshot.is_installed("pkg3"));
+    assert!(!snapshot.is_installed("pkg4"));
+    assert_eq!(snapshot.get("pkg3").unwrap().bridge, "other");
+    assert!(snapshot.get("pkg3").unwrap().installed_at > 0);
+    assert_eq!(
+        snapshot
+            .pkgs_by_bridge("bridge")
+            .iter()
+            .map(|r| r.pkg.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["pkg1", "pkg2"]
+    );
+    assert_eq!(snapshot.bridges(), vec!["bridge", "other"]);
+}
+
+#[test]
+fn versions_are_compared_as_numbers() {
+    let version = |first: &str, second: &str, third: &str| Version {
+        first_cell: first.into(),
+        second_cell: second.into(),
+        third_cell: third.into(),
+    };
+
+    assert!(
+        version("1", "10", "0")
+            .compare(&version("1", "9", "0"))
+            .is_gt()
+    );
+    assert!(
+        version("1", "2", "3")
+            .compare(&version("1", "2", "3"))
+            .is_eq()
+    );
+    assert!(
+        version("1", "2", "beta")
+            .compare(&version("1", "2", "rc"))
+            .is_lt()
+    );
+
+    assert!(
+        Version::parse("1.2.3")
+            .unwrap()
+            .compare(&version("1", "2", "3"))
+            .is_eq()
+    );
+    assert!(Version::parse("1.2").is_none());
+}
+
+#[test]
+fn backups_are_restored_and_rotated() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("packages.db");
+    let mut db = Db::new(&db_path).unwrap();
+    let pkg = Pkg {
+        name: "pkg1".into(),
+        version: Version {
+            first_cell: "1".into(),
+            second_cell: "2".into(),
+            third_cell: "3".into(),
+        },
+        path: "some/path".into(),
+        pkg_type: PkgType::SingleExecutable,
+        artifacts: Vec::new(),
+        metadata: PkgMetadata::default(),
+    };
+
+    db.install_bridge_pkgs(&[&pkg], &"bridge".to_string())
+        .unwrap();
+    let backup = backup_rotating(&db_path, 2).unwrap().unwrap();
+
+    db.remove_pkgs(&["pkg1".to_string()]).unwrap();
+    assert!(db.get_pkgs().unwrap().is_empty());
+
+    db.restore(&backup).unwrap();
+    assert_eq!(db.get_pkgs().unwrap()[0].name, "pkg1");
+
+    for _ in 0..3 {
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        backup_rotating(&db_path, 2).unwrap();
+    }
+    let backups = list_backups(&db_path).unwrap();
+    assert_eq!(backups.len(), 2);
+    assert!(!backups.contains(&backup));
+
+    assert!(matches!(
+        db.restore(&dir.path().join("nope.db")),
+        Err(DbError::BackupNotFound(_))
+    ));
+}
+
+#[test]
+fn the_last_build_is_recorded() {
+    use std::time::Duration;
+
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Db::new(&db_file.path().to_path_buf()).unwrap();
+
+    assert!(db.last_build().unwrap().is_none());
+
+    db.record_build(2, 0, 0, Duration::from_secs(4)).unwrap();
+    db.record_build(1, 3, 2, Duration::from_millis(1500))
+        .unwrap();
+
+    let build = db.last_build().unwrap().unwrap();
+    assert_eq!((build.installed, build.removed), (1, 3));
+    assert_eq!(build.failures, 2);
+    assert_eq!(build.duration, Duration::from_millis(1500));
+    assert!(build.finished_at > 0);
+}
+
+#[test]
+fn the_cache_keys_go_with_the_pkg() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Db::new(&db_file.path().to_path_buf()).unwrap();
+
+    assert_eq!(db.get_cache_key("pkg1", "install").unwrap(), None);
+
+    db.set_cache_key("pkg1", "install", "old").unwrap();
+    db.set_cache_key("pkg1", "install", "new").unwrap();
+    db.set_cache_key("pkg1", "update", "up").unwrap();
+    assert_eq!(
+        db.get_cache_key("pkg1", "install").unwrap().as_deref(),
+        Some("new")
+    );
+
+    db.remove_pkgs(&["pkg1".to_string()]).unwrap();
+    assert_eq!(db.get_cache_key("pkg1", "install").unwrap(), None);
+    assert_eq!(db.get_cache_key("pkg1", "update").unwrap(), None);
+}
+
+#[test]
+fn an_update_replaces_the_row_in_place() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Db::new(&db_file.path().to_path_buf()).unwrap();
+    let pkg = |version: &str, path: &str| Pkg {
+        name: "pkg1".into(),
+        version: Version::parse(version).unwrap(),
+        path: path.into(),
+        pkg_type: PkgType::SingleExecutable,
+        artifacts: Vec::new(),
+        metadata: PkgMetadata::default(),
+    };
+
+    db.install_bridge_pkgs(&[&pkg("1.0.0", "old/path")], &"bridge".to_string())
+        .unwrap();
+    db.set_pkg_tags("pkg1", &["dev".to_string()]).unwrap();
+    db.set_cache_key("pkg1", "install", "key").unwrap();
+
+    db.update_pkg(&pkg("1.1.0", "new/path"), "bridge").unwrap();
+
+    let updated = db.get_pkgs_by_name(&["pkg1".to_string()]).unwrap();
+    assert_eq!(updated.len(), 1);
+    assert_eq!(updated[0].version.to_string(), "1.1.0");
+    assert_eq!(updated[0].path, std::path::PathBuf::from("new/path"));
+
+    // what's not the pkg itself stays
+    let snapshot = db.snapshot().unwrap();
+    assert_eq!(snapshot.get("pkg1").unwrap().tags, vec!["dev"]);
+    assert_eq!(
+        db.get_cache_key("pkg1", "install").unwrap().as_deref(),
+        Some("key")
+    );
+
+    // it's an install when the pkg isn't there
+    db.remove_pkgs(&["pkg1".to_string()]).unwrap();
+    db.update_pkg(&pkg("1.1.0", "new/path"), "bridge").unwrap();
+    assert!(db.snapshot().unwrap().is_installed("pkg1"));
+}
+
+#[test]
+fn the_metadata_is_kept_with_the_pkg() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Db::new(&db_file.path().to_path_buf()).unwrap();
+
+    let mut metadata = PkgMetadata::default();
+    assert!(metadata.parse_line("description,a fast find, in rust"));
+    assert!(metadata.parse_line("license,MIT OR Apache-2.0"));
+    assert!(metadata.parse_line("homepage,"));
+    assert!(!metadata.parse_line("systemd-unit,./app.service"));
+    assert_eq!(
+        metadata.description.as_deref(),
+        Some("a fast find, in rust")
+    );
+    assert_eq!(metadata.homepage, None);
+
+    let pkg = Pkg {
+        name: "fd".into(),
+        version: Version::parse("10.2.0").unwrap(),
+        path: "some/path".into(),
+        pkg_type: PkgType::SingleExecutable,
+        artifacts: Vec::new(),
+        metadata: metadata.clone(),
+    };
+    db.install_bridge_pkgs(&[&pkg], &"bridge".to_string())
+        .unwrap();
+    assert_eq!(db.get_pkgs().unwrap()[0].metadata, metadata);
+
+    // an update without metadata clears it, it's what the bridge says now
+    db.update_pkg(
+        &Pkg {
+            metadata: PkgMetadata::default(),
+            ..pkg
+        },
+        "bridge",
+    )
+    .unwrap();
+    assert!(
+        db.snapshot()
+            .unwrap()
+            .get("fd")
+            .unwrap()
+            .pkg
+            .metadata
+            .is_empty()
+    );
+}
+
+#[test]
+fn the_installed_pkgs_are_diffed_with_the_inputs() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Db::new(&db_file.path().to_path_buf()).unwrap();
+    let pkg = |name: &str, version: &str| Pkg {
+        name: name.into(),
+        version: Version::parse(version).unwrap(),
+        path: "some/path".into(),
+        pkg_type: PkgType::SingleExecutable,
+        artifacts: Vec::new(),
+        metadata: PkgMetadata::default(),
+    };
+    db.install_bridge_pkgs(
+        &[&pkg("fd", "10.2.0"), &pkg("bat", "0.24.0")],
+        &"cargo".to_string(),
+    )
+    .unwrap();
+    db.install_bridge_pkgs(&[&pkg("old", "1.0.0")], &"eget".to_string())
+        .unwrap();
+
+    let declaration = |name: &str, version: Option<&str>| crate::input::PkgDeclaration {
+        name: name.to_string(),
+        input: name.to_string(),
+        attributes: version
+            .map(|v| {
+                [(
+                    "version".to_string(),
+                    crate::input::AttributeValue::String(v.to_string()),
+                )]
+                .into()
+            })
+            .unwrap_or_default(),
+        tags: Vec::new(),
+        bridge: None,
+    };
+    let declared = [
+        ("cargo", declaration("fd", Some("10.1.0"))),
+        ("eget", declaration("bat", Some("0.24.0"))),
+        ("cargo", declaration("rg", None)),
+    ];
+
+    let diff = db
+        .snapshot()
+        .unwrap()
+        .diff_inputs(declared.iter().map(|(bridge, pkg)| (*bridge, pkg)));
+
+    assert_eq!(diff.missing, [("cargo".to_string(), "rg".to_string())]);
+    assert_eq!(
+        diff.undeclared,
+        [("eget".to_string(), "old".to_string(), "1.0.0".to_string())]
+    );
+    assert_eq!(
+        diff.bridge_differs,
+        [("bat".to_string(), "cargo".to_string(), "eget".to_string())]
+    );
+    assert_eq!(
+        diff.version_drift,
+        [("fd".to_string(), "10.2.0".to_string(), "10.1.0".to_string())]
+    );
+    assert!(!diff.is_empty());
+}
+
+#[test]
+fn the_auto_pkgs_nothing_needs_are_orphans() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Db::new(&db_file.path().to_path_buf()).unwrap();
+    let pkg = |name: &str| Pkg {
+        name: name.into(),
+        version: Version::parse("1.0.0").unwrap(),
+        path: "some/path".into(),
+        pkg_type: PkgType::SingleExecutable,
+        artifacts: Vec::new(),
+        metadata: PkgMetadata::default(),
+    };
+    db.install_bridge_pkgs(
+        &[&pkg("app"), &pkg("lib"), &pkg("old"), &pkg("old-lib")],
+        &"bridge".to_string(),
+    )
+    .unwrap();
+    db.set_pkg_deps("app", &["lib".to_string(), "gone".to_string()], false)
+        .unwrap();
+    db.set_pkg_deps("lib", &[], true).unwrap();
+    // nothing needs it, so its deps are orphans too
+    db.set_pkg_deps("old", &["old-lib".to_string()], true)
+        .unwrap();
+    db.set_pkg_deps("old-lib", &[], true).unwrap();
+
+    let snapshot = db.snapshot().unwrap();
+    assert_eq!(snapshot.get("app").unwrap().deps, vec!["lib", "gone"]);
+    assert_eq!(
+        snapshot
+            .orphans()
+            .iter()
+            .map(|r| r.pkg.name.as_str())
+            .collect::<Vec<&str>>(),
+        vec!["old", "old-lib"]
+    );
+    assert_eq!(
+        snapshot.broken_deps(),
+        [("app".to_string(), "gone".to_string())]
+    );
+}
+
+#[test]
+fn the_pkgs_of_another_major_version_of_their_bridge_are_found() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Db::new(&db_file.path().to_path_buf()).unwrap();
+    let pkg = |name: &str| Pkg {
+        name: name.into(),
+        version: Version::parse("1.0.0").unwrap(),
+        path: "some/path".into(),
+        pkg_type: PkgType::SingleExecutable,
+        artifacts: Vec::new(),
+        metadata: PkgMetadata::default(),
+    };
+    db.install_bridge_pkgs(
+        &[&pkg("old"), &pkg("new"), &pkg("unversioned"), &pkg("zero")],
+        &"github".to_string(),
+    )
+    .unwrap();
+    db.set_pkg_bridge_version("old", "1.4.2").unwrap();
+    db.set_pkg_bridge_version("new", "2.0.1").unwrap();
+    db.set_pkg_bridge_version("zero", "0.3.0").unwrap();
+
+    let snapshot = db.snapshot().unwrap();
+    assert_eq!(snapshot.get("old").unwrap().bridge_version, "1.4.2");
+    assert_eq!(snapshot.get("unversioned").unwrap().bridge_version, "");
+    assert_eq!(
+        snapshot
+            .installed_by_other_major("github", "2.1.0")
+            .iter()
+            .map(|r| r.pkg.name.as_str())
+            .collect::<Vec<&str>>(),
+        vec!["old", "zero"]
+    );
+
+    assert!(crate::manifest::is_major_change("0.3.0", "0.4.0"));
+    assert!(!crate::manifest::is_major_change("1.4.2", "v1.9.0"));
+    assert!(!crate::manifest::is_major_change("nightly", "2.0.0"));
+}
+
+#[test]
+fn the_pkgs_are_queried_by_the_filter() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Db::new(&db_file.path().to_path_buf()).unwrap();
+    let pkg = |name: &str, version: &str, pkg_type: PkgType| Pkg {
+        name: name.into(),
+        version: Version::parse(version).unwrap(),
+        path: "some/path".into(),
+        pkg_type,
+        artifacts: Vec::new(),
+        metadata: PkgMetadata::default(),
+    };
+    db.install_bridge_pkgs(
+        &[
+            &pkg("fd", "10.2.0", PkgType::SingleExecutable),
+            &pkg("fzf", "0.9.0", PkgType::SingleExecutable),
+            &pkg("nvim", "0.11.0", PkgType::Directory("bin/nvim".into())),
+        ],
+        &"github".to_string(),
+    )
+    .unwrap();
+    db.install_bridge_pkgs(
+        &[&pkg("ripgrep", "14.1.0", PkgType::SingleExecutable)],
+        &"cargo".to_string(),
+    )
+    .unwrap();
+
+    let names = |filter: PkgFilter| {
+        db.query(&filter)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.pkg.name)
+            .collect::<Vec<String>>()
+    };
+
+    assert_eq!(
+        names(PkgFilter::default()),
+        ["fd", "fzf", "nvim", "ripgrep"]
+    );
+    assert_eq!(
+        names(PkgFilter {
+            bridge: Some("github".to_string()),
+            name_glob: Some("f*".to_string()),
+            ..Default::default()
+        }),
+        ["fd", "fzf"]
+    );
+    assert_eq!(
+        names(PkgFilter {
+            pkg_type: Some(PkgKind::Directory),
+            ..Default::default()
+        }),
+        ["nvim"]
+    );
+    // 10.2.0 is after 9.0.0, not before it like the text
+    assert_eq!(
+        names(PkgFilter {
+            min_version: Version::parse("9.0.0"),
+            max_version: Version::parse("12.0.0"),
+            ..Default::default()
+        }),
+        ["fd"]
+    );
+    assert_eq!(
+        names(PkgFilter {
+            names: vec!["ripgrep".to_string(), "fd".to_string()],
+            installed_before: Some(0),
+            ..Default::default()
+        }),
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn a_corrupted_db_is_reported_when_it_is_opened() {
+    let db_file = NamedTempFile::new().unwrap();
+    std::fs::write(
+        db_file.path(),
+        "not a sqlite db, but long enough to have a header",
+    )
+    .unwrap();
+
+    assert!(matches!(
+        Db::new(&db_file.path().to_path_buf()),
+        Err(DbError::Corrupted { .. })
+    ));
+    assert!(matches!(
+        backup_rotating(db_file.path(), KEPT_BACKUPS),
+        Err(DbError::Corrupted { .. })
+    ));
+
+    // a backup brings it back
+    let good = NamedTempFile::new().unwrap();
+    Db::new(&good.path().to_path_buf()).unwrap();
+    Db::open_unchecked(&db_file.path().to_path_buf())
+        .unwrap()
+        .restore(good.path())
+        .unwrap();
+    let db = Db::new(&db_file.path().to_path_buf()).unwrap();
+    db.vacuum().unwrap();
+}
+
+#[test]
+fn the_state_stores_keep_the_same_records() {
+    let record = |name: &str, bridge: &str, version: &str| PkgRecord {
+        pkg: Pkg {
+            name: name.into(),
+            version: Version::parse(version).unwrap(),
+            path: format!("some/{name}").into(),
+            pkg_type: PkgType::Directory(format!("some/{name}/bin").into()),
+            artifacts: vec![Artifact::SystemdUnit("some/unit.service".into())],
+            metadata: PkgMetadata {
+                description: Some("a pkg".to_string()),
+                ..Default::default()
+            },
+        },
+        bridge: bridge.into(),
+        installed_at: 1_700_000_000,
+        tags: vec!["cli".to_string()],
+        deps: vec!["libc".to_string()],
+        auto: true,
+        bridge_version: "1.0.0".to_string(),
+    };
+
+    let db_file = NamedTempFile::new().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("state.json");
+    let stores: Vec<Box<dyn StateStore>> = vec![
+        Box::new(Db::new(&db_file.path().to_path_buf()).unwrap()),
+        Box::new(MemoryStore::new()),
+        Box::new(JsonStore::open(&state_file).unwrap()),
+    ];
+
+    for store in &stores {
+        store.migrate().unwrap();
+        store.insert(record("fd", "github", "10.2.0")).unwrap();
+        store.insert(record("rg", "cargo", "14.1.0")).unwrap();
+        // replaced, not added
+        store.insert(record("rg", "cargo", "14.1.1")).unwrap();
+
+        let fd = store.get("fd").unwrap().unwrap();
+        assert_eq!(fd.bridge, "github");
+        assert_eq!(fd.installed_at, 1_700_000_000);
+        assert_eq!(fd.tags, ["cli"]);
+        assert_eq!(fd.deps, ["libc"]);
+        assert!(fd.auto);
+        assert_eq!(fd.bridge_version, "1.0.0");
+        assert_eq!(fd.pkg.artifacts.len(), 1);
+        assert_eq!(fd.pkg.metadata.description.as_deref(), Some("a pkg"));
+        assert!(matches!(fd.pkg.pkg_type, PkgType::Directory(_)));
+
+        let cargo = store
+            .query(&PkgFilter {
+                bridge: Some("cargo".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(cargo.len(), 1);
+        assert_eq!(cargo[0].pkg.version.to_string(), "14.1.1");
+
+        assert!(store.remove("fd").unwrap());
+        assert!(!store.remove("fd").unwrap());
+        assert!(store.get("fd").unwrap().is_none());
+    }
+
+    // what the json store wrote is read back
+    let reopened = JsonStore::open(&state_file).unwrap();
+    assert_eq!(
+        reopened
+            .query(&PkgFilter::default())
+            .unwrap()
+            .iter()
+            .map(|r| r.pkg.name.as_str())
+            .collect::<Vec<_>>(),
+        ["rg"]
+    );
+
+    std::fs::write(&state_file, r#"{ "version": 99, "packages": [] }"#).unwrap();
+    assert!(matches!(
+        JsonStore::open(&state_file),
+        Err(DbError::BadStateFile { .. })
+    ));
+}
+
+#[test]
+fn a_db_opened_read_only_is_read_and_not_written() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Db::new(&db_file.path().to_path_buf()).unwrap();
+    let pkg = Pkg {
+        name: "fd".into(),
+        version: Version::parse("10.2.0").unwrap(),
+        path: "some/fd".into(),
+        pkg_type: PkgType::SingleExecutable,
+        artifacts: Vec::new(),
+        metadata: PkgMetadata::default(),
+    };
+    db.install_bridge_pkgs(&[&pkg], &"github".to_string())
+        .unwrap();
+
+    // while the writer still has it open, like a build would
+    let read_only = Db::open_read_only(db_file.path()).unwrap();
+    assert!(read_only.snapshot().unwrap().is_installed("fd"));
+    assert!(read_only.remove_pkgs(&["fd".to_string()]).is_err());
+    assert!(db.snapshot().unwrap().is_installed("fd"));
+}
+
+#[test]
+fn the_state_of_another_machine_is_compared_by_name_and_version() {
+    let pkg = |name: &str, version: &str| Pkg {
+        name: name.into(),
+        version: Version::parse(version).unwrap(),
+        path: format!("some/{name}").into(),
+        pkg_type: PkgType::SingleExecutable,
+        artifacts: Vec::new(),
+        metadata: PkgMetadata::default(),
+    };
+
+    let local_file = NamedTempFile::new().unwrap();
+    let local = Db::new(&local_file.path().to_path_buf()).unwrap();
+    local
+        .install_bridge_pkgs(
+            &[
+                &pkg("fd", "10.2.0"),
+                &pkg("bat", "0.24.0"),
+                &pkg("rg", "14.1.0"),
+            ],
+            &"github".to_string(),
+        )
+        .unwrap();
+
+    // the exported db of the other machine
+    let other_file = NamedTempFile::new().unwrap();
+    let other = Db::new(&other_file.path().to_path_buf()).unwrap();
+    other
+        .install_bridge_pkgs(
+            &[
+                &pkg("fd", "10.2.0"),
+                &pkg("bat", "0.25.0"),
+                &pkg("jq", "1.7.1"),
+            ],
+            &"github".to_string(),
+        )
+        .unwrap();
+    drop(other);
+
+    let diff = local
+        .compare(&Db::open_read_only(other_file.path()).unwrap())
+        .unwrap();
+    let names = |pkgs: &[Pkg]| pkgs.iter().map(|p| p.name.clone()).collect::<Vec<_>>();
+
+    assert_eq!(names(&diff.only_local), ["rg"]);
+    assert_eq!(names(&diff.only_other), ["jq"]);
+    assert_eq!(
+        diff.version_differs,
+        [(
+            "bat".to_string(),
+            "0.24.0".to_string(),
+            "0.25.0".to_string()
+        )]
+    );
+}
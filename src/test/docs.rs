@@ -0,0 +1,22 @@
+use crate::docs::*;
+
+#[test]
+fn every_topic_is_listed_in_the_index() {
+    let index = index();
+
+    for topic in &TOPICS {
+        assert!(index.contains(&format!("`{}`", topic.name)));
+        assert!(topic.content.starts_with("# "));
+    }
+}
+
+#[test]
+fn the_markdown_is_rendered_without_its_marks() {
+    let markdown =
+        "# Title\n\n## part\n\n- an `item`\n\n```kdl\nnode \"x\"\n```\nan unclosed `tick\n";
+
+    assert_eq!(
+        render(markdown, false),
+        "TITLE\n\npart\n\n  • an `item`\n\n    node \"x\"\nan unclosed `tick\n"
+    );
+}
@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+
+use miette::Report;
+
+use crate::{
+    config::ConfigError,
+    db::DbError,
+    error::Error,
+    event::{CountingSink, Event, EventSink, NoopSink, Step},
+    exit::*,
+    input::InputError,
+    lock::LockError,
+};
+
+#[test]
+fn the_errors_get_the_code_of_their_class() {
+    let config = Report::new(ConfigError::MissingValue("db"));
+    assert_eq!(code(&config), CONFIG_ERROR);
+
+    let input = Report::new(Error::from(InputError::InputsDirExists(PathBuf::from("x"))));
+    assert_eq!(code(&input), CONFIG_ERROR);
+
+    let locked = Report::new(LockError::AlreadyLocked {
+        holder: "pid 1".into(),
+        path: PathBuf::from("packages.lock"),
+    });
+    assert_eq!(code(&locked), LOCKED);
+
+    let db = Report::new(DbError::InvalidPath);
+    assert_eq!(code(&db), FAILURE);
+}
+
+#[test]
+fn the_failures_of_a_build_are_counted() {
+    let mut sink = CountingSink::new(NoopSink);
+
+    sink.emit(Event::PackageInstalled { name: "a".into() });
+    assert_eq!(sink.failures(), 0);
+
+    sink.emit(Event::PackageFailed {
+        name: "b".into(),
+        step: Step::Store,
+        error: "no space left".into(),
+        exit_code: None,
+    });
+    sink.emit(Event::HookFailed {
+        pkg: None,
+        error: "exit 1".into(),
+    });
+    assert_eq!(sink.failures(), 2);
+}
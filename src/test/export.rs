@@ -0,0 +1,94 @@
+use std::{os::unix::fs::PermissionsExt, process::Command};
+
+use crate::export::*;
+
+#[test]
+fn the_bootstrap_script_writes_the_files_and_builds() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    std::fs::create_dir_all(source.join("inputs/work")).unwrap();
+    std::fs::create_dir_all(source.join("inputs/.git")).unwrap();
+    std::fs::create_dir_all(source.join("bridges/cargo")).unwrap();
+    std::fs::write(source.join("config.kdl"), "config {}\n").unwrap();
+    std::fs::write(source.join("inputs/cargo.kdl"), "cargo { fd; }\n").unwrap();
+    std::fs::write(
+        source.join("inputs/work/eget.kdl"),
+        "eget { gh \"cli/cli\"; }",
+    )
+    .unwrap();
+    std::fs::write(source.join("inputs/.git/HEAD"), "ref").unwrap();
+    let bridge = source.join("bridges/cargo/run");
+    std::fs::write(&bridge, "#!/bin/sh\necho \"$1\"\n").unwrap();
+    std::fs::set_permissions(&bridge, std::fs::Permissions::from_mode(0o755)).unwrap();
+    std::fs::write(source.join("bridges/cargo/blob"), [0u8, 159, 146, 150, 255]).unwrap();
+
+    let mut bootstrap = Bootstrap {
+        version: "1.2.3".to_string(),
+        build_args: vec!["--non-interactive".to_string(), "build".to_string()],
+        ..Default::default()
+    };
+    bootstrap
+        .add_file(
+            &source.join("config.kdl"),
+            "$config_dir/.config.kdl".to_string(),
+        )
+        .unwrap();
+    bootstrap
+        .add_dir(&source.join("inputs"), "$config_dir")
+        .unwrap();
+    bootstrap
+        .add_dir(&source.join("bridges"), "$config_dir/.bridges")
+        .unwrap();
+    assert_eq!(bootstrap.files.len(), 5);
+
+    let script = bootstrap.script();
+    assert!(script.contains("cargo install pkg-rs --version 1.2.3 --locked"));
+    assert!(script.ends_with("\npkg --non-interactive build\n"));
+
+    // a pkg that's there already isn't installed, this one records its args
+    let bin = dir.path().join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    std::fs::write(
+        bin.join("pkg"),
+        "#!/bin/sh\necho \"$@\" > \"$HOME/built\"\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(bin.join("pkg"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let home = dir.path().join("home");
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(&script)
+        .env("HOME", &home)
+        .env_remove("XDG_CONFIG_HOME")
+        .env(
+            "PATH",
+            format!("{}:{}", bin.display(), std::env::var("PATH").unwrap()),
+        )
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let config_dir = home.join(".config/pkg");
+    let read = |path: &str| std::fs::read(config_dir.join(path)).unwrap();
+    assert_eq!(read(".config.kdl"), b"config {}\n");
+    assert_eq!(read("cargo.kdl"), b"cargo { fd; }\n");
+    assert_eq!(read("work/eget.kdl"), b"eget { gh \"cli/cli\"; }");
+    assert_eq!(read(".bridges/cargo/blob"), [0u8, 159, 146, 150, 255]);
+    assert!(!config_dir.join(".git").exists());
+    let mode = std::fs::metadata(config_dir.join(".bridges/cargo/run"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o755);
+    assert_eq!(
+        std::fs::read_to_string(home.join("built")).unwrap(),
+        "--non-interactive build\n"
+    );
+
+    assert!(
+        bootstrap
+            .cloud_init()
+            .starts_with("#cloud-config\nwrite_files:\n")
+    );
+}
@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+
+use crate::{
+    event::{Event, EventSink, NoopSink, Step},
+    failures::*,
+};
+
+#[test]
+fn the_failure_report_is_made_from_the_events_and_read_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut sink = FailureSink::new("build", dir.path().to_path_buf(), NoopSink);
+
+    sink.emit(Event::BridgeStarted {
+        bridge: "cargo".to_string(),
+        install: 2,
+        remove: 0,
+        update: 0,
+    });
+    sink.emit(Event::PackageFailed {
+        name: "rg".to_string(),
+        step: Step::BridgeOperation,
+        error: "exit code 101".to_string(),
+        exit_code: Some(101),
+    });
+    sink.emit(Event::PackageFailed {
+        name: "rg".to_string(),
+        step: Step::Hook,
+        error: "post-install".to_string(),
+        exit_code: None,
+    });
+    sink.emit(Event::BridgeStarted {
+        bridge: "github".to_string(),
+        install: 1,
+        remove: 0,
+        update: 0,
+    });
+    sink.emit(Event::PackageFailed {
+        name: "fd".to_string(),
+        step: Step::Store,
+        error: "no space left".to_string(),
+        exit_code: None,
+    });
+    sink.emit(Event::LinkFailed {
+        error: "a file is in the way".to_string(),
+    });
+
+    let report = sink.report();
+    assert_eq!(
+        report.pkgs[0],
+        FailedPkg {
+            name: "rg".to_string(),
+            bridge: "cargo".to_string(),
+            step: "bridge-operation".to_string(),
+            exit_code: Some(101),
+            error: "exit code 101".to_string(),
+            log: dir.path().join("cargo.log"),
+        }
+    );
+    assert_eq!(report.pkgs[2].bridge, "github");
+    assert_eq!(report.pkgs[2].step, "store");
+    assert_eq!(report.others, ["a file is in the way"]);
+    // once each
+    assert_eq!(report.pkg_names(), ["rg", "fd"]);
+
+    let path = report.write(dir.path()).unwrap();
+    assert_eq!(path, dir.path().join(FAILURES_FILE_NAME));
+    assert_eq!(&FailureReport::read(dir.path()).unwrap(), report);
+
+    FailureReport::remove(dir.path()).unwrap();
+    assert!(matches!(
+        FailureReport::read(dir.path()),
+        Err(FailuresError::NoReport(_))
+    ));
+    // nothing to remove is fine
+    FailureReport::remove(dir.path()).unwrap();
+
+    assert!(matches!(
+        FailureReport::parse("{}", &PathBuf::from("failures.json")),
+        Err(FailuresError::BadReport { .. })
+    ));
+}
@@ -0,0 +1,842 @@
+use std::rc::Rc;
+
+use tempfile::NamedTempFile;
+
+use crate::{
+    db::{Artifact, Db, PkgMetadata, PkgType},
+    event::Event,
+    fs::*,
+};
+
+#[test]
+fn adopt_moves_or_copies_the_pkg_to_the_target_dir() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let fs = Fs::new(root.path().join("opt"), root.path().join("bin"), db).unwrap();
+
+    let bin = root.path().join("tool");
+    std::fs::write(&bin, "#!/bin/sh\n").unwrap();
+
+    let pkg = fs.adopt("tool", &bin, None, false).unwrap();
+    assert_eq!(pkg.path, root.path().join("opt/adopted/tool"));
+    assert!(pkg.path.is_file());
+    assert!(!bin.exists());
+
+    let dir = root.path().join("app");
+    std::fs::create_dir_all(dir.join("bin")).unwrap();
+    std::fs::write(dir.join("bin/app"), "#!/bin/sh\n").unwrap();
+
+    assert!(matches!(
+        fs.adopt("app", &dir, None, true),
+        Err(FsError::MissingEntryPoint(_))
+    ));
+
+    let pkg = fs
+        .adopt("app", &dir, Some(std::path::Path::new("bin/app")), true)
+        .unwrap();
+    let PkgType::Directory(entry_point) = pkg.pkg_type else {
+        panic!("expected a directory pkg");
+    };
+    assert_eq!(entry_point, root.path().join("opt/adopted/app/bin/app"));
+    assert!(entry_point.is_file());
+    // copied, the original is still there
+    assert!(dir.join("bin/app").is_file());
+}
+
+#[test]
+fn adopt_refuses_the_names_and_paths_out_of_its_dir() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let fs = Fs::new(root.path().join("opt"), root.path().join("bin"), db).unwrap();
+
+    // an installed pkg the bad names would wipe
+    let kept = root.path().join("opt/adopted/kept");
+    std::fs::create_dir_all(&kept).unwrap();
+    let outside = root.path().join("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+
+    let bin = root.path().join("tool");
+    std::fs::write(&bin, "#!/bin/sh\n").unwrap();
+
+    for name in ["..", "", outside.to_str().unwrap(), "a/b", "."] {
+        assert!(
+            matches!(
+                fs.adopt(name, &bin, None, false),
+                Err(FsError::BadPkgName(_))
+            ),
+            "{name:?} was accepted"
+        );
+    }
+    assert!(kept.is_dir());
+    assert!(outside.is_dir());
+    assert!(bin.is_file());
+
+    // the store itself, a pkg in it and a dir holding it
+    for path in [
+        root.path().join("opt"),
+        kept.clone(),
+        root.path().to_path_buf(),
+    ] {
+        assert!(matches!(
+            fs.adopt("app", &path, Some(std::path::Path::new("app")), true),
+            Err(FsError::AdoptInTargetDir(_))
+        ));
+    }
+    assert!(kept.is_dir());
+
+    let dir = root.path().join("app");
+    std::fs::create_dir_all(dir.join("bin")).unwrap();
+    std::fs::write(dir.join("bin/app"), "#!/bin/sh\n").unwrap();
+    for entry_point in ["../tool", "bin/../../tool", bin.to_str().unwrap()] {
+        assert!(matches!(
+            fs.adopt("app", &dir, Some(std::path::Path::new(entry_point)), true),
+            Err(FsError::EntryPointOutOfDir(_))
+        ));
+    }
+}
+
+#[test]
+fn link_prunes_the_links_of_gone_pkgs_only() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let target_dir = root.path().join("opt");
+    let load_path = root.path().join("bin");
+    let fs = Fs::new(target_dir.clone(), load_path.clone(), db.clone()).unwrap();
+
+    let bin = root.path().join("tool");
+    std::fs::write(&bin, "#!/bin/sh\n").unwrap();
+    let pkg = fs.adopt("tool", &bin, None, false).unwrap();
+    db.install_bridge_pkgs(&[&pkg], &"adopted".to_string())
+        .unwrap();
+
+    // a pkg that was removed, a dead link, and the user's own things
+    std::fs::write(target_dir.join("adopted/gone"), "").unwrap();
+    std::os::unix::fs::symlink(target_dir.join("adopted/gone"), load_path.join("gone")).unwrap();
+    std::os::unix::fs::symlink(root.path().join("nothing"), load_path.join("dead")).unwrap();
+    std::os::unix::fs::symlink(db_file.path(), load_path.join("mine")).unwrap();
+    std::fs::write(load_path.join("script"), "").unwrap();
+
+    let report = fs.link().unwrap();
+
+    assert_eq!(report.linked, 1);
+    assert_eq!(report.pruned, [load_path.join("gone")]);
+    assert_eq!(
+        std::fs::read_link(load_path.join("tool")).unwrap(),
+        pkg.path
+    );
+    // dead, but not ours
+    assert!(load_path.join("dead").symlink_metadata().is_ok());
+    assert!(load_path.join("mine").exists());
+    assert!(load_path.join("script").exists());
+}
+
+#[test]
+fn the_dead_links_of_others_stay_in_the_load_path() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let target_dir = root.path().join("opt");
+    let load_path = root.path().join("bin");
+    let fs = Fs::new(target_dir.clone(), load_path.clone(), db).unwrap();
+    std::fs::create_dir_all(&load_path).unwrap();
+
+    // a tool that's not installed yet, a relative dead link and one that
+    // went through the target dir to get out of it
+    std::os::unix::fs::symlink(root.path().join("other/tool"), load_path.join("foreign")).unwrap();
+    std::os::unix::fs::symlink("../nothing", load_path.join("relative")).unwrap();
+    std::os::unix::fs::symlink(target_dir.join("../elsewhere"), load_path.join("out")).unwrap();
+    // a pkg that's gone, with a relative link
+    std::os::unix::fs::symlink("../opt/adopted/gone", load_path.join("gone")).unwrap();
+
+    let report = fs.link().unwrap();
+
+    assert_eq!(report.pruned, [load_path.join("gone")]);
+    for name in ["foreign", "relative", "out"] {
+        assert!(load_path.join(name).symlink_metadata().is_ok());
+    }
+}
+
+#[test]
+fn link_replaces_a_changed_link_in_place() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let load_path = root.path().join("bin");
+    let fs = Fs::new(root.path().join("opt"), load_path.clone(), db.clone()).unwrap();
+
+    let bin = root.path().join("tool");
+    std::fs::write(&bin, "#!/bin/sh\n").unwrap();
+    let mut pkg = fs.adopt("tool", &bin, None, false).unwrap();
+    db.install_bridge_pkgs(&[&pkg], &"adopted".to_string())
+        .unwrap();
+    fs.link().unwrap();
+
+    // the pkg moved, like after an update
+    let new_path = root.path().join("opt/adopted/tool-2");
+    std::fs::rename(&pkg.path, &new_path).unwrap();
+    pkg.path = new_path.clone();
+    db.remove_pkgs(&["tool".to_string()]).unwrap();
+    db.install_bridge_pkgs(&[&pkg], &"adopted".to_string())
+        .unwrap();
+
+    let report = fs.link().unwrap();
+
+    assert!(report.pruned.is_empty());
+    assert_eq!(
+        std::fs::read_link(load_path.join("tool")).unwrap(),
+        new_path
+    );
+    assert!(!load_path.join(".tool.pkg-link").exists());
+}
+
+#[test]
+fn link_strategies() {
+    use std::os::unix::fs::MetadataExt;
+
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let load_path = root.path().join("bin");
+
+    let per_pkg = [
+        ("hard", LinkStrategy::Hardlink),
+        ("copied", LinkStrategy::Copy),
+        ("wrapped", LinkStrategy::WrapperScript),
+    ]
+    .into_iter()
+    .map(|(name, strategy)| (name.to_string(), strategy))
+    .collect();
+    let fs = Fs::new(root.path().join("opt"), load_path.clone(), db.clone())
+        .unwrap()
+        .with_link_strategies(LinkStrategy::Symlink, per_pkg);
+
+    let mut pkgs = Vec::new();
+    for name in ["sym", "hard", "copied", "wrapped"] {
+        let bin = root.path().join(name);
+        std::fs::write(&bin, "#!/bin/sh\necho hi\n").unwrap();
+        pkgs.push(fs.adopt(name, &bin, None, false).unwrap());
+    }
+    db.install_bridge_pkgs(&pkgs.iter().collect::<Vec<_>>(), &"adopted".to_string())
+        .unwrap();
+
+    fs.link().unwrap();
+
+    assert!(load_path.join("sym").is_symlink());
+    assert_eq!(
+        load_path.join("hard").metadata().unwrap().ino(),
+        pkgs[1].path.metadata().unwrap().ino()
+    );
+    assert!(!load_path.join("copied").is_symlink());
+    assert_eq!(
+        std::fs::read_to_string(load_path.join("copied")).unwrap(),
+        "#!/bin/sh\necho hi\n"
+    );
+    assert!(
+        std::fs::read_to_string(load_path.join("wrapped"))
+            .unwrap()
+            .contains(&format!("exec '{}'", pkgs[3].path.display()))
+    );
+
+    // the copies aren't symlinks, but they are known to be ours
+    db.remove_pkgs(&["copied".to_string()]).unwrap();
+    let report = fs.link().unwrap();
+    assert_eq!(report.pruned, [load_path.join("copied")]);
+}
+
+#[test]
+fn move_path_copies_across_file_systems() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let to_dir = tempfile::tempdir().unwrap();
+    // a tmpfs, when there is one on another device
+    let Ok(from_dir) = tempfile::tempdir_in("/dev/shm") else {
+        return;
+    };
+    if from_dir.path().metadata().unwrap().dev() == to_dir.path().metadata().unwrap().dev() {
+        return;
+    }
+
+    let from = from_dir.path().join("pkg");
+    std::fs::create_dir_all(from.join("bin")).unwrap();
+    std::fs::write(from.join("bin/tool"), "#!/bin/sh\n").unwrap();
+    std::fs::set_permissions(
+        from.join("bin/tool"),
+        std::fs::Permissions::from_mode(0o750),
+    )
+    .unwrap();
+    std::os::unix::fs::symlink("bin/tool", from.join("tool")).unwrap();
+
+    let to = to_dir.path().join("pkg");
+    move_path(&from, &to).unwrap();
+
+    assert!(!from.exists());
+    assert_eq!(
+        to.join("bin/tool").metadata().unwrap().permissions().mode() & 0o777,
+        0o750
+    );
+    assert_eq!(
+        std::fs::read_link(to.join("tool")).unwrap(),
+        std::path::Path::new("bin/tool")
+    );
+    assert!(!to_dir.path().join(".pkg.pkg-move").exists());
+}
+
+#[test]
+fn remove_pkgs_removes_the_stored_pkg_and_its_link() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let load_path = root.path().join("bin");
+    let fs = Fs::new(root.path().join("opt"), load_path.clone(), db.clone()).unwrap();
+
+    let bin = root.path().join("tool");
+    std::fs::write(&bin, "#!/bin/sh\n").unwrap();
+    let pkg = fs.adopt("tool", &bin, None, false).unwrap();
+
+    // a path out of the target dir, like a db edited by hand
+    std::fs::write(root.path().join("outside"), "").unwrap();
+    let outside = crate::Pkg {
+        name: "outside".to_string(),
+        version: crate::PkgVersion {
+            first_cell: "1".to_string(),
+            second_cell: "0".to_string(),
+            third_cell: "0".to_string(),
+        },
+        path: root.path().join("outside"),
+        pkg_type: PkgType::SingleExecutable,
+        artifacts: Vec::new(),
+        metadata: PkgMetadata::default(),
+    };
+
+    db.install_bridge_pkgs(&[&pkg, &outside], &"adopted".to_string())
+        .unwrap();
+    fs.link().unwrap();
+
+    let removed = fs
+        .remove_pkgs(&[&"tool".to_string(), &"outside".to_string()])
+        .unwrap();
+
+    assert_eq!(removed.len(), 2);
+    for (name, result) in removed {
+        match name.as_str() {
+            "tool" => assert!(result.unwrap()),
+            _ => assert!(matches!(result, Err(FsError::OutOfTargetDir(_)))),
+        }
+    }
+    assert!(!pkg.path.exists());
+    assert!(load_path.join("tool").symlink_metadata().is_err());
+    assert!(root.path().join("outside").exists());
+}
+
+#[test]
+fn a_link_of_the_same_name_that_is_not_ours_is_kept() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let load_path = root.path().join("bin");
+    let fs = Fs::new(root.path().join("opt"), load_path.clone(), db.clone()).unwrap();
+
+    let bin = root.path().join("tool");
+    std::fs::write(&bin, "#!/bin/sh\n").unwrap();
+    let pkg = fs.adopt("tool", &bin, None, false).unwrap();
+    db.install_bridge_pkgs(&[&pkg], &"adopted".to_string())
+        .unwrap();
+
+    // the user's own `tool`, pkg never linked it
+    std::fs::create_dir_all(&load_path).unwrap();
+    std::fs::write(root.path().join("mine"), "").unwrap();
+    std::os::unix::fs::symlink(root.path().join("mine"), load_path.join("tool")).unwrap();
+
+    assert!(fs.pkg_files(&pkg).unwrap().links.is_empty());
+
+    let removed = fs.remove_pkgs(&[&"tool".to_string()]).unwrap();
+
+    assert!(removed[0].1.as_ref().unwrap());
+    assert!(!pkg.path.exists());
+    assert_eq!(
+        std::fs::read_link(load_path.join("tool")).unwrap(),
+        root.path().join("mine")
+    );
+}
+
+#[test]
+fn stored_pkgs_get_sane_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = |path: &std::path::Path| path.metadata().unwrap().permissions().mode() & 0o7777;
+    let set_mode = |path: &std::path::Path, mode: u32| {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap()
+    };
+
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let fs = Fs::new(root.path().join("opt"), root.path().join("bin"), db)
+        .unwrap()
+        .with_permissions(StorePermissions {
+            normalize: true,
+            setuid_allowed: vec!["allowed".to_string()],
+        });
+
+    let mut pkgs = Vec::new();
+    for name in ["pkg", "allowed"] {
+        let dir = root.path().join("work").join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("run"), "").unwrap();
+        std::fs::write(dir.join("data"), "").unwrap();
+        set_mode(&dir.join("run"), 0o4700);
+        set_mode(&dir.join("data"), 0o666);
+        set_mode(&dir, 0o700);
+
+        pkgs.push(crate::Pkg {
+            name: name.to_string(),
+            version: crate::PkgVersion {
+                first_cell: "1".to_string(),
+                second_cell: "0".to_string(),
+                third_cell: "0".to_string(),
+            },
+            pkg_type: PkgType::Directory(dir.join("run")),
+            path: dir,
+            artifacts: Vec::new(),
+            metadata: PkgMetadata::default(),
+        });
+    }
+
+    let [pkg, allowed] = &mut pkgs[..] else {
+        unreachable!()
+    };
+    fs.store_or_overwrite(&mut [pkg, allowed], Some("bridge"))
+        .unwrap();
+
+    assert_eq!(mode(&pkgs[0].path), 0o755);
+    assert_eq!(mode(&pkgs[0].path.join("run")), 0o755);
+    assert_eq!(mode(&pkgs[0].path.join("data")), 0o644);
+    assert_eq!(mode(&pkgs[1].path.join("run")), 0o4755);
+}
+
+#[test]
+fn pkgs_are_linked_in_their_load_path() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let load_path = root.path().join("bin");
+    let user_bin = root.path().join("home/bin");
+    let extra = [("user".to_string(), user_bin.clone())]
+        .into_iter()
+        .collect();
+
+    let fs = Fs::new(root.path().join("opt"), load_path.clone(), db.clone()).unwrap();
+
+    let mut pkgs = Vec::new();
+    for name in ["system", "tool"] {
+        let bin = root.path().join(name);
+        std::fs::write(&bin, "#!/bin/sh\n").unwrap();
+        pkgs.push(fs.adopt(name, &bin, None, false).unwrap());
+    }
+    db.install_bridge_pkgs(&pkgs.iter().collect::<Vec<_>>(), &"adopted".to_string())
+        .unwrap();
+
+    // everything in the main one first, then the tool moves to the user one
+    fs.link().unwrap();
+    let per_pkg = [("tool".to_string(), "user".to_string())]
+        .into_iter()
+        .collect();
+    let fs = fs.with_load_paths(extra, per_pkg).unwrap();
+    let report = fs.link().unwrap();
+
+    assert!(load_path.join("system").is_symlink());
+    assert!(user_bin.join("tool").is_symlink());
+    assert_eq!(report.pruned, [load_path.join("tool")]);
+
+    let unknown = [("tool".to_string(), "nope".to_string())]
+        .into_iter()
+        .collect();
+    assert!(matches!(
+        fs.with_load_paths(Default::default(), unknown),
+        Err(FsError::UnknownLoadPath { .. })
+    ));
+}
+
+#[test]
+fn the_units_of_the_pkgs_are_linked_in_the_unit_dir() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let unit_dir = root.path().join("units");
+    let fs = Fs::new(root.path().join("opt"), root.path().join("bin"), db.clone())
+        .unwrap()
+        .with_unit_dir(Some(unit_dir.clone()));
+
+    // as the bridge api gives it, in the working dir
+    let work_dir = root.path().join("work/app");
+    std::fs::create_dir_all(&work_dir).unwrap();
+    std::fs::write(work_dir.join("app"), "#!/bin/sh\n").unwrap();
+    std::fs::write(work_dir.join("app.service"), "[Service]\n").unwrap();
+
+    let mut pkg = crate::Pkg {
+        name: "app".to_string(),
+        version: crate::PkgVersion {
+            first_cell: "1".to_string(),
+            second_cell: "0".to_string(),
+            third_cell: "0".to_string(),
+        },
+        pkg_type: PkgType::Directory(work_dir.join("app")),
+        path: work_dir.clone(),
+        artifacts: vec![Artifact::SystemdUnit(work_dir.join("app.service"))],
+        metadata: PkgMetadata::default(),
+    };
+    fs.store_or_overwrite(&mut [&mut pkg], Some("b")).unwrap();
+    db.install_bridge_pkgs(&[&pkg], &"b".to_string()).unwrap();
+
+    let stored_unit = root.path().join("opt/b/app/app.service");
+    assert_eq!(
+        db.get_pkgs().unwrap()[0].artifacts,
+        [Artifact::SystemdUnit(stored_unit.clone())]
+    );
+
+    fs.link().unwrap();
+    assert_eq!(
+        std::fs::read_link(unit_dir.join("app.service")).unwrap(),
+        stored_unit
+    );
+
+    db.remove_pkgs(&["app".to_string()]).unwrap();
+    let report = fs.link().unwrap();
+
+    assert_eq!(
+        report.pruned,
+        [root.path().join("bin/app"), unit_dir.join("app.service")]
+    );
+    assert!(unit_dir.join(".pkg-links").exists());
+}
+
+#[test]
+fn the_missing_and_dead_links_are_broken() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let load_path = root.path().join("bin");
+    let fs = Fs::new(root.path().join("opt"), load_path.clone(), db.clone()).unwrap();
+
+    for name in ["tool", "other"] {
+        let bin = root.path().join(name);
+        std::fs::write(&bin, "#!/bin/sh\n").unwrap();
+        let pkg = fs.adopt(name, &bin, None, false).unwrap();
+        db.install_bridge_pkgs(&[&pkg], &"adopted".to_string())
+            .unwrap();
+    }
+
+    fs.link().unwrap();
+    assert!(fs.broken_links().unwrap().is_empty());
+
+    // a dead link and a missing one
+    std::fs::remove_file(root.path().join("opt/adopted/tool")).unwrap();
+    std::fs::remove_file(load_path.join("other")).unwrap();
+
+    assert_eq!(
+        fs.broken_links().unwrap(),
+        vec![load_path.join("other"), load_path.join("tool")]
+    );
+}
+
+#[test]
+fn the_links_in_a_root_point_in_the_root() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let in_root = |path: &str| crate::config::under_root(root.path(), std::path::Path::new(path));
+    let load_path = in_root("/usr/local/bin");
+    let fs = Fs::new(in_root("/opt/pkg"), load_path.clone(), db.clone())
+        .unwrap()
+        .with_root(Some(root.path().to_path_buf()));
+
+    let bin = root.path().join("tool");
+    std::fs::write(&bin, "#!/bin/sh\n").unwrap();
+    let pkg = fs.adopt("tool", &bin, None, false).unwrap();
+    db.install_bridge_pkgs(&[&pkg], &"adopted".to_string())
+        .unwrap();
+
+    // the system's own link, dead from the host but not in the root
+    std::fs::create_dir_all(in_root("/usr/lib/pkg-root-test")).unwrap();
+    std::fs::write(in_root("/usr/lib/pkg-root-test/sh"), "").unwrap();
+    std::os::unix::fs::symlink("/usr/lib/pkg-root-test/sh", load_path.join("sh")).unwrap();
+
+    let report = fs.link().unwrap();
+
+    assert!(report.pruned.is_empty());
+    assert_eq!(
+        std::fs::read_link(load_path.join("tool")).unwrap(),
+        std::path::Path::new("/opt/pkg/adopted/tool")
+    );
+    assert!(load_path.join("sh").symlink_metadata().is_ok());
+}
+
+#[test]
+fn the_files_of_a_pkg_are_its_store_files_and_its_links() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let load_path = root.path().join("bin");
+    let fs = Fs::new(root.path().join("opt"), load_path.clone(), db.clone()).unwrap();
+
+    let dir = root.path().join("app");
+    std::fs::create_dir_all(dir.join("bin")).unwrap();
+    std::fs::create_dir_all(dir.join("share/doc")).unwrap();
+    std::fs::write(dir.join("bin/app"), "#!/bin/sh\n").unwrap();
+    std::fs::write(dir.join("share/doc/README"), "").unwrap();
+    std::fs::write(dir.join("share/app.1"), "").unwrap();
+    let pkg = fs
+        .adopt("app", &dir, Some(std::path::Path::new("bin/app")), false)
+        .unwrap();
+    db.install_bridge_pkgs(&[&pkg], &"adopted".to_string())
+        .unwrap();
+    fs.link().unwrap();
+
+    let files = fs.pkg_files(&pkg).unwrap();
+    assert_eq!(
+        files.store,
+        [
+            pkg.path.join("bin/app"),
+            pkg.path.join("share/app.1"),
+            pkg.path.join("share/doc/README"),
+        ]
+    );
+    assert_eq!(files.links, [load_path.join("app")]);
+
+    assert_eq!(
+        file_tree(&pkg.path, &files.store),
+        format!(
+            "{}\n├── bin\n│   └── app\n└── share\n    ├── app.1\n    └── doc\n        └── README\n",
+            pkg.path.display()
+        )
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn the_unix_links_keep_the_pkg_name_and_the_wrappers_exec_it() {
+    use crate::fs::platform;
+
+    for strategy in [
+        LinkStrategy::Symlink,
+        LinkStrategy::Hardlink,
+        LinkStrategy::Copy,
+        LinkStrategy::WrapperScript,
+    ] {
+        assert_eq!(
+            platform::link_name("tool", std::path::Path::new("/opt/pkg/tool.sh"), strategy),
+            "tool"
+        );
+    }
+
+    assert_eq!(
+        platform::wrapper_script(std::path::Path::new("/opt/it's/tool")),
+        "#!/bin/sh\nexec '/opt/it'\\''s/tool' \"$@\"\n"
+    );
+
+    // the args go through as they are
+    let dir = tempfile::tempdir().unwrap();
+    let wrapper = dir.path().join("echo");
+    std::fs::write(
+        &wrapper,
+        platform::wrapper_script(std::path::Path::new("/bin/echo")),
+    )
+    .unwrap();
+    platform::set_executable(&wrapper).unwrap();
+    assert!(platform::is_executable(&wrapper));
+
+    let output = std::process::Command::new(&wrapper)
+        .args(["a b", "$HOME"])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a b $HOME\n");
+}
+
+#[cfg(windows)]
+#[test]
+fn the_windows_links_keep_an_extension_windows_runs() {
+    use crate::fs::platform;
+
+    let exe = std::path::Path::new("C:/pkg/tool.exe");
+    assert_eq!(
+        platform::link_name("tool", exe, LinkStrategy::Symlink),
+        "tool.exe"
+    );
+    assert_eq!(
+        platform::link_name("tool.EXE", exe, LinkStrategy::Copy),
+        "tool.EXE"
+    );
+    assert_eq!(
+        platform::link_name("tool", exe, LinkStrategy::WrapperScript),
+        "tool.cmd"
+    );
+    assert_eq!(
+        platform::link_name(
+            "tool",
+            std::path::Path::new("C:/pkg/tool"),
+            LinkStrategy::Symlink
+        ),
+        "tool"
+    );
+
+    assert_eq!(
+        platform::wrapper_script(exe),
+        "@echo off\r\n\"C:/pkg/tool.exe\" %*\r\n"
+    );
+}
+
+#[test]
+fn the_link_step_is_told_to_the_sink() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let target_dir = root.path().join("opt");
+    let load_path = root.path().join("bin");
+    let fs = Fs::new(target_dir.clone(), load_path.clone(), db.clone()).unwrap();
+
+    let bin = root.path().join("tool");
+    std::fs::write(&bin, "#!/bin/sh\n").unwrap();
+    let pkg = fs.adopt("tool", &bin, None, false).unwrap();
+    db.install_bridge_pkgs(&[&pkg], &"adopted".to_string())
+        .unwrap();
+    std::fs::write(target_dir.join("adopted/gone"), "").unwrap();
+    std::os::unix::fs::symlink(target_dir.join("adopted/gone"), load_path.join("gone")).unwrap();
+
+    let mut events: Vec<Event> = Vec::new();
+    fs.link_with_events(&mut events).unwrap();
+    assert_eq!(
+        events,
+        [
+            Event::LinkStarted,
+            Event::LinkDone {
+                pruned: vec![load_path.join("gone")],
+            },
+        ]
+    );
+
+    // the load path is a file now, the link fails
+    std::fs::remove_dir_all(&load_path).unwrap();
+    std::fs::write(&load_path, "").unwrap();
+
+    let mut events: Vec<Event> = Vec::new();
+    let err = fs.link_with_events(&mut events).unwrap_err();
+    assert!(matches!(err, FsError::LoadPathIsFile(_)));
+    assert_eq!(
+        events,
+        [
+            Event::LinkStarted,
+            Event::LinkFailed {
+                error: err.to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn clean_removes_the_store_orphans_and_nothing_the_db_knows() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let target_dir = root.path().join("opt");
+    let fs = Fs::new(target_dir.clone(), root.path().join("bin"), db.clone()).unwrap();
+
+    let bin = root.path().join("tool");
+    std::fs::write(&bin, "#!/bin/sh\n").unwrap();
+    let pkg = fs.adopt("tool", &bin, None, false).unwrap();
+    db.install_bridge_pkgs(&[&pkg], &"adopted".to_string())
+        .unwrap();
+
+    // an interrupted build and a file copied by hand
+    std::fs::create_dir_all(target_dir.join("github/half/bin")).unwrap();
+    std::fs::write(target_dir.join("github/half/bin/half"), "12345").unwrap();
+    std::fs::write(target_dir.join("stray"), "123").unwrap();
+
+    let orphans = fs.store_orphans().unwrap();
+    assert_eq!(
+        orphans,
+        [target_dir.join("github/half"), target_dir.join("stray")]
+    );
+    assert_eq!(orphans.iter().map(|p| dir_size(p)).sum::<u64>(), 8);
+
+    for orphan in &orphans {
+        assert!(remove_path(orphan).unwrap());
+    }
+    assert!(!remove_path(&target_dir.join("stray")).unwrap());
+    assert!(fs.store_orphans().unwrap().is_empty());
+    assert!(pkg.path.is_file());
+
+    // a link is removed, not what it points to
+    let link = root.path().join("link");
+    std::os::unix::fs::symlink(&pkg.path, &link).unwrap();
+    assert!(remove_path(&link).unwrap());
+    assert!(pkg.path.is_file());
+}
+
+#[test]
+fn du_lists_the_stored_pkgs_the_largest_first() {
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let target_dir = root.path().join("opt");
+    let fs = Fs::new(target_dir.clone(), root.path().join("bin"), db).unwrap();
+
+    assert!(fs.disk_usage().unwrap().is_empty());
+
+    std::fs::create_dir_all(target_dir.join("github/fd")).unwrap();
+    std::fs::write(target_dir.join("github/fd/fd"), vec![0; 2048]).unwrap();
+    std::fs::write(target_dir.join("github/fd/fd.1"), vec![0; 512]).unwrap();
+    std::fs::create_dir_all(target_dir.join("cargo")).unwrap();
+    std::fs::write(target_dir.join("cargo/bat"), vec![0; 100]).unwrap();
+    std::fs::write(target_dir.join("cargo/rg"), vec![0; 100]).unwrap();
+
+    let usage = fs.disk_usage().unwrap();
+    assert_eq!(
+        usage
+            .iter()
+            .map(|u| (u.bridge.as_str(), u.name.as_str(), u.size))
+            .collect::<Vec<_>>(),
+        [
+            ("github", "fd", 2560),
+            ("cargo", "bat", 100),
+            ("cargo", "rg", 100)
+        ]
+    );
+
+    assert_eq!(format_size(100), "100B");
+    assert_eq!(format_size(2560), "2.5K");
+    assert_eq!(format_size(3 * 1024 * 1024), "3.0M");
+}
+
+#[cfg(unix)]
+#[test]
+fn run_execs_the_pkg_with_the_load_paths_first_in_the_path() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let db_file = NamedTempFile::new().unwrap();
+    let db = Rc::new(Db::new(&db_file.path().to_path_buf()).unwrap());
+    let root = tempfile::tempdir().unwrap();
+    let load_path = root.path().join("bin");
+    let fs = Fs::new(root.path().join("opt"), load_path.clone(), db).unwrap();
+
+    let bin = root.path().join("hello");
+    std::fs::write(&bin, "#!/bin/sh\necho \"$1 $PATH\"\n").unwrap();
+    std::fs::set_permissions(&bin, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let pkg = fs.adopt("hello", &bin, None, false).unwrap();
+
+    let output = run_command(
+        &pkg,
+        &["world".to_string()],
+        std::slice::from_ref(&load_path),
+    )
+    .unwrap()
+    .output()
+    .unwrap();
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stdout)
+            .starts_with(&format!("world {}:", load_path.display()))
+    );
+}
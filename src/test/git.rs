@@ -0,0 +1,122 @@
+use std::{path::Path, process::Command};
+
+use tempfile::tempdir;
+
+use crate::{
+    git::*,
+    input::{Input, disable_bridge},
+};
+
+fn git(dir: &Path, args: &[&str]) {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["-c", "user.name=pkg", "-c", "user.email=pkg@localhost"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git {args:?} failed");
+}
+
+// a bare repo with one commit of `inputs/main.kdl` on `main`, and the
+// checkout it was pushed from
+fn remote(dir: &Path) -> (String, std::path::PathBuf) {
+    let bare = dir.join("inputs.git");
+    let work = dir.join("work");
+    std::fs::create_dir_all(&bare).unwrap();
+    std::fs::create_dir_all(work.join("inputs")).unwrap();
+
+    git(&bare, &["init", "--bare", "--initial-branch", "main"]);
+    git(&work, &["init", "--initial-branch", "main"]);
+    std::fs::write(work.join("inputs/main.kdl"), "cargo {\n    bat\n}\n").unwrap();
+    git(&work, &["add", "-A"]);
+    git(&work, &["commit", "-m", "the inputs"]);
+    git(&work, &["push", bare.to_str().unwrap(), "main"]);
+
+    (format!("file://{}", bare.display()), work)
+}
+
+#[test]
+fn the_git_inputs_are_cloned_then_pulled() {
+    let dir = tempdir().unwrap();
+    let (url, work) = remote(dir.path());
+    let checkout = dir.path().join("checkout");
+
+    clone(&url, &checkout, None).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(checkout.join("inputs/main.kdl")).unwrap(),
+        "cargo {\n    bat\n}\n"
+    );
+    let first = head(&checkout).unwrap();
+
+    std::fs::write(
+        work.join("inputs/main.kdl"),
+        "cargo {\n    bat\n    fd\n}\n",
+    )
+    .unwrap();
+    git(&work, &["commit", "-am", "fd"]);
+    git(&work, &["push", &url, "main"]);
+
+    pull(&checkout).unwrap();
+    assert_ne!(head(&checkout).unwrap(), first);
+    assert!(
+        std::fs::read_to_string(checkout.join("inputs/main.kdl"))
+            .unwrap()
+            .contains("fd")
+    );
+}
+
+#[test]
+fn a_git_inputs_branch_is_checked_out() {
+    let dir = tempdir().unwrap();
+    let (url, work) = remote(dir.path());
+
+    git(&work, &["checkout", "-b", "laptop"]);
+    std::fs::write(work.join("inputs/laptop.kdl"), "cargo {\n    fd\n}\n").unwrap();
+    git(&work, &["add", "-A"]);
+    git(&work, &["commit", "-m", "the laptop"]);
+    git(&work, &["push", &url, "laptop"]);
+
+    let checkout = dir.path().join("checkout");
+    clone(&url, &checkout, Some("laptop")).unwrap();
+    assert!(checkout.join("inputs/laptop.kdl").is_file());
+
+    assert!(matches!(
+        clone(&url, &dir.path().join("missing"), Some("desktop")),
+        Err(GitError::CommandFailed("clone", _))
+    ));
+    assert_eq!(repo_name(&url), "inputs");
+}
+
+#[test]
+fn an_inputs_template_is_cloned_and_its_missing_bridges_disabled() {
+    let dir = tempdir().unwrap();
+    let (url, work) = remote(dir.path());
+    std::fs::write(work.join("inputs/mac.kdl"), "brew {\n    coreutils\n}\n").unwrap();
+    git(&work, &["add", "-A"]);
+    git(&work, &["commit", "-m", "the mac"]);
+    git(&work, &["push", &url, "main"]);
+
+    // what `pkg inputs init` does with the template, without a `brew` bridge
+    let dest = dir.path().join("source").join(repo_name(&url));
+    clone(&url, &dest, None).unwrap();
+    let bridges = |dest: &std::path::PathBuf| {
+        let mut names = Input::load(dest)
+            .unwrap()
+            .bridges
+            .into_iter()
+            .map(|bridge| bridge.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    };
+    assert_eq!(bridges(&dest), ["brew", "cargo"]);
+
+    assert_eq!(disable_bridge(&dest, "brew").unwrap(), 1);
+    assert_eq!(bridges(&dest), ["cargo"]);
+    assert!(
+        std::fs::read_to_string(dest.join("inputs/mac.kdl"))
+            .unwrap()
+            .starts_with("/-brew")
+    );
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use crate::{
+    db::{Db, Pkg, PkgMetadata, PkgType, Version},
+    hooks::*,
+    input::AttributeValue,
+};
+
+#[test]
+fn hooks_are_read_from_the_pkg_attributes() {
+    let attributes = HashMap::from([
+        (
+            POST_LINK.to_string(),
+            AttributeValue::String("fc-cache -f".to_string()),
+        ),
+        (
+            PRE_REMOVE.to_string(),
+            AttributeValue::List(vec![
+                AttributeValue::String("echo a".to_string()),
+                AttributeValue::String("echo b".to_string()),
+            ]),
+        ),
+    ]);
+
+    let hooks = Hooks::from_attributes("fonts", &attributes).unwrap();
+    assert_eq!(hooks.post_link, vec!["fc-cache -f"]);
+    assert_eq!(hooks.pre_remove, vec!["echo a", "echo b"]);
+
+    let attributes = HashMap::from([(POST_LINK.to_string(), AttributeValue::Integer(1))]);
+    assert!(matches!(
+        Hooks::from_attributes("fonts", &attributes),
+        Err(HookError::WrongValue {
+            hook: POST_LINK,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn hooks_log_their_output_and_stop_at_the_first_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("logs/hooks.log");
+
+    let commands = vec![
+        "echo \"bye $pkg_name\"".to_string(),
+        "exit 3".to_string(),
+        "touch never".to_string(),
+    ];
+    let err = run(PRE_REMOVE, &commands, Some("tool"), "PKG=tool", &log).unwrap_err();
+    assert!(matches!(err, HookError::Failed { code: 3, .. }));
+
+    let log = std::fs::read_to_string(&log).unwrap();
+    assert!(log.contains("|PKG=tool|"));
+    assert!(log.contains("bye tool"));
+    assert!(!log.contains("touch never"));
+}
+
+#[test]
+fn the_pre_remove_hooks_are_kept_for_any_bridge_and_run_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Db::new(&dir.path().join("pkg.db")).unwrap();
+    let log = dir.path().join("logs/hooks.log");
+
+    for (pkg_name, bridge) in [("tool", "cargo"), ("fonts", "github-releases")] {
+        let pkg = Pkg {
+            name: pkg_name.into(),
+            version: Version {
+                first_cell: "1".into(),
+                second_cell: "0".into(),
+                third_cell: "0".into(),
+            },
+            path: dir.path().join(pkg_name),
+            pkg_type: PkgType::SingleExecutable,
+            artifacts: Vec::new(),
+            metadata: PkgMetadata::default(),
+        };
+        db.install_bridge_pkgs(&[&pkg], &bridge.to_string())
+            .unwrap();
+
+        let attributes = HashMap::from([(
+            PRE_REMOVE.to_string(),
+            AttributeValue::List(vec![
+                AttributeValue::String("echo \"first $pkg_name\"".to_string()),
+                AttributeValue::String("echo \"second $pkg_name\"".to_string()),
+            ]),
+        )]);
+        let hooks = Hooks::from_attributes(pkg_name, &attributes).unwrap();
+        db.set_pkg_pre_remove(pkg_name, &hooks.pre_remove).unwrap();
+    }
+
+    // the pkgs aren't in the inputs anymore, their hooks come from the db
+    for pkg_name in ["tool", "fonts"] {
+        let commands = db.get_pkg_pre_remove(pkg_name).unwrap();
+        assert_eq!(commands.len(), 2);
+        run(
+            PRE_REMOVE,
+            &commands,
+            Some(pkg_name),
+            &format!("PKG={pkg_name}"),
+            &log,
+        )
+        .unwrap();
+    }
+
+    let log = std::fs::read_to_string(&log).unwrap();
+    let at = |line: &str| log.find(line).unwrap();
+    assert!(at("first tool") < at("second tool"));
+    assert!(at("second tool") < at("first fonts"));
+    assert!(at("first fonts") < at("second fonts"));
+}
@@ -0,0 +1,99 @@
+use crate::host::*;
+
+#[cfg(unix)]
+#[test]
+fn the_default_dirs_are_the_unix_ones() {
+    for dir in [crate::DEFAULT_LOG_DIR, crate::DEFAULT_WORKING_DIR] {
+        assert_eq!(platform_dir(dir), std::path::Path::new(dir));
+    }
+    assert!(platform_dir(crate::DEFAULT_LOG_DIR).is_absolute());
+}
+
+#[cfg(windows)]
+#[test]
+fn the_default_dirs_are_in_the_local_app_data() {
+    let local = std::env::var_os("LOCALAPPDATA").map(std::path::PathBuf::from);
+
+    for dir in [crate::DEFAULT_LOG_DIR, crate::DEFAULT_WORKING_DIR] {
+        let path = platform_dir(dir);
+        assert!(path.ends_with(dir));
+        if let Some(local) = &local {
+            assert!(path.starts_with(local));
+        }
+    }
+}
+
+#[test]
+fn a_command_is_found_in_the_path() {
+    assert!(find_command("pkg-surely-not-a-command").is_none());
+
+    // what the tests run with is there, `sh` or `cmd`
+    let shell = if cfg!(windows) { "cmd" } else { "sh" };
+    assert!(find_command(shell).is_some_and(|path| path.is_file()));
+}
+
+#[test]
+fn the_container_is_told_by_its_env_var_or_the_cgroup_of_pid_1() {
+    assert_eq!(
+        container_of(Some("systemd-nspawn"), ""),
+        Some(Container::Other("systemd-nspawn".to_string()))
+    );
+    assert_eq!(
+        container_of(None, "0::/kubepods/besteffort/pod1/docker-2"),
+        Some(Container::Kubernetes)
+    );
+    assert_eq!(
+        container_of(Some(""), "0::/system.slice/docker-1a2b.scope"),
+        Some(Container::Docker)
+    );
+    assert_eq!(container_of(None, "0::/lxc/ct1"), Some(Container::Lxc));
+    assert_eq!(container_of(None, "0::/init.scope"), None);
+}
+
+#[test]
+fn a_user_in_a_container_is_asked_for_root_like_anywhere_else() {
+    let host = |container: Option<Container>, var_writable| HostEnv {
+        container,
+        systemd: false,
+        var_writable,
+        overlay_root: false,
+    };
+
+    // the system paths aren't its own
+    let docker = host(Some(Container::Docker), false);
+    assert_eq!(docker.privilege(false, true, false), Privilege::Escalate);
+    assert_eq!(docker.privilege(false, true, true), Privilege::AsIs);
+    assert_eq!(docker.privilege(true, true, false), Privilege::Unprivileged);
+    assert_eq!(docker.privilege(false, false, false), Privilege::AsIs);
+
+    // a container made for its user
+    let own = host(Some(Container::Podman), true);
+    assert_eq!(own.privilege(false, true, false), Privilege::AsIs);
+
+    let machine = host(None, true);
+    assert_eq!(machine.privilege(false, true, false), Privilege::Escalate);
+    assert_eq!(
+        machine.privilege(true, true, false),
+        Privilege::Unprivileged
+    );
+}
+
+#[test]
+fn a_container_with_a_read_only_var_keeps_the_dirs_in_home() {
+    let host = HostEnv {
+        container: Some(Container::Docker),
+        systemd: false,
+        var_writable: false,
+        overlay_root: false,
+    };
+    assert!(host.user_mode());
+    assert_eq!(host.log_dir(), user_log_dir());
+    assert_eq!(host.working_dir(), user_working_dir());
+
+    let host = HostEnv {
+        var_writable: true,
+        ..host
+    };
+    assert!(!host.user_mode());
+    assert_eq!(host.log_dir(), platform_dir(crate::DEFAULT_LOG_DIR));
+}
@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+
+use crate::import::*;
+
+#[test]
+fn the_lists_are_parsed() {
+    let brewfile = r#"
+tap "homebrew/bundle"
+brew "fd"
+brew "user/tools/jq", args: ["HEAD"]
+cask "firefox"
+mas "Xcode", id: 497799835
+cask_args appdir: "/Applications"
+# brew "old"
+"#;
+    assert_eq!(
+        parse_brewfile(brewfile)
+            .iter()
+            .map(|p| (p.kind.as_str(), p.name.as_str(), p.input.as_str()))
+            .collect::<Vec<_>>(),
+        [
+            ("brew", "fd", "fd"),
+            ("brew", "jq", "user/tools/jq"),
+            ("cask", "firefox", "firefox"),
+            ("mas", "Xcode", "497799835"),
+        ]
+    );
+
+    let apt = "Listing... Done\n\
+        fd-find/jammy,now 8.3.1-1 amd64 [installed]\n\
+        libc6/jammy,now 2.35-0ubuntu3 amd64 [installed,automatic]\n";
+    let names = |pkgs: Vec<ImportedPkg>| pkgs.into_iter().map(|p| p.name).collect::<Vec<_>>();
+    assert_eq!(names(parse_apt(apt)), ["fd-find"]);
+    assert_eq!(
+        names(parse_apt(
+            "git\t\tinstall\nvim:amd64\tinstall\nnano\tdeinstall\n"
+        )),
+        ["git", "vim"]
+    );
+
+    let cargo = "bat v0.24.0:\n    bat\nripgrep v14.1.0:\n    rg\ntools v0.1.0 (https://github.com/me/tools#1a2b):\n    a\n    b\n";
+    assert_eq!(
+        parse_cargo(cargo)
+            .iter()
+            .map(|p| (p.name.as_str(), p.input.as_str()))
+            .collect::<Vec<_>>(),
+        [("bat", "bat"), ("rg", "ripgrep"), ("tools", "tools")]
+    );
+}
+
+#[test]
+fn the_pkgs_without_a_bridge_are_commented_out() {
+    let pkgs = parse_brewfile("brew \"fd\"\nbrew \"user/tools/jq\"\ncask \"firefox\"\n");
+    let map = parse_map(&["brew=eget".to_string()]).unwrap();
+
+    let (kdl, unmapped) = to_kdl(&pkgs, &map, &["eget".to_string()]);
+    assert_eq!(
+        unmapped.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(),
+        ["firefox"]
+    );
+    assert!(kdl.contains("/-cask"));
+
+    // the disabled block isn't loaded
+    let doc = kdl.parse::<kdl::KdlDocument>().unwrap();
+    assert_eq!(doc.nodes().len(), 1);
+    let eget = doc.get("eget").unwrap().children().unwrap();
+    assert!(eget.get("fd").unwrap().entries().is_empty());
+    assert_eq!(
+        eget.get("jq").unwrap().entries()[0].value().as_string(),
+        Some("user/tools/jq")
+    );
+
+    assert!(matches!(
+        parse_map(&["cask".to_string()]),
+        Err(ImportError::BadMap(_))
+    ));
+    assert_eq!(parse_map(&[]).unwrap(), BTreeMap::new());
+}
@@ -0,0 +1,448 @@
+use crate::input::*;
+
+#[test]
+fn duplicate_pkg_points_at_both_declarations() {
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(inputs.path().join("a.kdl"), "bridge1 {\n  pkg1\n}\n").unwrap();
+    std::fs::write(inputs.path().join("b.kdl"), "bridge2 {\n  pkg1\n}\n").unwrap();
+
+    let err = Input::load(&inputs.path().to_path_buf()).unwrap_err();
+    let InputError::DuplicatePkgDeclaration {
+        name, span, first, ..
+    } = &err
+    else {
+        panic!("expected a duplicate declaration error, got {err:?}");
+    };
+
+    assert_eq!(name, "pkg1");
+    assert_eq!(span.offset(), 12);
+    assert_eq!(first.len(), 1);
+}
+
+#[test]
+fn input_errors_can_be_matched_through_the_library_error() {
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(inputs.path().join("a.kdl"), "bridge1 {\n  pkg1 x=\n}\n").unwrap();
+
+    let err = crate::Error::from(Input::load(&inputs.path().to_path_buf()).unwrap_err());
+
+    assert!(matches!(
+        err,
+        crate::Error::Input(InputError::ParseError { .. })
+    ));
+}
+
+#[test]
+fn check_reports_every_problem() {
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(
+        inputs.path().join("a.kdl"),
+        "bridge1 {\n  pkg1\n}\nbridge2\n",
+    )
+    .unwrap();
+    std::fs::write(
+        inputs.path().join("b.kdl"),
+        "bridge1 {\n  pkg1\n  pkg2\n}\n",
+    )
+    .unwrap();
+    std::fs::write(inputs.path().join("c.kdl"), "bridge1 {\n  pkg3 x=\n}\n").unwrap();
+
+    let (input, problems) = Input::check(&inputs.path().to_path_buf()).unwrap();
+
+    // a missing block, a duplicate and a parse error
+    assert_eq!(problems.len(), 3);
+    assert_eq!(input.bridges.len(), 1);
+    assert_eq!(input.bridges[0].pkgs.len(), 2);
+}
+
+#[test]
+fn tags_are_parsed_and_filtered() {
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(
+        inputs.path().join("a.kdl"),
+        "bridge1 {\n  pkg1 {\n    tags \"dev\" \"gui\"\n  }\n  pkg2 \"input\" {\n    tags \"dev\"\n  }\n  pkg3\n}\n",
+    )
+    .unwrap();
+
+    let input = Input::load(&inputs.path().to_path_buf()).unwrap();
+    let pkgs = &input.bridges[0].pkgs;
+    assert_eq!(pkgs[0].tags, vec!["dev", "gui"]);
+    assert_eq!(pkgs[1].input, "input");
+
+    let filter = TagFilter {
+        include: vec!["dev".to_string()],
+        exclude: vec!["gui".to_string()],
+    };
+    assert_eq!(
+        pkgs.iter()
+            .filter(|p| filter.matches(&p.tags))
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["pkg2"]
+    );
+    assert!(TagFilter::default().matches(&pkgs[2].tags));
+}
+
+#[test]
+fn when_conditions_select_declarations() {
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(
+        inputs.path().join("a.kdl"),
+        r#"
+bridge1 {
+  pkg1 {
+    when hostname="laptop"
+    when profile="work"
+  }
+  pkg2 {
+    when hostname="laptop" os="macos"
+  }
+  pkg3
+}
+bridge2 {
+  when arch="riscv64"
+  pkg4
+}
+"#,
+    )
+    .unwrap();
+
+    let context = InputContext {
+        hostname: "server".to_string(),
+        os: "linux".to_string(),
+        arch: "x86_64".to_string(),
+        profile: Some("work".to_string()),
+        vars: Default::default(),
+    };
+    let input = Input::load_for(&inputs.path().to_path_buf(), &context).unwrap();
+
+    assert_eq!(input.bridges.len(), 1);
+    assert_eq!(
+        input.bridges[0]
+            .pkgs
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["pkg1", "pkg3"]
+    );
+    // only bridge2 is left out as a whole on this machine
+    assert_eq!(input.when_excluded, ["bridge2"]);
+    assert_eq!(input.when_excluded_pkgs, ["pkg2"]);
+}
+
+#[test]
+fn darwin_and_macos_are_the_same_os() {
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(
+        inputs.path().join("a.kdl"),
+        "bridge1 {\n  pkg1 {\n    when os=\"darwin\"\n  }\n  pkg2 \"pkg2\" os=\"darwin\"\n  pkg3 \"pkg3\" os=\"linux\"\n}\n",
+    )
+    .unwrap();
+
+    let context = InputContext {
+        os: "macos".to_string(),
+        arch: "aarch64".to_string(),
+        ..Default::default()
+    };
+    let input = Input::load_for(&inputs.path().to_path_buf(), &context).unwrap();
+
+    assert_eq!(
+        input.bridges[0]
+            .pkgs
+            .iter()
+            .map(|p| (p.name.as_str(), p.platform_mismatch(&context).is_none()))
+            .collect::<Vec<_>>(),
+        vec![("pkg1", true), ("pkg2", true), ("pkg3", false)]
+    );
+}
+
+#[test]
+fn variables_are_expanded() {
+    let vars = std::collections::HashMap::from([("NAME".to_string(), "pkg".to_string())]);
+
+    assert_eq!(expand("${NAME}-${NAME}.tar", &vars).unwrap(), "pkg-pkg.tar");
+    assert_eq!(expand("$${NAME}", &vars).unwrap(), "${NAME}");
+    assert_eq!(expand("${OTHER}", &vars).unwrap_err(), "OTHER");
+
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(
+        inputs.path().join("a.kdl"),
+        "vars {\n  version \"1.2\"\n}\nbridge1 {\n  pkg1 \"pkg1@${version}\" url=\"${base}/pkg1\"\n}\n",
+    )
+    .unwrap();
+
+    let mut context = InputContext::default();
+    context
+        .vars
+        .insert("base".to_string(), "https://example.com".to_string());
+    let input = Input::load_for(&inputs.path().to_path_buf(), &context).unwrap();
+    let pkg = &input.bridges[0].pkgs[0];
+
+    assert_eq!(pkg.input, "pkg1@1.2");
+    assert_eq!(
+        pkg.attributes["url"],
+        AttributeValue::String("https://example.com/pkg1".to_string())
+    );
+
+    let (_, problems) =
+        Input::check_for(&inputs.path().to_path_buf(), &InputContext::default()).unwrap();
+    assert!(matches!(
+        &problems[..],
+        [InputError::UndefinedVariable { name, .. }] if name == "base"
+    ));
+}
+
+#[test]
+fn includes_are_resolved_from_the_including_file() {
+    let root = tempfile::tempdir().unwrap();
+    let inputs = root.path().join("inputs");
+    let shared = root.path().join("shared");
+    std::fs::create_dir_all(&inputs).unwrap();
+    std::fs::create_dir_all(&shared).unwrap();
+
+    std::fs::write(
+        inputs.join("main.kdl"),
+        "include \"../shared/*.kdl\"\nbridge1 {\n  pkg1\n}\n",
+    )
+    .unwrap();
+    std::fs::write(shared.join("a.kdl"), "bridge1 {\n  pkg2\n}\n").unwrap();
+    // back to the file that included it
+    std::fs::write(
+        shared.join("b.kdl"),
+        "include \"../inputs/main.kdl\"\nbridge2 {\n  pkg3\n}\n",
+    )
+    .unwrap();
+
+    let (input, problems) = Input::check(&inputs).unwrap();
+
+    assert_eq!(input.bridges.len(), 2);
+    assert_eq!(input.bridges[0].pkgs.len(), 2);
+    assert!(matches!(&problems[..], [InputError::IncludeCycle { .. }]));
+}
+
+#[test]
+fn child_nodes_are_list_and_map_attributes() {
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(
+        inputs.path().join("a.kdl"),
+        "bridge1 {\n  pkg1 \"pkg1\" version=\"1\" {\n    tags \"dev\"\n    assets \"linux-x64\" \"musl\"\n    build {\n      cmd \"make\"\n      jobs 4\n    }\n    static\n  }\n}\n",
+    )
+    .unwrap();
+
+    let input = Input::load(&inputs.path().to_path_buf()).unwrap();
+    let pkg = &input.bridges[0].pkgs[0];
+
+    assert_eq!(pkg.tags, vec!["dev"]);
+    assert!(!pkg.attributes.contains_key("tags"));
+    assert_eq!(
+        pkg.attributes["assets"],
+        AttributeValue::List(vec![
+            AttributeValue::String("linux-x64".to_string()),
+            AttributeValue::String("musl".to_string()),
+        ])
+    );
+    assert_eq!(
+        pkg.attributes["build"],
+        AttributeValue::Map(std::collections::BTreeMap::from([
+            (
+                "cmd".to_string(),
+                AttributeValue::String("make".to_string())
+            ),
+            ("jobs".to_string(), AttributeValue::Integer(4)),
+        ]))
+    );
+    assert_eq!(pkg.attributes["static"], AttributeValue::Boolean(true));
+}
+
+#[test]
+fn pkgs_can_bring_their_own_bridge() {
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(
+        inputs.path().join("a.kdl"),
+        "dotfiles {\n  tool1 {\n    bridge \"./bridges/custom\"\n  }\n  tool2 {\n    exec \"echo hi\"\n  }\n}\nbridge1 {\n  pkg1\n}\n",
+    )
+    .unwrap();
+
+    let input = Input::load(&inputs.path().to_path_buf()).unwrap();
+    let dotfiles = &input.bridges[0];
+
+    assert_eq!(
+        dotfiles.pkgs[0].bridge,
+        Some(BridgeOverride::Path(inputs.path().join("./bridges/custom")))
+    );
+    assert_eq!(
+        dotfiles.pkgs[1].bridge,
+        Some(BridgeOverride::Exec("echo hi".to_string()))
+    );
+    assert!(dotfiles.pkgs[1].attributes.is_empty());
+    assert!(!dotfiles.needs_bridge_dir());
+    assert!(input.bridges[1].needs_bridge_dir());
+}
+
+#[test]
+#[cfg(all(feature = "toml_inputs", feature = "yaml_inputs"))]
+fn toml_and_yaml_inputs_are_read_like_kdl() {
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(
+        inputs.path().join("a.toml"),
+        "[vars]\nversion = \"1.2\"\n\n[bridge1]\npkg1 = \"pkg1@${version}\"\n\n[bridge1.pkg2]\ninput = \"pkg2-input\"\nurl = \"https://example.com\"\ntags = [\"dev\"]\nassets = [\"linux-x64\", \"musl\"]\nbuild = { cmd = \"make\" }\n\n[bridge1.pkg3]\nwhen = { os = \"plan9\" }\n",
+    )
+    .unwrap();
+    std::fs::write(
+        inputs.path().join("b.yaml"),
+        "bridge2:\n  pkg4:\n    jobs: 4\n",
+    )
+    .unwrap();
+
+    let input = Input::load(&inputs.path().to_path_buf()).unwrap();
+    let bridge = |name: &str| input.bridges.iter().find(|b| b.name == name).unwrap();
+
+    let pkgs = &bridge("bridge1").pkgs;
+    assert_eq!(pkgs.len(), 2);
+    assert_eq!(pkgs[0].input, "pkg1@1.2");
+    assert_eq!(pkgs[1].input, "pkg2-input");
+    assert_eq!(pkgs[1].tags, vec!["dev"]);
+    assert_eq!(
+        pkgs[1].attributes["url"],
+        AttributeValue::String("https://example.com".to_string())
+    );
+    assert!(matches!(
+        pkgs[1].attributes["assets"],
+        AttributeValue::List(_)
+    ));
+    assert!(matches!(
+        pkgs[1].attributes["build"],
+        AttributeValue::Map(_)
+    ));
+
+    let pkg4 = &bridge("bridge2").pkgs[0];
+    assert_eq!(pkg4.input, "pkg4");
+    assert_eq!(pkg4.attributes["jobs"], AttributeValue::Integer(4));
+
+    std::fs::write(inputs.path().join("c.yml"), "- not a table\n").unwrap();
+    let (_, problems) =
+        Input::check_for(&inputs.path().to_path_buf(), &InputContext::default()).unwrap();
+    assert!(matches!(&problems[..], [InputError::FormatError { .. }]));
+}
+
+#[test]
+fn lua_files_in_the_inputs_are_not_read() {
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(inputs.path().join("a.kdl"), "bridge1 {\n  pkg1\n}\n").unwrap();
+    std::fs::write(
+        inputs.path().join("init.lua"),
+        "pkg.add(\"bridge2\", \"pkg2\")\n",
+    )
+    .unwrap();
+
+    let input = Input::load(&inputs.path().to_path_buf()).unwrap();
+
+    assert_eq!(input.bridges.len(), 1);
+    assert_eq!(input.bridges[0].name, "bridge1");
+    assert_eq!(input.bridges[0].pkgs.len(), 1);
+    assert_eq!(input.bridges[0].pkgs[0].input, "pkg1");
+}
+
+#[test]
+fn name_filters_select_pkgs() {
+    let filter = NameFilter {
+        only: vec!["pkg1".to_string(), "pkg2".to_string()],
+        exclude: vec!["pkg2".to_string()],
+    };
+    assert!(filter.matches("pkg1"));
+    assert!(!filter.matches("pkg2"));
+    assert!(!filter.matches("pkg3"));
+
+    assert!(NameFilter::default().matches("pkg3"));
+}
+
+#[test]
+fn os_and_arch_attributes_skip_the_other_machines() {
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(
+        inputs.path().join("a.kdl"),
+        "bridge1 {\n  pkg1 \"pkg1\" os=\"linux\"\n  pkg2 \"pkg2\" arch=\"aarch64\"\n  pkg3 {\n    arch \"x86_64\" \"aarch64\"\n  }\n  pkg4\n}\n",
+    )
+    .unwrap();
+
+    let context = InputContext {
+        os: "linux".to_string(),
+        arch: "x86_64".to_string(),
+        ..Default::default()
+    };
+    let input = Input::load_for(&inputs.path().to_path_buf(), &context).unwrap();
+
+    // unlike `when`, the pkgs stay declared
+    let mismatches = input.bridges[0]
+        .pkgs
+        .iter()
+        .map(|p| p.platform_mismatch(&context))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        mismatches,
+        vec![
+            None,
+            Some("only for arch aarch64, not x86_64".to_string()),
+            None,
+            None
+        ]
+    );
+}
+
+#[test]
+fn the_auto_pkgs_are_needed_through_the_deps() {
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(
+        inputs.path().join("a.kdl"),
+        "bridge1 {\n  app \"app\" deps=\"lib\"\n  lib \"lib\" auto=#true {\n    deps \"zlib\" \"ssl\"\n  }\n  zlib \"zlib\" auto=#true\n  ssl \"ssl\" auto=#true\n  unused \"unused\" auto=#true deps=\"old\"\n  old \"old\" auto=#true\n}\n",
+    )
+    .unwrap();
+
+    let input = Input::load(&inputs.path().to_path_buf()).unwrap();
+    let lib = &input.bridges[0].pkgs[1];
+    assert!(lib.is_auto());
+    assert_eq!(lib.deps(), vec!["zlib", "ssl"]);
+
+    let mut needed = needed_deps(&input.bridges)
+        .into_iter()
+        .collect::<Vec<String>>();
+    needed.sort();
+    assert_eq!(needed, vec!["lib", "ssl", "zlib"]);
+}
+
+#[test]
+fn presets_go_under_the_pkg_declarations() {
+    let inputs = tempfile::tempdir().unwrap();
+    std::fs::write(
+        inputs.path().join("presets.kdl"),
+        "preset \"rust-tool\" locked=#true {\n  tags \"rust\"\n  features \"default\"\n}\n",
+    )
+    .unwrap();
+    std::fs::write(
+        inputs.path().join("a.kdl"),
+        "cargo {\n  rg \"ripgrep\" {\n    use \"rust-tool\"\n    features \"pcre2\"\n    tags \"search\"\n  }\n  fd \"fd-find\"\n}\n",
+    )
+    .unwrap();
+
+    let input = Input::load(&inputs.path().to_path_buf()).unwrap();
+    let rg = &input.bridges[0].pkgs[0];
+    assert_eq!(rg.attributes["locked"], AttributeValue::Boolean(true));
+    // its own attributes win
+    assert_eq!(
+        rg.attributes["features"],
+        AttributeValue::String("pcre2".to_string())
+    );
+    assert!(!rg.attributes.contains_key("use"));
+    assert_eq!(rg.tags, vec!["rust", "search"]);
+    assert!(input.bridges[0].pkgs[1].attributes.is_empty());
+
+    std::fs::write(
+        inputs.path().join("a.kdl"),
+        "cargo {\n  rg \"ripgrep\" {\n    use \"rust-tol\"\n  }\n}\n",
+    )
+    .unwrap();
+    assert!(matches!(
+        Input::load(&inputs.path().to_path_buf()),
+        Err(InputError::UnknownPreset { name, .. }) if name == "rust-tol"
+    ));
+}
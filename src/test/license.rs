@@ -0,0 +1,38 @@
+use crate::license::*;
+
+#[test]
+fn the_license_expressions_are_checked_against_the_policy() {
+    let policy = LicensePolicy {
+        allow: vec!["MIT".to_string(), "Apache-2.0".to_string()],
+        deny: Vec::new(),
+    };
+
+    assert!(policy.check("fd", Some("MIT OR Apache-2.0")).is_ok());
+    assert!(policy.check("fd", Some("(GPL-3.0-only OR mit)")).is_ok());
+    assert!(policy.check("fd", Some("MIT AND Apache-2.0")).is_ok());
+    assert!(
+        policy
+            .check("fd", Some("Apache-2.0 WITH LLVM-exception"))
+            .is_ok()
+    );
+    // the bridge didn't say
+    assert!(policy.check("fd", None).is_ok());
+
+    assert!(matches!(
+        policy.check("fd", Some("MIT AND GPL-3.0-only")),
+        Err(LicenseError::NotAllowed { pkg, license }) if pkg == "fd" && license == "MIT AND GPL-3.0-only"
+    ));
+
+    let policy = LicensePolicy {
+        allow: Vec::new(),
+        deny: vec!["AGPL-3.0-only".to_string()],
+    };
+    assert!(policy.check("app", Some("BSD-3-Clause")).is_ok());
+    assert!(policy.check("app", Some("AGPL-3.0-only")).is_err());
+    assert!(policy.check("app", Some("AGPL-3.0-only OR MIT")).is_ok());
+    assert!(
+        LicensePolicy::default()
+            .check("app", Some("AGPL-3.0-only"))
+            .is_ok()
+    );
+}
@@ -0,0 +1,17 @@
+use tempfile::tempdir;
+
+use crate::lock::*;
+
+#[test]
+fn second_lock_is_refused() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("packages.lock");
+
+    let lock = Lock::acquire(&path).unwrap();
+
+    let err = Lock::acquire(&path).unwrap_err();
+    assert!(matches!(err, LockError::AlreadyLocked { .. }));
+
+    drop(lock);
+    assert!(Lock::acquire(&path).is_ok());
+}
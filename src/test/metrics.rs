@@ -0,0 +1,44 @@
+use crate::metrics::*;
+
+#[test]
+fn the_metrics_are_in_the_prometheus_text_format() {
+    let mut metrics = Metrics::new();
+    metrics.gauge(
+        "pkg_installed_packages",
+        "The installed packages, by bridge",
+        &[
+            (vec![("bridge", "cargo")], 3.0),
+            (vec![("bridge", "my \"bridge\"")], 1.0),
+        ],
+    );
+    metrics.gauge(
+        "pkg_last_build_duration_seconds",
+        "How long the last build took",
+        &[(Vec::new(), 1.5)],
+    );
+
+    assert_eq!(
+        metrics.as_str(),
+        "# HELP pkg_installed_packages The installed packages, by bridge\n\
+         # TYPE pkg_installed_packages gauge\n\
+         pkg_installed_packages{bridge=\"cargo\"} 3\n\
+         pkg_installed_packages{bridge=\"my \\\"bridge\\\"\"} 1\n\
+         # HELP pkg_last_build_duration_seconds How long the last build took\n\
+         # TYPE pkg_last_build_duration_seconds gauge\n\
+         pkg_last_build_duration_seconds 1.5\n"
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let textfile = dir.path().join("collector/pkg.prom");
+    metrics.write_textfile(&textfile).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&textfile).unwrap(),
+        metrics.as_str()
+    );
+    assert!(!textfile.with_extension("prom.tmp").exists());
+
+    assert!(matches!(
+        metrics.write_textfile(&dir.path().join("pkg.txt")),
+        Err(MetricsError::NotProm(_))
+    ));
+}
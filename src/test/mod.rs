@@ -1,2 +1,30 @@
+mod audit;
 mod bridge;
+mod build;
+#[cfg(feature = "cli_complation")]
+mod completions;
+mod config;
 mod db;
+mod docs;
+mod exit;
+mod export;
+mod failures;
+mod fs;
+mod git;
+mod hooks;
+mod host;
+mod import;
+mod input;
+mod license;
+mod lock;
+mod metrics;
+mod notify;
+mod output;
+mod plan;
+mod privilege;
+mod registry;
+mod schedule;
+mod secrets;
+mod systemd;
+#[cfg(target_os = "linux")]
+mod watch;
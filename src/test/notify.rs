@@ -0,0 +1,78 @@
+use crate::{
+    event::{Event, EventSink, NoopSink, Step},
+    notify::*,
+};
+
+#[test]
+fn the_report_is_made_from_the_events_and_sent_on_failure() {
+    let mut sink = ReportSink::new("update", NoopSink);
+    sink.emit(Event::PackageInstalled {
+        name: "fd".to_string(),
+    });
+    sink.emit(Event::PackageRemoved {
+        name: "bat".to_string(),
+    });
+    sink.emit(Event::Summary {
+        installed: 1,
+        removed: 1,
+    });
+    assert!(sink.report().success());
+
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("summary.json");
+    let notifiers = Notifiers {
+        on: NotifyOn::Failure,
+        exec: vec![format!("cat > {}; echo $PKG_STATUS >> {0}", out.display())],
+        ..Default::default()
+    };
+
+    // nothing failed, nothing is sent
+    assert!(notifiers.send(sink.report()).is_empty());
+    assert!(!out.exists());
+
+    sink.emit(Event::PackageFailed {
+        name: "rg".to_string(),
+        step: Step::BridgeOperation,
+        error: "exit code 1".to_string(),
+        exit_code: Some(1),
+    });
+    sink.emit(Event::HookFailed {
+        pkg: None,
+        error: "fc-cache".to_string(),
+    });
+    assert_eq!(
+        sink.report().failures,
+        [
+            Failure {
+                pkg: Some("rg".to_string()),
+                error: "exit code 1".to_string(),
+            },
+            Failure {
+                pkg: None,
+                error: "fc-cache".to_string(),
+            },
+        ]
+    );
+
+    assert!(notifiers.send(sink.report()).is_empty());
+    let sent = std::fs::read_to_string(&out).unwrap();
+    let (json, status) = sent.rsplit_once('}').unwrap();
+    assert_eq!(status.trim(), "failure");
+    let json: serde_json::Value = serde_json::from_str(&format!("{json}}}")).unwrap();
+    assert_eq!(json["command"], "update");
+    assert_eq!(json["installed"], serde_json::json!(["fd"]));
+    assert_eq!(json["removed"], serde_json::json!(["bat"]));
+    assert_eq!(json["failures"][0]["pkg"], "rg");
+
+    let failing = Notifiers {
+        exec: vec!["exit 3".to_string()],
+        ..Default::default()
+    };
+    assert!(matches!(
+        failing.send(&BuildReport::new("build")).as_slice(),
+        [NotifyError::Failed {
+            notifier: "exec",
+            ..
+        }]
+    ));
+}
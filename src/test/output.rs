@@ -0,0 +1,44 @@
+use owo_colors::Style;
+
+use crate::output::*;
+
+// one test, the colors are a global switch
+#[test]
+fn the_text_is_painted_only_with_the_colors_on() {
+    set_colors(false);
+    assert_eq!("done".paint(Style::new().green().bold()), "done");
+
+    set_colors(true);
+    let painted = "done".paint(Style::new().green().bold());
+    assert!(painted.starts_with("\x1b["));
+    assert!(painted.contains("done"));
+}
+
+#[test]
+fn a_question_gets_its_default_answer_without_a_terminal() {
+    let asked = |default, interactive, answer: &str| {
+        let mut out = Vec::new();
+        let yes = ask(
+            "continue?",
+            default,
+            interactive,
+            &mut answer.as_bytes(),
+            &mut out,
+        )
+        .unwrap();
+        (yes, String::from_utf8(out).unwrap())
+    };
+
+    // nothing is read, the answer is printed for the logs
+    let (yes, out) = asked(true, false, "n\n");
+    assert!(yes);
+    assert!(out.ends_with("y\n"));
+    let (yes, out) = asked(false, false, "y\n");
+    assert!(!yes);
+    assert!(out.ends_with("n\n"));
+
+    assert!(asked(true, true, "\n").0);
+    assert!(!asked(true, true, "No\n").0);
+    assert!(!asked(false, true, "\n").0);
+    assert!(asked(false, true, "yes\n").0);
+}
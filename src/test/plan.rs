@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use crate::{
+    db::{DbSnapshot, Pkg, PkgMetadata, PkgRecord, PkgType, Version},
+    input::{Bridge, NameFilter, PkgDeclaration, TagFilter},
+    plan::*,
+};
+
+// `count` pkgs of the bridge
+fn installed(bridge: &str, count: usize) -> DbSnapshot {
+    DbSnapshot {
+        records: (0..count)
+            .map(|i| {
+                let name = format!("pkg{i}");
+                let record = PkgRecord {
+                    pkg: Pkg {
+                        name: name.clone(),
+                        version: Version::parse("1.0.0").unwrap(),
+                        path: format!("some/{name}").into(),
+                        pkg_type: PkgType::SingleExecutable,
+                        artifacts: Vec::new(),
+                        metadata: PkgMetadata::default(),
+                    },
+                    bridge: bridge.to_string(),
+                    installed_at: 0,
+                    tags: Vec::new(),
+                    deps: Vec::new(),
+                    auto: false,
+                    bridge_version: String::new(),
+                };
+                (name, record)
+            })
+            .collect(),
+    }
+}
+
+// the first `count` pkgs of the snapshot are still declared
+fn bridge(name: &str, count: usize) -> Bridge {
+    Bridge {
+        name: name.to_string(),
+        pkgs: (0..count)
+            .map(|i| PkgDeclaration {
+                name: format!("pkg{i}"),
+                input: format!("pkg{i}"),
+                attributes: HashMap::new(),
+                tags: Vec::new(),
+                bridge: None,
+            })
+            .collect(),
+    }
+}
+
+fn build_plan(snapshot: &DbSnapshot, bridges: &[Bridge], out_of_service: &[String]) -> Plan {
+    build_plan_for(snapshot, bridges, out_of_service, &[], &[])
+}
+
+fn build_plan_for(
+    snapshot: &DbSnapshot,
+    bridges: &[Bridge],
+    out_of_service: &[String],
+    when_excluded: &[String],
+    when_excluded_pkgs: &[String],
+) -> Plan {
+    Plan::new(
+        snapshot,
+        bridges,
+        out_of_service,
+        PlanScope {
+            only_bridge: None,
+            name_filter: &NameFilter::default(),
+            tag_filter: &TagFilter::default(),
+            removes_declared: true,
+            when_excluded,
+            when_excluded_pkgs,
+            updated: None,
+        },
+    )
+}
+
+#[test]
+fn a_removal_over_the_threshold_is_asked_even_with_yes() {
+    // 6 of the 20 pkgs, more than a quarter
+    let snapshot = installed("github", 20);
+    let plan = build_plan(&snapshot, &[bridge("github", 14)], &[]);
+
+    assert_eq!(plan.installed, 20);
+    assert_eq!(plan.removed.len(), 6);
+    assert!(plan.is_mass_remove());
+    assert!(plan.emptied_bridges.is_empty());
+
+    for (yes, interactive) in [(true, false), (false, false), (true, true)] {
+        assert_eq!(
+            plan.confirmation(false, yes, interactive),
+            Confirmation::Ask { mass_remove: true }
+        );
+    }
+    assert_eq!(plan.confirmation(true, true, true), Confirmation::Proceed);
+    assert_eq!(
+        plan.confirmation(true, false, true),
+        Confirmation::Ask { mass_remove: false }
+    );
+}
+
+#[test]
+fn a_small_removal_is_not_a_mass_remove() {
+    let snapshot = installed("github", 20);
+
+    // 2 of 20, and 5 of 20 (a quarter, not more)
+    for declared in [18, 15] {
+        let plan = build_plan(&snapshot, &[bridge("github", declared)], &[]);
+
+        assert_eq!(plan.removed.len(), 20 - declared);
+        assert!(!plan.is_mass_remove());
+        assert_eq!(plan.confirmation(false, true, true), Confirmation::Proceed);
+        assert_eq!(
+            plan.confirmation(false, false, false),
+            Confirmation::Proceed
+        );
+        assert_eq!(
+            plan.confirmation(false, false, true),
+            Confirmation::Ask { mass_remove: false }
+        );
+    }
+
+    // all of them, but fewer than `MASS_REMOVE_MIN`
+    let snapshot = installed("github", MASS_REMOVE_MIN - 1);
+    let plan = build_plan(&snapshot, &[bridge("github", 0)], &[]);
+    assert!(!plan.is_mass_remove());
+
+    // nothing to remove
+    let plan = build_plan(&snapshot, &[bridge("github", MASS_REMOVE_MIN - 1)], &[]);
+    assert!(plan.removed.is_empty());
+    assert_eq!(plan.confirmation(false, false, true), Confirmation::Proceed);
+}
+
+#[test]
+fn a_bridge_that_would_lose_all_its_pkgs_is_refused() {
+    let snapshot = installed("github", 3);
+
+    // not in the inputs anymore, and in them without a pkg
+    let out_of_service = build_plan(&snapshot, &[], &["github".to_string()]);
+    let emptied = build_plan(&snapshot, &[bridge("github", 0)], &[]);
+
+    for plan in [out_of_service, emptied] {
+        assert_eq!(plan.removed.len(), 3);
+        assert_eq!(
+            plan.confirmation(false, true, false),
+            Confirmation::BridgesEmptied(vec!["github".to_string()])
+        );
+        assert_eq!(plan.confirmation(true, true, false), Confirmation::Proceed);
+    }
+}
+
+#[test]
+fn a_rebuild_target_has_to_be_installed_from_the_inputs() {
+    let snapshot = installed("github", 2);
+    let bridges = [bridge("github", 2), bridge("cargo", 0)];
+    let targets = |packages: &[&str], only_bridge| {
+        let packages = packages.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        missing_rebuild_targets(&snapshot, &bridges, &packages, only_bridge)
+    };
+
+    assert!(targets(&["pkg0", "pkg1"], None).is_empty());
+    assert!(targets(&["pkg1"], Some("github")).is_empty());
+
+    // a typo, and a pkg of another bridge than the `--bridge`
+    assert_eq!(targets(&["pkg0", "pgk1"], None), ["pgk1"]);
+    assert_eq!(targets(&["pkg0"], Some("cargo")), ["pkg0"]);
+}
+
+#[test]
+fn a_bridge_left_out_by_when_loses_its_pkgs_like_a_pkg_left_out() {
+    let snapshot = installed("brew", 3);
+    let excluded = ["brew".to_string()];
+    let excluded_pkgs = ["pkg0", "pkg1", "pkg2"].map(String::from);
+
+    // the whole bridge, then each of its pkgs
+    for (bridges, out_of_service, excluded, excluded_pkgs) in [
+        (Vec::new(), excluded.to_vec(), &excluded[..], &[][..]),
+        (vec![bridge("brew", 0)], Vec::new(), &[], &excluded_pkgs[..]),
+    ] {
+        let plan = build_plan_for(
+            &snapshot,
+            &bridges,
+            &out_of_service,
+            excluded,
+            excluded_pkgs,
+        );
+        assert_eq!(plan.removed.len(), 3);
+        assert!(plan.emptied_bridges.is_empty());
+        assert_eq!(plan.confirmation(false, true, false), Confirmation::Proceed);
+
+        // no declaration loaded is still refused
+        let plan = build_plan_for(&snapshot, &bridges, &out_of_service, &[], &[]);
+        assert_eq!(plan.emptied_bridges, ["brew"]);
+    }
+}
+
+#[test]
+fn a_pkg_left_out_by_when_does_not_excuse_the_rest_of_its_bridge() {
+    let snapshot = installed("brew", 3);
+
+    // pkg0 is left out by its `when`, pkg1 and pkg2 aren't declared anymore
+    let plan = build_plan_for(
+        &snapshot,
+        &[bridge("brew", 0)],
+        &[],
+        &[],
+        &["pkg0".to_string()],
+    );
+    assert_eq!(plan.emptied_bridges, ["brew"]);
+    assert_eq!(
+        plan.confirmation(false, true, false),
+        Confirmation::BridgesEmptied(vec!["brew".to_string()])
+    );
+}
@@ -0,0 +1,63 @@
+use std::{ffi::OsString, path::Path};
+
+use crate::privilege::*;
+
+#[test]
+fn pkg_is_run_again_through_the_escalation_command() {
+    for escalation in Escalation::ALL {
+        assert_eq!(escalation.to_string().parse::<Escalation>(), Ok(escalation));
+    }
+    assert!("su".parse::<Escalation>().is_err());
+
+    let args = [OsString::from("build"), OsString::from("--quiet")];
+    let argv = |escalation: Escalation, interactive| {
+        let command = escalation.command_for(Path::new("/usr/bin/pkg"), &args, interactive);
+        std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<String>>()
+    };
+
+    assert_eq!(
+        argv(Escalation::Sudo, true),
+        ["sudo", "/usr/bin/pkg", "build", "--quiet"]
+    );
+    // it fails instead of asking for the password
+    assert_eq!(
+        argv(Escalation::Sudo, false),
+        ["sudo", "-n", "/usr/bin/pkg", "build", "--quiet"]
+    );
+    assert_eq!(
+        argv(Escalation::Doas, false),
+        ["doas", "-n", "/usr/bin/pkg", "build", "--quiet"]
+    );
+    assert_eq!(
+        argv(Escalation::Run0, false),
+        [
+            "run0",
+            "--no-ask-password",
+            "/usr/bin/pkg",
+            "build",
+            "--quiet"
+        ]
+    );
+}
+
+#[test]
+fn the_polkit_policy_names_this_pkg() {
+    assert_eq!("pkexec".parse::<Escalation>(), Ok(Escalation::Pkexec));
+
+    let dir = tempfile::tempdir().unwrap();
+    let actions = dir.path().join("polkit-1/actions");
+
+    let path = install_polkit_policy(&actions, Path::new("/usr/local/bin/pkg")).unwrap();
+    assert_eq!(path, actions.join(POLKIT_POLICY_NAME));
+    let policy = std::fs::read_to_string(&path).unwrap();
+    assert!(policy.contains(&format!("<action id=\"{POLKIT_ACTION}\">")));
+    assert!(policy.contains(
+        "<annotate key=\"org.freedesktop.policykit.exec.path\">/usr/local/bin/pkg</annotate>"
+    ));
+
+    assert!(remove_polkit_policy(&actions).unwrap());
+    assert!(!remove_polkit_policy(&actions).unwrap());
+}
@@ -0,0 +1,135 @@
+use crate::registry::*;
+
+#[test]
+fn the_index_is_parsed() {
+    let index = r#"
+bridge "github" version="1.2.0" url="github-1.2.0.tar.gz" sha256="9F86D0"
+bridge "cargo" url="https://mirror.example/cargo.tar.gz" sha256="60303a"
+"#;
+    let entries = parse_index(index, "https://bridges.example/index.kdl").unwrap();
+
+    assert_eq!(
+        entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.version.as_str(), e.sha256.as_str()))
+            .collect::<Vec<_>>(),
+        [("github", "1.2.0", "9f86d0"), ("cargo", "", "60303a")]
+    );
+    assert_eq!(
+        resolve_url("https://bridges.example/index.kdl", &entries[0].url),
+        "https://bridges.example/github-1.2.0.tar.gz"
+    );
+    assert_eq!(
+        resolve_url("https://bridges.example/index.kdl", &entries[1].url),
+        "https://mirror.example/cargo.tar.gz"
+    );
+
+    assert!(parse_index(r#"bridge "github" version="1.2.0""#, "index.kdl").is_err());
+
+    assert_eq!(
+        Registry::parse("https://github.com/user/bridges.git"),
+        Registry::Git("https://github.com/user/bridges.git".to_string())
+    );
+    assert_eq!(
+        Registry::parse("git+https://git.example/bridges"),
+        Registry::Git("https://git.example/bridges".to_string())
+    );
+    assert_eq!(
+        Registry::parse("https://bridges.example/index.kdl"),
+        Registry::Index("https://bridges.example/index.kdl".to_string())
+    );
+}
+
+#[test]
+fn a_tarball_that_does_not_match_its_sha256_is_not_installed() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("github.tar.gz"), b"not the tarball").unwrap();
+    std::fs::write(
+        dir.path().join("index.kdl"),
+        format!(
+            "bridge \"github\" url=\"github.tar.gz\" sha256=\"{}\"\n",
+            sha256(b"the tarball")
+        ),
+    )
+    .unwrap();
+
+    let registry = Registry::Index(dir.path().join("index.kdl").display().to_string());
+    let cache = dir.path().join("cache");
+
+    assert!(matches!(
+        fetch(&registry, "github", &cache),
+        Err(RegistryError::ChecksumMismatch { .. })
+    ));
+    assert!(matches!(
+        fetch(&registry, "cargo", &cache),
+        Err(RegistryError::NotFound { .. })
+    ));
+}
+
+#[test]
+fn an_installed_bridge_replaces_the_one_in_the_set() {
+    let dir = tempfile::tempdir().unwrap();
+    let fetched = dir.path().join("fetched");
+    std::fs::create_dir_all(&fetched).unwrap();
+    std::fs::write(fetched.join("run"), "#!/bin/sh\necho new\n").unwrap();
+    let set = dir.path().join("bridges");
+    std::fs::create_dir_all(set.join("github")).unwrap();
+    std::fs::write(set.join("github").join("old"), "").unwrap();
+
+    let fetched = Fetched {
+        dir: fetched,
+        version: "1.2.0".to_string(),
+        revision: "9f86d0".to_string(),
+    };
+    let installed = install(&fetched, &set, "github").unwrap();
+
+    assert_eq!(installed, set.join("github"));
+    assert!(installed.join("run").is_file());
+    assert!(!installed.join("old").exists());
+
+    std::fs::remove_file(fetched.dir.join("run")).unwrap();
+    assert!(matches!(
+        install(&fetched, &set, "github"),
+        Err(RegistryError::NotABridge(_))
+    ));
+    assert!(installed.join("run").is_file());
+}
+
+#[test]
+fn a_bridge_name_is_one_dir_of_the_set() {
+    for name in ["github", "cargo-binstall", "my.bridge"] {
+        assert!(check_name(name).is_ok());
+    }
+    for name in ["", ".", "..", "a/../../x", "a/b", "/abs", ".git", "github/"] {
+        assert!(
+            matches!(check_name(name), Err(RegistryError::BadBridgeName(_))),
+            "{name:?}"
+        );
+    }
+
+    // the set and the checkout of a git registry stay as they are
+    let dir = tempfile::tempdir().unwrap();
+    let set = dir.path().join("bridges");
+    std::fs::create_dir_all(set.join("github")).unwrap();
+    let fetched = Fetched {
+        dir: dir.path().to_path_buf(),
+        version: String::new(),
+        revision: String::new(),
+    };
+    std::fs::write(dir.path().join("run"), "").unwrap();
+    for name in ["", "..", "a/../../x"] {
+        assert!(matches!(
+            install(&fetched, &set, name),
+            Err(RegistryError::BadBridgeName(_))
+        ));
+        assert!(matches!(
+            fetch(
+                &Registry::Git("file:///nothing.git".to_string()),
+                name,
+                dir.path()
+            ),
+            Err(RegistryError::BadBridgeName(_))
+        ));
+    }
+    assert!(set.join("github").is_dir());
+}
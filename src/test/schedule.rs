@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use crate::schedule::*;
+
+fn schedule(frequency: Frequency) -> Schedule {
+    Schedule {
+        frequency,
+        jitter: Duration::from_secs(30 * 60),
+        env: vec![(
+            "XDG_CONFIG_HOME".to_string(),
+            "/home/me/.config".to_string(),
+        )],
+        command: vec![
+            "/usr/local/bin/pkg".to_string(),
+            "--profile".to_string(),
+            "my work".to_string(),
+            "update".to_string(),
+        ],
+    }
+}
+
+#[test]
+fn a_timer_and_its_service_are_written_and_removed() {
+    let dir = tempfile::tempdir().unwrap();
+    let scheduler = Scheduler::SystemdTimer(dir.path().to_path_buf());
+
+    let files = scheduler.install(&schedule(Frequency::Weekly)).unwrap();
+    assert_eq!(
+        files,
+        [
+            dir.path().join("pkg-update.service"),
+            dir.path().join("pkg-update.timer")
+        ]
+    );
+
+    let service = std::fs::read_to_string(&files[0]).unwrap();
+    assert!(service.contains("Environment=\"XDG_CONFIG_HOME=/home/me/.config\"\n"));
+    assert!(service.contains("ExecStart=/usr/local/bin/pkg --profile \"my work\" update\n"));
+    let timer = std::fs::read_to_string(&files[1]).unwrap();
+    assert!(timer.contains("OnCalendar=weekly\nRandomizedDelaySec=1800\n"));
+
+    // installed again, it's replaced
+    scheduler.install(&schedule(Frequency::Daily)).unwrap();
+    assert_eq!(scheduler.installed().unwrap().len(), 2);
+
+    assert_eq!(scheduler.remove().unwrap(), files);
+    assert!(scheduler.installed().unwrap().is_empty());
+    assert!(scheduler.remove().unwrap().is_empty());
+}
+
+#[test]
+fn a_cron_entry_is_used_without_systemd_and_the_other_files_are_kept() {
+    let dir = tempfile::tempdir().unwrap();
+    let scheduler = Scheduler::Cron(dir.path().to_path_buf());
+
+    let files = scheduler.install(&schedule(Frequency::Daily)).unwrap();
+    let cron = std::fs::read_to_string(&files[0]).unwrap();
+    let line = cron.lines().last().unwrap();
+    let fields = line.split(' ').collect::<Vec<&str>>();
+
+    // at a random minute in the first half hour
+    assert!(fields[0].parse::<u64>().unwrap() < 30);
+    assert_eq!(fields[1..6], ["0", "*", "*", "*", "root"]);
+    assert!(cron.contains("\nXDG_CONFIG_HOME=/home/me/.config\n"));
+
+    std::fs::write(&files[0], "0 0 * * * root backup\n").unwrap();
+    assert!(matches!(
+        scheduler.install(&schedule(Frequency::Daily)),
+        Err(ScheduleError::NotOurs(_))
+    ));
+    assert!(matches!(scheduler.remove(), Err(ScheduleError::NotOurs(_))));
+    assert!(files[0].exists());
+}
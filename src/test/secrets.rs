@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use crate::{
+    input::{AttributeValue, PkgDeclaration},
+    secrets::*,
+};
+
+#[test]
+fn the_secrets_are_resolved_and_redacted() {
+    let dir = tempfile::tempdir().unwrap();
+    let token_file = dir.path().join("token");
+    std::fs::write(&token_file, "s3cr3t\n").unwrap();
+
+    let pkg = PkgDeclaration {
+        name: "private".to_string(),
+        input: "org/private".to_string(),
+        attributes: HashMap::from([
+            (
+                "token".to_string(),
+                AttributeValue::String(format!("{FILE_PREFIX}{}", token_file.display())),
+            ),
+            (
+                "home".to_string(),
+                AttributeValue::List(vec![AttributeValue::String(format!("{ENV_PREFIX}HOME"))]),
+            ),
+            (
+                "version".to_string(),
+                AttributeValue::String("1".to_string()),
+            ),
+        ]),
+        tags: Vec::new(),
+        bridge: None,
+    };
+
+    let (resolved, secrets) = resolve(&pkg).unwrap();
+    let home = std::env::var("HOME").unwrap();
+    assert_eq!(
+        resolved.attributes["token"],
+        AttributeValue::String("s3cr3t".to_string())
+    );
+    assert_eq!(
+        resolved.attributes["home"],
+        AttributeValue::List(vec![AttributeValue::String(home.clone())])
+    );
+    assert_eq!(resolved.attributes["version"], pkg.attributes["version"]);
+
+    assert_eq!(
+        secrets.redact(&format!("curl -H 'token: s3cr3t' {home}")),
+        format!("curl -H 'token: {REDACTED}' {REDACTED}")
+    );
+
+    let missing = PkgDeclaration {
+        attributes: HashMap::from([(
+            "token".to_string(),
+            AttributeValue::String(format!("{ENV_PREFIX}PKG_TEST_NO_SUCH_SECRET")),
+        )]),
+        ..pkg
+    };
+    assert!(matches!(
+        resolve(&missing),
+        Err(SecretError::NotFound { .. })
+    ));
+}
@@ -0,0 +1,58 @@
+use std::{collections::HashMap, os::unix::fs::PermissionsExt, path::PathBuf};
+
+use crate::{input::AttributeValue, systemd::*};
+
+#[test]
+fn enabled_units_are_reloaded_too() {
+    let attributes = HashMap::from([(ENABLE_ATTRIBUTE.to_string(), AttributeValue::Boolean(true))]);
+    assert_eq!(
+        UnitOptions::from_attributes("app", &attributes).unwrap(),
+        UnitOptions {
+            enable: true,
+            reload: true
+        }
+    );
+
+    let attributes = HashMap::from([(
+        RELOAD_ATTRIBUTE.to_string(),
+        AttributeValue::String("yes".to_string()),
+    )]);
+    assert!(matches!(
+        UnitOptions::from_attributes("app", &attributes),
+        Err(SystemdError::WrongValue {
+            attribute: RELOAD_ATTRIBUTE,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn only_the_enabled_units_are_disabled() {
+    let dir = tempfile::tempdir().unwrap();
+    let calls = dir.path().join("calls");
+
+    // `a.service` is the only enabled one
+    let program = dir.path().join("systemctl");
+    std::fs::write(
+        &program,
+        format!(
+            "#!/bin/sh\necho \"$@\" >> '{}'\n[ \"$1\" != is-enabled ] || [ \"$3\" = a.service ]\n",
+            calls.display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let systemctl = Systemctl::new(program);
+    let units = [
+        PathBuf::from("/opt/b/app/a.service"),
+        PathBuf::from("/opt/b/app/b.service"),
+    ];
+
+    assert!(systemctl.disable_now(&units).unwrap());
+    assert!(
+        std::fs::read_to_string(&calls)
+            .unwrap()
+            .ends_with("disable --now a.service\n")
+    );
+}
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use crate::watch::*;
+
+#[test]
+fn a_burst_of_changes_is_one_wait() {
+    let dir = tempfile::tempdir().unwrap();
+    let inputs = dir.path().join("inputs");
+    std::fs::create_dir_all(inputs.join(".git")).unwrap();
+    let config = dir.path().join(".config.kdl");
+    std::fs::write(&config, "config {}").unwrap();
+
+    let mut watcher = Watcher::new().unwrap();
+    watcher.add_dir(&inputs).unwrap();
+    watcher.add_file(&config).unwrap();
+
+    let writer = {
+        let inputs = inputs.clone();
+        let config = config.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            std::fs::write(inputs.join("cargo.kdl"), "cargo { fd; }").unwrap();
+            std::fs::write(inputs.join("cargo.kdl~"), "").unwrap();
+            std::fs::write(inputs.join(".git/index"), "").unwrap();
+            std::fs::create_dir(inputs.join("work")).unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            // the new dir is watched too
+            std::fs::write(inputs.join("work/eget.kdl"), "eget { gh; }").unwrap();
+            std::fs::write(&config, "config { }").unwrap();
+            // not the config
+            std::fs::write(config.with_file_name("other"), "").unwrap();
+        })
+    };
+
+    let changed = watcher.wait(Duration::from_millis(300)).unwrap();
+    writer.join().unwrap();
+
+    assert_eq!(
+        changed,
+        [
+            config,
+            inputs.join("cargo.kdl"),
+            inputs.join("work"),
+            inputs.join("work/eget.kdl"),
+        ]
+    );
+}
@@ -0,0 +1,314 @@
+// `pkg watch`, the inputs dir (and the config) watched with inotify, a burst
+// of changes (an editor saving, a `git pull`) is one build. there's no
+// watcher on the other oses yet, `Watcher::new` says so
+#[cfg(target_os = "linux")]
+use std::{
+    collections::HashMap,
+    ffi::{CString, OsStr, OsString},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::ffi::{OsStrExt, OsStringExt},
+    },
+};
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use miette::Diagnostic;
+use thiserror::Error;
+
+use crate::host;
+
+// what changes a file or the tree, the access and the attributes don't
+#[cfg(target_os = "linux")]
+const WATCHED_EVENTS: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO;
+
+// `wd`, `mask`, `cookie` and `len` before the name
+#[cfg(target_os = "linux")]
+const EVENT_HEADER_SIZE: usize = 16;
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum WatchError {
+    #[error(transparent)]
+    #[diagnostic(code(watch::io_error))]
+    IoError(#[from] std::io::Error),
+
+    #[error("Couldn't watch {path:?}: {source}")]
+    #[diagnostic(
+        code(watch::add_failed),
+        help("The inotify watches can be raised with the `fs.inotify.max_user_watches` sysctl")
+    )]
+    AddFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("pkg can't watch files on {0} yet")]
+    #[diagnostic(
+        code(watch::unsupported),
+        help("Run `pkg build` after the changes, or from a file watcher of the os")
+    )]
+    Unsupported(&'static str),
+}
+
+type Result<T, E = WatchError> = std::result::Result<T, E>;
+
+// a watched dir, with all its files (not the hidden ones) or only some names
+#[cfg(target_os = "linux")]
+#[derive(Debug, Default)]
+struct Watch {
+    dir: PathBuf,
+    all: bool,
+    names: Vec<OsString>,
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct Watcher {
+    fd: OwnedFd,
+    watches: HashMap<i32, Watch>,
+}
+
+// it can't be made, the methods are never called
+#[cfg(not(target_os = "linux"))]
+#[derive(Debug)]
+pub struct Watcher {
+    never: std::convert::Infallible,
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    pub fn new() -> Result<Self> {
+        Err(WatchError::Unsupported(std::env::consts::OS))
+    }
+
+    pub fn add_dir(&mut self, _dir: &Path) -> Result<()> {
+        match self.never {}
+    }
+
+    pub fn add_file(&mut self, _file: &Path) -> Result<()> {
+        match self.never {}
+    }
+
+    pub fn wait(&mut self, _debounce: Duration) -> Result<Vec<PathBuf>> {
+        match self.never {}
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    pub fn new() -> Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(Self {
+            // SAFETY: it was just opened and nothing else owns it
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            watches: HashMap::new(),
+        })
+    }
+
+    // the dir and its sub dirs, the hidden ones (`.git`, `.bridges`) aren't
+    // inputs
+    pub fn add_dir(&mut self, dir: &Path) -> Result<()> {
+        self.watch(dir)?.all = true;
+
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() && !is_hidden(path.file_name().unwrap_or_default()) {
+                self.add_dir(&path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // its dir is watched, an editor often replaces the file instead of
+    // writing it
+    pub fn add_file(&mut self, file: &Path) -> Result<()> {
+        let (Some(dir), Some(name)) = (file.parent(), file.file_name()) else {
+            return Ok(());
+        };
+
+        let watch = self.watch(dir)?;
+        if !watch.names.iter().any(|n| n == name) {
+            watch.names.push(name.to_os_string());
+        }
+
+        Ok(())
+    }
+
+    fn watch(&mut self, dir: &Path) -> Result<&mut Watch> {
+        let add_failed = |source| WatchError::AddFailed {
+            path: dir.to_path_buf(),
+            source,
+        };
+
+        let path = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|err| add_failed(std::io::Error::other(err)))?;
+        // the same dir is the same wd
+        let wd =
+            unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), WATCHED_EVENTS) };
+        if wd < 0 {
+            return Err(add_failed(std::io::Error::last_os_error()));
+        }
+
+        Ok(self.watches.entry(wd).or_insert_with(|| Watch {
+            dir: dir.to_path_buf(),
+            ..Default::default()
+        }))
+    }
+
+    // blocks until something changes, then until nothing changed for
+    // `debounce`, the changed paths are sorted without duplicates
+    pub fn wait(&mut self, debounce: Duration) -> Result<Vec<PathBuf>> {
+        let mut changed = Vec::new();
+
+        while changed.is_empty() {
+            self.poll(None)?;
+            changed.extend(self.read_events()?);
+        }
+
+        while self.poll(Some(debounce))? {
+            changed.extend(self.read_events()?);
+        }
+
+        changed.sort();
+        changed.dedup();
+        Ok(changed)
+    }
+
+    // false when the timeout passed without an event
+    fn poll(&self, timeout: Option<Duration>) -> Result<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.map_or(-1, |t| i32::try_from(t.as_millis()).unwrap_or(i32::MAX));
+
+        loop {
+            match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+                -1 => {
+                    let err = std::io::Error::last_os_error();
+                    if err.kind() != std::io::ErrorKind::Interrupted {
+                        return Err(err.into());
+                    }
+                }
+                ready => return Ok(ready > 0),
+            }
+        }
+    }
+
+    // the events that are ready, a new sub dir is watched too
+    fn read_events(&mut self) -> Result<Vec<PathBuf>> {
+        let mut buffer = [0u8; 4096];
+        let mut changed = Vec::new();
+        let mut new_dirs = Vec::new();
+
+        loop {
+            let read = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr().cast(),
+                    buffer.len(),
+                )
+            };
+            if read < 0 {
+                let err = std::io::Error::last_os_error();
+                match err.kind() {
+                    std::io::ErrorKind::WouldBlock => break,
+                    std::io::ErrorKind::Interrupted => continue,
+                    _ => return Err(err.into()),
+                }
+            }
+            if read == 0 {
+                break;
+            }
+
+            let mut offset = 0;
+            while offset + EVENT_HEADER_SIZE <= read as usize {
+                let field = |at: usize| {
+                    u32::from_ne_bytes(buffer[offset + at..offset + at + 4].try_into().unwrap())
+                };
+                let (wd, mask, len) = (field(0) as i32, field(4), field(12) as usize);
+
+                let name = &buffer[offset + EVENT_HEADER_SIZE..offset + EVENT_HEADER_SIZE + len];
+                // padded with nuls
+                let name = OsString::from_vec(
+                    name.iter().copied().take_while(|byte| *byte != 0).collect(),
+                );
+                offset += EVENT_HEADER_SIZE + len;
+
+                let Some(watch) = self.watches.get(&wd) else {
+                    continue;
+                };
+                let path = watch.dir.join(&name);
+
+                if watch.names.contains(&name) {
+                    changed.push(path);
+                } else if watch.all && !is_hidden(&name) && !is_editor_file(&name) {
+                    if mask & libc::IN_ISDIR != 0
+                        && mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0
+                    {
+                        new_dirs.push(path.clone());
+                    }
+                    changed.push(path);
+                }
+            }
+        }
+
+        for dir in new_dirs {
+            // it can be gone already
+            if dir.is_dir() {
+                self.add_dir(&dir)?;
+            }
+        }
+
+        Ok(changed)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_hidden(name: &OsStr) -> bool {
+    name.as_bytes().starts_with(b".")
+}
+
+// the swap and backup files of the editors (vim writes a `4913` to test the dir)
+#[cfg(target_os = "linux")]
+fn is_editor_file(name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    name.ends_with('~') || name.ends_with(".swp") || name.ends_with(".swx") || name == "4913"
+}
+
+// a desktop notification and a syslog line, what's missing is skipped, the
+// watch goes on anyway
+pub fn notify_failure(message: &str) {
+    let notifiers: [(&str, &[&str]); 2] = [
+        (
+            "notify-send",
+            &["--app-name=pkg", "--urgency=critical", "pkg watch"],
+        ),
+        ("logger", &["--tag", "pkg"]),
+    ];
+
+    for (command, args) in notifiers {
+        if let Some(path) = host::find_command(command) {
+            let _ = Command::new(path)
+                .args(args)
+                .arg(message)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+        }
+    }
+}